@@ -17,7 +17,7 @@ export const language = {
 
   lispKeywords: [
     'def', 'fn', 'quote', 'unquote', 'if', 'begin', 'focus', 'let',
-    'letrec', 'match', 'match-fn', 'match-fn*'
+    'letrec', 'match', 'match-fn', 'match-fn*', 'defmacro'
   ],
 
   lispBuiltins: [
@@ -25,15 +25,22 @@ export const language = {
     'max', 'min', '-', '//', '%', '<', '<=', '>', '>=', '=', 'shl', 'shr',
     'band', 'bor', 'bxor', 'bnot', '==', '->string', 'string->atom',
     'string-append', 'string-len', 'string-nth', 'substr', 'string->list',
-    'list->string', 'not', 'and', 'or', 'list', 'cons', 'hd', 'tl', 'nth',
-    'map', 'bool?', 'atom?', 'pair?', 'null?', 'number?', 'string?', 'fn?',
+    'list->string', 'number->string', 'string-upcase', 'string-downcase',
+    'string-index', 'string-split', 'string-join', 'string-replace', 'format',
+    'not', 'and', 'or', 'list', 'cons', 'hd', 'tl', 'nth',
+    'map', 'bool?', 'atom?', 'pair?', 'null?', 'number?', 'string?', 'fn?', 'macro',
     'def?', 'ref?', 'ref!', 'get!', 'set!', 'set-weak!', 'copy-span', 'stack-span',
-    'async', 'atom-map?', 'atom-map!', 'lookup', 'insert!', 'insert', 'set-timeout',
+    'async', 'atom-map?', 'atom-map!', 'lookup', 'insert!', 'insert', 'remove!',
+    'hash-map?', 'hash-map!', 'hash-set!', 'map-size', 'map->list', 'map-has?',
+    'map-union', 'map-intersect', 'map-diff', 'set-timeout',
     'set-stack-limit', 'mvar?', 'goal?', 'mvar!', 'pp', 'goal', 'goal-type',
     'infer-type', 'infer-sort', 'get-mvars', 'get-goals', 'set-goals',
     'set-close-fn', 'local-ctx', 'to-expr', 'refine', 'have', 'stat', 'get-decl',
     'add-decl!', 'add-term!', 'add-thm!', 'dummy!', 'check-proofs', 'set-reporting',
-    'refine-extra-args', 'eval-string', 'mmc-init'
+    'refine-extra-args', 'eval-string', 'register-eq', 'register-congr', 'register-simp',
+    'rw', 'simp', 'auto',
+    'unfold', 'fold', 'conv-unfold', 'conv-fold', 'conv-at', 'conv-lhs', 'conv-rhs', 'conv-goal',
+    'mmc-init'
   ],
 
   // symbols used as brackets
@@ -186,6 +186,7 @@ typedef struct {
 #define CMD_STMT_SORT      0x04
 #define CMD_STMT_DEF       0x05
 #define CMD_STMT_THM       0x06
+#define CMD_STMT_INPUT_STRING 0x07
 #define CMD_STMT_LOCAL_DEF 0x0D
 #define CMD_STMT_LOCAL_THM 0x0E
 
@@ -785,6 +785,20 @@ void verify(u8* file, u64 len) {
         g_num_thms++;
       } break;
 
+      // An input string statement has a proof stream containing the string
+      // expressions from the MM0 `input string` statement. We do not parse
+      // input statements in the MM0 file, so we only check that the expressions
+      // are well formed and then skip the statement; it declares nothing.
+      case CMD_STMT_INPUT_STRING: {
+        g_store_size = 0;
+        g_stack_top = g_stack;
+        g_heap_size = 0;
+        ENSURE("Next statement incorrect",
+          next_stmt == run_proof(Def, stmt+sz));
+        for (u32* e = g_stack; e < g_stack_top; e++)
+          ENSURE("bad stack slot", (*e & STACK_TYPE_MASK) == STACK_TYPE_EXPR);
+      } break;
+
       default: {
         ENSURE("bad statement command", false);
       } break;
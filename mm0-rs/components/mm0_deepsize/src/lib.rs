@@ -264,7 +264,7 @@ macro_rules! deep_size_0 {
 use std::sync::atomic;
 deep_size_0!(
     u8, u16, u32, u64, usize, i8, i16, i32, i64, isize,
-    bool, char, f32, f64, (), {!Copy} str,
+    bool, char, f32, f64, (), {!Copy} str, std::time::Duration, std::time::Instant,
     {!Copy} atomic::AtomicBool, {!Copy} atomic::AtomicIsize, {!Copy} atomic::AtomicUsize,
     {T: ?Sized} &T,
     {!Copy T} std::cell::Cell<T>,
@@ -76,4 +76,14 @@ make_atoms! {
   REFINE_EXTRA_ARGS: "refine-extra-args",
   /// `to-expr-fallback` is called when elaborating a term that is not otherwise recognized
   TO_EXPR_FALLBACK: "to-expr-fallback",
+  /// `elab-input` is called to elaborate an `input` command with a kind other than `string`
+  ELAB_INPUT: "elab-input",
+  /// `input-file` is the path of the file checked by `input string`
+  INPUT_FILE: "input-file",
+  /// `eq-schemes` is the atom map of equality schemes registered by `register-eq`
+  EQ_SCHEMES: "eq-schemes",
+  /// `congr-lemmas` is the atom map of congruence theorems registered by `register-congr`
+  CONGR_LEMMAS: "congr-lemmas",
+  /// `simp-lemmas` is the atom map of default lemmas for `simp`, added by `register-simp`
+  SIMP_LEMMAS: "simp-lemmas",
 }
@@ -581,6 +581,16 @@ impl FileRef {
   pub fn has_extension(&self, ext: &str) -> bool {
     self.path().extension().map_or(false, |s| s == ext)
   }
+
+  /// Resolve the file named by an `import "f";` statement in this file. The path `f` is
+  /// relative to the directory containing this file.
+  #[cfg(any(target_arch = "wasm32", feature = "lined_string"))]
+  pub fn resolve_import(&self, f: &[u8]) -> std::io::Result<FileRef> {
+    use std::io::{Error, ErrorKind};
+    let f = std::str::from_utf8(f).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    let path = self.path().parent().map_or_else(|| PathBuf::from(f), |p| p.join(f));
+    Ok(path.canonicalize()?.into())
+  }
 }
 impl PartialEq for FileRef {
   fn eq(&self, other: &Self) -> bool { self.0.rel == other.0.rel }
@@ -210,6 +210,7 @@ pub struct SExpr {
 /// atoms have data `quote`, `unquote` and `:nfx` respectively,
 /// but the span does not contain this text because
 /// these atoms are created implicitly via keywords like `'`.
+/// The [`Gen`](Atom::Gen) atom does not come from the input at all.
 #[derive(Copy, Clone, Debug)]
 pub enum Atom {
   /// This indicates that the atom text is a span from the input, i.e. the user wrote
@@ -224,6 +225,10 @@ pub enum Atom {
   /// This is an atom with the text `:nfx` that was generated by a malformed curly list
   /// (see [`curly_transform`]).
   Nfx,
+  /// This is an atom that was produced by a macro expansion rather than parsed from
+  /// the input. The number is an index into a table maintained by the macro expander,
+  /// which holds the actual text.
+  Gen(u32),
 }
 #[cfg(feature = "memory")]
 mm0_deepsize::deep_size_0!(Atom);
@@ -598,7 +603,8 @@ impl Ast {
 
 /// Given an [`Atom`] and associated [`Span`], such as those associated with
 /// [`SExprKind::Atom`], construct a string slice with the string contents
-/// of the atom.
+/// of the atom. (Generated atoms have no text in the source, so this returns
+/// the empty string for [`Atom::Gen`].)
 #[must_use]
 pub fn span_atom(lined: &LinedString, sp: Span, a: Atom) -> &[u8] {
   match a {
@@ -606,6 +612,7 @@ pub fn span_atom(lined: &LinedString, sp: Span, a: Atom) -> &[u8] {
     Atom::Quote => b"quote",
     Atom::Unquote => b"unquote",
     Atom::Nfx => b":nfx",
+    Atom::Gen(_) => b"",
   }
 }
 
@@ -5,7 +5,7 @@ use std::fmt::{Debug, Display};
 use num::Zero;
 use regalloc2::{MachineEnv, Operand};
 
-use crate::codegen::InstSink;
+use crate::codegen::{InstSink, RelocTarget};
 use crate::types::{mir, Size,
   vcode::{BlockId, GlobalId, SpillId, ProcId, InstId, VReg, IsReg, Inst as VInst, VCode}};
 
@@ -55,7 +55,7 @@ impl Debug for PReg {
 
 const RAX: PReg = PReg::new(0);
 const RCX: PReg = PReg::new(1);
-const RDX: PReg = PReg::new(2);
+pub(crate) const RDX: PReg = PReg::new(2);
 const RBX: PReg = PReg::new(3);
 pub(crate) const RSP: PReg = PReg::new(4);
 const RBP: PReg = PReg::new(5);
@@ -69,7 +69,7 @@ const R12: PReg = PReg::new(12);
 const R13: PReg = PReg::new(13);
 const R14: PReg = PReg::new(14);
 const R15: PReg = PReg::new(15);
-// pub(crate) const ARG_REGS: [PReg; 6] = [RDI, RSI, RDX, RCX, R8, R9];
+pub(crate) const ARG_REGS: [PReg; 6] = [RDI, RSI, RDX, RCX, R8, R9];
 pub(crate) const RET_AND_ARG_REGS: [PReg; 7] = [RAX, RDI, RSI, RDX, RCX, R8, R9];
 pub(crate) const SYSCALL_ARG_REGS: (PReg, [PReg; 6]) = (RAX, [RDI, RSI, RDX, R10, R8, R9]);
 pub(crate) const CALLER_SAVED: [PReg; 8] = [RAX, RDI, RSI, RDX, RCX, R8, R9, R10];
@@ -787,19 +787,18 @@ pub(crate) enum Inst {
     dst: VReg, // dst = src
     src: VReg,
   },
-  // /// Unsigned integer quotient and remainder pseudo-operation:
-  // /// `RDX:RAX <- cdq RAX`
-  // /// `RAX,RDX <- divrem RDX:RAX r/m.`
-  // DivRem {
-  //   sz: Size, // 2, 4 or 8
-  //   dst_div: VReg, // = RAX
-  //   dst_rem: VReg, // = RDX
-  //   src1: VReg, // = RAX
-  //   src2: RegMem,
-  // },
-  /// Unsigned integer quotient and remainder pseudo-operation:
-  // `RDX:RAX <- cdq RAX`
-  // `RAX,RDX <- divrem RDX:RAX r/m`.
+  /// (Un)signed integer quotient and remainder: `RAX,RDX <- (i)divrem RDX:RAX r/m`.
+  /// The divisor must be nonzero, and for signed division the quotient must be in range.
+  DivRem {
+    sz: Size, // 2, 4 or 8
+    signed: bool,
+    dst_div: VReg, // = RAX
+    dst_rem: VReg, // = RDX
+    src1_hi: VReg, // = RDX
+    src1_lo: VReg, // = RAX
+    src2: RegMem,
+  },
+  /// Unsigned integer double wide multiplication: `RDX:RAX <- RAX * r/m`.
   Mul {
     sz: Size, // 2, 4 or 8
     dst_lo: VReg, // = RAX
@@ -813,13 +812,12 @@ pub(crate) enum Inst {
   //   signed: bool,
   //   rhs: RegMem,
   // },
-  // /// Do a sign-extend based on the sign of the value in rax into rdx: (cwd cdq cqo)
-  // /// or al into ah: (cbw)
-  // SignExtendData {
-  //   sz: Size, // 1, 2, 4 or 8
-  //   dst: VReg, // dst = RDX
-  //   src: VReg, // src = RAX
-  // },
+  /// Do a sign-extend based on the sign of the value in rax into rdx: (cwd cdq cqo)
+  Cdx {
+    sz: Size, // 2, 4 or 8
+    dst: VReg, // dst = RDX
+    src: VReg, // src = RAX
+  },
   /// Constant materialization: `reg <- (imm32|imm64)`.
   /// Either: movl $imm32, %reg32 or movabsq $imm64, %reg32.
   Imm {
@@ -949,26 +947,29 @@ pub(crate) enum Inst {
   Ud2,
 }
 
+/// Displays an operand of a call or jump instruction, like `out v3 @ rax`.
+pub(crate) struct PrintOperand(pub(crate) Operand);
+
+impl Display for PrintOperand {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    use regalloc2::{OperandKind, OperandConstraint};
+    let vreg = VReg(self.0.vreg());
+    match self.0.kind() {
+      OperandKind::Def => write!(f, "out ")?,
+      OperandKind::Mod => write!(f, "inout ")?,
+      OperandKind::Use => {}
+    }
+    match self.0.constraint() {
+      OperandConstraint::FixedReg(r) => write!(f, "{} @ {}", vreg, PReg(r)),
+      OperandConstraint::Reg => write!(f, "{}", vreg),
+      _ => unreachable!(),
+    }
+  }
+}
+
 impl Debug for Inst {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     use itertools::Itertools;
-    struct PrintOperand(Operand);
-    impl Display for PrintOperand {
-      fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        use regalloc2::{OperandKind, OperandConstraint};
-        let vreg = VReg(self.0.vreg());
-        match self.0.kind() {
-          OperandKind::Def => write!(f, "out ")?,
-          OperandKind::Mod => write!(f, "inout ")?,
-          OperandKind::Use => {}
-        }
-        match self.0.constraint() {
-          OperandConstraint::FixedReg(r) => write!(f, "{} @ {}", vreg, PReg(r)),
-          OperandConstraint::Reg => write!(f, "{}", vreg),
-          _ => unreachable!(),
-        }
-      }
-    }
     match self {
       Self::Fallthrough { dst } => write!(f, "fallthrough -> bb{}", dst.0),
       Self::SyncLet { inst, dst } => write!(f, "sync_let {:?} @ i[{}]", dst, inst),
@@ -977,6 +978,10 @@ impl Debug for Inst {
         write!(f, "{} <- {:?}.{} {}, {}", dst, op, sz.bits0(), src1, src2),
       Self::Unop { op, sz, dst, src } =>
         write!(f, "{} <- {:?}.{} {}", dst, op, sz.bits0(), src),
+      Self::DivRem { sz, signed, dst_div, dst_rem, src1_hi, src1_lo, src2 } =>
+        write!(f, "{},{} <- {}divrem.{} {}:{}, {}",
+          dst_div, dst_rem, if *signed { "i" } else { "" }, sz.bits0(), src1_hi, src1_lo, src2),
+      Self::Cdx { sz, dst, src } => write!(f, "{} <- cdx.{} {}", dst, sz.bits0(), src),
       Self::Mul { sz, dst_lo, dst_hi, src1, src2 } =>
         write!(f, "{}:{} <- mul.{} {}, {}", dst_hi, dst_lo, sz.bits0(), src1, src2),
       Self::Imm { sz, dst, src } => write!(f, "{} <- imm.{} {}", dst, sz.bits0(), src),
@@ -1061,12 +1066,17 @@ impl VInst for Inst {
         args.push(Operand::reg_fixed_def(dst_lo.0, RAX.0));
         args.push(Operand::reg_fixed_def(dst_hi.0, RDX.0));
       },
-      // Inst::DivRem { dst_div, dst_rem, src1, ref src2, .. } => {
-      //   args.push(Operand::reg_fixed_use(src1, RAX));
-      //   src2.collect_operands(args);
-      //   args.push(Operand::reg_fixed_def(dst_div, RAX));
-      //   args.push(Operand::reg_fixed_def(dst_rem, RDX));
-      // },
+      Inst::DivRem { dst_div, dst_rem, src1_hi, src1_lo, ref src2, .. } => {
+        args.push(Operand::reg_fixed_use(src1_hi.0, RDX.0));
+        args.push(Operand::reg_fixed_use(src1_lo.0, RAX.0));
+        src2.collect_operands(args);
+        args.push(Operand::reg_fixed_def(dst_div.0, RAX.0));
+        args.push(Operand::reg_fixed_def(dst_rem.0, RDX.0));
+      },
+      Inst::Cdx { dst, src, .. } => {
+        args.push(Operand::reg_fixed_use(src.0, RAX.0));
+        args.push(Operand::reg_fixed_def(dst.0, RDX.0));
+      }
       // Inst::MovRP { dst, src } => args.push(Operand::reg_fixed_use(src, dst)),
       Inst::MovPR { dst, src } => args.push(Operand::reg_fixed_def(dst.0, src.0)),
       Inst::MovzxRmR { dst, ref src, .. } |
@@ -1168,10 +1178,23 @@ impl VCode<Inst> {
     dst
   }
 
+  /// The encoder does not support 16-bit immediates, so 16-bit operations with an immediate
+  /// that does not fit in a sign-extended byte (or any immediate, if `imm8` is false)
+  /// get the immediate put into a register first.
+  fn legalize_imm16(&mut self, sz: Size, src: RegMemImm, imm8: bool) -> RegMemImm {
+    match src {
+      #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+      RegMemImm::Imm(i) if sz == Size::S16 && !(imm8 && i as i8 as u32 == i) =>
+        RegMemImm::Reg(self.emit_imm(Size::S32, i)),
+      _ => src
+    }
+  }
+
   pub(crate) fn emit_binop(&mut self, sz: Size, op: Binop, src1: VReg, src2: impl Into<RegMemImm>
   ) -> VReg {
+    let src2 = self.legalize_imm16(sz, src2.into(), true);
     let dst = self.fresh_vreg();
-    self.emit(Inst::Binop { sz, op, dst, src1, src2: src2.into() });
+    self.emit(Inst::Binop { sz, op, dst, src1, src2 });
     dst
   }
 
@@ -1183,7 +1206,7 @@ impl VCode<Inst> {
 
   pub(crate) fn emit_imm(&mut self, sz: Size, src: impl Into<u64>) -> VReg {
     let dst = self.fresh_vreg();
-    self.emit(Inst::Imm { sz, dst, src: src.into() });
+    self.emit(Inst::Imm { sz: sz.max(Size::S32), dst, src: src.into() });
     dst
   }
 
@@ -1194,6 +1217,26 @@ impl VCode<Inst> {
     (dst_lo, dst_hi)
   }
 
+  pub(crate) fn emit_divrem(&mut self,
+    sz: Size, signed: bool, src1: VReg, src2: impl Into<RegMem>
+  ) -> (VReg, VReg) {
+    // regalloc2 cannot handle `src1` being live across the instruction when it is also
+    // clobbered by the result, so we use a temporary (which is coalesced when possible).
+    let src1_lo = self.fresh_vreg();
+    self.emit(Inst::MovRR { dst: src1_lo, src: src1 });
+    let src1_hi = if signed {
+      let dst = self.fresh_vreg();
+      self.emit(Inst::Cdx { sz, dst, src: src1_lo });
+      dst
+    } else {
+      self.emit_imm(Size::S32, 0_u32)
+    };
+    let dst_div = self.fresh_vreg();
+    let dst_rem = self.fresh_vreg();
+    self.emit(Inst::DivRem { sz, signed, dst_div, dst_rem, src1_hi, src1_lo, src2: src2.into() });
+    (dst_div, dst_rem)
+  }
+
   pub(crate) fn emit_shift(&mut self, sz: Size, kind: ShiftKind, src1: VReg, src2: Result<u8, VReg>
   ) -> VReg {
     let dst = self.fresh_vreg();
@@ -1206,7 +1249,8 @@ impl VCode<Inst> {
 
   pub(crate) fn emit_cmp(&mut self, sz: Size, op: Cmp, cc: CC, src1: VReg, src2: impl Into<RegMemImm>
   ) -> Flags<'_> {
-    self.emit(Inst::Cmp { sz, op, src1, src2: src2.into() });
+    let src2 = self.legalize_imm16(sz, src2.into(), op == Cmp::Cmp);
+    self.emit(Inst::Cmp { sz, op, src1, src2 });
     Flags(self, cc)
   }
 
@@ -1305,13 +1349,14 @@ pub enum PInst {
     sz: Size, // 2, 4 or 8
     src: PRegMem,
   },
-  /// Part of `DivRem` pseudo-operation. `RDX:RAX <- cdq RAX`
+  /// Sign-extend RAX into RDX: `RDX:RAX <- (cwd|cdq|cqo) RAX`
   Cdx {
     sz: Size, // 2, 4 or 8
   },
-  /// Unsigned integer quotient and remainder operation: `RAX,RDX <- divrem RDX:RAX r/m.`
+  /// (Un)signed integer quotient and remainder operation: `RAX,RDX <- (i)divrem RDX:RAX r/m.`
   DivRem {
     sz: Size, // 2, 4 or 8
+    signed: bool,
     src: PRegMem,
   },
   // /// The high bits (RDX) of a (un)signed multiply: RDX:RAX := RAX * rhs.
@@ -1453,7 +1498,8 @@ impl Debug for PInst {
         write!(f, "{} <- {:?}.{} {0}", dst, op, sz.bits0()),
       Self::Mul { sz, src } => write!(f, "rdx:rax <- mul.{} rax, {}", sz.bits0(), src),
       Self::Cdx { sz } => write!(f, "rdx:rax <- cdx.{} rax", sz.bits0()),
-      Self::DivRem { sz, src } => write!(f, "rax,rdx <- divrem.{} rdx:rax, {}", sz.bits0(), src),
+      Self::DivRem { sz, signed, src } => write!(f, "rax,rdx <- {}divrem.{} rdx:rax, {}",
+        if *signed { "i" } else { "" }, sz.bits0(), src),
       Self::Imm { sz, dst, src } => write!(f, "{} <- imm.{} {}", dst, sz.bits0(), src),
       Self::MovRR { sz, dst, src } => write!(f, "{} <- mov.{} {}", dst, sz.bits0(), src),
       Self::MovzxRmR { ext_mode, dst, src } =>
@@ -1646,6 +1692,9 @@ impl OpcodeLayout {
 /// enough information to determine the byte length of the instruction.
 #[derive(Clone, Copy)]
 pub struct InstLayout {
+  /// Does the instruction have an operand-size override prefix (`0x66`)?
+  /// This is used for 16-bit operations.
+  pub opsz: bool,
   /// Does the instruction have a REX byte?
   pub rex: bool,
   /// The layout of the instruction itself.
@@ -1654,6 +1703,7 @@ pub struct InstLayout {
 
 impl Debug for InstLayout {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    if self.opsz { write!(f, "OPSZ + ")? }
     if self.rex { write!(f, "REX + {:?}", self.opc) } else { self.opc.fmt(f) }
   }
 }
@@ -1661,7 +1711,9 @@ impl Debug for InstLayout {
 impl InstLayout {
   /// The byte length of any instruction with this layout.
   #[allow(clippy::len_without_is_empty)]
-  #[must_use] pub fn len(self) -> u8 { u8::from(self.rex) + self.opc.len() }
+  #[must_use] pub fn len(self) -> u8 {
+    u8::from(self.opsz) + u8::from(self.rex) + self.opc.len()
+  }
 }
 
 #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
@@ -1757,17 +1809,18 @@ fn layout_binop_lo(sz: Size, dst: PReg, src: &PRegMemImm) -> InstLayout {
   let mut opc = match *src {
     PRegMemImm::Imm(i) => match sz {
       Size::S8 => OpcodeLayout::BinopImm(false, layout_opc_reg(&mut rex, dst)),
-      Size::S16 => unimplemented!(),
       _ if i as i8 as u32 == i => OpcodeLayout::BinopImm8(layout_opc_reg(&mut rex, dst)),
+      // 16-bit immediates are legalized away by `VCode::emit_binop`
+      Size::S16 => unreachable!("16-bit immediate"),
       _ => OpcodeLayout::BinopImm(true, layout_opc_reg(&mut rex, dst)),
     }
     _ => OpcodeLayout::BinopReg(layout_rmi(&mut rex, dst, src))
   };
-  if dst == RAX && matches!(src, PRegMemImm::Imm(..)) {
+  if dst == RAX && sz != Size::S16 && matches!(src, PRegMemImm::Imm(..)) {
     let rax_layout = OpcodeLayout::BinopRAX(sz != Size::S8);
     if rax_layout.len() <= opc.len() { opc = rax_layout }
   }
-  InstLayout { rex, opc }
+  InstLayout { opsz: sz == Size::S16, rex, opc }
 }
 
 impl PInst {
@@ -1777,18 +1830,23 @@ impl PInst {
   pub(crate) fn layout_inst(&self) -> InstLayout {
     match *self {
       PInst::Fallthrough { .. } |
-      PInst::SyncLet { .. } => InstLayout { rex: false, opc: OpcodeLayout::Ghost },
+      PInst::SyncLet { .. } => InstLayout { opsz: false, rex: false, opc: OpcodeLayout::Ghost },
       PInst::Binop { sz, dst, ref src, .. } => layout_binop_lo(sz, dst, src),
       PInst::Unop { sz, dst, .. } => {
         let mut rex = sz == Size::S64;
         if sz == Size::S8 { high_reg(&mut rex, dst) }
-        InstLayout { opc: OpcodeLayout::Hi(layout_opc_reg(&mut rex, dst)), rex }
+        InstLayout {
+          opsz: sz == Size::S16, opc: OpcodeLayout::Hi(layout_opc_reg(&mut rex, dst)), rex
+        }
       }
-      PInst::DivRem { sz, ref src } | PInst::Mul { sz, ref src } => {
+      PInst::DivRem { sz, ref src, .. } | PInst::Mul { sz, ref src } => {
         let mut rex = sz == Size::S64;
-        InstLayout { opc: OpcodeLayout::Hi(layout_opc_rm(&mut rex, src)), rex }
+        InstLayout {
+          opsz: sz == Size::S16, opc: OpcodeLayout::Hi(layout_opc_rm(&mut rex, src)), rex
+        }
       }
-      PInst::Cdx { sz } => InstLayout { rex: sz == Size::S64, opc: OpcodeLayout::Cdx },
+      PInst::Cdx { sz } =>
+        InstLayout { opsz: sz == Size::S16, rex: sz == Size::S64, opc: OpcodeLayout::Cdx },
       PInst::Imm { sz, dst, src } => {
         let opc = match (sz, src) {
           (_, 0) => {
@@ -1799,33 +1857,41 @@ impl PInst {
             OpcodeLayout::MovImm(layout_opc_reg(&mut true, dst)),
           _ => OpcodeLayout::Mov64(sz == Size::S64),
         };
-        InstLayout { rex: sz == Size::S64 || dst.large(), opc }
+        InstLayout { opsz: sz == Size::S16, rex: sz == Size::S64 || dst.large(), opc }
       }
       PInst::MovRR { sz, dst, src } => {
         let mut rex = sz == Size::S64;
-        InstLayout { opc: OpcodeLayout::MovReg(layout_reg(&mut rex, dst, src)), rex }
+        InstLayout {
+          opsz: sz == Size::S16, opc: OpcodeLayout::MovReg(layout_reg(&mut rex, dst, src)), rex
+        }
       }
       PInst::MovzxRmR { ext_mode: ExtMode::LQ, dst, ref src } => {
         let mut rex = false;
-        InstLayout { opc: OpcodeLayout::MovReg(layout_rm(&mut rex, dst, src)), rex }
+        InstLayout { opsz: false, opc: OpcodeLayout::MovReg(layout_rm(&mut rex, dst, src)), rex }
       }
       PInst::MovsxRmR { ext_mode: ExtMode::LQ, dst, ref src } =>
-        InstLayout { opc: OpcodeLayout::MovSX(layout_rm(&mut true, dst, src)), rex: true },
+        InstLayout {
+          opsz: false, opc: OpcodeLayout::MovSX(layout_rm(&mut true, dst, src)), rex: true
+        },
       PInst::MovzxRmR { ext_mode, dst, ref src } |
       PInst::MovsxRmR { ext_mode, dst, ref src } => {
         let mut rex = ext_mode.dst() == Size::S64;
-        InstLayout { opc: OpcodeLayout::MovX(layout_rm(&mut rex, dst, src)), rex }
+        InstLayout { opsz: false, opc: OpcodeLayout::MovX(layout_rm(&mut rex, dst, src)), rex }
       }
       PInst::Load64 { spill: _, dst, ref src } =>
-        InstLayout { opc: OpcodeLayout::MovReg(layout_mem(&mut true, dst, src)), rex: true },
+        InstLayout {
+          opsz: false, opc: OpcodeLayout::MovReg(layout_mem(&mut true, dst, src)), rex: true
+        },
       PInst::Lea { sz, dst, ref addr } => {
         let mut rex = sz == Size::S64;
-        InstLayout { opc: OpcodeLayout::Lea(layout_mem(&mut rex, dst, addr)), rex }
+        InstLayout { opsz: false, opc: OpcodeLayout::Lea(layout_mem(&mut rex, dst, addr)), rex }
       }
       PInst::Store { spill: _, sz, ref dst, src } => {
         let mut rex = sz == Size::S64;
         if sz == Size::S8 { high_amode(&mut rex, dst); high_reg(&mut rex, src) }
-        InstLayout { opc: OpcodeLayout::MovReg(layout_mem(&mut rex, src, dst)), rex }
+        InstLayout {
+          opsz: sz == Size::S16, opc: OpcodeLayout::MovReg(layout_mem(&mut rex, src, dst)), rex
+        }
       }
       PInst::Shift { sz, dst, num_bits, .. } => {
         let mut rex = sz == Size::S64;
@@ -1835,7 +1901,7 @@ impl PInst {
           Some(1) => OpcodeLayout::BinopHi1(layout_opc_reg(&mut rex, dst)),
           Some(_) => OpcodeLayout::BinopHi(layout_opc_reg(&mut rex, dst)),
         };
-        InstLayout { rex, opc }
+        InstLayout { opsz: sz == Size::S16, rex, opc }
       }
       PInst::Cmp { sz, op: Cmp::Cmp, src1, ref src2 } => layout_binop_lo(sz, src1, src2),
       PInst::Cmp { sz, op: Cmp::Test, src1, ref src2 } => {
@@ -1848,16 +1914,18 @@ impl PInst {
           }
           _ => OpcodeLayout::Test(layout_rmi(&mut rex, src1, src2))
         };
-        InstLayout { rex, opc }
+        InstLayout { opsz: sz == Size::S16, rex, opc }
       }
       PInst::SetCC { dst, .. } => {
         let mut rex = false;
         high_reg(&mut rex, dst);
-        InstLayout { opc: OpcodeLayout::SetCC(layout_opc_reg(&mut rex, dst)), rex }
+        InstLayout { opsz: false, opc: OpcodeLayout::SetCC(layout_opc_reg(&mut rex, dst)), rex }
       }
       PInst::CMov { sz, dst, ref src, .. } => {
         let mut rex = sz == Size::S64;
-        InstLayout { opc: OpcodeLayout::CMov(layout_rm(&mut rex, dst, src)), rex }
+        InstLayout {
+          opsz: sz == Size::S16, opc: OpcodeLayout::CMov(layout_rm(&mut rex, dst, src)), rex
+        }
       }
       PInst::Push64 { ref src } => {
         let mut rex = false;
@@ -1866,17 +1934,21 @@ impl PInst {
           PRegMemImm::Reg(r) => { rex |= r.large(); OpcodeLayout::PushReg }
           PRegMemImm::Mem(ref a) => OpcodeLayout::Hi(layout_opc_mem(&mut rex, a))
         };
-        InstLayout { rex, opc }
+        InstLayout { opsz: false, rex, opc }
       }
-      PInst::Pop64 { dst } => InstLayout { opc: OpcodeLayout::PopReg, rex: dst.large() },
-      PInst::CallKnown { .. } => InstLayout { opc: OpcodeLayout::Call, rex: false },
-      PInst::SysCall => InstLayout { opc: OpcodeLayout::SysCall, rex: false },
-      PInst::Ret => InstLayout { opc: OpcodeLayout::Ret, rex: false },
-      PInst::JmpKnown { short, .. } => InstLayout { opc: OpcodeLayout::Jump(!short), rex: false },
-      PInst::JmpCond { short: true, .. } => InstLayout { opc: OpcodeLayout::Jcc8, rex: false },
-      PInst::JmpCond { short: false, .. } => InstLayout { opc: OpcodeLayout::Jcc, rex: false },
-      PInst::Assert { .. } => InstLayout { opc: OpcodeLayout::Assert, rex: false },
-      PInst::Ud2 => InstLayout { opc: OpcodeLayout::Ud2, rex: false },
+      PInst::Pop64 { dst } =>
+        InstLayout { opsz: false, opc: OpcodeLayout::PopReg, rex: dst.large() },
+      PInst::CallKnown { .. } => InstLayout { opsz: false, opc: OpcodeLayout::Call, rex: false },
+      PInst::SysCall => InstLayout { opsz: false, opc: OpcodeLayout::SysCall, rex: false },
+      PInst::Ret => InstLayout { opsz: false, opc: OpcodeLayout::Ret, rex: false },
+      PInst::JmpKnown { short, .. } =>
+        InstLayout { opsz: false, opc: OpcodeLayout::Jump(!short), rex: false },
+      PInst::JmpCond { short: true, .. } =>
+        InstLayout { opsz: false, opc: OpcodeLayout::Jcc8, rex: false },
+      PInst::JmpCond { short: false, .. } =>
+        InstLayout { opsz: false, opc: OpcodeLayout::Jcc, rex: false },
+      PInst::Assert { .. } => InstLayout { opsz: false, opc: OpcodeLayout::Assert, rex: false },
+      PInst::Ud2 => InstLayout { opsz: false, opc: OpcodeLayout::Ud2, rex: false },
     }
   }
 
@@ -1903,8 +1975,7 @@ impl PInst {
     fn op_size_w(rex: &mut u8, sz: Size) -> u8 {
       match sz {
         Size::S8 => 0,
-        Size::S16 => unimplemented!(),
-        Size::S32 => 1,
+        Size::S16 | Size::S32 => 1,
         Size::S64 => { *rex |= REX_W; 1 },
         Size::Inf => unreachable!(),
       }
@@ -1945,12 +2016,19 @@ impl PInst {
       }
     }
 
-    fn encode_offset(buf: &InstSink<'_>, off: &Offset) -> u32 {
+    fn encode_offset(buf: &mut InstSink<'_>, off: &Offset) -> u32 {
       match *off {
         Offset::Real(off) => off,
         Offset::Spill(..) => unreachable!("removed by regalloc"),
-        Offset::Global(id, n) => buf[id] + n,
-        Offset::Const(n) => buf.rodata_start + n,
+        Offset::Global(id, n) => {
+          let off = buf[id] + n;
+          buf.reloc(RelocTarget::Bss, off);
+          off
+        }
+        Offset::Const(n) => {
+          buf.reloc(RelocTarget::Rodata, n);
+          buf.rodata_start + n
+        }
       }
     }
 
@@ -2001,6 +2079,7 @@ impl PInst {
 
     let layout = self.layout_inst();
     buf.update_rip(layout.len());
+    if layout.opsz { buf.push_u8(0x66) }
     if layout.rex { buf.push_u8(0) }
     let mut rex = 0;
     match (layout.opc, self) {
@@ -2096,6 +2175,10 @@ impl PInst {
         buf.push_u32(dst as u32);
       }
       (OpcodeLayout::Ret, PInst::Ret) => buf.push_u8(0xc3),
+      (OpcodeLayout::Cdx, &PInst::Cdx { sz }) => {
+        assert!(op_size_w(&mut rex, sz) == 1);
+        buf.push_u8(0x99);
+      }
       (OpcodeLayout::Lea(modrm), &PInst::Lea { sz, dst, addr }) => {
         assert!(op_size_w(&mut rex, sz) == 1);
         buf.push_u8(0x8d);
@@ -2118,7 +2201,8 @@ impl PInst {
           PInst::Unop { sz, op: Unop::Not, dst } => (0xf6 + op_size_w(&mut rex, sz), 2, dst.into()),
           PInst::Unop { sz, op: Unop::Neg, dst } => (0xf6 + op_size_w(&mut rex, sz), 3, dst.into()),
           PInst::Mul { sz, src } => (0xf6 + op_size_w(&mut rex, sz), 4, src),
-          PInst::DivRem { sz, src } => (0xf6 + op_size_w(&mut rex, sz), 6, src),
+          PInst::DivRem { sz, signed, src } =>
+            (0xf6 + op_size_w(&mut rex, sz), 6 + u8::from(signed), src),
           PInst::Push64 { src: PRegMemImm::Mem(a) } => (0xff, 6, a.into()),
           _ => unreachable!(),
         };
@@ -2171,7 +2255,7 @@ impl PInst {
 
     debug_assert!(usize::from(layout.len()) == buf.len());
     if layout.rex {
-      buf.set_rex(0x40 + rex);
+      buf.set_rex(usize::from(layout.opsz), 0x40 + rex);
     } else {
       assert!(rex == 0);
     }
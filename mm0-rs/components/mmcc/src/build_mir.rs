@@ -238,6 +238,18 @@ impl<'a> Translate<'a> for ty::Lifetime {
   }
 }
 
+impl<'a> Translate<'a> for hir::Variant<'a> {
+  type Output = Variant;
+  fn tr(self, tr: &mut Translator<'a, '_>) -> Variant {
+    let hir::Variant(e, vt) = self;
+    Variant(e.tr(tr), match vt {
+      hir::VariantType::Down => VariantType::Down,
+      hir::VariantType::UpLt(e) => VariantType::UpLt(e.tr(tr)),
+      hir::VariantType::UpLe(e) => VariantType::UpLe(e.tr(tr)),
+    })
+  }
+}
+
 impl ty::TyS<'_> {
   fn is_unit_dest(&self) -> bool {
     matches!(self.k,
@@ -571,7 +583,8 @@ impl<'a, 'n> BuildMir<'a, 'n> {
       Statement::Assign(_, _, _, ref vars) => for v in &**vars {
         self.extend_ctx(v.to, v.rel, v.ety.clone())
       }
-      Statement::LabelGroup(..) | Statement::PopLabelGroup | Statement::DominatedBlock(..) => {}
+      Statement::LabelGroup(..) | Statement::PopLabelGroup |
+      Statement::DominatedBlock(..) | Statement::Span(_) => {}
     }
     self.cur_block().stmts.push(stmt);
   }
@@ -784,6 +797,13 @@ impl<'a, 'n> BuildMir<'a, 'n> {
         let v2 = self.as_temp(*e2)?;
         RValue::Binop(op, v1.into(), v2.into())
       }
+      hir::ExprKind::DivNonzero(op, args) => {
+        let [e1, e2, h] = *args;
+        let v1 = self.as_temp(e1)?;
+        let v2 = self.as_temp(e2)?;
+        self.expr(h, None)?;
+        RValue::Binop(op, v1.into(), v2.into())
+      }
       hir::ExprKind::Eq(ty, inv, e1, e2) => {
         let ty = self.tr(ty);
         let v1 = self.as_temp(*e1)?;
@@ -936,6 +956,7 @@ impl<'a, 'n> BuildMir<'a, 'n> {
             this.expr(*e1, None)?;
             this.expr(*e2, None)?;
           }
+          hir::ExprKind::DivNonzero(_, args) => for e in *args { this.expr(e, None)? }
           hir::ExprKind::Sn(e1, h) => {
             this.expr(*e1, None)?;
             if let Some(h) = h { this.expr(*h, None)?; }
@@ -1179,6 +1200,7 @@ impl<'a, 'n> BuildMir<'a, 'n> {
   }
 
   fn stmt(&mut self, stmt: hir::Stmt<'a>, brk: Option<&(JoinBlock, BlockDest)>) -> Block<()> {
+    self.cur_block().stmts.push(Statement::Span(stmt.span.clone()));
     match stmt.k {
       hir::StmtKind::Let { lhs, rhs } => self.let_stmt(false, lhs, rhs),
       hir::StmtKind::Expr(e) => self.expr(hir::Spanned {span: stmt.span, k: e}, None),
@@ -1233,8 +1255,10 @@ impl<'a, 'n> BuildMir<'a, 'n> {
     } else { None };
     let r = (|| {
       for stmt in stmts { self.stmt(stmt, jb.as_ref())? }
-      if let Some(e) = expr { self.expr(*e, dest)? }
-      else { self.expr_unit(dest) }
+      if let Some(e) = expr {
+        self.cur_block().stmts.push(Statement::Span(e.span.clone()));
+        self.expr(*e, dest)?
+      } else { self.expr_unit(dest) }
       let stmts = &mut self.cfg[self.cur_block].stmts;
       for _ in self.labels.len()..reset.0 { stmts.push(Statement::PopLabelGroup) }
       Ok(())
@@ -1517,12 +1541,10 @@ impl<'a, 'n> BuildMir<'a, 'n> {
     tgt: ty::Ty<'a>,
     dest: &[PreVar],
   ) -> Block<()> {
-    if variant.is_some() {
-      unimplemented!("recursive functions not supported")
-    }
     let tys = self.tr(tys);
     let args = args.into_iter().map(|e| Ok((!e.k.1 .1.ghostly(), self.operand(e)?)))
       .collect::<Block<Box<[_]>>>()?;
+    let variant = variant.map(|v| self.operand(*v)).transpose()?;
     let base_ctx = self.cur_ctx;
     self.tr.try_add_gen(self.tr.cur_gen, gen);
     self.tr.cur_gen = gen;
@@ -1532,7 +1554,7 @@ impl<'a, 'n> BuildMir<'a, 'n> {
         self.extend_ctx(v, false, (None, Rc::new(TyKind::False)));
         let bl = self.dominated_block(base_ctx);
         self.cur_block().terminate(Terminator::Call {
-          f: f.k, se, tys, args, reach: false, tgt: bl, rets: Box::new([(false, v)])
+          f: f.k, se, tys, args, reach: false, tgt: bl, rets: Box::new([(false, v)]), variant
         });
         let bl = &mut self.cfg[bl];
         bl.reachable = false;
@@ -1563,7 +1585,7 @@ impl<'a, 'n> BuildMir<'a, 'n> {
     };
     let bl = self.dominated_block(base_ctx);
     self.cur_block().terminate(Terminator::Call {
-      f: f.k, se, tys, args, reach: true, tgt: bl, rets: vars
+      f: f.k, se, tys, args, reach: true, tgt: bl, rets: vars, variant
     });
     self.cur_block = bl;
     Ok(())
@@ -1589,16 +1611,13 @@ impl<'a, 'n> BuildMir<'a, 'n> {
     it: hir::Item<'a>
   ) -> Option<Symbol> {
     match it.k {
-      hir::ItemKind::Proc { kind, name, tyargs, args, gen, outs, rets, variant, body } => {
+      hir::ItemKind::Proc { kind, inline, name, tyargs, args, gen, outs, rets, variant, body } => {
         fn tr_attr(attr: ty::ArgAttr) -> ArgAttr {
           let mut out = ArgAttr::empty();
           if attr.contains(ty::ArgAttr::NONDEP) { out |= ArgAttr::NONDEP }
           if attr.contains(ty::ArgAttr::GHOST) { out |= ArgAttr::GHOST }
           out
         }
-        if variant.is_some() {
-          unimplemented!("recursive functions not supported")
-        }
         let outs2 = outs.iter().map(|&i| args[u32_as_usize(i)].1.var().k.var)
           .collect::<Box<[_]>>();
         let mut args2 = Vec::with_capacity(args.len());
@@ -1612,6 +1631,7 @@ impl<'a, 'n> BuildMir<'a, 'n> {
           rets2.push(Arg {attr: tr_attr(attr), var, ty: ty.clone()})
         })[outs2.len()..].into();
         self.returns = Some(Rc::new((outs2, ret_vs)));
+        let variant = variant.map(|v| self.tr(v));
         self.tr.cur_gen = GenId::ROOT;
         match self.block(body, None) {
           Ok(()) => unreachable!("bodies should end in unconditional return"),
@@ -1621,11 +1641,13 @@ impl<'a, 'n> BuildMir<'a, 'n> {
         self.tree.append_to(&mut self.cfg.tree);
         mir.insert(name.k, Proc {
           kind,
+          inline,
           name: Spanned {span: name.span.clone(), k: name.k},
           tyargs,
           args: args2,
           outs,
           rets: rets2,
+          variant,
           body: self.cfg,
           allocs: None,
         });
@@ -1681,6 +1703,7 @@ impl Initializer {
           reach: true,
           tgt,
           rets,
+          variant: None,
         });
         build.cur_block = tgt;
         o
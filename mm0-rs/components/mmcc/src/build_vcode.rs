@@ -13,16 +13,17 @@ use std::rc::Rc;
 use arrayvec::ArrayVec;
 use regalloc2::Operand as ROperand;
 
-use crate::linker::ConstData;
+use crate::linker::{ConstData, GenericCall};
 use crate::types::entity::{IntrinsicProc, ProcTc, ProcTy};
 use crate::{Symbol, Entity};
 use crate::arch::{AMode, Binop as VBinop, CC, Cmp, ExtMode, Inst, PReg, RegMem, RegMemImm,
-  RET_AND_ARG_REGS, SYSCALL_ARG_REGS, ShiftKind, SysCall, Unop as VUnop};
+  ARG_REGS, RET_AND_ARG_REGS, SYSCALL_ARG_REGS, ShiftKind, SysCall, Unop as VUnop,
+  non_callee_saved};
 use crate::mir_opt::BitSet;
 use crate::mir_opt::storage::{Allocations, AllocId};
 use crate::types::{Idx, IdxVec, IntTy, Size, Spanned};
 use crate::types::vcode::{self, ArgAbi, BlockId as VBlockId,
-  ChunkVec, ConstRef, InstId, GlobalId, ProcAbi, ProcId, SpillId, VReg};
+  CallConv, ChunkVec, ConstRef, InstId, GlobalId, ProcAbi, ProcId, SpillId, VReg};
 
 #[allow(clippy::wildcard_imports)]
 use crate::types::mir::*;
@@ -33,13 +34,29 @@ pub(crate) type VCode = vcode::VCode<Inst>;
 /// edges to unvisited basic blocks as long as possible. Then start over somewhere else.
 /// This ordering is good for code placement since a jump or branch to the immediately following
 /// block can be elided.
+/// The successors of `bl` in the generated code. An `if` on a constant condition only jumps to
+/// the branch that is taken; the other branch is kept in the MIR for the proof, but it has no code.
+fn code_successors(bl: &BasicBlock) -> impl Iterator<Item = BlockId> + '_ {
+  let taken = match *bl.terminator() {
+    Terminator::If(Operand::Const(ref c), [(_, bl1), (_, bl2)]) =>
+      c.as_bool().map(|b| if b { bl1 } else { bl2 }),
+    _ => None,
+  };
+  bl.successors().map(|(_, j)| j).filter(move |&j| taken.is_none() || taken == Some(j))
+}
+
 fn visit_blocks<'a>(cfg: &'a Cfg, mut f: impl FnMut(BlockId, &'a BasicBlock)) {
+  let mut accessible: BitSet<BlockId> = BitSet::default();
+  let mut stack = vec![BlockId::ENTRY];
+  while let Some(i) = stack.pop() {
+    if accessible.insert(i) { stack.extend(code_successors(&cfg[i])) }
+  }
   let mut visited: BitSet<BlockId> = BitSet::default();
   for (mut i, mut bl) in cfg.blocks() {
-    if visited.insert(i) && !bl.is_dead() && bl.reachable {
-      while let Some((_, j)) = {
+    if accessible.contains(i) && visited.insert(i) && !bl.is_dead() && bl.reachable {
+      while let Some(j) = {
         f(i, bl);
-        bl.successors().find(|&(_, j)| visited.insert(j))
+        code_successors(bl).find(|&j| visited.insert(j))
       } {
         i = j;
         bl = &cfg[i];
@@ -116,22 +133,22 @@ impl From<&VRetAbi> for ArgAbi {
 /// The ABI expected by the caller.
 #[derive(Clone, Copy, Debug)]
 pub(crate) enum VCodeCtx<'a> {
-  /// This is a regular procedure; the `&[Arg]` is the function returns.
-  Proc(&'a [Arg]),
+  /// This is a regular procedure, compiled using the given calling convention.
+  Proc(&'a Proc, CallConv),
   /// This is the `start` function, which is called by the operating system and has a
   /// special stack layout.
   Start(&'a [(Symbol, bool, VarId, Ty)]),
 }
 
-impl<'a> From<&'a [Arg]> for VCodeCtx<'a> {
-    fn from(v: &'a [Arg]) -> Self { Self::Proc(v) }
+impl<'a> From<&'a Proc> for VCodeCtx<'a> {
+    fn from(v: &'a Proc) -> Self { Self::Proc(v, CallConv::Mmc) }
 }
 
 struct LowerCtx<'a> {
   cfg: &'a Cfg,
   allocs: &'a Allocations,
   names: &'a HashMap<Symbol, Entity>,
-  func_mono: &'a HashMap<Symbol, ProcId>,
+  func_mono: &'a HashMap<GenericCall, ProcId>,
   funcs: &'a IdxVec<ProcId, ProcAbi>,
   consts: &'a ConstData,
   code: VCode,
@@ -148,7 +165,7 @@ impl<'a> LowerCtx<'a> {
   /// Create a new lowering context.
   fn new(
     names: &'a HashMap<Symbol, Entity>,
-    func_mono: &'a HashMap<Symbol, ProcId>,
+    func_mono: &'a HashMap<GenericCall, ProcId>,
     funcs: &'a IdxVec<ProcId, ProcAbi>,
     consts: &'a ConstData,
     cfg: &'a Cfg,
@@ -170,7 +187,7 @@ impl<'a> LowerCtx<'a> {
       abi_rets: Rc::new([]),
       can_return: cfg.can_return(),
       globals: match ctx {
-        VCodeCtx::Proc(_) => HashMap::new(),
+        VCodeCtx::Proc(..) => HashMap::new(),
         VCodeCtx::Start(ls) => {
           let mut map = HashMap::new();
           for (id, &(_, r, v, _)) in ls.iter().enumerate() {
@@ -374,6 +391,87 @@ impl<'a> LowerCtx<'a> {
     self.code.emit_copy(Size::S8, dst, temp);
   }
 
+  /// Get an operand of type `ity` in a register of at least 32 bits,
+  /// zero or sign extending it if necessary.
+  fn get_operand_ext32(&mut self, ity: IntTy, o: &Operand) -> VReg {
+    let sz = ity.size();
+    match ExtMode::new(sz, Size::S32) {
+      None => self.get_operand_reg(o, sz),
+      Some(ext_mode) => {
+        let src = self.get_operand_rm(o, sz);
+        let dst = self.code.fresh_vreg();
+        self.code.emit(if ity.signed() {
+          Inst::MovsxRmR { ext_mode, dst, src }
+        } else {
+          Inst::MovzxRmR { ext_mode, dst, src }
+        });
+        dst
+      }
+    }
+  }
+
+  /// Lower `o1 // o2` (or `o1 % o2` if `rem` is true). The `div` instruction faults if
+  /// the divisor is zero or the quotient overflows, so unless the divisor is a constant for
+  /// which this cannot happen, the divisor is replaced by `1` in these cases and the result
+  /// is fixed up afterward, giving `x // 0 = 0`, `x % 0 = x`, and wrapping `x // -1 = -x`.
+  /// If `nonzero` is true then the divisor is known to be nonzero, so only the overflow case
+  /// needs to be fixed up.
+  fn build_divrem(&mut self,
+    ity: IntTy, dst: RegMem, rem: bool, nonzero: bool, o1: &Operand, o2: &Operand
+  ) {
+    let sz0 = ity.size(); assert_ne!(sz0, Size::Inf);
+    let signed = ity.signed();
+    // 8 and 16 bit division is done at 32 bits, which avoids the remainder landing in AH
+    // as well as any possibility of overflow.
+    let sz = sz0.max(Size::S32);
+    let bits = sz.bits().expect("bounded");
+    let minus_one = u64::MAX >> (64 - bits);
+    let overflow = signed && sz0 == sz;
+    let safe = match self.get_operand(o2) {
+      RegMemImm::Imm(n) => n != 0 && !(overflow && n == minus_one),
+      _ => nonzero && !overflow,
+    };
+    let src1 = self.get_operand_ext32(ity, o1);
+    let src2 = self.get_operand_ext32(ity, o2);
+    if safe {
+      let (div, modulus) = self.code.emit_divrem(sz, signed, src1, src2);
+      return self.code.emit_copy(sz0, dst, if rem { modulus } else { div })
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    let minus_one32 = minus_one as u32;
+    let one = self.code.emit_imm(Size::S32, 1_u32);
+    let divisor = if !overflow {
+      self.code.emit_cmp(sz, Cmp::Cmp, CC::Z, src2, 0).select(sz, one, src2)
+    } else if nonzero {
+      self.code.emit_cmp(sz, Cmp::Cmp, CC::Z, src2, minus_one32).select(sz, one, src2)
+    } else {
+      // src2 + 1 <= 1 (unsigned) iff src2 = -1 or src2 = 0
+      let temp = self.code.emit_binop(sz, VBinop::Add, src2, 1);
+      self.code.emit_cmp(sz, Cmp::Cmp, CC::BE, temp, 1).select(sz, one, src2)
+    };
+    let (div, modulus) = self.code.emit_divrem(sz, signed, src1, divisor);
+    // In the fixup cases the divisor is 1, so `div` is a copy of `src1`.
+    let res = if rem {
+      // if src2 = -1 then src1 % 1 = 0 is already correct
+      if nonzero { modulus } else {
+        // if src2 = 0 then src1 % 1 = 0, so we fix it up to src1
+        self.code.emit_cmp(sz, Cmp::Cmp, CC::Z, src2, 0).select(sz, div, modulus)
+      }
+    } else {
+      let div = if overflow {
+        // if src2 = -1 then src1 // 1 = src1, so we fix it up to -src1
+        let neg = self.code.emit_unop(sz, VUnop::Neg, div);
+        self.code.emit_cmp(sz, Cmp::Cmp, CC::Z, src2, minus_one32).select(sz, neg, div)
+      } else { div };
+      if nonzero { div } else {
+        // if src2 = 0 then src1 // 1 = src1, so we fix it up to 0
+        let zero = self.code.emit_imm(Size::S32, 0_u32);
+        self.code.emit_cmp(sz, Cmp::Cmp, CC::Z, src2, 0).select(sz, zero, div)
+      }
+    };
+    self.code.emit_copy(sz0, dst, res)
+  }
+
   fn build_as(&mut self, dst: RegMem, from: IntTy, to: IntTy, o: &Operand) {
     let sz = from.size().min(to.size()); assert_ne!(sz, Size::Inf);
     let src = self.get_operand(o).into_rm(&mut self.code, sz);
@@ -390,23 +488,94 @@ impl<'a> LowerCtx<'a> {
     }
   }
 
-  fn build_memcpy(&mut self, _tysize: u64, sz: Size, dst: RegMem, src: AMode) {
+  /// The chunks (size and offset) that cover a value of size `tysize` in memory, using the
+  /// largest chunks possible. If the size is not a multiple of the chunk size, the last chunk
+  /// overlaps the one before it.
+  fn mem_chunks(tysize: u64) -> impl Iterator<Item = (Size, u32)> {
+    let tysize = u32::try_from(tysize).expect("type too large");
+    let (sz, n) = match tysize {
+      0..=3 => (Size::S8, 1),
+      4..=7 => (Size::S32, 4),
+      _ => (Size::S64, 8),
+    };
+    let last = if tysize % n == 0 || tysize < 4 { None } else { Some(tysize - n) };
+    #[allow(clippy::integer_division)]
+    (0..tysize / n).map(move |i| (sz, n * i)).chain(last.map(|off| (sz, off)))
+  }
+
+  fn build_memcpy(&mut self, tysize: u64, sz: Size, dst: RegMem, src: AMode) {
     if sz == Size::Inf {
-      unimplemented!("large copy");
+      let dst = if let RegMem::Mem(dst) = dst { dst } else { unreachable!("large copy to register") };
+      for (sz, off) in Self::mem_chunks(tysize) {
+        let temp = self.code.fresh_vreg();
+        self.code.emit(Inst::load_mem(sz, temp, &src + off));
+        self.code.emit(Inst::Store { sz, dst: &dst + off, src: temp });
+      }
     } else {
       self.code.emit_copy(sz, dst, src);
     }
   }
 
-  fn build_move(&mut self, _tysize: u64, sz: Size, dst: RegMem, o: &Operand) {
+  /// Get an operand of size `tysize` which is too large for a register, as a memory location.
+  /// Constants of this size (such as a large `uninit`) are stored into a fresh stack slot first.
+  fn get_operand_mem(&mut self, tysize: u64, o: &Operand) -> AMode {
+    match self.get_operand(o) {
+      RegMemImm::Mem(src) => src,
+      RegMemImm::Imm(val) => {
+        let a = AMode::spill(
+          self.code.fresh_spill(tysize.try_into().expect("allocation too large")));
+        for (sz, off) in Self::mem_chunks(tysize) {
+          let bits = sz.bits().expect("impossible");
+          let chunk = val.checked_shr(off * 8).unwrap_or(0);
+          let chunk = if bits == 64 { chunk } else { chunk & ((1_u64 << bits) - 1) };
+          self.code.emit_copy(sz, RegMem::Mem(&a + off), chunk);
+        }
+        a
+      }
+      RegMemImm::Reg(_) => unreachable!("large operand in a register"),
+    }
+  }
+
+  fn build_move(&mut self, tysize: u64, sz: Size, dst: RegMem, o: &Operand) {
     if sz == Size::Inf {
-      unimplemented!("large copy");
+      let src = self.get_operand_mem(tysize, o);
+      self.build_memcpy(tysize, sz, dst, src)
     } else {
       let src = self.get_operand(o);
       self.code.emit_copy(sz, dst, src);
     }
   }
 
+  /// Compare two values in memory for equality, by taking the XOR of corresponding chunks
+  /// and accumulating the differences with OR, then setting `dst` from the zero flag.
+  fn build_memcmp(&mut self, tysize: u64, dst: RegMem, cc: CC, o1: &Operand, o2: &Operand) {
+    let src1 = self.get_operand_mem(tysize, o1);
+    let src2 = self.get_operand_mem(tysize, o2);
+    let mut acc = None;
+    for (sz, off) in Self::mem_chunks(tysize) {
+      let temp = self.code.fresh_vreg();
+      self.code.emit(Inst::load_mem(sz, temp, &src1 + off));
+      let diff = if sz == Size::S8 {
+        let temp2 = self.code.fresh_vreg();
+        self.code.emit(Inst::load_mem(sz, temp2, &src2 + off));
+        self.code.emit_binop(Size::S32, VBinop::Xor, temp, temp2)
+      } else {
+        self.code.emit_binop(sz, VBinop::Xor, temp, &src2 + off)
+      };
+      acc = Some(match acc {
+        None => diff,
+        Some(acc) => self.code.emit_binop(Size::S64, VBinop::Or, acc, diff),
+      });
+    }
+    if let Some(acc) = acc {
+      let temp = self.code.emit_cmp(Size::S64, Cmp::Cmp, cc, acc, 0).into_reg();
+      self.code.emit_copy(Size::S8, dst, temp);
+    } else {
+      // Zero-sized values are always equal
+      self.code.emit_copy(Size::S8, dst, u64::from(matches!(cc, CC::Z)));
+    }
+  }
+
   fn build_rvalue(&mut self, ty: &TyKind, tysize: u64, sz: Size, dst: RegMem, rv: &RValue) {
     match rv {
       RValue::Use(o) => self.build_move(tysize, sz, dst, o),
@@ -440,6 +609,10 @@ impl<'a> LowerCtx<'a> {
       }
       RValue::Binop(Binop::Sub(ity), o1, o2) =>
         self.build_binop(ity.size(), dst, VBinop::Sub, o1, o2),
+      &RValue::Binop(Binop::Div(ity, nz), ref o1, ref o2) =>
+        self.build_divrem(ity, dst, false, nz, o1, o2),
+      &RValue::Binop(Binop::Mod(ity, nz), ref o1, ref o2) =>
+        self.build_divrem(ity, dst, true, nz, o1, o2),
       RValue::Binop(Binop::Max(ity), o1, o2) => {
         let sz = ity.size(); assert_ne!(sz, Size::Inf);
         let src1 = self.get_operand_reg(o1, sz);
@@ -483,10 +656,11 @@ impl<'a> LowerCtx<'a> {
       &RValue::Eq(ref ty, invert, ref o1, ref o2) => {
         let meta = ty.meta(self.names).expect("size of type not a compile time constant");
         let sz = Size::from_u64(meta.size);
+        let cc = if invert { CC::NZ } else { CC::Z };
         if meta.on_stack {
-          unimplemented!("memcmp")
+          self.build_memcmp(meta.size, dst, cc, o1, o2)
         } else {
-          self.build_cmp(sz, dst, if invert { CC::NZ } else { CC::Z }, o1, o2)
+          self.build_cmp(sz, dst, cc, o1, o2)
         }
       }
       RValue::Pun(..) => unreachable!("handled in build()"),
@@ -616,6 +790,7 @@ impl<'a> LowerCtx<'a> {
     rets: &[(bool, VarId)],
   ) {
     let fabi = &self.funcs[f];
+    self.code.mk_outgoing_spill(fabi.args_space);
     let outgoing = AMode::spill(SpillId::OUTGOING);
     let mut operands = vec![];
     for (arg, &(r, ref o)) in fabi.args.iter().zip(args) {
@@ -805,11 +980,17 @@ impl<'a> LowerCtx<'a> {
         let dst = self.code.fresh_vreg();
         self.build_syscall(SysCall::Exit, &[0.into()], dst);
       }
+      Terminator::If(Operand::Const(ref c), [(_, bl1), (_, bl2)]) if c.as_bool().is_some() => {
+        let tgt = if c.as_bool() == Some(true) { bl1 } else { bl2 };
+        self.unpatched.push((vbl, self.code.emit(Inst::Fallthrough { dst: VBlockId(tgt.0) })))
+      }
       Terminator::If(ref o, [(_, bl1), (_, bl2)]) => {
         let src = self.get_operand_reg(o, Size::S8);
         let cond = self.code.emit_cmp(Size::S8, Cmp::Cmp, CC::NZ, src, 0_u32);
         self.unpatched.push((vbl, cond.branch(VBlockId(bl1.0), VBlockId(bl2.0))));
       }
+      Terminator::Assert(Operand::Const(ref c), _, true, bl) if c.as_bool() == Some(true) =>
+        self.unpatched.push((vbl, self.code.emit(Inst::Fallthrough { dst: VBlockId(bl.0) }))),
       Terminator::Assert(ref o, _, true, bl) => {
         let src = self.get_operand_reg(o, Size::S8);
         let cond = self.code.emit_cmp(Size::S8, Cmp::Cmp, CC::NZ, src, 0_u32);
@@ -817,8 +998,7 @@ impl<'a> LowerCtx<'a> {
       }
       Terminator::Assert(_, _, false, _) => { self.code.emit(Inst::Ud2); }
       Terminator::Call { f, ref tys, ref args, reach, tgt, ref rets, .. } => {
-        if !tys.is_empty() { unimplemented!("monomorphization") }
-        if let Some(&f) = self.func_mono.get(&f) {
+        if let Some(&f) = self.func_mono.get(&(f, tys.clone())) {
           self.build_call(vbl, f, args, reach, tgt, rets)
         } else if let Some(&Entity::Proc(Spanned {
           k: ProcTc::Typed(ProcTy {intrinsic: Some(intrinsic), ..}), ..
@@ -864,74 +1044,60 @@ impl<'a> LowerCtx<'a> {
   }
 
   fn build_prologue(&mut self, bl: &'a BasicBlock, ctx: VCodeCtx<'_>) {
-    let mut arg_regs = RET_AND_ARG_REGS.iter();
     let incoming = AMode::spill(SpillId::INCOMING);
-    let mut off = 0_u32;
-    let mut alloc = |sz| {
-      let old = off;
-      off = off.checked_add(sz).expect("overflow");
-      old
+    let (args, rets, args_space) = match ctx {
+      VCodeCtx::Proc(proc, cc) =>
+        compute_abi(self.names, self.cfg, self.allocs, &proc.args, &proc.rets, cc),
+      VCodeCtx::Start(_) => compute_abi(self.names, self.cfg, self.allocs, &[], &[], CallConv::Mmc),
     };
 
-    if let VCodeCtx::Proc(rets) = ctx {
-      self.abi_rets = rets.iter().map(|ret| {
-        if ret.attr.contains(ArgAttr::GHOST) { return VRetAbi::Ghost }
-        let meta = ret.ty.meta(self.names).expect("return must have compile time known size");
-        let size = meta.size;
-        let sz = Size::from_u64(size);
-        let on_stack = meta.on_stack || sz == Size::Inf;
-        match (on_stack, arg_regs.next()) {
-          (false, Some(&r)) => VRetAbi::Reg(r, sz),
-          (true, Some(&r)) => {
-            let ptr = self.code.fresh_vreg();
-            self.code.emit(Inst::MovPR { dst: ptr, src: r });
-            VRetAbi::Boxed { reg: (ptr, r), sz: size.try_into().expect("overflow") }
-          }
-          (_, None) if size <= 8 => {
-            let size32 = size.try_into().expect("overflow");
-            VRetAbi::Mem { off: alloc(size32), sz: size32 }
-          },
-          (_, None) => VRetAbi::BoxedMem { off: alloc(8), sz: size.try_into().expect("overflow") }
-        }
-      }).collect();
-    }
+    self.abi_rets = rets.iter().map(|&abi| match abi {
+      ArgAbi::Ghost => VRetAbi::Ghost,
+      ArgAbi::Reg(reg, sz) => VRetAbi::Reg(reg, sz),
+      ArgAbi::Mem { off, sz } => VRetAbi::Mem { off, sz },
+      ArgAbi::Boxed { reg, sz } => {
+        let ptr = self.code.fresh_vreg();
+        self.code.emit(Inst::MovPR { dst: ptr, src: reg });
+        VRetAbi::Boxed { reg: (ptr, reg), sz }
+      }
+      ArgAbi::BoxedMem { off, sz } => VRetAbi::BoxedMem { off, sz },
+    }).collect();
+
+    // Read all the argument registers before anything else, because copying an argument
+    // uses temporaries that may be assigned to the registers of the later arguments
+    let arg_vregs = args.iter().map(|&abi| match abi {
+      ArgAbi::Reg(reg, _) | ArgAbi::Boxed { reg, .. } => {
+        let src = self.code.fresh_vreg();
+        self.code.emit(Inst::MovPR { dst: src, src: reg });
+        Some(src)
+      }
+      _ => None,
+    }).collect::<Vec<_>>();
 
-    self.abi_args = bl.ctx_iter(&self.cfg.ctxs).map(|(v, b, _)| {
-      if !b { return ArgAbi::Ghost }
+    for (((v, _, _), &abi), src) in bl.ctx_iter(&self.cfg.ctxs).zip(&*args).zip(arg_vregs) {
+      if matches!(abi, ArgAbi::Ghost) { continue }
       let a = self.allocs.get(v);
       assert_ne!(a, AllocId::ZERO);
       let (&(dst, sz), size) = self.get_alloc(a);
-      match (dst, arg_regs.next()) {
-        (RegMem::Reg(dst), Some(&r)) => {
-          let src = self.code.fresh_vreg();
-          self.code.emit(Inst::MovPR { dst: src, src: r });
-          self.code.emit(Inst::MovRR { dst, src });
-          ArgAbi::Reg(r, sz)
-        },
-        (RegMem::Mem(_), Some(&r)) => {
-          let src = self.code.fresh_vreg();
-          self.code.emit(Inst::MovPR { dst: src, src: r });
-          let size32 = size.try_into().expect("overflow");
-          self.build_memcpy(size, sz, dst, AMode::reg(src));
-          ArgAbi::Boxed { reg: r, sz: size32 }
-        },
-        (_, None) if size <= 8 => {
-          let size32 = size.try_into().expect("overflow");
-          let off = alloc(size32);
-          self.build_memcpy(size, sz, dst, &incoming + off);
-          ArgAbi::Mem { off, sz: size32 }
-        },
-        (_, None) => {
-          let off = alloc(8);
+      match abi {
+        ArgAbi::Ghost => unreachable!(),
+        ArgAbi::Reg(..) => {
+          let dst = if let RegMem::Reg(dst) = dst { dst } else { unreachable!() };
+          self.code.emit(Inst::MovRR { dst, src: src.expect("register argument") });
+        }
+        ArgAbi::Boxed { .. } =>
+          self.build_memcpy(size, sz, dst, AMode::reg(src.expect("register argument"))),
+        ArgAbi::Mem { off, .. } => self.build_memcpy(size, sz, dst, &incoming + off),
+        ArgAbi::BoxedMem { off, .. } => {
           let ptr = self.code.fresh_vreg();
           self.code.emit_copy(Size::S64, ptr.into(), &incoming + off);
           self.build_memcpy(size, sz, dst, AMode::reg(ptr));
-          ArgAbi::BoxedMem { off, sz: size.try_into().expect("overflow") }
-        },
+        }
       }
-    }).collect();
+    }
+    self.abi_args = args;
 
-    self.code.grow_spill(SpillId::INCOMING, off);
+    self.code.grow_spill(SpillId::INCOMING, args_space);
   }
 
   fn build_blocks(&mut self, block_args: &ChunkVec<BlockId, VReg>, ctx: VCodeCtx<'_>) {
@@ -949,6 +1115,7 @@ impl<'a> LowerCtx<'a> {
         self.code.emit(Inst::BlockParam {var: v, val});
       }
       for (inst, stmt) in bl.stmts.iter().enumerate() {
+        if let Statement::Span(sp) = stmt { self.code.set_span(sp) }
         if stmt.relevant() {
           match stmt {
             &Statement::Let(ref lk, r, ref ty, ref rv) => {
@@ -974,7 +1141,7 @@ impl<'a> LowerCtx<'a> {
               self.build_move(size, Size::from_u64(size), dst, o);
             }
             Statement::LabelGroup(..) | Statement::PopLabelGroup |
-            Statement::DominatedBlock(..) => {}
+            Statement::DominatedBlock(..) | Statement::Span(_) => {}
           }
         }
         stmt.foreach_def(|v, _, _, ty| self.ctx.insert(v, ty.clone()))
@@ -1011,9 +1178,104 @@ impl<'a> LowerCtx<'a> {
   }
 }
 
+/// Compute the calling convention of a procedure, given the storage allocations of its
+/// arguments (the variables in the context of the entry block), its declared arguments, and its
+/// return values. Returns the ABI of the arguments and returns, and the size of the incoming
+/// stack arguments.
+fn compute_abi(
+  names: &HashMap<Symbol, Entity>,
+  cfg: &Cfg,
+  allocs: &Allocations,
+  decl_args: &[Arg],
+  rets: &[Arg],
+  cc: CallConv,
+) -> (Vec<ArgAbi>, Vec<ArgAbi>, u32) {
+  // In the C calling convention, only one value can be returned in `rax`,
+  // and everything else is passed in the argument registers.
+  let (mut ret_reg, mut arg_regs) = match cc {
+    CallConv::Mmc => (None, RET_AND_ARG_REGS.iter()),
+    CallConv::C => (Some(RET_AND_ARG_REGS[0]), ARG_REGS.iter()),
+  };
+  // Stack arguments are packed in the internal calling convention, and use 8 byte slots in C.
+  let slot = |sz: u32| if cc == CallConv::C { 8 } else { sz };
+  let mut off = 0_u32;
+  let mut alloc = |sz| {
+    let old = off;
+    off = off.checked_add(sz).expect("overflow");
+    old
+  };
+
+  let rets = rets.iter().map(|ret| {
+    if ret.attr.contains(ArgAttr::GHOST) { return ArgAbi::Ghost }
+    let meta = ret.ty.meta(names).expect("return must have compile time known size");
+    let size = meta.size;
+    let sz = Size::from_u64(size);
+    let on_stack = meta.on_stack || sz == Size::Inf;
+    if !on_stack {
+      if let Some(r) = ret_reg.take() { return ArgAbi::Reg(r, sz) }
+    }
+    match (on_stack || cc == CallConv::C, arg_regs.next()) {
+      (false, Some(&r)) => ArgAbi::Reg(r, sz),
+      (true, Some(&reg)) => ArgAbi::Boxed { reg, sz: size.try_into().expect("overflow") },
+      (_, None) if size <= 8 && cc == CallConv::Mmc => {
+        let size32 = size.try_into().expect("overflow");
+        ArgAbi::Mem { off: alloc(size32), sz: size32 }
+      },
+      (_, None) => ArgAbi::BoxedMem { off: alloc(8), sz: size.try_into().expect("overflow") }
+    }
+  }).collect();
+
+  let args = cfg[BlockId::ENTRY].ctx_iter(&cfg.ctxs).enumerate().map(|(i, (v, b, _))| {
+    if !b {
+      // The C signature is determined by the declaration, so an argument that is not ghost
+      // but is not used by the body still occupies a register or stack slot.
+      if_chain! {
+        if cc == CallConv::C;
+        if let Some(arg) = decl_args.get(i);
+        if !arg.attr.contains(ArgAttr::GHOST);
+        if arg.ty.meta(names).expect("argument must have compile time known size").size != 0;
+        if arg_regs.next().is_none();
+        then { alloc(8); }
+      }
+      return ArgAbi::Ghost
+    }
+    let a = allocs.get(v);
+    assert_ne!(a, AllocId::ZERO);
+    let m = allocs[a].m;
+    match (m.on_stack, arg_regs.next()) {
+      (false, Some(&r)) => ArgAbi::Reg(r, Size::from_u64(m.size)),
+      (true, Some(&reg)) => ArgAbi::Boxed { reg, sz: m.size.try_into().expect("overflow") },
+      (_, None) if m.size <= 8 => {
+        let size32 = m.size.try_into().expect("overflow");
+        ArgAbi::Mem { off: alloc(slot(size32)), sz: size32 }
+      },
+      (_, None) => ArgAbi::BoxedMem { off: alloc(8), sz: m.size.try_into().expect("overflow") },
+    }
+  }).collect();
+
+  (args, rets, off)
+}
+
+/// Compute the calling convention of a procedure without lowering it. This agrees with the ABI
+/// returned by [`regalloc_vcode`](crate::regalloc::regalloc_vcode), except that all
+/// non-callee-saved registers are assumed to be clobbered, because we have not done
+/// register allocation yet. This is used for calls to procedures that have not yet been
+/// compiled, which happens in recursive procedures.
+pub(crate) fn proc_abi(names: &HashMap<Symbol, Entity>, proc: &Proc, cc: CallConv) -> ProcAbi {
+  let allocs = proc.allocs.as_deref().expect("optimized already");
+  let (args, rets, args_space) =
+    compute_abi(names, &proc.body, allocs, &proc.args, &proc.rets, cc);
+  ProcAbi {
+    args: args.into(),
+    rets: proc.body.can_return().then(|| rets.into()),
+    args_space,
+    clobbers: non_callee_saved().collect(),
+  }
+}
+
 pub(crate) fn build_vcode(
   names: &HashMap<Symbol, Entity>,
-  func_mono: &HashMap<Symbol, ProcId>,
+  func_mono: &HashMap<GenericCall, ProcId>,
   funcs: &IdxVec<ProcId, ProcAbi>,
   consts: &ConstData,
   cfg: &Cfg,
@@ -4,8 +4,9 @@ use byteorder::{LE, WriteBytesExt};
 use crate::{LinkedCode, TEXT_START, regalloc::PCode, types::vcode::{GlobalId, ProcId, BlockId}};
 
 pub(crate) const FUNCTION_ALIGN: u32 = 16;
+pub(crate) const BSS_ALIGN: u64 = 16;
 
-#[inline] fn align_to<const N: u64>(i: u64) -> u64 { (i + N - 1) & !(N - 1) }
+#[inline] pub(crate) fn align_to<const N: u64>(i: u64) -> u64 { (i + N - 1) & !(N - 1) }
 
 #[allow(clippy::cast_lossless, clippy::cast_possible_truncation)]
 fn function_pad(pos: u64) -> &'static [u8] {
@@ -17,7 +18,6 @@ impl LinkedCode {
   /// This can then be executed to run the compiled program.
   #[allow(clippy::cast_lossless)]
   pub fn write_elf(&self, w: &mut impl Write) -> io::Result<()> {
-    const BSS_ALIGN: u64 = 16;
     const HEADER: [u8; 0x60] = [
       // ELF header
       0x7f, b'E', b'L', b'F', // ELF magic
@@ -63,34 +63,74 @@ impl LinkedCode {
     w.write_u64::<LE>(1 << 21)?;
     // end of program header, now at offset 0x78
 
-    let mut ctx = InstSink {
-      linked: self, proc: &self.init.1,
-      rodata_start: rodata_start.try_into().expect("overflow"),
-      proc_start: TEXT_START,
-      local_rip: 0,
-      buf: ArrayVec::new(),
-    };
+    let mut ctx = InstSink::new(self, rodata_start.try_into().expect("overflow"), None);
     ctx.write_to(w)?;
     w.write_all(function_pad(u64::from(TEXT_START + self.init.1.len)))?;
+    self.write_funcs(&mut ctx, w)?;
+    w.write_all(&self.consts.rodata)
+  }
 
+  /// Write the code of all procedures except the start routine, each padded to
+  /// [`FUNCTION_ALIGN`].
+  pub(crate) fn write_funcs<'a>(&'a self,
+    ctx: &mut InstSink<'a>, w: &mut impl Write
+  ) -> io::Result<()> {
     for &(start, ref code) in &self.funcs.0 {
       ctx.proc = code;
       ctx.proc_start = start;
       ctx.write_to(w)?;
       w.write_all(function_pad(u64::from(code.len)))?;
     }
-
-    w.write_all(&self.consts.rodata)
+    Ok(())
   }
 }
 
+/// The section containing the target of a [`Reloc`].
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum RelocTarget {
+  /// The constants, in the `.rodata` section.
+  Rodata,
+  /// The globals, in the `.bss` section.
+  Bss,
+}
+
+/// A 32-bit absolute address in the code, which has to be filled in by the linker when the code
+/// is emitted as a relocatable object.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Reloc {
+  /// The (absolute) address of the field to patch.
+  pub(crate) addr: u32,
+  /// The section containing the referenced data.
+  pub(crate) target: RelocTarget,
+  /// The offset of the referenced data from the start of the section.
+  pub(crate) addend: u32,
+}
+
 pub(crate) struct InstSink<'a> {
   linked: &'a LinkedCode,
   proc: &'a PCode,
   buf: ArrayVec<u8, 15>,
   proc_start: u32,
   local_rip: u32,
+  /// The value of `local_rip` at the start of the current instruction.
+  inst_start: u32,
   pub(crate) rodata_start: u32,
+  /// If set, the addresses of constants and globals in the code are recorded here.
+  pub(crate) relocs: Option<Vec<Reloc>>,
+}
+
+impl<'a> InstSink<'a> {
+  pub(crate) fn new(linked: &'a LinkedCode, rodata_start: u32, relocs: Option<Vec<Reloc>>) -> Self {
+    InstSink {
+      linked, proc: &linked.init.1,
+      rodata_start,
+      proc_start: TEXT_START,
+      local_rip: 0,
+      inst_start: 0,
+      buf: ArrayVec::new(),
+      relocs,
+    }
+  }
 }
 
 impl InstSink<'_> {
@@ -102,9 +142,19 @@ impl InstSink<'_> {
   pub(crate) fn push_u64(&mut self, n: u64) {
     self.buf.try_extend_from_slice(&n.to_le_bytes()).expect("instruction overflow")
   }
-  pub(crate) fn set_rex(&mut self, n: u8) { self.buf[0] = n }
+  pub(crate) fn set_rex(&mut self, pos: usize, n: u8) { self.buf[pos] = n }
   pub(crate) fn update_rip(&mut self, size: u8) { self.local_rip += u32::from(size) }
 
+  /// Record that the next 4 bytes of the instruction are the absolute address `addend`
+  /// relative to the start of `target`.
+  pub(crate) fn reloc(&mut self, target: RelocTarget, addend: u32) {
+    if let Some(relocs) = &mut self.relocs {
+      let pos = u32::try_from(self.buf.len()).expect("impossible");
+      let addr = self.proc_start + self.inst_start + pos;
+      relocs.push(Reloc { addr, target, addend })
+    }
+  }
+
   pub(crate) fn rip_relative_block(&self, tgt: BlockId) -> i32 {
     let addr = i64::from(self.proc.block_addr[tgt]) - i64::from(self.local_rip);
     i32::try_from(addr).expect("jump out of range")
@@ -118,6 +168,7 @@ impl InstSink<'_> {
   fn write_to(&mut self, w: &mut impl Write) -> io::Result<()> {
     self.local_rip = 0;
     self.proc.insts.0.iter().try_for_each(|inst| {
+      self.inst_start = self.local_rip;
       inst.write(self);
       // println!("{:?} (layout {:?})\n  = {:x?}", inst, inst.layout_inst(), self.buf);
       w.write_all(&self.buf)?;
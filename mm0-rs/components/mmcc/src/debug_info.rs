@@ -0,0 +1,293 @@
+//! Debugging information for compiled programs: section headers, a symbol table, and DWARF line
+//! information.
+//!
+//! The proof only covers basic ELF files (see `isBasicElf` in `x86.mm0`), which have a single
+//! program segment and no section headers, so this information cannot be added to the file
+//! produced by [`LinkedCode::write_elf`]. Instead, [`LinkedCode::write_elf_debug`] writes the
+//! same file with the section header fields of the ELF header filled in and the extra sections
+//! appended after the program segment. The program header and the segment itself are
+//! byte-identical to the proven file, so the same code is loaded and run, but the debug file as a
+//! whole is not covered by the proof.
+
+use std::io::{self, Read, Write};
+use byteorder::{LE, WriteBytesExt};
+use crate::{FileRef, FileSpan, LinkedCode, TEXT_START, u32_as_usize};
+use crate::codegen::{BSS_ALIGN, FUNCTION_ALIGN, align_to};
+
+pub(crate) const SHT_PROGBITS: u32 = 1;
+pub(crate) const SHT_SYMTAB: u32 = 2;
+pub(crate) const SHT_STRTAB: u32 = 3;
+pub(crate) const SHT_NOBITS: u32 = 8;
+
+pub(crate) const SHF_WRITE: u64 = 1;
+pub(crate) const SHF_ALLOC: u64 = 2;
+pub(crate) const SHF_EXECINSTR: u64 = 4;
+
+pub(crate) const STB_GLOBAL: u8 = 1;
+pub(crate) const STT_OBJECT: u8 = 1;
+pub(crate) const STT_FUNC: u8 = 2;
+
+/// The size of an entry in the symbol table.
+pub(crate) const SYM_SIZE: u64 = 24;
+
+// Indexes into the section header table. These are shared with the relocatable object files
+// written by [`LinkedCode::write_object`]. (`.debug_line` is at index 6.)
+pub(crate) const SEC_TEXT: u16 = 1;
+pub(crate) const SEC_RODATA: u16 = 2;
+pub(crate) const SEC_BSS: u16 = 3;
+pub(crate) const SEC_SYMTAB: u16 = 4;
+pub(crate) const SEC_STRTAB: u16 = 5;
+const SEC_SHSTRTAB: u16 = 7;
+const NUM_SECTIONS: u16 = 8;
+
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+const LINE_BASE: i8 = -5;
+const LINE_RANGE: u8 = 14;
+const OPCODE_BASE: u8 = 13;
+const STANDARD_OPCODE_LENGTHS: [u8; 12] = [0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1];
+
+pub(crate) fn write_zeros(w: &mut impl Write, n: u64) -> io::Result<()> {
+  io::copy(&mut io::repeat(0).take(n), w).map(|_| ())
+}
+
+fn write_uleb(buf: &mut Vec<u8>, mut n: u64) {
+  loop {
+    #[allow(clippy::cast_possible_truncation)] let b = (n & 0x7f) as u8;
+    n >>= 7;
+    if n == 0 { return buf.push(b) }
+    buf.push(b | 0x80)
+  }
+}
+
+fn write_sleb(buf: &mut Vec<u8>, mut n: i64) {
+  loop {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] let b = (n & 0x7f) as u8;
+    n >>= 7;
+    if (n == 0 && b & 0x40 == 0) || (n == -1 && b & 0x40 != 0) { return buf.push(b) }
+    buf.push(b | 0x80)
+  }
+}
+
+/// Append an entry to a symbol table. `info` contains the binding and type of the symbol.
+pub(crate) fn push_symbol(symtab: &mut Vec<u8>,
+  name: u32, info: u8, shndx: u16, value: u64, size: u64
+) {
+  symtab.extend_from_slice(&name.to_le_bytes());
+  symtab.push(info);
+  symtab.push(0);
+  symtab.extend_from_slice(&shndx.to_le_bytes());
+  symtab.extend_from_slice(&value.to_le_bytes());
+  symtab.extend_from_slice(&size.to_le_bytes());
+}
+
+/// A string table, as used by the `.strtab` and `.shstrtab` sections.
+pub(crate) struct StrTab(pub(crate) Vec<u8>);
+
+impl StrTab {
+  pub(crate) fn new() -> Self { Self(vec![0]) }
+
+  pub(crate) fn push(&mut self, s: &str) -> u32 {
+    let n = self.0.len().try_into().expect("overflow");
+    self.0.extend_from_slice(s.as_bytes());
+    self.0.push(0);
+    n
+  }
+}
+
+/// An entry in the section header table.
+#[derive(Default)]
+pub(crate) struct Section {
+  pub(crate) name: u32,
+  pub(crate) ty: u32,
+  pub(crate) flags: u64,
+  pub(crate) addr: u64,
+  pub(crate) offset: u64,
+  pub(crate) size: u64,
+  pub(crate) link: u32,
+  pub(crate) info: u32,
+  pub(crate) align: u64,
+  pub(crate) entsize: u64,
+}
+
+impl Section {
+  pub(crate) fn write(&self, w: &mut impl Write) -> io::Result<()> {
+    w.write_u32::<LE>(self.name)?;
+    w.write_u32::<LE>(self.ty)?;
+    w.write_u64::<LE>(self.flags)?;
+    w.write_u64::<LE>(self.addr)?;
+    w.write_u64::<LE>(self.offset)?;
+    w.write_u64::<LE>(self.size)?;
+    w.write_u32::<LE>(self.link)?;
+    w.write_u32::<LE>(self.info)?;
+    w.write_u64::<LE>(self.align)?;
+    w.write_u64::<LE>(self.entsize)
+  }
+}
+
+impl LinkedCode {
+  /// Construct the `.symtab` and `.strtab` sections, with one symbol for the start routine,
+  /// one for each procedure, and one for each global.
+  fn symtab(&self, global_start: u64) -> (Vec<u8>, StrTab) {
+    let mut strtab = StrTab::new();
+    let mut symtab = vec![0; 24];
+    let mut push = |name: &str, ty: u8, shndx: u16, value: u64, size: u64| {
+      let name = strtab.push(name);
+      push_symbol(&mut symtab, name, (STB_GLOBAL << 4) | ty, shndx, value, size)
+    };
+    push("_start", STT_FUNC, SEC_TEXT, TEXT_START.into(), self.init.1.len.into());
+    for (f, &(start, ref code)) in self.funcs.enum_iter() {
+      push(self.func_names[f].as_str(), STT_FUNC, SEC_TEXT, start.into(), code.len.into());
+    }
+    for &(g, off, size) in &self.globals.0 {
+      push(g.as_str(), STT_OBJECT, SEC_BSS, global_start + u64::from(off), size.into());
+    }
+    (symtab, strtab)
+  }
+
+  /// Construct a DWARF (version 2) `.debug_line` section, which maps the start of each procedure
+  /// to the line of its declaration, and the code of each source statement to the line of the
+  /// statement, as given by `line`.
+  fn debug_line(&self, mut line: impl FnMut(&FileSpan) -> Option<u32>) -> Vec<u8> {
+    fn set_address(prog: &mut Vec<u8>, addr: u64) {
+      prog.extend_from_slice(&[0, 9, DW_LNE_SET_ADDRESS]);
+      prog.extend_from_slice(&addr.to_le_bytes());
+    }
+    let mut files: Vec<FileRef> = vec![];
+    let mut prog = vec![];
+    let (mut cur_file, mut cur_line) = (1, 1);
+    let mut row = |addr: u32, span: &FileSpan| {
+      let l = match line(span) { Some(l) => l, None => return };
+      let file = match files.iter().position(|file| *file == span.file) {
+        Some(i) => i + 1,
+        None => { files.push(span.file.clone()); files.len() }
+      };
+      if !prog.is_empty() && file == cur_file && l == cur_line { return }
+      set_address(&mut prog, addr.into());
+      if file != cur_file {
+        prog.push(DW_LNS_SET_FILE);
+        write_uleb(&mut prog, file as u64);
+        cur_file = file;
+      }
+      if l != cur_line {
+        prog.push(DW_LNS_ADVANCE_LINE);
+        write_sleb(&mut prog, i64::from(l) - i64::from(cur_line));
+        cur_line = l;
+      }
+      prog.push(DW_LNS_COPY);
+    };
+    for &(off, ref span) in &self.init.1.spans { row(TEXT_START + off, span) }
+    for (f, &(start, ref code)) in self.funcs.enum_iter() {
+      if let Some(p) = self.mir.get(&self.func_names[f]) { row(start, &p.name.span) }
+      for &(off, ref span) in &code.spans { row(start + off, span) }
+    }
+    if !prog.is_empty() {
+      set_address(&mut prog, u64::from(TEXT_START + self.text_size));
+      prog.extend_from_slice(&[0, 1, DW_LNE_END_SEQUENCE]);
+    }
+
+    #[allow(clippy::cast_sign_loss)]
+    let mut header = vec![1, 1, LINE_BASE as u8, LINE_RANGE, OPCODE_BASE];
+    header.extend_from_slice(&STANDARD_OPCODE_LENGTHS);
+    header.push(0); // no include directories
+    for file in files {
+      // An empty name would end the table
+      let name = file.path().to_string_lossy();
+      header.extend_from_slice(if name.is_empty() { b"<unknown>" } else { name.as_bytes() });
+      header.extend_from_slice(&[0, 0, 0, 0]); // directory, modification time, length
+    }
+    header.push(0);
+    let header_len = u32::try_from(header.len()).expect("overflow");
+    let unit_len = u32::try_from(2 + 4 + header.len() + prog.len()).expect("overflow");
+    let mut out = Vec::with_capacity(4 + u32_as_usize(unit_len));
+    out.extend_from_slice(&unit_len.to_le_bytes());
+    out.extend_from_slice(&2_u16.to_le_bytes());
+    out.extend_from_slice(&header_len.to_le_bytes());
+    out.extend_from_slice(&header);
+    out.extend_from_slice(&prog);
+    out
+  }
+
+  /// Write this code object as an ELF file with section headers, a symbol table and DWARF line
+  /// information, for use with debuggers and profilers. `line` maps a source span to a (1-based)
+  /// line number in its file; code for which it returns `None` gets no line information.
+  ///
+  /// The result is the file produced by [`write_elf`](Self::write_elf), except for the section
+  /// header fields of the ELF header and the data appended after the program segment. In
+  /// particular, it is not the file described by the proof.
+  pub fn write_elf_debug(&self,
+    w: &mut impl Write, line: impl FnMut(&FileSpan) -> Option<u32>
+  ) -> io::Result<()> {
+    let mut file = vec![];
+    self.write_elf(&mut file)?;
+    let text_start = u64::from(TEXT_START);
+    let text_end = text_start + u64::from(self.text_size);
+    let file_end = text_start + (file.len() as u64 - 0x78);
+    let global_start = align_to::<BSS_ALIGN>(file_end);
+
+    let (symtab, strtab) = self.symtab(global_start);
+    let debug_line = self.debug_line(line);
+    let mut shstrtab = StrTab::new();
+    let mut sections = Vec::with_capacity(NUM_SECTIONS.into());
+    sections.push(Section::default());
+    let mut push = |shstrtab: &mut StrTab, name, sec| {
+      sections.push(Section { name: shstrtab.push(name), ..sec })
+    };
+    push(&mut shstrtab, ".text", Section {
+      ty: SHT_PROGBITS, flags: SHF_ALLOC | SHF_EXECINSTR,
+      addr: text_start, offset: 0x78, size: text_end - text_start,
+      align: FUNCTION_ALIGN.into(), ..Default::default()
+    });
+    push(&mut shstrtab, ".rodata", Section {
+      ty: SHT_PROGBITS, flags: SHF_ALLOC,
+      addr: text_end, offset: text_end - text_start + 0x78, size: file_end - text_end,
+      align: 1, ..Default::default()
+    });
+    push(&mut shstrtab, ".bss", Section {
+      ty: SHT_NOBITS, flags: SHF_ALLOC | SHF_WRITE,
+      addr: global_start, offset: global_start - text_start + 0x78,
+      size: self.global_size.into(), align: BSS_ALIGN, ..Default::default()
+    });
+    let mut offset = align_to::<8>(file.len() as u64);
+    let mut data = |sec: Section, len: usize| {
+      let sec = Section { offset, size: len as u64, ..sec };
+      offset += len as u64;
+      sec
+    };
+    push(&mut shstrtab, ".symtab", data(Section {
+      ty: SHT_SYMTAB, link: SEC_STRTAB.into(), info: 1, align: 8, entsize: SYM_SIZE,
+      ..Default::default()
+    }, symtab.len()));
+    push(&mut shstrtab, ".strtab", data(Section {
+      ty: SHT_STRTAB, align: 1, ..Default::default()
+    }, strtab.0.len()));
+    push(&mut shstrtab, ".debug_line", data(Section {
+      ty: SHT_PROGBITS, align: 1, ..Default::default()
+    }, debug_line.len()));
+    debug_assert_eq!(sections.len(), usize::from(SEC_SHSTRTAB));
+    let name = shstrtab.push(".shstrtab");
+    let shstrtab_sec = data(Section { name, ty: SHT_STRTAB, align: 1, ..Default::default() },
+      shstrtab.0.len());
+    sections.push(shstrtab_sec);
+    debug_assert_eq!(sections.len(), usize::from(NUM_SECTIONS));
+    debug_assert_eq!(sections[usize::from(SEC_RODATA)].size, self.consts.rodata.len() as u64);
+    let shoff = align_to::<8>(offset);
+
+    // e_shoff, e_shnum and e_shstrndx
+    file[0x28..0x30].copy_from_slice(&shoff.to_le_bytes());
+    file[0x3c..0x3e].copy_from_slice(&NUM_SECTIONS.to_le_bytes());
+    file[0x3e..0x40].copy_from_slice(&SEC_SHSTRTAB.to_le_bytes());
+    w.write_all(&file)?;
+    write_zeros(w, sections[usize::from(SEC_SYMTAB)].offset - file.len() as u64)?;
+    w.write_all(&symtab)?;
+    w.write_all(&strtab.0)?;
+    w.write_all(&debug_line)?;
+    w.write_all(&shstrtab.0)?;
+    write_zeros(w, shoff - offset)?;
+    for sec in &sections { sec.write(w)? }
+    Ok(())
+  }
+}
@@ -0,0 +1,341 @@
+//! Human readable listings of the generated code, for debugging the compiler backend.
+//!
+//! [`LinkedCode::write_asm`] prints the final machine code in Intel syntax, and
+//! [`LinkedCode::write_vcode`] prints the code before register allocation, using virtual
+//! registers. Like [`LinkedCode::write_elf_debug`], these listings are not covered by the proof.
+
+use std::fmt::{self, Display};
+use std::io::{self, Write};
+use itertools::Itertools;
+use crate::{LinkedCode, Symbol, TEXT_START, u32_as_usize};
+use crate::arch::{ExtMode, Inst, Offset, PAMode, PInst, PReg, PRegMem, PRegMemImm, PrintOperand,
+  RegMem};
+use crate::build_vcode::VCode;
+use crate::codegen::{BSS_ALIGN, align_to};
+use crate::regalloc::PCode;
+use crate::types::{IdxVec, Size, vcode::{ConstRef, IsReg, ProcId, SpillId}};
+
+/// A physical register, accessed at the given size, like `eax` or `r8b`.
+#[derive(Clone, Copy)]
+struct Reg(PReg, Size);
+
+impl Display for Reg {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    const NAMES: [[&str; 4]; 8] = [
+      ["al", "ax", "eax", "rax"], ["cl", "cx", "ecx", "rcx"],
+      ["dl", "dx", "edx", "rdx"], ["bl", "bx", "ebx", "rbx"],
+      ["spl", "sp", "esp", "rsp"], ["bpl", "bp", "ebp", "rbp"],
+      ["sil", "si", "esi", "rsi"], ["dil", "di", "edi", "rdi"],
+    ];
+    let i = match self.1 {
+      Size::S8 => 0, Size::S16 => 1, Size::S32 => 2, Size::S64 | Size::Inf => 3
+    };
+    match self.0.index() {
+      n @ 0..=7 => f.write_str(NAMES[usize::from(n)][i]),
+      n => write!(f, "r{}{}", n, ["b", "w", "d", ""][i]),
+    }
+  }
+}
+
+/// An immediate operand of an instruction of the given size. 32-bit immediates are
+/// sign-extended in 64-bit instructions, so we print the extended value like `objdump` does.
+struct Imm(u32, Size);
+
+impl Display for Imm {
+  #[allow(clippy::cast_possible_wrap, clippy::cast_sign_loss)]
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.1 {
+      Size::S8 => write!(f, "{:#x}", self.0 & 0xff),
+      Size::S16 => write!(f, "{:#x}", self.0 & 0xffff),
+      Size::S32 => write!(f, "{:#x}", self.0),
+      Size::S64 | Size::Inf => write!(f, "{:#x}", i64::from(self.0 as i32) as u64),
+    }
+  }
+}
+
+/// The context for printing the machine code of a [`LinkedCode`].
+struct AsmPrinter<'a> {
+  code: &'a LinkedCode,
+  /// The constants in the `.rodata` section, as `(start, size, name)` sorted by address.
+  consts: Vec<(u32, u32, Symbol)>,
+}
+
+impl<'a> AsmPrinter<'a> {
+  fn new(code: &'a LinkedCode) -> Self {
+    let mut consts = code.consts.ordered.iter().filter_map(|&c| match code.consts[c] {
+      (size, ConstRef::Ptr(addr)) => Some((addr, size, c)),
+      (_, ConstRef::Value(_)) => None,
+    }).collect::<Vec<_>>();
+    consts.sort_unstable_by_key(|c| c.0);
+    Self { code, consts }
+  }
+
+  /// Print the address `n` in the `.rodata` section, relative to the enclosing constant.
+  fn const_ref(&self, f: &mut impl fmt::Write, n: u32) -> fmt::Result {
+    let i = self.consts.partition_point(|c| c.0 <= n);
+    match i.checked_sub(1).map(|i| self.consts[i]) {
+      Some((start, size, c)) if n == start || n - start < size => {
+        write!(f, "{}", c)?;
+        if n != start { write!(f, " + {:#x}", n - start)? }
+        Ok(())
+      }
+      _ => write!(f, ".rodata + {:#x}", n)
+    }
+  }
+
+  /// Print a memory operand, like `dword ptr [rsp + 0x8]`.
+  /// The `ptr` prefix is omitted if `sz` is `None` (for `lea`).
+  #[allow(clippy::cast_possible_wrap)]
+  fn mem(&self, f: &mut impl fmt::Write, a: &PAMode, sz: Option<Size>) -> fmt::Result {
+    if let Some(sz) = sz {
+      let ptr = match sz { Size::S8 => "byte", Size::S16 => "word", Size::S32 => "dword",
+        Size::S64 | Size::Inf => "qword" };
+      write!(f, "{} ptr ", ptr)?
+    }
+    write!(f, "[")?;
+    let mut sep = "";
+    if a.base.is_valid() { write!(f, "{}", a.base)?; sep = " + " }
+    if let Some(si) = &a.si {
+      write!(f, "{}{}", sep, si.index)?;
+      if si.shift != 0 { write!(f, "*{}", 1 << si.shift)? }
+      sep = " + ";
+    }
+    match a.off {
+      Offset::Real(n) => match n as i32 {
+        n if sep.is_empty() => write!(f, "{:#x}", n)?,
+        0 => {}
+        n if n < 0 => write!(f, " - {:#x}", n.unsigned_abs())?,
+        n => write!(f, " + {:#x}", n)?,
+      }
+      Offset::Const(n) => { write!(f, "{}", sep)?; self.const_ref(f, n)? }
+      Offset::Global(g, n) => {
+        write!(f, "{}{}", sep, self.code.globals[g].0)?;
+        if n != 0 { write!(f, " + {:#x}", n)? }
+      }
+      Offset::Spill(..) => write!(f, "{}{:?}", sep, a.off)?,
+    }
+    write!(f, "]")
+  }
+
+  fn rm(&self, f: &mut impl fmt::Write, rm: &PRegMem, sz: Size) -> fmt::Result {
+    match rm {
+      RegMem::Reg(r) => write!(f, "{}", Reg(*r, sz)),
+      RegMem::Mem(a) => self.mem(f, a, Some(sz)),
+    }
+  }
+
+  fn rmi(&self, f: &mut impl fmt::Write, rmi: &PRegMemImm, sz: Size) -> fmt::Result {
+    match rmi {
+      PRegMemImm::Reg(r) => write!(f, "{}", Reg(*r, sz)),
+      PRegMemImm::Mem(a) => self.mem(f, a, Some(sz)),
+      PRegMemImm::Imm(i) => write!(f, "{}", Imm(*i, sz)),
+    }
+  }
+
+  /// Print an instruction located at `addr`. An `assert` is printed as the conditional jump
+  /// over the `ud2` instruction, which the caller prints separately.
+  #[allow(clippy::cast_possible_truncation)]
+  fn inst(&self, f: &mut impl fmt::Write, addr: u32, inst: &PInst) -> fmt::Result {
+    match *inst {
+      PInst::Fallthrough { .. } | PInst::SyncLet { .. } => Ok(()),
+      PInst::Binop { op, sz, dst, ref src } => {
+        write!(f, "{} {}, ", op, Reg(dst, sz))?;
+        self.rmi(f, src, sz)
+      }
+      PInst::Unop { op, sz, dst } => write!(f, "{} {}", op, Reg(dst, sz)),
+      PInst::Mul { sz, ref src } => { write!(f, "mul ")?; self.rm(f, src, sz) }
+      PInst::Cdx { sz } =>
+        f.write_str(match sz { Size::S16 => "cwd", Size::S32 => "cdq", _ => "cqo" }),
+      PInst::DivRem { sz, signed, ref src } => {
+        write!(f, "{} ", if signed { "idiv" } else { "div" })?;
+        self.rm(f, src, sz)
+      }
+      PInst::Imm { sz, dst, src } => match (sz, src) {
+        (_, 0) => {
+          let r = Reg(dst, sz.min(Size::S32));
+          write!(f, "xor {}, {}", r, r)
+        }
+        (Size::S64, _) => {
+          // A 32-bit immediate is sign-extended, so we print it as a signed value
+          #[allow(clippy::cast_possible_wrap)]
+          let imm = i32::try_from(src as i64);
+          match imm {
+            Ok(n) if n < 0 => write!(f, "mov {}, -{:#x}", Reg(dst, sz), n.unsigned_abs()),
+            Ok(_) => write!(f, "mov {}, {:#x}", Reg(dst, sz), src),
+            Err(_) => write!(f, "movabs {}, {:#x}", Reg(dst, sz), src),
+          }
+        }
+        _ => write!(f, "mov {}, {:#x}", Reg(dst, sz), src),
+      }
+      PInst::MovRR { sz, dst, src } => write!(f, "mov {}, {}", Reg(dst, sz), Reg(src, sz)),
+      PInst::MovzxRmR { ext_mode: ExtMode::LQ, dst, ref src } => {
+        write!(f, "mov {}, ", Reg(dst, Size::S32))?;
+        self.rm(f, src, Size::S32)
+      }
+      PInst::MovsxRmR { ext_mode: ExtMode::LQ, dst, ref src } => {
+        write!(f, "movsxd {}, ", Reg(dst, Size::S64))?;
+        self.rm(f, src, Size::S32)
+      }
+      PInst::MovzxRmR { ext_mode, dst, ref src } => {
+        write!(f, "movzx {}, ", Reg(dst, ext_mode.dst()))?;
+        self.rm(f, src, ext_mode.src())
+      }
+      PInst::MovsxRmR { ext_mode, dst, ref src } => {
+        write!(f, "movsx {}, ", Reg(dst, ext_mode.dst()))?;
+        self.rm(f, src, ext_mode.src())
+      }
+      PInst::Load64 { dst, ref src, .. } => {
+        write!(f, "mov {}, ", Reg(dst, Size::S64))?;
+        self.mem(f, src, Some(Size::S64))
+      }
+      PInst::Lea { sz, dst, ref addr } => {
+        write!(f, "lea {}, ", Reg(dst, sz))?;
+        self.mem(f, addr, None)
+      }
+      PInst::Store { sz, ref dst, src, .. } => {
+        write!(f, "mov ")?;
+        self.mem(f, dst, Some(sz))?;
+        write!(f, ", {}", Reg(src, sz))
+      }
+      PInst::Shift { kind, sz, dst, num_bits: Some(n) } =>
+        write!(f, "{} {}, {}", kind, Reg(dst, sz), n),
+      PInst::Shift { kind, sz, dst, num_bits: None } =>
+        write!(f, "{} {}, cl", kind, Reg(dst, sz)),
+      PInst::Cmp { sz, op, src1, ref src2 } => {
+        write!(f, "{} {}, ", op, Reg(src1, sz))?;
+        self.rmi(f, src2, sz)
+      }
+      PInst::SetCC { cc, dst } => write!(f, "set{} {}", cc, Reg(dst, Size::S8)),
+      PInst::CMov { sz, cc, dst, ref src } => {
+        write!(f, "cmov{} {}, ", cc, Reg(dst, sz))?;
+        self.rm(f, src, sz)
+      }
+      PInst::Push64 { ref src } => { write!(f, "push ")?; self.rmi(f, src, Size::S64) }
+      PInst::Pop64 { dst } => write!(f, "pop {}", Reg(dst, Size::S64)),
+      PInst::CallKnown { f: func } => write!(f, "call {}", self.code.func_names[func]),
+      PInst::SysCall => write!(f, "syscall"),
+      PInst::Ret => write!(f, "ret"),
+      PInst::JmpKnown { dst, .. } => write!(f, "jmp .bb{}", dst.0),
+      PInst::JmpCond { cc, dst, .. } => write!(f, "j{} .bb{}", cc, dst.0),
+      PInst::Assert { cc, .. } => write!(f, "j{} {:#x}", cc, addr + 4),
+      PInst::Ud2 => write!(f, "ud2"),
+    }
+  }
+
+  /// Print the code of a procedure (or the start routine) starting at address `start`.
+  fn write_proc(&self,
+    w: &mut impl Write, name: impl Display, start: u32, code: &PCode
+  ) -> io::Result<()> {
+    writeln!(w, "\n{}:", name)?;
+    // The blocks are laid out contiguously, but `block_addr` has no entries for empty blocks
+    let mut addr = start;
+    for (bl, &(inst_start, inst_end)) in code.blocks.enum_iter() {
+      writeln!(w, ".bb{}:", bl.0)?;
+      for inst in &code.insts[inst_start..inst_end] {
+        let len = inst.len();
+        if len == 0 { continue }
+        let mut s = String::new();
+        self.inst(&mut s, addr, inst).expect("writing to a string");
+        match *inst {
+          PInst::Load64 { spill: true, .. } => writeln!(w, "  {:x}:  {:<32}; reload", addr, s)?,
+          PInst::Store { spill: true, .. } => writeln!(w, "  {:x}:  {:<32}; spill", addr, s)?,
+          PInst::Assert { .. } => {
+            writeln!(w, "  {:x}:  {}", addr, s)?;
+            writeln!(w, "  {:x}:  ud2", addr + 2)?
+          }
+          _ => writeln!(w, "  {:x}:  {}", addr, s)?,
+        }
+        addr += u32::from(len);
+      }
+    }
+    Ok(())
+  }
+
+  /// Print the bytes of `.rodata` in the range `start..end`, 8 per line.
+  fn write_bytes(&self, w: &mut impl Write, rodata_start: u32, start: u32, end: u32
+  ) -> io::Result<()> {
+    let data = &self.code.consts.rodata[u32_as_usize(start)..u32_as_usize(end)];
+    for (i, chunk) in data.chunks(8).enumerate() {
+      let addr = rodata_start + start + 8 * u32::try_from(i).expect("overflow");
+      writeln!(w, "  {:x}:  db {:#04x}", addr, chunk.iter().format(", "))?
+    }
+    Ok(())
+  }
+}
+
+impl LinkedCode {
+  /// Write an assembly listing of the program in Intel syntax. This shows the machine code
+  /// after register allocation, with the address of every instruction. Jump targets are shown
+  /// as block labels, calls use procedure names, and references to constants and globals
+  /// are resolved to their names. The listing ends with the contents of the `.rodata` section
+  /// and the layout of the `.bss` section.
+  pub fn write_asm(&self, w: &mut impl Write) -> io::Result<()> {
+    let p = AsmPrinter::new(self);
+    writeln!(w, "; .text")?;
+    p.write_proc(w, "_start", TEXT_START, &self.init.1)?;
+    for (f, &(start, ref code)) in self.funcs.enum_iter() {
+      p.write_proc(w, self.func_names[f], start, code)?;
+    }
+    let rodata_start = TEXT_START + self.text_size;
+    if !self.consts.rodata.is_empty() {
+      writeln!(w, "\n; .rodata")?;
+      let mut pos = 0;
+      for &(start, size, c) in &p.consts {
+        if pos < start { p.write_bytes(w, rodata_start, pos, start)? }
+        writeln!(w, "{}:", c)?;
+        pos = pos.max(start);
+        p.write_bytes(w, rodata_start, pos, start + size)?;
+        pos = pos.max(start + size);
+      }
+      let len = u32::try_from(self.consts.rodata.len()).expect("overflow");
+      if pos < len { p.write_bytes(w, rodata_start, pos, len)? }
+    }
+    if !self.globals.0.is_empty() {
+      writeln!(w, "\n; .bss")?;
+      let file_end = u64::from(rodata_start) + self.consts.rodata.len() as u64;
+      let global_start = align_to::<BSS_ALIGN>(file_end);
+      for &(g, off, size) in &self.globals.0 {
+        writeln!(w, "{}:\n  {:x}:  resb {:#x}", g, global_start + u64::from(off), size)?
+      }
+    }
+    Ok(())
+  }
+
+  /// Write a listing of the code of the program before register allocation. This uses virtual
+  /// registers `%1, %2, ...` in place of machine registers, and shows the calling convention and
+  /// stack slots of each procedure, the parameters of each block, and the register constraints
+  /// on the arguments of calls and returns.
+  pub fn write_vcode(&self, w: &mut impl Write) -> io::Result<()> {
+    fn write_proc(w: &mut impl Write,
+      func_names: &IdxVec<ProcId, Symbol>, name: impl Display, vcode: &VCode
+    ) -> io::Result<()> {
+      writeln!(w, "{}:", name)?;
+      writeln!(w, "  ; args {:?}", vcode.abi.args)?;
+      match &vcode.abi.rets {
+        Some(rets) => writeln!(w, "  ; rets {:?}", rets)?,
+        None => writeln!(w, "  ; does not return")?,
+      }
+      writeln!(w, "  ; stack args {} in, {} out; spill slots [{}]",
+        vcode.spills[SpillId::INCOMING], vcode.spills[SpillId::OUTGOING],
+        vcode.spills.enum_iter().skip(2)
+          .format_with(", ", |(sp, n), f| f(&format_args!("{:?} = {}", sp, n))))?;
+      for (bl, &(start, end)) in vcode.blocks.enum_iter() {
+        writeln!(w, "bb{}({}):", bl.0, vcode.block_params[bl].iter().format(", "))?;
+        for inst in &vcode.insts[start..end] {
+          if let Inst::CallKnown { f, ref operands, .. } = *inst {
+            writeln!(w, "    call {}({})", func_names[f],
+              operands.iter().map(|&x| PrintOperand(x)).format(", "))?
+          } else {
+            writeln!(w, "    {:?}", inst)?
+          }
+        }
+      }
+      writeln!(w)
+    }
+    write_proc(w, &self.func_names, "_start", &self.vcode.0)?;
+    for (f, vcode) in self.vcode.1.enum_iter() {
+      write_proc(w, &self.func_names, self.func_names[f], vcode)?;
+    }
+    Ok(())
+  }
+}
@@ -69,6 +69,12 @@ pub enum TypeError<'a> {
   UnexpectedVariant,
   /// More than one `main` function defined
   DoubleMain,
+  /// A procedure with this name has already been defined
+  DoubleProc(Symbol),
+  /// A call closes a cycle in the call graph without providing a `(variant h)` clause
+  MissingVariant(Symbol),
+  /// This operation cannot be expressed in the x86 model used for generating proofs
+  Unprovable(&'static str),
 }
 
 impl<'a, C: DisplayCtx<'a>> CtxDisplay<C> for TypeError<'a> {
@@ -118,6 +124,11 @@ impl<'a, C: DisplayCtx<'a>> CtxDisplay<C> for TypeError<'a> {
       TypeError::UnexpectedVariant => write!(f, "A (variant h) clause was provided \
         to a function or label that does not declare a variant"),
       TypeError::DoubleMain => write!(f, "The `main` function has been defined more than once"),
+      TypeError::DoubleProc(g) => write!(f, "The procedure '{}' has been defined more than once", g),
+      TypeError::MissingVariant(g) => write!(f, "Recursive call to '{}' requires a \
+        (variant h) clause proving that the variant decreases", g),
+      TypeError::Unprovable(what) => write!(f,
+        "The x86 model used for proofs does not support {}", what),
     }
   }
 }
@@ -1259,6 +1270,12 @@ pub struct InferCtx<'a, 'n> {
   /// as well as waiting for all variables to be as unified as possible so that
   /// the error messages are as precise as possible.
   pub errors: Vec<hir::Spanned<'a, TypeError<'a>>>,
+  /// The calls made in the current item that do not provide a variant.
+  /// Termination checking requires that these calls do not form a cycle.
+  pub calls: Vec<hir::Spanned<'a, Symbol>>,
+  /// If true, reject operations that the x86 model cannot express, because
+  /// the compiled code will be given a proof of correctness.
+  pub proofs: bool,
 }
 
 /// A relation between types, used as an argument to [`InferCtx::relate_ty`].
@@ -1561,6 +1578,17 @@ impl<'a> Common<'a> {
   }
 }
 
+/// The result of lowering a procedure signature, used to check the body.
+struct ProcSig<'a> {
+  args: Box<[hir::Arg<'a>]>,
+  gen: GenId,
+  outs: Box<[u32]>,
+  rets: Box<[hir::Arg<'a>]>,
+  variant: Option<hir::Variant<'a>>,
+  t_rets: &'a [Arg<'a>],
+  ctx: Context<'a>,
+}
+
 impl<'a, 'n> InferCtx<'a, 'n> {
   /// Create a new `InferCtx` from the given allocator.
   pub fn new(
@@ -1587,6 +1615,8 @@ impl<'a, 'n> InferCtx<'a, 'n> {
       labels: HashMap::new(),
       returns: None,
       errors: vec![],
+      calls: vec![],
+      proofs: false,
     }
   }
 
@@ -2681,8 +2711,8 @@ impl<'a, 'n> InferCtx<'a, 'n> {
       ast::TypeKind::False => self.common.t_false,
       ast::TypeKind::Bool => self.common.t_bool,
       &ast::TypeKind::Var(v) => intern!(self, TyKind::Var(v)),
-      &ast::TypeKind::Int(sz) => self.common.t_int(sz),
-      &ast::TypeKind::UInt(sz) => self.common.t_uint(sz),
+      &ast::TypeKind::Int(sz) => { self.check_int_size(&ty.span, sz); self.common.t_int(sz) }
+      &ast::TypeKind::UInt(sz) => { self.check_int_size(&ty.span, sz); self.common.t_uint(sz) }
       ast::TypeKind::Array(ty, n) => {
         let ty = self.lower_ty(ty, ExpectTy::Any);
         let (n, _) = self.lower_pure_expr(n, ExpectExpr::HasTy(self.common.nat()));
@@ -2990,6 +3020,7 @@ impl<'a, 'n> InferCtx<'a, 'n> {
     let (es, pes, mut subst) = gctx.ic.check_args(span, es, args, |x| x.k);
     let variant = variant.map(|v| v.from_global(&mut gctx));
     let variant = gctx.ic.check_variant_use(&mut subst, pf, variant);
+    if pf.is_none() { gctx.ic.calls.push(hir::Spanned {span, k: f}) }
     if !outs.is_empty() {
       let newgen = gctx.ic.new_generation();
       for (&i, ret) in outs.iter().zip(&*rets) {
@@ -3102,6 +3133,22 @@ impl<'a, 'n> InferCtx<'a, 'n> {
     }
   }
 
+  /// 16-bit operations are not supported by the x86 model, so 16-bit integer types are
+  /// rejected if we will be generating proofs.
+  fn check_int_size(&mut self, span: &'a FileSpan, sz: Size) {
+    if self.proofs && sz == Size::S16 {
+      self.errors.push(hir::Spanned {span, k: TypeError::Unprovable("16-bit integers")})
+    }
+  }
+
+  /// Signed division is not supported by the x86 model, so it is rejected if we will be
+  /// generating proofs.
+  fn check_divrem(&mut self, span: &'a FileSpan, op: Binop, ity: IntTy) {
+    if self.proofs && matches!(op, Binop::Div | Binop::Mod) && ity.signed() {
+      self.errors.push(hir::Spanned {span, k: TypeError::Unprovable("signed division")})
+    }
+  }
+
   fn whnf_expect(&mut self, sp: &'a FileSpan, expect: ExpectExpr<'a>) -> Option<Ty<'a>> {
     Some(self.whnf_ty(sp, expect.to_ty()?.into()).ty)
   }
@@ -3125,6 +3172,28 @@ impl<'a, 'n> InferCtx<'a, 'n> {
     (hir::ExprKind::Sn(Box::new(e), h), y, intern!(self, TyKind::Sn(x, ty)))
   }
 
+  /// Lowers the operands of a binary operation on integers, returning the integer type at which
+  /// the operation is performed, the coerced operands, and the output type.
+  #[allow(clippy::type_complexity)]
+  fn lower_int_binop(&mut self, span: &'a FileSpan, expect: ExpectExpr<'a>,
+    op: Binop, e1: &'a ast::Expr, e2: &'a ast::Expr,
+  ) -> (IntTy, (hir::Expr<'a>, RExpr<'a>), (hir::Expr<'a>, RExpr<'a>), Ty<'a>) {
+    let ityin = self.as_int_ty(span, expect).unwrap_or(IntTy::INT);
+    let tyin1 = self.common.int_ty(ityin);
+    let (e1, pe1) = self.lower_expr(e1, ExpectExpr::HasTy(tyin1));
+    let tyin2 = if let (BinopType::IntNatInt, IntTy::Int(sz)) = (op.ty(), ityin) {
+      self.common.t_uint(sz)
+    } else { tyin1 };
+    let (e2, pe2) = self.lower_expr(e2, ExpectExpr::HasTy(tyin2));
+    let (ityin2, tyout) = self.binop_ty(op,
+      |this| this.as_int_ty(span, ExpectExpr::HasTy(e1.ty())),
+      |this| this.as_int_ty(span, ExpectExpr::HasTy(e2.ty())));
+    let tyin2 = self.common.int_ty(ityin2);
+    let e1 = self.coerce_expr(e1, pe1, tyin1);
+    let e2 = self.coerce_expr(e2, pe2, tyin2);
+    (ityin2, (e1, pe1), (e2, pe2), tyout)
+  }
+
   #[allow(clippy::similar_names)]
   fn lower_expr_kind(&mut self, span: &'a FileSpan,
     e: &'a ast::ExprKind, expect: ExpectExpr<'a>
@@ -3215,34 +3284,36 @@ impl<'a, 'n> InferCtx<'a, 'n> {
             pe1.and_then(|pe1| Ok(intern!(self, ExprKind::Binop(op, pe1, pe2?)))),
             self.common.t_bool]
         }
-        let opty = op.ty();
-        let (ity, (e1, pe1), (e2, pe2), tyout) = if opty.int_in() {
-          let ityin = self.as_int_ty(span, expect).unwrap_or(IntTy::INT);
-          let tyin1 = self.common.int_ty(ityin);
-          let (e1, pe1) = self.lower_expr(e1, ExpectExpr::HasTy(tyin1));
-          let tyin2 = if let (BinopType::IntNatInt, IntTy::Int(sz)) = (opty, ityin) {
-            self.common.t_uint(sz)
-          } else { tyin1 };
-          let (e2, pe2) = self.lower_expr(e2, ExpectExpr::HasTy(tyin2));
-          let (ityin2, tyout) = self.binop_ty(op,
-            |this| this.as_int_ty(span, ExpectExpr::HasTy(e1.ty())),
-            |this| this.as_int_ty(span, ExpectExpr::HasTy(e2.ty())));
-          let tyin2 = self.common.int_ty(ityin2);
-          let e1 = self.coerce_expr(e1, pe1, tyin1);
-          let e2 = self.coerce_expr(e2, pe2, tyin2);
-          (ityin2, (e1, pe1), (e2, pe2), tyout)
+        let (ity, (e1, pe1), (e2, pe2), tyout) = if op.ty().int_in() {
+          self.lower_int_binop(span, expect, op, e1, e2)
         } else {
           (IntTy::INT,
            self.check_expr(e1, self.common.t_bool),
            self.check_expr(e2, self.common.t_bool),
            self.common.t_bool)
         };
+        self.check_divrem(span, op, ity);
         ret![Binop(op.as_hir(ity), Box::new(e1), Box::new(e2)),
           pe1.and_then(|pe1| pe2.map(|pe2|
             intern!(self, ExprKind::Binop(op, pe1, pe2)))),
           tyout]
       }
 
+      ast::ExprKind::DivNonzero(op, args) => {
+        let (e1, e2, h) = &**args;
+        let (ity, (e1, pe1), (e2, pe2), tyout) = self.lower_int_binop(span, expect, *op, e1, e2);
+        self.check_divrem(span, *op, ity);
+        let y = self.as_pure(e2.span, pe2);
+        let ty = intern!(self, TyKind::Pure(
+          intern!(self, ExprKind::Binop(Binop::Ne, y, self.common.num(0)))));
+        let (h, _) = self.check_expr(h, ty);
+        let hop = if *op == Binop::Div { hir::Binop::Div(ity, true) }
+          else { hir::Binop::Mod(ity, true) };
+        ret![DivNonzero(hop, Box::new([e1, e2, h])),
+          pe1.map(|pe1| intern!(self, ExprKind::Binop(*op, pe1, y))),
+          tyout]
+      }
+
       ast::ExprKind::Sn(x, h) => {
         let (ek, pe, ty) = self.lower_expr_sn(span, expect, x, h.as_deref());
         ret![ek, Ok(pe), ty]
@@ -4280,61 +4351,81 @@ impl<'a, 'n> InferCtx<'a, 'n> {
     (bl, pe)
   }
 
+  /// Lower the signature of a procedure, and add it to the name map so that
+  /// calls to the procedure can be checked.
+  fn lower_proc_sig(&mut self, span: &'a FileSpan, item: &'a ast::ItemKind) -> ProcSig<'a> {
+    let (intrinsic, kind, name, tyargs, args, outs, rets, variant) = match *item {
+      ast::ItemKind::Proc {
+        intrinsic, kind, ref name, tyargs, ref args, ref outs, ref rets, ref variant, ..
+      } => (intrinsic, kind, name, tyargs, args, outs, rets, variant),
+      _ => unreachable!(),
+    };
+    let args2 = args.iter()
+      .map(|arg| self.lower_arg(&arg.span, arg.k.0, &arg.k.1)).collect::<Vec<_>>();
+    let mut subst = Subst::default();
+    let gen = self.new_generation();
+    let mut rets2 = vec![];
+    let outs = outs.iter().map(|&(i, n, v, ref ty)| {
+      let i_usize = u32_as_usize(i);
+      let span = &args[i_usize].span;
+      let ty = if let Some(ty) = ty {
+        self.lower_ty(ty, ExpectTy::Any)
+      } else {
+        subst.subst_ty(self, span, args2[i_usize].1.var().ctx.ty)
+      };
+      let ctx = self.new_context_next(v, None, ty);
+      self.dc.context = ctx.into();
+      rets2.push((ArgAttr::GHOST, UnelabArgKind::Lam(
+        UnelabTupPat { span, ctx, k: UnelabTupPatKind::Name(false, n) })));
+      i
+    }).collect::<Box<[_]>>();
+    rets2.extend(rets.iter().map(|pat| (ArgAttr::empty(), UnelabArgKind::Lam(
+      self.lower_tuple_pattern(&pat.span, &pat.k, None, None).0))));
+    let rets = self.finish_args(rets2);
+    let t_rets = self.args_to_ty_args(&rets);
+    self.returns = Some(t_rets);
+    let ctx = self.dc.context;
+    let variant = self.lower_variant(variant);
+    let args = self.finish_args(args2);
+    let t_args = self.args_to_ty_args(&args);
+    let mut gctx = self.to_global_ctx();
+    let item = Entity::Proc(Spanned {
+      span: span.clone(),
+      k: ProcTc::Typed(ProcTy {
+        kind, intrinsic, tyargs,
+        args: t_args.to_global(&mut gctx),
+        outs: outs.clone(),
+        rets: t_rets.to_global(&mut gctx),
+        variant: variant.to_global(&mut gctx),
+      })
+    });
+    match self.names.entry(name.k) {
+      Entry::Occupied(mut e) => {
+        match e.get() {
+          Entity::Proc(Spanned {k: ProcTc::ForwardDeclared, ..}) => { e.insert(item); }
+          // The procedure is already typed if this item was declared using `declare_item`
+          Entity::Proc(Spanned {span: sp2, k: ProcTc::Typed(_)}) if sp2 == span => { e.insert(item); }
+          _ => self.errors.push(hir::Spanned {span: &name.span, k: TypeError::DoubleProc(name.k)}),
+        }
+      }
+      Entry::Vacant(e) => { e.insert(item); }
+    }
+    ProcSig {args, gen, outs, rets, variant, t_rets, ctx}
+  }
+
+  /// Add the signature of a procedure item to the name map, without checking the body.
+  /// This allows a group of mutually recursive procedures to be checked, by declaring
+  /// all of them before lowering any of the bodies with [`lower_item`](Self::lower_item).
+  pub fn declare_item(&mut self, Spanned {span, k: item}: &'a ast::Item) {
+    if let ast::ItemKind::Proc {..} = item { self.lower_proc_sig(span, item); }
+  }
+
   /// Construct the HIR for a top level item, performing type inference.
   pub fn lower_item(&mut self, Spanned {span, k: item}: &'a ast::Item) -> Option<hir::Item<'a>> {
     let item = match item {
-      &ast::ItemKind::Proc {
-        intrinsic, kind, ref name, tyargs, ref args, ref outs, ref rets, ref variant, ref body
-      } => {
+      &ast::ItemKind::Proc {intrinsic, inline, kind, ref name, tyargs, ref body, ..} => {
+        let ProcSig {args, gen, outs, rets, variant, t_rets, ctx} = self.lower_proc_sig(span, item);
         let name = hir::Spanned {span: &name.span, k: name.k};
-        let args2 = args.iter()
-          .map(|arg| self.lower_arg(&arg.span, arg.k.0, &arg.k.1)).collect::<Vec<_>>();
-        let mut subst = Subst::default();
-        let gen = self.new_generation();
-        let mut rets2 = vec![];
-        let outs = outs.iter().map(|&(i, n, v, ref ty)| {
-          let i_usize = u32_as_usize(i);
-          let span = &args[i_usize].span;
-          let ty = if let Some(ty) = ty {
-            self.lower_ty(ty, ExpectTy::Any)
-          } else {
-            subst.subst_ty(self, span, args2[i_usize].1.var().ctx.ty)
-          };
-          let ctx = self.new_context_next(v, None, ty);
-          self.dc.context = ctx.into();
-          rets2.push((ArgAttr::GHOST, UnelabArgKind::Lam(
-            UnelabTupPat { span, ctx, k: UnelabTupPatKind::Name(false, n) })));
-          i
-        }).collect::<Box<[_]>>();
-        rets2.extend(rets.iter().map(|pat| (ArgAttr::empty(), UnelabArgKind::Lam(
-          self.lower_tuple_pattern(&pat.span, &pat.k, None, None).0))));
-        let rets = self.finish_args(rets2);
-        let t_rets = self.args_to_ty_args(&rets);
-        self.returns = Some(t_rets);
-        let ctx = self.dc.context;
-        let variant = self.lower_variant(variant);
-        let args = self.finish_args(args2);
-        let t_args = self.args_to_ty_args(&args);
-        let mut gctx = self.to_global_ctx();
-        let item = Entity::Proc(Spanned {
-          span: span.clone(),
-          k: ProcTc::Typed(ProcTy {
-            kind, intrinsic, tyargs,
-            args: t_args.to_global(&mut gctx),
-            outs: outs.clone(),
-            rets: t_rets.to_global(&mut gctx),
-            variant: variant.to_global(&mut gctx),
-          })
-        });
-        match self.names.entry(name.k) {
-          Entry::Occupied(mut e) => {
-            assert!(
-              matches!(e.get(), Entity::Proc(Spanned {k: ProcTc::ForwardDeclared, ..})),
-              "procedure {} declared twice", name.k);
-            e.insert(item);
-          }
-          Entry::Vacant(e) => { e.insert(item); }
-        }
         if intrinsic.is_some() { return None }
         self.dc.context = ctx;
         let sigma = match *t_rets {
@@ -4359,7 +4450,7 @@ impl<'a, 'n> InferCtx<'a, 'n> {
         };
         body.expr = Some(Box::new(hir::Spanned {span, k:
           (k, (Some(self.common.e_unit), self.common.t_false))}));
-        hir::ItemKind::Proc {kind, name, tyargs, args, gen, outs, rets, variant, body}
+        hir::ItemKind::Proc {kind, inline, name, tyargs, args, gen, outs, rets, variant, body}
       }
       ast::ItemKind::Global(intrinsic, lhs, rhs) => {
         if let Some(intrinsic) = intrinsic { match *intrinsic {} }
@@ -0,0 +1,1000 @@
+//! An interpreter for [`MIR`](crate::types::mir), which runs programs without compiling them.
+//!
+//! Values are stored in a flat byte-addressed [`Memory`] using the same layout as the code
+//! generator, so pointers, punning and `sizeof` behave as they do in the compiled program,
+//! and the system call intrinsics are executed against an in-memory [`FileSystem`]. This makes
+//! it possible to test the output of the compiler against the MIR it was compiled from.
+//!
+//! Ghost state is not stored, but whenever the relevant values are enough to evaluate a type
+//! annotation or hypothesis, it is checked, and a false claim is reported as an error.
+
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+use num::{BigInt, Signed, Zero};
+
+use crate::{Entity, Symbol, intern};
+use crate::linker::GenericCall;
+use crate::types::{self, IntTy, Size, Spanned, ast::ProcKind, global};
+use crate::types::entity::{ConstTc, IntrinsicProc, ProcTc, ProcTy};
+#[allow(clippy::wildcard_imports)]
+use crate::types::mir::*;
+
+/// A position in the program, used for error reporting.
+#[derive(Clone, Copy, Debug)]
+pub struct Location {
+  /// The procedure being executed.
+  pub proc: Symbol,
+  /// The basic block being executed.
+  pub block: BlockId,
+  /// The index of the statement in the block, or `None` for the terminator.
+  pub stmt: Option<usize>,
+}
+
+/// An error encountered while interpreting a program.
+#[derive(Clone, Copy, Debug)]
+pub enum InterpError {
+  /// An `assert` failed.
+  AssertFailed(Location),
+  /// Execution reached an `unreachable` or dead block,
+  /// or returned from a call that was marked as not returning.
+  Unreachable(Location),
+  /// The value of a variable contradicts its type or annotation,
+  /// or a hypothesis was introduced that is false.
+  GhostMismatch(Location, VarId),
+  /// A cast was performed on a value that is not in the range of the target type.
+  InvalidCast(Location),
+  /// An array index or slice was out of bounds.
+  OutOfBounds(Location),
+  /// A division or remainder whose divisor was proved nonzero was given a zero divisor.
+  DivByZero(Location),
+  /// A memory access `(addr, len)` was not contained in an allocation.
+  BadAccess(Location, u64, u64),
+  /// A call to a procedure that does not exist.
+  UnknownProc(Location, Symbol),
+  /// The program uses a feature the interpreter does not support,
+  /// such as a type whose size is not a constant.
+  Unsupported(Location, &'static str),
+  /// The program ran for longer than the [`fuel`](Interpreter::fuel) permits.
+  OutOfFuel(Location),
+  /// The program used more memory than the [`LIMIT`](Memory::LIMIT) permits.
+  OutOfMemory(Location),
+}
+
+type Result<T> = std::result::Result<T, InterpError>;
+
+fn to_usize(n: u64) -> usize { n.try_into().expect("overflow") }
+fn to_i64<T: TryInto<i64>>(n: T) -> i64 where T::Error: std::fmt::Debug {
+  n.try_into().expect("overflow")
+}
+
+/// The memory of an interpreted program, as a collection of disjoint allocations.
+#[derive(Debug, Default)]
+pub struct Memory {
+  allocs: BTreeMap<u64, Vec<u8>>,
+  next: u64,
+  used: u64,
+}
+
+impl Memory {
+  /// The lowest address handed out, so that small integers are never valid pointers.
+  const START: u64 = 0x1000;
+  /// The end of the address space, which (as on x86-64 Linux) fits in 47 bits.
+  const END: u64 = 1 << 47;
+
+  /// The maximum total size of the live allocations.
+  pub const LIMIT: u64 = 1 << 30;
+
+  /// Allocate a zeroed region of `len` bytes, returning its address,
+  /// or `None` if this would exceed the [`LIMIT`](Self::LIMIT).
+  pub fn alloc(&mut self, len: u64) -> Option<u64> {
+    let used = self.used.checked_add(len).filter(|&n| n <= Self::LIMIT)?;
+    let addr = self.next.max(Self::START);
+    // Leave a gap after each allocation so that overruns are detected
+    self.next = addr.checked_add((len + 31) & !15).filter(|&next| next <= Self::END)?;
+    self.used = used;
+    self.allocs.insert(addr, vec![0; to_usize(len)]);
+    Some(addr)
+  }
+
+  /// Allocate a region holding a copy of `data`, returning its address,
+  /// or `None` if this would exceed the [`LIMIT`](Self::LIMIT).
+  pub fn alloc_bytes(&mut self, data: &[u8]) -> Option<u64> {
+    let addr = self.alloc(data.len() as u64)?;
+    self.allocs.get_mut(&addr).expect("just allocated").copy_from_slice(data);
+    Some(addr)
+  }
+
+  /// Free the allocation starting at `addr`.
+  pub fn free(&mut self, addr: u64) {
+    if let Some(data) = self.allocs.remove(&addr) { self.used -= data.len() as u64 }
+  }
+
+  fn locate(&self, addr: u64, len: u64) -> Option<(u64, usize)> {
+    let (&base, data) = self.allocs.range(..=addr).next_back()?;
+    let off = addr - base;
+    if off.checked_add(len)? <= data.len() as u64 { Some((base, to_usize(off))) } else { None }
+  }
+
+  /// Read `len` bytes at `addr`, or `None` if this is not an accessible region.
+  #[must_use] pub fn read(&self, addr: u64, len: u64) -> Option<&[u8]> {
+    if len == 0 { return Some(&[]) }
+    let (base, off) = self.locate(addr, len)?;
+    Some(&self.allocs[&base][off..off + to_usize(len)])
+  }
+
+  /// Write `data` at `addr`, returning `None` if this is not an accessible region.
+  pub fn write(&mut self, addr: u64, data: &[u8]) -> Option<()> {
+    if data.is_empty() { return Some(()) }
+    let (base, off) = self.locate(addr, data.len() as u64)?;
+    self.allocs.get_mut(&base)?[off..off + data.len()].copy_from_slice(data);
+    Some(())
+  }
+
+  /// Read a null-terminated string at `addr` (not including the terminator).
+  #[must_use] pub fn read_cstr(&self, addr: u64) -> Option<&[u8]> {
+    let (base, off) = self.locate(addr, 1)?;
+    let data = &self.allocs[&base][off..];
+    Some(&data[..data.iter().position(|&c| c == 0)?])
+  }
+}
+
+/// The `ENOENT` error code, returned when opening a file that does not exist.
+const ENOENT: i64 = 2;
+/// The `EBADF` error code, returned when using a bad file descriptor.
+const EBADF: i64 = 9;
+/// The `ENOMEM` error code, returned by `mmap` when the memory limit is exceeded.
+const ENOMEM: i64 = 12;
+/// The `EINVAL` error code, returned by `mmap` on a zero length mapping.
+const EINVAL: i64 = 22;
+
+#[derive(Clone, Debug)]
+struct OpenFile {
+  path: Vec<u8>,
+  pos: usize,
+  write: bool,
+}
+
+/// An in-memory file system, which services the system calls of the interpreted program.
+///
+/// File descriptors `0`, `1` and `2` are the standard streams, and files opened by the
+/// program are given descriptors starting at `3`.
+#[derive(Clone, Debug, Default)]
+pub struct FileSystem {
+  /// The contents of the files, indexed by path.
+  pub files: HashMap<Vec<u8>, Vec<u8>>,
+  /// The data that will be read from standard input.
+  pub stdin: Vec<u8>,
+  /// The data that has been written to standard output.
+  pub stdout: Vec<u8>,
+  /// The data that has been written to standard error.
+  pub stderr: Vec<u8>,
+  stdin_pos: usize,
+  fds: Vec<Option<OpenFile>>,
+}
+
+impl FileSystem {
+  fn get(&self, fd: u64) -> Option<&OpenFile> {
+    self.fds.get(to_usize(fd.checked_sub(3)?))?.as_ref()
+  }
+
+  /// The contents of the file referred to by `fd`, if it is an open file.
+  #[must_use] pub fn contents(&self, fd: u64) -> Option<&[u8]> {
+    Some(&self.files[&self.get(fd)?.path])
+  }
+
+  /// Open the file at `path`, returning a file descriptor or a negated error code.
+  /// If `create` is true, the file is opened for writing, and created or truncated.
+  pub fn open(&mut self, path: &[u8], create: bool) -> i64 {
+    if create {
+      self.files.insert(path.into(), vec![]);
+    } else if !self.files.contains_key(path) {
+      return -ENOENT
+    }
+    let file = Some(OpenFile { path: path.into(), pos: 0, write: create });
+    let i = if let Some(i) = self.fds.iter().position(Option::is_none) {
+      self.fds[i] = file;
+      i
+    } else {
+      self.fds.push(file);
+      self.fds.len() - 1
+    };
+    to_i64(i + 3)
+  }
+
+  /// Read up to `len` bytes from `fd`, or return a negated error code.
+  pub fn read(&mut self, fd: u64, len: u64) -> std::result::Result<&[u8], i64> {
+    let (data, pos) = match fd {
+      0 => (&self.stdin, &mut self.stdin_pos),
+      _ => match fd.checked_sub(3).and_then(|i| self.fds.get_mut(to_usize(i))) {
+        Some(Some(OpenFile { path, pos, write: false })) => (&self.files[&*path], pos),
+        _ => return Err(-EBADF)
+      }
+    };
+    let start = (*pos).min(data.len());
+    let end = data.len().min(start.saturating_add(len.try_into().unwrap_or(usize::MAX)));
+    *pos = end;
+    Ok(&data[start..end])
+  }
+
+  /// Write `data` to `fd`, returning the number of bytes written or a negated error code.
+  pub fn write(&mut self, fd: u64, data: &[u8]) -> i64 {
+    let out = match fd {
+      1 => &mut self.stdout,
+      2 => &mut self.stderr,
+      _ => match fd.checked_sub(3).and_then(|i| self.fds.get_mut(to_usize(i))) {
+        Some(Some(OpenFile { path, pos, write: true })) => {
+          let file = self.files.get_mut(&*path).expect("open file");
+          file.truncate(*pos);
+          *pos += data.len();
+          file
+        }
+        _ => return -EBADF
+      }
+    };
+    out.extend_from_slice(data);
+    to_i64(data.len())
+  }
+}
+
+/// A value computed while checking ghost annotations.
+#[derive(Debug, PartialEq, Eq)]
+enum Pure { Bool(bool), Int(BigInt) }
+
+impl Pure {
+  /// Returns false if the two values are known to be different.
+  fn agrees(&self, other: &Self) -> bool {
+    match (self, other) {
+      (Pure::Bool(_), Pure::Bool(_)) | (Pure::Int(_), Pure::Int(_)) => self == other,
+      _ => true,
+    }
+  }
+}
+
+fn peel(ty: &TyKind) -> &TyKind {
+  match ty {
+    TyKind::Sn(_, ty) |
+    TyKind::Uninit(ty) |
+    TyKind::Moved(ty) |
+    TyKind::All(_, _, ty) => peel(ty),
+    _ => ty
+  }
+}
+
+/// Interpret `bytes` (in little endian) as a value of type `ity`.
+fn decode(ity: IntTy, bytes: &[u8]) -> BigInt {
+  let mut buf = [0; 8];
+  buf[..bytes.len()].copy_from_slice(bytes);
+  let n = u64::from_le_bytes(buf);
+  match ity {
+    IntTy::Int(sz) => {
+      let shift = 64 - u32::from(sz.bits0());
+      #[allow(clippy::cast_possible_wrap)]
+      ((n << shift) as i64 >> shift).into()
+    }
+    IntTy::UInt(_) => n.into(),
+  }
+}
+
+/// Truncate `n` to `ity`, and write it as `size` bytes.
+fn encode(ity: IntTy, n: &BigInt, size: u64) -> Vec<u8> {
+  let n = types::Unop::As(ity).apply_int(n).expect("is a truncation");
+  let mut out = ity.zero_extend_as_u64(&n).expect("truncated").to_le_bytes().to_vec();
+  out.resize(to_usize(size), 0);
+  out
+}
+
+/// Find the type of `v` in the context of block `tgt` of `proc`.
+fn ctx_lookup(proc: &Proc, at: Location, tgt: BlockId, v: VarId) -> Result<&ExprTy> {
+  proc.body[tgt].ctx_rev_iter(&proc.body.ctxs).find(|p| p.0 == v).map(|p| p.2)
+    .ok_or(InterpError::Unsupported(at, "variable not in context"))
+}
+
+/// The `reach`, `tgt` and `rets` fields of a [`Terminator::Call`].
+type CallCont = (bool, BlockId, Box<[(bool, VarId)]>);
+
+/// The state of a procedure call in progress.
+#[derive(Debug)]
+struct Frame {
+  /// The procedure being executed.
+  proc: Rc<Proc>,
+  /// The current basic block.
+  block: BlockId,
+  /// The address and type of each (relevant) variable in scope.
+  vars: HashMap<VarId, (u64, Ty)>,
+  /// The storage owned by each variable, with its size, which can be reused
+  /// when the variable is reassigned.
+  slots: HashMap<VarId, (u64, u64)>,
+  /// The allocations made by this frame, which are freed on return.
+  owned: Vec<u64>,
+  /// If this frame is waiting on a call, the `reach`, `tgt` and `rets` fields of the call.
+  cont: Option<CallCont>,
+}
+
+impl Frame {
+  fn new(proc: Rc<Proc>) -> Self {
+    Self {
+      proc, block: BlockId::ENTRY,
+      vars: HashMap::new(), slots: HashMap::new(), owned: vec![], cont: None
+    }
+  }
+}
+
+/// The result of executing a terminator.
+enum Step {
+  /// Continue executing the current frame (at a new block).
+  Next,
+  /// Push a new frame for a procedure call.
+  Call(Frame),
+  /// Return from the current frame with the given values.
+  Return(Vec<Option<Vec<u8>>>),
+  /// The program exited.
+  Exit,
+}
+
+/// The MIR interpreter. See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct Interpreter<'a> {
+  names: &'a HashMap<Symbol, Entity>,
+  mir: &'a HashMap<Symbol, Proc>,
+  procs: HashMap<GenericCall, Rc<Proc>>,
+  stack: Vec<Frame>,
+  /// The memory of the program.
+  pub mem: Memory,
+  /// The file system and standard streams available to the program.
+  pub fs: FileSystem,
+  /// The number of statements and terminators that can be executed before giving up
+  /// with [`InterpError::OutOfFuel`].
+  pub fuel: u64,
+}
+
+impl<'a> Interpreter<'a> {
+  /// The default value of [`fuel`](Self::fuel).
+  pub const DEFAULT_FUEL: u64 = 10_000_000;
+
+  /// Construct a new interpreter for the procedures in `mir`.
+  #[must_use] pub fn new(names: &'a HashMap<Symbol, Entity>, mir: &'a HashMap<Symbol, Proc>) -> Self {
+    Self {
+      names, mir,
+      procs: HashMap::new(),
+      stack: vec![],
+      mem: Memory::default(),
+      fs: FileSystem::default(),
+      fuel: Self::DEFAULT_FUEL,
+    }
+  }
+
+  /// Run a start routine (a procedure body with no arguments or return values) until it exits.
+  pub fn run(&mut self, start: Cfg) -> Result<()> {
+    let proc = Proc {
+      kind: ProcKind::Proc,
+      inline: false,
+      name: Spanned::dummy(intern("_start")),
+      tyargs: 0,
+      args: vec![],
+      outs: Box::new([]),
+      rets: vec![],
+      variant: None,
+      body: start,
+      allocs: None,
+    };
+    self.exec(Frame::new(Rc::new(proc))).map(|_| ())
+  }
+
+  /// Call the procedure `f`, passing the byte representations of the relevant arguments
+  /// in `args`, and return the byte representations of the relevant return values.
+  /// Returns `Ok(None)` if the program exits instead of returning.
+  pub fn call(&mut self, f: Symbol, args: &[&[u8]]) -> Result<Option<Vec<Vec<u8>>>> {
+    let at = Location { proc: f, block: BlockId::ENTRY, stmt: None };
+    let proc = self.get_proc(at, f, &[])?;
+    let mut frame = Frame::new(proc.clone());
+    let mut args = args.iter();
+    for (v, r, (_, ty)) in proc.body[BlockId::ENTRY].ctx_iter(&proc.body.ctxs) {
+      if r {
+        let arg = args.next().ok_or(InterpError::Unsupported(at, "not enough arguments"))?;
+        self.bind_bytes(&mut frame, at, v, ty, arg)?;
+      }
+    }
+    Ok(self.exec(frame)?.map(|rets| rets.into_iter().flatten().collect()))
+  }
+
+  fn get_proc(&mut self, at: Location, f: Symbol, tys: &[Ty]) -> Result<Rc<Proc>> {
+    let key = (f, tys.into());
+    if let Some(proc) = self.procs.get(&key) { return Ok(proc.clone()) }
+    let proc = self.mir.get(&f).ok_or(InterpError::UnknownProc(at, f))?;
+    let proc = Rc::new(if tys.is_empty() { proc.clone() } else { proc.instantiate(self.names, f, tys) });
+    self.procs.insert(key, proc.clone());
+    Ok(proc)
+  }
+
+  /// The size of `ty`, which is checked to be small enough to allocate.
+  fn sizeof(&self, at: Location, ty: &TyKind) -> Result<u64> {
+    let size = ty.sizeof(self.names)
+      .ok_or(InterpError::Unsupported(at, "size of type is not a constant"))?;
+    if size > Memory::LIMIT { return Err(InterpError::OutOfMemory(at)) }
+    Ok(size)
+  }
+
+  fn read(&self, at: Location, addr: u64, len: u64) -> Result<&[u8]> {
+    self.mem.read(addr, len).ok_or(InterpError::BadAccess(at, addr, len))
+  }
+
+  fn write(&mut self, at: Location, addr: u64, data: &[u8]) -> Result<()> {
+    self.mem.write(addr, data).ok_or(InterpError::BadAccess(at, addr, data.len() as u64))
+  }
+
+  fn read_u64(&self, at: Location, addr: u64) -> Result<u64> {
+    Ok(u64::from_le_bytes(self.read(at, addr, 8)?.try_into().expect("impossible")))
+  }
+
+  /// Get the storage for a new value of `v` of the given size,
+  /// reusing the previous storage of `v` if possible.
+  fn slot(&mut self, f: &mut Frame, at: Location, v: VarId, size: u64) -> Result<u64> {
+    if let Some(&(addr, sz)) = f.slots.get(&v) {
+      if sz == size { return Ok(addr) }
+    }
+    let addr = self.mem.alloc(size).ok_or(InterpError::OutOfMemory(at))?;
+    f.owned.push(addr);
+    f.slots.insert(v, (addr, size));
+    Ok(addr)
+  }
+
+  fn bind_bytes(&mut self, f: &mut Frame, at: Location, v: VarId, ty: &Ty, data: &[u8]
+  ) -> Result<()> {
+    let addr = self.slot(f, at, v, data.len() as u64)?;
+    self.mem.write(addr, data).expect("just allocated");
+    f.vars.insert(v, (addr, ty.clone()));
+    Ok(())
+  }
+
+  fn var(f: &Frame, at: Location, v: VarId) -> Result<u64> {
+    match f.vars.get(&v) {
+      Some(&(addr, _)) => Ok(addr),
+      None => Err(InterpError::Unsupported(at, "use of a ghost variable")),
+    }
+  }
+
+  /// The value of `v`, if it is a relevant variable of integral or boolean type.
+  fn value(&self, f: &Frame, v: VarId) -> Option<Pure> {
+    let (addr, ty) = f.vars.get(&v)?;
+    match peel(ty) {
+      TyKind::Bool => Some(Pure::Bool(self.mem.read(*addr, 1)?[0] != 0)),
+      &TyKind::Int(ity) => {
+        let bytes = ity.size().bytes()?;
+        Some(Pure::Int(decode(ity, self.mem.read(*addr, bytes.into())?)))
+      }
+      _ => None,
+    }
+  }
+
+  fn global_const(&self, c: Symbol) -> Option<Pure> {
+    if let Some(Entity::Const(Spanned { k: ConstTc::Checked { whnf, .. }, .. })) = self.names.get(&c) {
+      match **whnf {
+        global::ExprKind::Bool(b) => return Some(Pure::Bool(b)),
+        global::ExprKind::Int(ref n) => return Some(Pure::Int(n.clone())),
+        _ => {}
+      }
+    }
+    None
+  }
+
+  /// Evaluate a pure expression, if it only depends on relevant values.
+  fn eval(&self, f: &Frame, e: &ExprKind) -> Option<Pure> {
+    use types::Unop as U;
+    match e {
+      &ExprKind::Var(v) => self.value(f, v),
+      &ExprKind::Const(c) => self.global_const(c),
+      &ExprKind::Bool(b) => Some(Pure::Bool(b)),
+      ExprKind::Int(n) => Some(Pure::Int(n.clone())),
+      ExprKind::Unop(op, e) => match (op, self.eval(f, e)?) {
+        (U::Not, Pure::Bool(b)) => Some(Pure::Bool(!b)),
+        (U::As(IntTy::UInt(Size::Inf)) | U::Not, _) | (_, Pure::Bool(_)) => None,
+        (op, Pure::Int(n)) => Some(Pure::Int(op.apply_int(&n)?.into_owned())),
+      },
+      ExprKind::Binop(op, e1, e2) => match (op.ty(), self.eval(f, e1)?, self.eval(f, e2)?) {
+        (types::BinopType::BoolBoolBool, Pure::Bool(b1), Pure::Bool(b2)) =>
+          Some(Pure::Bool(op.apply_bool_bool(b1, b2))),
+        (types::BinopType::IntIntBool, Pure::Int(n1), Pure::Int(n2)) =>
+          Some(Pure::Bool(op.apply_int_bool(&n1, &n2))),
+        (types::BinopType::IntNatInt, Pure::Int(_), Pure::Int(n2))
+          if n2 > BigInt::from(u16::MAX) => None,
+        (types::BinopType::IntIntInt | types::BinopType::IntNatInt, Pure::Int(n1), Pure::Int(n2)) =>
+          Some(Pure::Int(op.apply_int_int(&n1, &n2)?)),
+        _ => None,
+      },
+      ExprKind::If { cond, then, els } => match self.eval(f, cond)? {
+        Pure::Bool(true) => self.eval(f, then),
+        Pure::Bool(false) => self.eval(f, els),
+        Pure::Int(_) => None,
+      },
+      ExprKind::Sizeof(ty) => Some(Pure::Int(ty.sizeof(self.names)?.into())),
+      _ => None,
+    }
+  }
+
+  /// Check that the value of `v` agrees with the claims made about it by `ty` and `e`.
+  fn check(&self, f: &Frame, at: Location, v: VarId, e: Option<&Expr>, ty: &TyKind) -> Result<()> {
+    let ok = match ty {
+      TyKind::Pure(p) => self.eval(f, p) != Some(Pure::Bool(false)),
+      TyKind::Sn(e2, _) => match (self.value(f, v), self.eval(f, e2)) {
+        (Some(val), Some(val2)) => val.agrees(&val2),
+        _ => true,
+      },
+      _ => true,
+    } && match (e, self.value(f, v)) {
+      (Some(e), Some(val)) => self.eval(f, e).map_or(true, |val2| val.agrees(&val2)),
+      _ => true,
+    };
+    if ok { Ok(()) } else { Err(InterpError::GhostMismatch(at, v)) }
+  }
+
+  fn array_len(&self, f: &Frame, n: &ExprKind) -> Option<BigInt> {
+    if let Some(Pure::Int(n)) = self.eval(f, n) { Some(n) } else { None }
+  }
+
+  fn index(&self, f: &Frame, at: Location, i: VarId) -> Result<u64> {
+    match self.value(f, i) {
+      Some(Pure::Int(n)) => n.try_into().map_err(|_| InterpError::OutOfBounds(at)),
+      _ => Err(InterpError::Unsupported(at, "array index is not an integer")),
+    }
+  }
+
+  /// Get the address of a place.
+  fn place(&self, f: &Frame, at: Location, place: &Place) -> Result<u64> {
+    let mut addr = Self::var(f, at, place.local)?;
+    for (ty, proj) in &place.proj {
+      match (peel(ty), *proj) {
+        (_, Projection::Deref) => addr = self.read_u64(at, addr)?,
+        (_, Projection::Proj(ListKind::And | ListKind::Sn, _)) => {}
+        (TyKind::Array(ty, n), Projection::Proj(ListKind::Array, i)) => {
+          if self.array_len(f, n).map_or(false, |n| n <= i.into()) {
+            return Err(InterpError::OutOfBounds(at))
+          }
+          addr += self.sizeof(at, ty)? * u64::from(i)
+        }
+        (TyKind::Struct(args), Projection::Proj(ListKind::Struct, i)) => {
+          for arg in &args[..i as usize] {
+            if !arg.attr.contains(ArgAttr::GHOST) { addr += self.sizeof(at, &arg.ty)? }
+          }
+        }
+        (TyKind::Array(ty, n), Projection::Index(i, _)) => {
+          let i = self.index(f, at, i)?;
+          if self.array_len(f, n).map_or(false, |n| n <= i.into()) {
+            return Err(InterpError::OutOfBounds(at))
+          }
+          // An out of range address is caught when it is accessed
+          addr = addr.wrapping_add(self.sizeof(at, ty)?.wrapping_mul(i))
+        }
+        (TyKind::Array(ty, n), Projection::Slice(i, len, _)) => {
+          let (i, len) = (self.index(f, at, i)?, self.index(f, at, len)?);
+          if self.array_len(f, n).map_or(false, |n| n < BigInt::from(i) + len) {
+            return Err(InterpError::OutOfBounds(at))
+          }
+          // An out of range address is caught when it is accessed
+          addr = addr.wrapping_add(self.sizeof(at, ty)?.wrapping_mul(i))
+        }
+        _ => return Err(InterpError::Unsupported(at, "projection does not match the type")),
+      }
+    }
+    Ok(addr)
+  }
+
+  fn constant(&self, at: Location, c: &Constant, size: u64) -> Result<Vec<u8>> {
+    fn value(this: &Interpreter<'_>, c: &Constant) -> Option<Pure> {
+      match c.k {
+        ConstKind::Bool | ConstKind::Int => match **c.ety.0.as_ref()? {
+          ExprKind::Bool(b) => Some(Pure::Bool(b)),
+          ExprKind::Int(ref n) => Some(Pure::Int(n.clone())),
+          _ => None,
+        },
+        ConstKind::Const(s) => this.global_const(s),
+        ConstKind::As(ref c) => match value(this, &c.0)? {
+          Pure::Int(n) => Some(Pure::Int(types::Unop::As(c.1).apply_int(&n)?.into_owned())),
+          Pure::Bool(_) => None,
+        },
+        _ => None,
+      }
+    }
+    let val = match c.k {
+      ConstKind::Unit |
+      ConstKind::ITrue |
+      ConstKind::Uninit |
+      ConstKind::Mm0Proof(_) |
+      ConstKind::Contra(_, _) => 0,
+      ConstKind::Sizeof => {
+        let (sz, ty) = c.ty_as_sizeof();
+        let n = self.sizeof(at, ty)?;
+        match sz.bits() { Some(b) if b < 64 => n & ((1 << b) - 1), _ => n }
+      }
+      _ => match (value(self, c), c.ety.1.as_int_ty()) {
+        (Some(Pure::Bool(b)), _) => b.into(),
+        (Some(Pure::Int(n)), Some(ity)) => ity.zero_extend_as_u64(&n)
+          .ok_or(InterpError::Unsupported(at, "constant out of range"))?,
+        _ => return Err(InterpError::Unsupported(at, "unknown constant")),
+      }
+    };
+    let mut out = val.to_le_bytes().to_vec();
+    out.resize(to_usize(size), 0);
+    Ok(out)
+  }
+
+  fn operand(&self, f: &Frame, at: Location, o: &Operand, size: u64) -> Result<Vec<u8>> {
+    match o.place() {
+      Ok(p) => {
+        let addr = self.place(f, at, p)?;
+        Ok(self.read(at, addr, size)?.to_vec())
+      }
+      Err(c) => self.constant(at, c, size),
+    }
+  }
+
+  fn int_operand(&self, f: &Frame, at: Location, o: &Operand, ity: IntTy) -> Result<BigInt> {
+    let size = ity.size().bytes().ok_or(InterpError::Unsupported(at, "unbounded integer"))?;
+    Ok(decode(ity, &self.operand(f, at, o, size.into())?))
+  }
+
+  fn bool_operand(&self, f: &Frame, at: Location, o: &Operand) -> Result<bool> {
+    Ok(self.operand(f, at, o, 1)?[0] != 0)
+  }
+
+  fn binop(&self, f: &Frame, at: Location, size: u64,
+    op: Binop, o1: &Operand, o2: &Operand
+  ) -> Result<Vec<u8>> {
+    use types::Binop as B;
+    let nonzero = matches!(op, Binop::Div(_, true) | Binop::Mod(_, true));
+    let (op, ity) = match op.split() {
+      (op, None) => {
+        let (b1, b2) = (self.bool_operand(f, at, o1)?, self.bool_operand(f, at, o2)?);
+        return Ok(vec![op.apply_bool_bool(b1, b2).into()])
+      }
+      (op, Some(ity)) => (op, ity),
+    };
+    let n1 = self.int_operand(f, at, o1, ity)?;
+    let n = match op {
+      B::Shl | B::Shr => {
+        let n2 = self.int_operand(f, at, o2, IntTy::UInt(ity.size()))?;
+        if n2 >= ity.size().bits0().into() {
+          if op == B::Shr && n1.is_negative() { -BigInt::from(1) } else { BigInt::zero() }
+        } else {
+          op.apply_int_int(&n1, &n2).expect("int op")
+        }
+      }
+      B::Lt | B::Le | B::Eq | B::Ne => {
+        let n2 = self.int_operand(f, at, o2, ity)?;
+        return Ok(vec![op.apply_int_bool(&n1, &n2).into()])
+      }
+      _ => {
+        let n2 = self.int_operand(f, at, o2, ity)?;
+        if nonzero && n2.is_zero() { return Err(InterpError::DivByZero(at)) }
+        op.apply_int_int(&n1, &n2).expect("int op")
+      }
+    };
+    Ok(encode(ity, &n, size))
+  }
+
+  /// Evaluate `rv` and store the result, of type `ty` and size `size`, at `addr`.
+  fn rvalue(&mut self, f: &Frame, at: Location,
+    ty: &TyKind, size: u64, addr: u64, rv: &RValue
+  ) -> Result<()> {
+    let data = match rv {
+      RValue::Use(o) => self.operand(f, at, o, size)?,
+      RValue::Unop(Unop::Not, o) => vec![(!self.bool_operand(f, at, o)?).into()],
+      &RValue::Unop(Unop::Neg(ity), ref o) => encode(ity, &-self.int_operand(f, at, o, ity)?, size),
+      &RValue::Unop(Unop::BitNot(ity), ref o) => encode(ity, &!self.int_operand(f, at, o, ity)?, size),
+      &RValue::Unop(Unop::As(from, to), ref o) => {
+        // Legalization replaces unbounded operands with values of the target type
+        let from = if from.size() == Size::Inf { to } else { from };
+        encode(to, &self.int_operand(f, at, o, from)?, size)
+      }
+      &RValue::Binop(op, ref o1, ref o2) => self.binop(f, at, size, op, o1, o2)?,
+      &RValue::Eq(ref ty, invert, ref o1, ref o2) => {
+        let sz = self.sizeof(at, ty)?;
+        let eq = self.operand(f, at, o1, sz)? == self.operand(f, at, o2, sz)?;
+        vec![(eq != invert).into()]
+      }
+      RValue::Pun(_, p) => {
+        let src = self.place(f, at, p)?;
+        self.read(at, src, size)?.to_vec()
+      }
+      RValue::Cast(_, o, tyin) => match (tyin.as_int_ty(), ty.as_int_ty()) {
+        (Some(from), Some(to)) => {
+          let n = self.int_operand(f, at, o, from)?;
+          if !to.contains(&n) { return Err(InterpError::InvalidCast(at)) }
+          encode(to, &n, size)
+        }
+        _ => self.operand(f, at, o, size)?,
+      },
+      RValue::List(os) => {
+        let args = if let TyKind::Struct(args) = peel(ty) { args } else {
+          return Err(InterpError::Unsupported(at, "list of non-struct type"))
+        };
+        let mut data = Vec::with_capacity(to_usize(size));
+        for (arg, o) in args.iter().zip(&**os) {
+          if !arg.attr.contains(ArgAttr::GHOST) {
+            let sz = self.sizeof(at, &arg.ty)?;
+            if sz != 0 { data.extend(self.operand(f, at, o, sz)?) }
+          }
+        }
+        data
+      }
+      RValue::Array(os) => if let [o] = &**os {
+        self.operand(f, at, o, size)?
+      } else {
+        let sz = if let TyKind::Array(ty, _) = peel(ty) { self.sizeof(at, ty)? } else {
+          return Err(InterpError::Unsupported(at, "array of non-array type"))
+        };
+        let mut data = Vec::with_capacity(to_usize(size));
+        for o in &**os { data.extend(self.operand(f, at, o, sz)?) }
+        data
+      },
+      RValue::Borrow(p) => {
+        let mut data = self.place(f, at, p)?.to_le_bytes().to_vec();
+        data.resize(to_usize(size), 0);
+        data
+      }
+      RValue::Ghost(_) | RValue::Mm0(..) | RValue::Typeof(_) => return Ok(()),
+    };
+    self.write(at, addr, &data)
+  }
+
+  fn stmt(&mut self, f: &mut Frame, at: Location, stmt: &Statement) -> Result<()> {
+    match stmt {
+      Statement::Let(lk, r, ty, rv) => {
+        let (v, e, ty) = match lk {
+          LetKind::Let(v, e) => (*v, e.as_ref(), ty),
+          LetKind::Own([_, (v, ty)]) => (*v, None, ty),
+        };
+        if *r {
+          let addr = if let RValue::Pun(_, p) = rv {
+            self.place(f, at, p)?
+          } else {
+            let size = self.sizeof(at, ty)?;
+            let addr = self.slot(f, at, v, size)?;
+            self.rvalue(f, at, ty, size, addr, rv)?;
+            addr
+          };
+          f.vars.insert(v, (addr, ty.clone()));
+        }
+        self.check(f, at, v, e, ty)
+      }
+      Statement::Assign(lhs, ty, o, renames) => {
+        if stmt.relevant() {
+          let size = self.sizeof(at, ty)?;
+          let data = self.operand(f, at, o, size)?;
+          // The old values of the renamed variables are preserved, and the new names
+          // take over the storage that is being modified
+          for r in &**renames {
+            if !r.rel { continue }
+            if let Some((addr, ty)) = f.vars.get(&r.from).cloned() {
+              let size = self.sizeof(at, &ty)?;
+              let old = self.read(at, addr, size)?.to_vec();
+              if let Some(slot) = f.slots.remove(&r.from) { f.slots.insert(r.to, slot); }
+              f.vars.insert(r.to, (addr, r.ety.1.clone()));
+              self.bind_bytes(f, at, r.from, &ty, &old)?;
+            }
+          }
+          let addr = self.place(f, at, lhs)?;
+          self.write(at, addr, &data)?;
+        }
+        for r in &**renames { self.check(f, at, r.to, r.ety.0.as_ref(), &r.ety.1)? }
+        Ok(())
+      }
+      Statement::LabelGroup(..) | Statement::PopLabelGroup |
+      Statement::DominatedBlock(..) | Statement::Span(_) => Ok(())
+    }
+  }
+
+  /// Evaluate the relevant `args` of a jump or call to block `tgt` of `proc`, returning the
+  /// values to bind to the variables of the context of the target block.
+  fn eval_args<'b>(&self, f: &Frame, at: Location, proc: &'b Proc, tgt: BlockId,
+    args: impl Iterator<Item=(VarId, &'b Operand)>
+  ) -> Result<Vec<(VarId, &'b ExprTy, Vec<u8>)>> where 'a: 'b {
+    let mut vals = vec![];
+    for (v, o) in args {
+      let ety = ctx_lookup(proc, at, tgt, v)?;
+      let size = self.sizeof(at, &ety.1)?;
+      vals.push((v, ety, self.operand(f, at, o, size)?));
+    }
+    Ok(vals)
+  }
+
+  /// Bind the values computed by [`eval_args`](Self::eval_args), and check their types.
+  fn bind_args(&mut self, f: &mut Frame, at: Location,
+    vals: &[(VarId, &ExprTy, Vec<u8>)]
+  ) -> Result<()> {
+    for (v, (_, ty), data) in vals { self.bind_bytes(f, at, *v, ty, data)? }
+    for (v, (e, ty), _) in vals { self.check(f, at, *v, e.as_ref(), ty)? }
+    Ok(())
+  }
+
+  /// Bind the return values `vals` of a call to the variables of block `tgt` of frame `f`.
+  fn bind_rets(&mut self, f: &mut Frame, at: Location, tgt: BlockId,
+    vals: impl Iterator<Item=(VarId, Vec<u8>)>
+  ) -> Result<()> {
+    let proc = f.proc.clone();
+    let vals = vals.map(|(v, data)| Ok((v, ctx_lookup(&proc, at, tgt, v)?, data)))
+      .collect::<Result<Vec<_>>>()?;
+    self.bind_args(f, at, &vals)
+  }
+
+  fn intrinsic(&mut self, f: &Frame, at: Location,
+    intrinsic: IntrinsicProc, args: &[(bool, Operand)]
+  ) -> Result<i64> {
+    let u32_arg = |this: &Self, o| -> Result<u64> {
+      Ok(u32::from_le_bytes(this.operand(f, at, o, 4)?.try_into().expect("impossible")).into())
+    };
+    let u64_arg = |this: &Self, o| -> Result<u64> {
+      Ok(u64::from_le_bytes(this.operand(f, at, o, 8)?.try_into().expect("impossible")))
+    };
+    Ok(match (intrinsic, args) {
+      (IntrinsicProc::Open | IntrinsicProc::Create, [(_, fname)]) => {
+        let p = u64_arg(self, fname)?;
+        let path = self.mem.read_cstr(p).ok_or(InterpError::BadAccess(at, p, 1))?.to_vec();
+        self.fs.open(&path, matches!(intrinsic, IntrinsicProc::Create))
+      }
+      (IntrinsicProc::Read, [(_, fd), (_, count), _, (_, p)]) => {
+        let (fd, count, p) = (u32_arg(self, fd)?, u32_arg(self, count)?, u64_arg(self, p)?);
+        self.read(at, p, count)?;
+        match self.fs.read(fd, count) {
+          Ok(data) => {
+            let len = to_i64(data.len());
+            self.mem.write(p, data).expect("checked");
+            len
+          }
+          Err(e) => e,
+        }
+      }
+      (IntrinsicProc::Write, [(_, fd), (_, count), _, (_, p)]) => {
+        let (fd, count, p) = (u32_arg(self, fd)?, u32_arg(self, count)?, u64_arg(self, p)?);
+        let data = self.read(at, p, count)?.to_vec();
+        self.fs.write(fd, &data)
+      }
+      (IntrinsicProc::FStat, [(_, fd), _, (_, p)]) => {
+        let (fd, p) = (u32_arg(self, fd)?, u64_arg(self, p)?);
+        match self.fs.contents(fd) {
+          Some(data) => {
+            let mut stat = [0; 120];
+            // st_mode = S_IFREG | 0o644
+            stat[24..28].copy_from_slice(&0o100_644_u32.to_le_bytes());
+            stat[48..56].copy_from_slice(&(data.len() as u64).to_le_bytes());
+            self.write(at, p, &stat)?;
+            0
+          }
+          None => -EBADF,
+        }
+      }
+      (IntrinsicProc::MMap, [(_, len), _, (_, fd)]) => {
+        let (len, fd) = (u64_arg(self, len)?, u32_arg(self, fd)?);
+        match self.fs.contents(fd) {
+          _ if len == 0 => -EINVAL,
+          Some(data) => match self.mem.alloc(len) {
+            Some(addr) => {
+              let data = &data[..data.len().min(to_usize(len))];
+              self.mem.write(addr, data).expect("just allocated");
+              to_i64(addr)
+            }
+            None => -ENOMEM,
+          },
+          None => -EBADF,
+        }
+      }
+      (IntrinsicProc::MMapAnon, [(_, len), _]) => match u64_arg(self, len)? {
+        0 => -EINVAL,
+        len => self.mem.alloc(len).map_or(-ENOMEM, to_i64),
+      }
+      _ => return Err(InterpError::Unsupported(at, "intrinsic has the wrong number of arguments"))
+    })
+  }
+
+  fn terminator(&mut self, f: &mut Frame, at: Location, term: &Terminator) -> Result<Step> {
+    match term {
+      Terminator::Jump(tgt, args, _) => {
+        let proc = f.proc.clone();
+        let vals = self.eval_args(f, at, &proc, *tgt,
+          args.iter().filter(|p| p.1).map(|(v, _, o)| (*v, o)))?;
+        self.bind_args(f, at, &vals)?;
+        f.block = *tgt;
+        Ok(Step::Next)
+      }
+      &Terminator::Jump1(tgt) => { f.block = tgt; Ok(Step::Next) }
+      Terminator::Return(_, args) => {
+        let rets = &f.proc.rets;
+        let mut vals = vec![];
+        for (i, (_, r, o)) in args.iter().enumerate() {
+          vals.push(match rets.get(i) {
+            Some(ret) if *r && !ret.attr.contains(ArgAttr::GHOST) => {
+              let size = self.sizeof(at, &ret.ty)?;
+              Some(self.operand(f, at, o, size)?)
+            }
+            _ => None,
+          })
+        }
+        Ok(Step::Return(vals))
+      }
+      Terminator::Unreachable(_) | Terminator::Dead => Err(InterpError::Unreachable(at)),
+      Terminator::If(o, [(_, bl1), (_, bl2)]) => {
+        f.block = if self.bool_operand(f, at, o)? { *bl1 } else { *bl2 };
+        Ok(Step::Next)
+      }
+      &Terminator::Assert(ref o, _, reach, bl) => {
+        if !self.bool_operand(f, at, o)? { return Err(InterpError::AssertFailed(at)) }
+        if !reach { return Err(InterpError::Unreachable(at)) }
+        f.block = bl;
+        Ok(Step::Next)
+      }
+      &Terminator::Call { f: func, ref tys, ref args, reach, tgt, ref rets, .. } => {
+        if let Some(Entity::Proc(Spanned {
+          k: ProcTc::Typed(ProcTy { intrinsic: Some(intrinsic), .. }), ..
+        })) = self.names.get(&func) {
+          let ret = self.intrinsic(f, at, *intrinsic, args)?;
+          if !reach { return Err(InterpError::Unreachable(at)) }
+          #[allow(clippy::cast_sign_loss)]
+          let ret = (ret as u64).to_le_bytes();
+          if let Some(&(true, v)) = rets.last() {
+            let size = self.sizeof(at, &ctx_lookup(&f.proc, at, tgt, v)?.1)?;
+            self.bind_rets(f, at, tgt, std::iter::once((v, ret[..to_usize(size)].to_vec())))?;
+          }
+          f.block = tgt;
+          return Ok(Step::Next)
+        }
+        let callee = self.get_proc(at, func, tys)?;
+        let mut g = Frame::new(callee.clone());
+        let entry = callee.body[BlockId::ENTRY].ctx_iter(&callee.body.ctxs);
+        let vals = self.eval_args(f, at, &callee, BlockId::ENTRY,
+          entry.zip(&**args).filter(|p| (p.0).1).map(|((v, _, _), (_, o))| (v, o)))?;
+        self.bind_args(&mut g, at, &vals)?;
+        f.cont = Some((reach, tgt, rets.clone()));
+        Ok(Step::Call(g))
+      }
+      Terminator::Exit(_) => Ok(Step::Exit),
+    }
+  }
+
+  /// Run `frame` until it returns or the program exits (in which case this returns `Ok(None)`).
+  fn exec(&mut self, frame: Frame) -> Result<Option<Vec<Option<Vec<u8>>>>> {
+    let base = self.stack.len();
+    self.stack.push(frame);
+    let res = self.exec_stack(base);
+    for f in self.stack.drain(base..) {
+      for addr in f.owned { self.mem.free(addr) }
+    }
+    res
+  }
+
+  fn exec_stack(&mut self, base: usize) -> Result<Option<Vec<Option<Vec<u8>>>>> {
+    loop {
+      let mut f = self.stack.pop().expect("nonempty");
+      let proc = f.proc.clone();
+      let mut at = Location { proc: proc.name.k, block: f.block, stmt: None };
+      let bl = &proc.body[f.block];
+      for (i, stmt) in bl.stmts.iter().enumerate() {
+        at.stmt = Some(i);
+        self.fuel = self.fuel.checked_sub(1).ok_or(InterpError::OutOfFuel(at))?;
+        let res = self.stmt(&mut f, at, stmt);
+        if let Err(e) = res { self.stack.push(f); return Err(e) }
+      }
+      at.stmt = None;
+      self.fuel = self.fuel.checked_sub(1).ok_or(InterpError::OutOfFuel(at))?;
+      match self.terminator(&mut f, at, bl.terminator()) {
+        Err(e) => { self.stack.push(f); return Err(e) }
+        Ok(Step::Next) => self.stack.push(f),
+        Ok(Step::Call(g)) => { self.stack.push(f); self.stack.push(g) }
+        Ok(Step::Exit) => { self.stack.push(f); return Ok(None) }
+        Ok(Step::Return(vals)) => {
+          for addr in f.owned { self.mem.free(addr) }
+          if self.stack.len() == base { return Ok(Some(vals)) }
+          let mut caller = self.stack.pop().expect("nonempty");
+          let (reach, tgt, rets) = caller.cont.take().expect("waiting on call");
+          let at = Location { proc: caller.proc.name.k, block: caller.block, stmt: None };
+          let bound = if reach {
+            let vals = vals.into_iter().zip(&*rets)
+              .filter_map(|(val, &(r, v))| Some((v, val.filter(|_| r)?)));
+            self.bind_rets(&mut caller, at, tgt, vals)
+          } else { Err(InterpError::Unreachable(at)) };
+          caller.block = tgt;
+          self.stack.push(caller);
+          bound?
+        }
+      }
+    }
+  }
+}
@@ -109,12 +109,16 @@ pub mod infer;
 mod nameck;
 mod build_mir;
 mod mir_opt;
+pub mod interp;
 mod symbol;
 mod build_vcode;
 pub mod arch;
 mod regalloc;
 mod linker;
 mod codegen;
+mod debug_info;
+mod object;
+mod dump;
 pub mod proof;
 
 use std::collections::HashMap;
@@ -128,7 +132,7 @@ pub use types::Idx;
 pub use symbol::{Symbol, Interner, intern, init_dense_symbol_map};
 pub use nameck::DeclarationError;
 pub use ty::{CtxPrint, CtxDisplay, DisplayCtx};
-pub use linker::{LinkedCode, TEXT_START};
+pub use linker::{LinkError, LinkedCode, TEXT_START};
 use types::{IdxVec, VarId, LambdaId, ty, ast, hir};
 
 /// Global configuration for the compiler.
@@ -208,6 +212,16 @@ pub struct Compiler<C> {
   main: Option<Symbol>,
   /// If true, some items have not been generated correctly, so compilation cannot proceed.
   has_type_errors: bool,
+  /// The call graph, restricted to calls that do not provide a variant. If
+  /// [`VERIFY_TERMINATION`](proof::VERIFY_TERMINATION) is set, this graph must be acyclic.
+  calls: HashMap<Symbol, Box<[Symbol]>>,
+  /// Procedures whose size is at most this value are inlined at every call site, even if
+  /// they are not declared `inline`. This is 0 by default, which disables automatic inlining.
+  pub inline_threshold: usize,
+  /// If true, the program will be given a proof of correctness, so typechecking rejects
+  /// operations that the x86 model used for proofs does not support, such as 16-bit integers
+  /// and signed division. This is false by default.
+  pub proofs: bool,
 }
 
 impl<C: Default> Default for Compiler<C> {
@@ -223,12 +237,49 @@ impl<C> Compiler<C> {
       init: Default::default(),
       main: None,
       has_type_errors: false,
+      calls: Default::default(),
+      inline_threshold: mir_opt::inline::INLINE_THRESHOLD,
+      proofs: false,
       config,
     })
   }
 }
 
 impl<C: Config> Compiler<C> {
+  /// Declare the signature of the given AST item, without checking the body.
+  /// Calling this on a group of items before calling [`add`](Self::add) on them
+  /// allows the items to be mutually recursive. (Errors in the signature are not reported
+  /// here; they will be reported when the item is added.)
+  pub fn declare(&mut self, item: &ast::Item, var_names: IdxVec<VarId, Symbol>) {
+    let hir_alloc = Bump::new();
+    let mut ctx = infer::InferCtx::new(&hir_alloc, &mut self.names, var_names);
+    ctx.declare_item(item);
+  }
+
+  /// Check that the calls in the given item that do not provide a variant do not close a
+  /// cycle in the call graph, pushing an error for each call that does.
+  fn check_calls(graph: &mut HashMap<Symbol, Box<[Symbol]>>,
+    ctx: &mut infer::InferCtx<'_, '_>, item: &ast::Item
+  ) {
+    let name = if let ast::ItemKind::Proc {ref name, ..} = item.k { name.k } else { return };
+    let calls = std::mem::take(&mut ctx.calls);
+    graph.insert(name, calls.iter().map(|c| c.k).collect());
+    if !proof::VERIFY_TERMINATION { return }
+    for call in calls {
+      let mut visited = std::collections::HashSet::new();
+      let mut stack = vec![call.k];
+      while let Some(f) = stack.pop() {
+        if f == name {
+          ctx.errors.push(hir::Spanned {span: call.span, k: TypeError::MissingVariant(call.k)});
+          break
+        }
+        if visited.insert(f) {
+          if let Some(gs) = graph.get(&f) { stack.extend_from_slice(gs) }
+        }
+      }
+    }
+  }
+
   /// Add the given AST item to the compiler state,
   /// performing typehecking but not code generation.
   /// This should be called repeatedly to add all top level function items,
@@ -236,9 +287,11 @@ impl<C: Config> Compiler<C> {
   pub fn add(&mut self, item: &ast::Item, var_names: IdxVec<VarId, Symbol>,
     mut ic: impl ItemContext<C>
   ) -> Result<(), C::Error> {
-    let Compiler {names, mir, init, main, has_type_errors, ..} = self;
+    let Compiler {names, mir, init, main, has_type_errors, calls, inline_threshold, proofs, ..} =
+      self;
     let hir_alloc = Bump::new();
     let mut ctx = infer::InferCtx::new(&hir_alloc, names, var_names);
+    ctx.proofs = *proofs;
     if let ast::ItemKind::Proc {kind: ast::ProcKind::Main, ref name, ..} = item.k {
       if main.is_some() {
         ctx.errors.push(hir::Spanned {span: &name.span, k: TypeError::DoubleMain});
@@ -246,7 +299,8 @@ impl<C: Config> Compiler<C> {
         *main = Some(name.k)
       }
     }
-    let item = ctx.lower_item(item);
+    let hir = ctx.lower_item(item);
+    Self::check_calls(calls, &mut ctx, item);
     if !ctx.errors.is_empty() {
       let errs = std::mem::take(&mut ctx.errors);
       let pr = ctx.print(&mut ic);
@@ -255,9 +309,12 @@ impl<C: Config> Compiler<C> {
         return Ok(())
       }
     }
-    if let Some(item) = item {
+    if let Some(item) = hir {
       if let Some(n) = build_mir::BuildMir::new(Some(&mut ctx.mvars)).build_item(mir, init, item) {
-        mir.get_mut(&n).expect("missing").optimize(names);
+        let mut proc = mir.remove(&n).expect("missing");
+        proc.inline_calls(mir, *inline_threshold);
+        proc.optimize(names);
+        mir.insert(n, proc);
       }
     }
     Ok(())
@@ -273,6 +330,26 @@ impl<C: Config> Compiler<C> {
     self.init = Default::default();
     self.main = None;
     self.has_type_errors = false;
+    self.calls = Default::default();
+  }
+
+  /// Construct an interpreter for the functions that have been added so far.
+  pub fn interpreter(&self) -> interp::Interpreter<'_> {
+    interp::Interpreter::new(&self.names, &self.mir)
+  }
+
+  /// Run the program in the MIR interpreter instead of generating code. The program reads
+  /// from and writes to the standard streams and files in `fs`. Unlike [`finish`](Self::finish),
+  /// this does not reset the compiler state.
+  pub fn interpret(&self, fs: &mut interp::FileSystem) -> Result<(), interp::InterpError> {
+    assert!(!self.has_type_errors);
+    let (mut init, _) = self.init.clone().finish(&self.mir, self.main);
+    init.optimize(&[]);
+    let mut interp = self.interpreter();
+    interp.fs = std::mem::take(fs);
+    let res = interp.run(init);
+    *fs = std::mem::take(&mut interp.fs);
+    res
   }
 
   /// Once we are done adding functions, this function performs final linking to produce an
@@ -280,15 +357,22 @@ impl<C: Config> Compiler<C> {
   /// The compiler is reset to the initial state after this operation, except for the user state
   /// [`Compiler::config`], so it can be used to compile another program but the library functions
   /// must first be loaded in again.
-  pub fn finish(&mut self) -> LinkedCode {
+  pub fn finish(&mut self) -> Result<LinkedCode, LinkError> { self.finish_with_exports(&[]) }
+
+  /// Like [`Compiler::finish`], but additionally compiles the procedures in `exports` using the
+  /// C calling convention (see [`LinkedCode::write_object`]), whether or not they are
+  /// reachable from the main function. The exported procedures must not be generic;
+  /// exporting a generic procedure or a symbol that is not a procedure is a [`LinkError`].
+  pub fn finish_with_exports(&mut self, exports: &[Symbol]) -> Result<LinkedCode, LinkError> {
     let names = std::mem::replace(&mut self.names, symbol::Interner::with(Self::make_names));
     let mir = std::mem::take(&mut self.mir);
+    self.calls = Default::default();
     assert!(!self.has_type_errors);
     // eprintln!("{:#?}", mir);
     let (mut init, globals) = std::mem::take(&mut self.init).finish(&mir, self.main.take());
     init.optimize(&[]);
     let allocs = init.storage(&names);
-    LinkedCode::link(&names, mir, init, &allocs, &globals)
+    LinkedCode::link(&names, mir, init, &allocs, &globals, exports)
   }
 }
 
@@ -299,8 +383,9 @@ mod test {
   use std::io::{self, Write};
   use crate::types::ast::{
     ArgAttr, ArgKind, Block, ExprKind, ItemKind, StmtKind, TuplePatternKind, TypeKind};
-  use crate::{Compiler, Idx, Symbol, intern};
+  use crate::{Compiler, Idx, LinkedCode, Symbol, intern};
   use crate::types::{Binop, Size, Spanned, VarId, hir::ProcKind, entity::IntrinsicProc};
+  use crate::interp::{FileSystem, InterpError};
 
   fn assert_eq_hex(test_name: &str, data: &[u8], hex: &str) {
     let mut result = String::from(hex);
@@ -326,8 +411,70 @@ mod test {
     }
   }
 
+  /// Run the executable `code`, and check that it behaves as the interpreter did: it exits
+  /// normally if `interp` is `Ok`, traps on a failed assertion, and writes `stdout`.
+  /// The generated code only runs on x86-64 Linux, so elsewhere this does nothing.
+  #[allow(unused_variables)]
+  fn assert_runs_like(test_name: &str,
+    code: &LinkedCode, interp: &Result<(), InterpError>, stdout: &[u8]
+  ) {
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))] {
+      use std::os::unix::{fs::OpenOptionsExt, process::ExitStatusExt};
+      use std::process::{Command, Stdio};
+      let path = std::env::temp_dir()
+        .join(format!("mmcc-{}-{}", test_name, std::process::id()));
+      let mut file = std::fs::OpenOptions::new()
+        .write(true).create(true).truncate(true).mode(0o755).open(&path).unwrap();
+      code.write_elf(&mut file).unwrap();
+      drop(file);
+      let out = loop {
+        match Command::new(&path).stdin(Stdio::null()).output() {
+          // ETXTBSY: a process forked by another test still has the file open for writing
+          Err(e) if e.raw_os_error() == Some(26) =>
+            std::thread::sleep(std::time::Duration::from_millis(10)),
+          out => break out.unwrap(),
+        }
+      };
+      std::fs::remove_file(&path).unwrap();
+      match interp {
+        Ok(()) => assert!(out.status.success(), "{}: {}", test_name, out.status),
+        // A failed assertion executes `ud2`, which raises SIGILL
+        Err(InterpError::AssertFailed(_)) =>
+          assert_eq!(out.status.signal(), Some(4), "{}: {}", test_name, out.status),
+        // A `div` by zero raises SIGFPE
+        Err(InterpError::DivByZero(_)) =>
+          assert_eq!(out.status.signal(), Some(8), "{}: {}", test_name, out.status),
+        Err(e) => panic!("{}: the interpreter failed: {:?}", test_name, e),
+      }
+      assert_eq!(out.stdout, stdout, "{}", test_name);
+    }
+  }
+
+  /// Link the object file produced by `code` (see [`LinkedCode::write_object`]) with the C
+  /// program `main_c` using `cc -no-pie`, and check that it succeeds and prints `stdout`.
+  fn assert_links_with_c(test_name: &str, code: &LinkedCode, main_c: &str, stdout: &[u8]) {
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))] {
+      use std::process::{Command, Stdio};
+      let dir = std::env::temp_dir()
+        .join(format!("mmcc-{}-{}", test_name, std::process::id()));
+      std::fs::create_dir_all(&dir).unwrap();
+      let mut file = std::fs::File::create(dir.join("code.o")).unwrap();
+      code.write_object(&mut file).unwrap();
+      drop(file);
+      std::fs::write(dir.join("main.c"), main_c).unwrap();
+      let cc = Command::new("cc").current_dir(&dir)
+        .args(["-no-pie", "-o", "main", "main.c", "code.o"]).output().unwrap();
+      assert!(cc.status.success(), "{}: cc failed:\n{}",
+        test_name, String::from_utf8_lossy(&cc.stderr));
+      let out = Command::new(dir.join("main")).stdin(Stdio::null()).output().unwrap();
+      std::fs::remove_dir_all(&dir).unwrap();
+      assert!(out.status.success(), "{}: {}", test_name, out.status);
+      assert_eq!(out.stdout, stdout, "{}", test_name);
+    }
+  }
+
   #[test] fn trivial_ir() {
-    use crate::{LinkedCode, mir::*};
+    use crate::mir::*;
     let names = Default::default();
     let mut cfg = Cfg::default();
     let bl = cfg.new_block(CtxId::ROOT);
@@ -337,9 +484,10 @@ mod test {
     // println!("after opt:\n{:#?}", cfg);
     let allocs = cfg.storage(&names);
     // println!("allocs = {:#?}", allocs);
-    let code = LinkedCode::link(&names, Default::default(), cfg, &allocs, &[]);
+    let code = LinkedCode::link(&names, Default::default(), cfg, &allocs, &[], &[]).unwrap();
     println!("code = {:#?}", code);
     // code.write_elf(&mut std::fs::File::create("trivial").unwrap());
+    assert_runs_like("trivial_ir", &code, &Ok(()), b"");
     let mut out = Vec::new();
     code.write_elf(&mut out).unwrap();
     assert_eq_hex("trivial_ir", &out, "\
@@ -357,7 +505,7 @@ mod test {
 
   #[test] fn two_plus_two_ir() {
     use std::{collections::HashMap, rc::Rc};
-    use crate::{LinkedCode, types::IntTy, mir::*};
+    use crate::{types::IntTy, mir::*};
 
     let names = HashMap::new();
     let mut fresh_var = VarId::default();
@@ -400,9 +548,10 @@ mod test {
     // println!("after opt:\n{:#?}", cfg);
     let allocs = cfg.storage(&names);
     // println!("allocs = {:#?}", allocs);
-    let code = LinkedCode::link(&names, mir, cfg, &allocs, &[]);
+    let code = LinkedCode::link(&names, mir, cfg, &allocs, &[], &[]).unwrap();
     // println!("code = {:#?}", code);
     // code.write_elf(&mut File::create("two_plus_two_ir").unwrap());
+    assert_runs_like("two_plus_two_ir", &code, &Ok(()), b"");
     let mut out = Vec::new();
     code.write_elf(&mut out).unwrap();
     assert_eq_hex("two_plus_two_ir", &out, "\
@@ -412,17 +561,151 @@ mod test {
       0000 0000 4000 3800 0100 4000 0000 0000\
       0100 0000 0700 0000 7800 0000 0000 0000\
       7800 4000 0000 0000 0000 0000 0000 0000\
-      2800 0000 0000 0000 2800 0000 0000 0000\
-      0000 2000 0000 0000 ba02 0000 00b9 0200\
-      0000 02d1 80fa 0441 0f94 c041 80f8 0075\
-      020f 0bb8 3c00 0000 33ff 0f05 0000 0000\
+      1800 0000 0000 0000 1800 0000 0000 0000\
+      0000 2000 0000 0000 b83c 0000 0033 ff0f\
+      0500 0000 0000 0000 0000 0000 0000 0000\
     ");
   }
 
+  #[test] fn const_branch_ir() {
+    use std::{collections::HashMap, rc::Rc};
+    use crate::{types::IntTy, mir::*};
+
+    // let x: u8 = 3; if x < 2 {h. exit} else {h'. exit}
+    let mut fresh_var = VarId::default();
+    let u8 = IntTy::UInt(Size::S8);
+    let mut cfg = Cfg::default();
+    let bl1 = cfg.new_block(CtxId::ROOT);
+    let x = fresh_var.fresh();
+    cfg[bl1].stmts.push(Statement::Let(
+      LetKind::Let(x, None), true,
+      Rc::new(TyKind::Int(u8)),
+      Constant::int(u8, 3.into()).into()));
+    let lt = fresh_var.fresh();
+    cfg[bl1].stmts.push(Statement::Let(
+      LetKind::Let(lt, None), true,
+      Rc::new(TyKind::Bool),
+      RValue::Binop(Binop::Lt(u8),
+        Operand::Copy(Place::local(x)),
+        Constant::int(u8, 2.into()).into())));
+    let hyp = Rc::new(TyKind::Pure(Rc::new(ExprKind::Var(lt))));
+    let [y1, y2] = [(); 2].map(|_| fresh_var.fresh());
+    let bl2ctx = cfg.ctxs.extend(CtxId::ROOT, y1, true, (None, hyp.clone()));
+    let bl3ctx = cfg.ctxs.extend(CtxId::ROOT, y2, true, (None, Rc::new(TyKind::Not(hyp))));
+    let [bl2, bl3] = [bl2ctx, bl3ctx].map(|ctx| {
+      let bl = cfg.new_block(ctx);
+      cfg[bl].terminate(Terminator::Exit(Constant::unit().into()));
+      bl
+    });
+    cfg[bl1].terminate(Terminator::If(lt.into(), [(y1, bl2), (y2, bl3)]));
+    cfg.optimize(&[]);
+    // The `if` is kept for the proof, but its condition is known
+    assert!(matches!(cfg[bl1].terminator(),
+      Terminator::If(Operand::Const(c), _) if c.as_bool() == Some(false)));
+    // and the branch that is not taken is kept only for the proof
+    assert!(!cfg[bl2].reachable && cfg[bl3].reachable);
+    assert!(cfg[bl1].stmts.iter().all(|s| !s.relevant()));
+    // and only the branch that is taken is compiled
+    let names = HashMap::new();
+    let allocs = cfg.storage(&names);
+    let code = LinkedCode::link(&names, HashMap::new(), cfg, &allocs, &[], &[]).unwrap();
+    let mut out = Vec::new();
+    code.write_asm(&mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "\
+; .text
+
+_start:
+.bb0:
+.bb1:
+  400078:  mov eax, 0x3c
+  40007d:  xor edi, edi
+  40007f:  syscall
+");
+  }
+
+  #[test] fn interp_assert_ir() {
+    use std::{collections::HashMap, rc::Rc};
+    use crate::{interp::Interpreter, types::IntTy, mir::*};
+
+    // let x: u8 = 2 + 3; assert(x = 4)
+    let names = HashMap::new();
+    let mir = HashMap::new();
+    let mut fresh_var = VarId::default();
+    let u8 = IntTy::UInt(Size::S8);
+    let mut cfg = Cfg::default();
+    let bl1 = cfg.new_block(CtxId::ROOT);
+    let x = fresh_var.fresh();
+    cfg[bl1].stmts.push(Statement::Let(
+      LetKind::Let(x, None), true,
+      Rc::new(TyKind::Int(u8)),
+      RValue::Binop(Binop::Add(u8),
+        Constant::int(u8, 2.into()).into(),
+        Constant::int(u8, 3.into()).into())));
+    let eq = fresh_var.fresh();
+    cfg[bl1].stmts.push(Statement::Let(
+      LetKind::Let(eq, None), true,
+      Rc::new(TyKind::Bool),
+      RValue::Binop(Binop::Eq(u8),
+        Operand::Copy(Place::local(x)),
+        Constant::int(u8, 4.into()).into())));
+    let y = fresh_var.fresh();
+    let bl2ctx = cfg.ctxs.extend(CtxId::ROOT, y, true, (None,
+      Rc::new(TyKind::Pure(Rc::new(ExprKind::Var(eq))))));
+    let bl2 = cfg.new_block(bl2ctx);
+    cfg[bl1].terminate(Terminator::Assert(eq.into(), y, true, bl2));
+    cfg[bl2].terminate(Terminator::Exit(Constant::unit().into()));
+    cfg.optimize(&[]);
+    let err = Interpreter::new(&names, &mir).run(cfg).unwrap_err();
+    assert!(matches!(err, InterpError::AssertFailed(at) if at.block == bl1));
+  }
+
+  #[test] fn interp_div_by_zero_ir() {
+    use std::{collections::HashMap, rc::Rc};
+    use crate::{interp::Interpreter, types::IntTy, mir::*};
+
+    // let x: u32 = 7 // (0 h), where h is a false proof that 0 != 0
+    let names = HashMap::new();
+    let mir = HashMap::new();
+    let mut fresh_var = VarId::default();
+    let u32 = IntTy::UInt(Size::S32);
+    let mut div = |nonzero| {
+      let mut cfg = Cfg::default();
+      let bl = cfg.new_block(CtxId::ROOT);
+      cfg[bl].stmts.push(Statement::Let(
+        LetKind::Let(fresh_var.fresh(), None), true,
+        Rc::new(TyKind::Int(u32)),
+        RValue::Binop(Binop::Div(u32, nonzero),
+          Constant::int(u32, 7.into()).into(),
+          Constant::int(u32, 0.into()).into())));
+      cfg[bl].terminate(Terminator::Exit(Constant::unit().into()));
+      (cfg, bl)
+    };
+    // Without the proof, division by zero is defined
+    let (cfg, _) = div(false);
+    Interpreter::new(&names, &mir).run(cfg).unwrap();
+    // With it, the interpreter reports the false hypothesis
+    let (cfg, bl) = div(true);
+    let err = Interpreter::new(&names, &mir).run(cfg).unwrap_err();
+    assert!(matches!(err, InterpError::DivByZero(at) if at.block == bl));
+  }
+
+  #[test] fn interp_memory_limit() {
+    use crate::interp::Memory;
+    let mut mem = Memory::default();
+    assert_eq!(mem.alloc(Memory::LIMIT + 1), None);
+    assert_eq!(mem.alloc(u64::MAX), None);
+    let addr = mem.alloc(Memory::LIMIT).unwrap();
+    assert_eq!(mem.alloc(1), None);
+    mem.free(addr);
+    let addr = mem.alloc_bytes(b"abc").unwrap();
+    assert_eq!(mem.read(addr, 3), Some(&b"abc"[..]));
+  }
+
   #[test] fn two_plus_two() {
     let mut compiler = Compiler::new(());
     let main = Spanned::dummy(ItemKind::Proc {
       intrinsic: None,
+      inline: false,
       kind: ProcKind::Main,
       name: Spanned::dummy(intern("main")),
       tyargs: 0,
@@ -447,9 +730,11 @@ mod test {
       },
     });
     compiler.add(&main, Default::default(), ()).unwrap();
-    let code = compiler.finish();
+    compiler.interpret(&mut Default::default()).unwrap();
+    let code = compiler.finish().unwrap();
     // println!("code = {:#?}", code);
     // code.write_elf(&mut File::create("two_plus_two").unwrap());
+    assert_runs_like("two_plus_two", &code, &Ok(()), b"");
     let mut out = Vec::new();
     code.write_elf(&mut out).unwrap();
     assert_eq_hex("two_plus_two", &out, "\
@@ -459,11 +744,10 @@ mod test {
       0000 0000 4000 3800 0100 4000 0000 0000\
       0100 0000 0700 0000 7800 0000 0000 0000\
       7800 4000 0000 0000 0000 0000 0000 0000\
-      3800 0000 0000 0000 3800 0000 0000 0000\
+      2800 0000 0000 0000 2800 0000 0000 0000\
       0000 2000 0000 0000 e813 0000 00b8 3c00\
       0000 33ff 0f05 0000 0000 0000 0000 0000\
-      ba02 0000 00b9 0200 0000 02d1 b904 0000\
-      003a d10f 94c0 3c00 7502 0f0b c300 0000\
+      c300 0000 0000 0000 0000 0000 0000 0000\
     ");
   }
 
@@ -481,6 +765,7 @@ mod test {
     compiler.add(
       &Spanned::dummy(ItemKind::Proc {
         intrinsic: Some(IntrinsicProc::Write),
+        inline: false,
         kind: ProcKind::Proc,
         name: Spanned::dummy(write),
         tyargs: 0,
@@ -535,6 +820,7 @@ mod test {
     compiler.add(
       &Spanned::dummy(ItemKind::Proc {
         intrinsic: None,
+        inline: false,
         kind: ProcKind::Main,
         name: Spanned::dummy(intern("main")),
         tyargs: 0,
@@ -572,9 +858,13 @@ mod test {
         },
       }),
       Default::default(), ()).unwrap();
-    let code = compiler.finish();
+    let mut fs = FileSystem::default();
+    compiler.interpret(&mut fs).unwrap();
+    assert_eq!(fs.stdout, hello);
+    let code = compiler.finish().unwrap();
     // println!("code = {:#?}", code);
     // code.write_elf(&mut File::create("hello_world").unwrap());
+    assert_runs_like("hello_world", &code, &Ok(()), hello);
     let mut out = Vec::new();
     code.write_elf(&mut out).unwrap();
     assert_eq_hex("hello_world", &out, "\
@@ -599,4 +889,1198 @@ mod test {
       c40b c300 0000 0000 0000 0000 0000 0000\
     ");
   }
+
+  #[test] fn mutual_recursion() {
+    use crate::{ItemContext, infer::TypeError, types::hir};
+    struct AllowMissingVariant(usize);
+    impl ItemContext<()> for &mut AllowMissingVariant {
+      type Printer = ();
+      fn print(&mut self) {}
+      fn emit_type_errors<'a>(&mut self, _: &mut (),
+        errs: Vec<hir::Spanned<'a, TypeError<'a>>>,
+        _: &impl crate::DisplayCtx<'a>,
+      ) -> Result<bool, std::convert::Infallible> {
+        for err in errs {
+          assert!(matches!(err.k, TypeError::MissingVariant(_)));
+          self.0 += 1;
+        }
+        Ok(true)
+      }
+    }
+
+    let mut compiler = Compiler::new(());
+    let [f, g] = [intern("f"), intern("g")];
+    // proc f(n: u8) { g(n) }
+    // proc g(n: u8) { f(n) }
+    let procs = [(f, g), (g, f)].map(|(name, callee)| {
+      let n = VarId::default();
+      Spanned::dummy(ItemKind::Proc {
+        intrinsic: None,
+        inline: false,
+        kind: ProcKind::Proc,
+        name: Spanned::dummy(name),
+        tyargs: 0,
+        args: Box::new([
+          Spanned::dummy((ArgAttr::empty(), ArgKind::Lam(TuplePatternKind::Typed(
+            Box::new(Spanned::dummy(TuplePatternKind::Name(false, intern("n"), n))),
+            Box::new(Spanned::dummy(TypeKind::UInt(Size::S8))),
+          )))),
+        ]),
+        outs: Box::new([]),
+        rets: Box::new([]),
+        variant: None,
+        body: Block {
+          stmts: vec![],
+          expr: Some(Box::new(Spanned::dummy(ExprKind::Call {
+            f: Spanned::dummy(callee),
+            tys: vec![],
+            args: vec![Spanned::dummy(ExprKind::Var(n))],
+            variant: None,
+          }))),
+        },
+      })
+    });
+    for item in &procs { compiler.declare(item, Default::default()) }
+    let mut errs = AllowMissingVariant(0);
+    for item in &procs { compiler.add(item, Default::default(), &mut errs).unwrap() }
+    // Only the call that closes the cycle is missing a variant
+    assert_eq!(errs.0, 1);
+
+    // main() { f(2) }
+    compiler.add(
+      &Spanned::dummy(ItemKind::Proc {
+        intrinsic: None,
+        inline: false,
+        kind: ProcKind::Main,
+        name: Spanned::dummy(intern("main")),
+        tyargs: 0,
+        args: Box::new([]),
+        outs: Box::new([]),
+        rets: Box::new([]),
+        variant: None,
+        body: Block {
+          stmts: vec![Spanned::dummy(StmtKind::Expr(ExprKind::Call {
+            f: Spanned::dummy(f),
+            tys: vec![],
+            args: vec![Spanned::dummy(ExprKind::Int(2.into()))],
+            variant: None,
+          }))],
+          expr: None,
+        },
+      }),
+      Default::default(), ()).unwrap();
+    // The program does not terminate
+    let mut interp = compiler.interpreter();
+    interp.fuel = 1000;
+    assert!(matches!(interp.call(intern("main"), &[]), Err(InterpError::OutOfFuel(_))));
+    let code = compiler.finish().unwrap();
+    // println!("code = {:#?}", code);
+    let mut out = Vec::new();
+    code.write_elf(&mut out).unwrap();
+    assert_eq_hex("mutual_recursion", &out, "\
+      7f45 4c46 0201 0100 0000 0000 0000 0000\
+      0200 3e00 0100 0000 7800 4000 0000 0000\
+      4000 0000 0000 0000 0000 0000 0000 0000\
+      0000 0000 4000 3800 0100 4000 0000 0000\
+      0100 0000 0700 0000 7800 0000 0000 0000\
+      7800 4000 0000 0000 0000 0000 0000 0000\
+      4800 0000 0000 0000 4800 0000 0000 0000\
+      0000 2000 0000 0000 e813 0000 00b8 3c00\
+      0000 33ff 0f05 0000 0000 0000 0000 0000\
+      b802 0000 00e8 0600 0000 c300 0000 0000\
+      e80b 0000 00c3 0000 0000 0000 0000 0000\
+      e8eb ffff ffc3 0000 0000 0000 0000 0000\
+    ");
+  }
+
+  #[test] fn duplicate_proc() {
+    use crate::{FileSpan, ItemContext, infer::TypeError, types::hir};
+    struct DoubleProcs(usize);
+    impl ItemContext<()> for &mut DoubleProcs {
+      type Printer = ();
+      fn print(&mut self) {}
+      fn emit_type_errors<'a>(&mut self, _: &mut (),
+        errs: Vec<hir::Spanned<'a, TypeError<'a>>>,
+        _: &impl crate::DisplayCtx<'a>,
+      ) -> Result<bool, std::convert::Infallible> {
+        for err in errs {
+          assert!(matches!(err.k, TypeError::DoubleProc(_)));
+          self.0 += 1;
+        }
+        Ok(true)
+      }
+    }
+
+    let mut compiler = Compiler::new(());
+    let f = intern("f");
+    // proc f() {}
+    // proc f() {}
+    let procs = [0, 1].map(|i| Spanned {
+      span: FileSpan {file: Default::default(), span: (i..i + 1).into()},
+      k: ItemKind::Proc {
+        intrinsic: None,
+        inline: false,
+        kind: ProcKind::Proc,
+        name: Spanned::dummy(f),
+        tyargs: 0,
+        args: Box::new([]),
+        outs: Box::new([]),
+        rets: Box::new([]),
+        variant: None,
+        body: Block { stmts: vec![], expr: None },
+      },
+    });
+    let mut errs = DoubleProcs(0);
+    // Declaring an item before adding it is not a redefinition
+    compiler.declare(&procs[0], Default::default());
+    compiler.add(&procs[0], Default::default(), &mut errs).unwrap();
+    assert_eq!(errs.0, 0);
+    compiler.add(&procs[1], Default::default(), &mut errs).unwrap();
+    assert_eq!(errs.0, 1);
+  }
+
+  #[test] fn generic_id() {
+    let mut compiler = Compiler::new(());
+    let id = intern("id");
+
+    // proc id<T>(x: T) -> T { x }
+    let mut fresh = VarId::default();
+    let x = fresh.fresh();
+    compiler.add(
+      &Spanned::dummy(ItemKind::Proc {
+        intrinsic: None,
+        inline: false,
+        kind: ProcKind::Proc,
+        name: Spanned::dummy(id),
+        tyargs: 1,
+        args: Box::new([
+          Spanned::dummy((ArgAttr::empty(), ArgKind::Lam(TuplePatternKind::Typed(
+            Box::new(Spanned::dummy(TuplePatternKind::Name(false, intern("x"), x))),
+            Box::new(Spanned::dummy(TypeKind::Var(0))),
+          )))),
+        ]),
+        outs: Box::new([]),
+        rets: Box::new([
+          Spanned::dummy(TuplePatternKind::Typed(
+            Box::new(Spanned::dummy(TuplePatternKind::Name(false, Symbol::UNDER, fresh.fresh()))),
+            Box::new(Spanned::dummy(TypeKind::Var(0))),
+          ))
+        ]),
+        variant: None,
+        body: Block { stmts: vec![], expr: Some(Box::new(Spanned::dummy(ExprKind::Var(x)))) },
+      }),
+      Default::default(), ()).unwrap();
+
+    // main() { id<u8>(1); id<u32>(2); id<u8>(3); }
+    let call = |ty, n: u32| Spanned::dummy(StmtKind::Expr(ExprKind::Call {
+      f: Spanned::dummy(id),
+      tys: vec![Spanned::dummy(ty)],
+      args: vec![Spanned::dummy(ExprKind::Int(n.into()))],
+      variant: None,
+    }));
+    compiler.add(
+      &Spanned::dummy(ItemKind::Proc {
+        intrinsic: None,
+        inline: false,
+        kind: ProcKind::Main,
+        name: Spanned::dummy(intern("main")),
+        tyargs: 0,
+        args: Box::new([]),
+        outs: Box::new([]),
+        rets: Box::new([]),
+        variant: None,
+        body: Block {
+          stmts: vec![
+            call(TypeKind::UInt(Size::S8), 1),
+            call(TypeKind::UInt(Size::S32), 2),
+            call(TypeKind::UInt(Size::S8), 3),
+          ],
+          expr: None,
+        },
+      }),
+      Default::default(), ()).unwrap();
+    compiler.interpret(&mut Default::default()).unwrap();
+    let code = compiler.finish().unwrap();
+    // println!("code = {:#?}", code);
+    assert_runs_like("generic_id", &code, &Ok(()), b"");
+    let mut out = Vec::new();
+    code.write_elf(&mut out).unwrap();
+    assert_eq_hex("generic_id", &out, "\
+      7f45 4c46 0201 0100 0000 0000 0000 0000\
+      0200 3e00 0100 0000 7800 4000 0000 0000\
+      4000 0000 0000 0000 0000 0000 0000 0000\
+      0000 0000 4000 3800 0100 4000 0000 0000\
+      0100 0000 0700 0000 7800 0000 0000 0000\
+      7800 4000 0000 0000 0000 0000 0000 0000\
+      5800 0000 0000 0000 5800 0000 0000 0000\
+      0000 2000 0000 0000 e813 0000 00b8 3c00\
+      0000 33ff 0f05 0000 0000 0000 0000 0000\
+      bf01 0000 00e8 1600 0000 bf02 0000 00e8\
+      1c00 0000 bf03 0000 00e8 0200 0000 c300\
+      488b c7c3 0000 0000 0000 0000 0000 0000\
+      488b c7c3 0000 0000 0000 0000 0000 0000\
+    ");
+  }
+
+  #[test] fn polymorphic_recursion() {
+    use crate::{ItemContext, LinkError, infer::TypeError, types::hir};
+    struct AllowMissingVariant;
+    impl ItemContext<()> for AllowMissingVariant {
+      type Printer = ();
+      fn print(&mut self) {}
+      fn emit_type_errors<'a>(&mut self, _: &mut (),
+        errs: Vec<hir::Spanned<'a, TypeError<'a>>>,
+        _: &impl crate::DisplayCtx<'a>,
+      ) -> Result<bool, std::convert::Infallible> {
+        assert!(errs.iter().all(|err| matches!(err.k, TypeError::MissingVariant(_))));
+        Ok(true)
+      }
+    }
+
+    let mut compiler = Compiler::new(());
+    let f = intern("f");
+    let proc = |name, kind, tyargs, call| Spanned::dummy(ItemKind::Proc {
+      intrinsic: None,
+      inline: false,
+      kind,
+      name: Spanned::dummy(name),
+      tyargs,
+      args: Box::new([]),
+      outs: Box::new([]),
+      rets: Box::new([]),
+      variant: None,
+      body: Block { stmts: vec![], expr: Some(Box::new(Spanned::dummy(ExprKind::Call {
+        f: Spanned::dummy(f),
+        tys: vec![Spanned::dummy(call)],
+        args: vec![],
+        variant: None,
+      }))) },
+    });
+    // proc f<T>() { f<(T, u8)>() }
+    let pair = TypeKind::List(Box::new([
+      Spanned::dummy(TypeKind::Var(0)), Spanned::dummy(TypeKind::UInt(Size::S8))]));
+    compiler.add(&proc(f, ProcKind::Proc, 1, pair), Default::default(), AllowMissingVariant)
+      .unwrap();
+    // main() { f<u8>() }
+    compiler.add(&proc(intern("main"), ProcKind::Main, 0, TypeKind::UInt(Size::S8)),
+      Default::default(), AllowMissingVariant).unwrap();
+    assert!(matches!(compiler.finish(), Err(LinkError::MonoDepth(g)) if g == f));
+  }
+
+  #[test] fn array_copy_eq() {
+    let mut compiler = Compiler::new(());
+    let eq = intern("eq");
+    let arr = || Box::new(Spanned::dummy(TypeKind::Array(
+      Box::new(Spanned::dummy(TypeKind::UInt(Size::S32))),
+      Box::new(Spanned::dummy(ExprKind::Int(5.into()))))));
+
+    // proc eq(x y: array u32 5): bool { x == y }
+    let mut fresh = VarId::default();
+    let [x, y] = [(); 2].map(|_| fresh.fresh());
+    let arg = |name, v| Spanned::dummy((ArgAttr::empty(), ArgKind::Lam(TuplePatternKind::Typed(
+      Box::new(Spanned::dummy(TuplePatternKind::Name(false, intern(name), v))), arr()))));
+    compiler.add(
+      &Spanned::dummy(ItemKind::Proc {
+        intrinsic: None,
+        inline: false,
+        kind: ProcKind::Proc,
+        name: Spanned::dummy(eq),
+        tyargs: 0,
+        args: Box::new([arg("x", x), arg("y", y)]),
+        outs: Box::new([]),
+        rets: Box::new([
+          Spanned::dummy(TuplePatternKind::Typed(
+            Box::new(Spanned::dummy(TuplePatternKind::Name(false, Symbol::UNDER, fresh.fresh()))),
+            Box::new(Spanned::dummy(TypeKind::Bool)),
+          ))
+        ]),
+        variant: None,
+        body: Block { stmts: vec![], expr: Some(Box::new(Spanned::dummy(ExprKind::Binop(Binop::Eq,
+          Box::new(Spanned::dummy(ExprKind::Var(x))),
+          Box::new(Spanned::dummy(ExprKind::Var(y))))))) },
+      }),
+      Default::default(), ()).unwrap();
+
+    // main() { let a: array u32 5 = [1, 2, 3, 4, 5]; let b = a; eq(a, b); }
+    let mut fresh = VarId::default();
+    let [a, b] = [(); 2].map(|_| fresh.fresh());
+    compiler.add(
+      &Spanned::dummy(ItemKind::Proc {
+        intrinsic: None,
+        inline: false,
+        kind: ProcKind::Main,
+        name: Spanned::dummy(intern("main")),
+        tyargs: 0,
+        args: Box::new([]),
+        outs: Box::new([]),
+        rets: Box::new([]),
+        variant: None,
+        body: Block {
+          stmts: vec![
+            Spanned::dummy(StmtKind::Let {
+              lhs: Spanned::dummy(TuplePatternKind::Typed(
+                Box::new(Spanned::dummy(TuplePatternKind::Name(false, intern("a"), a))), arr())),
+              rhs: Spanned::dummy(ExprKind::List(
+                (1..=5_u32).map(|n| Spanned::dummy(ExprKind::Int(n.into()))).collect())),
+            }),
+            Spanned::dummy(StmtKind::Let {
+              lhs: Spanned::dummy(TuplePatternKind::Name(false, intern("b"), b)),
+              rhs: Spanned::dummy(ExprKind::Var(a)),
+            }),
+            Spanned::dummy(StmtKind::Expr(ExprKind::Call {
+              f: Spanned::dummy(eq),
+              tys: vec![],
+              args: vec![Spanned::dummy(ExprKind::Var(a)), Spanned::dummy(ExprKind::Var(b))],
+              variant: None,
+            })),
+          ],
+          expr: None,
+        },
+      }),
+      Default::default(), ()).unwrap();
+    compiler.interpret(&mut Default::default()).unwrap();
+    let code = compiler.finish().unwrap();
+    // println!("code = {:#?}", code);
+    assert_runs_like("array_copy_eq", &code, &Ok(()), b"");
+    let mut out = Vec::new();
+    code.write_elf(&mut out).unwrap();
+    assert_eq_hex("array_copy_eq", &out, "\
+      7f45 4c46 0201 0100 0000 0000 0000 0000\
+      0200 3e00 0100 0000 7800 4000 0000 0000\
+      4000 0000 0000 0000 0000 0000 0000 0000\
+      0000 0000 4000 3800 0100 4000 0000 0000\
+      0100 0000 0700 0000 7800 0000 0000 0000\
+      7800 4000 0000 0000 0000 0000 0000 0000\
+      1801 0000 0000 0000 1801 0000 0000 0000\
+      0000 2000 0000 0000 e813 0000 00b8 3c00\
+      0000 33ff 0f05 0000 0000 0000 0000 0000\
+      4883 ec14 b801 0000 0089 0424 ba02 0000\
+      0089 5424 0441 b903 0000 0044 894c 2408\
+      bf04 0000 0089 7c24 0cb9 0500 0000 894c\
+      2410 488b 0424 4889 0424 488b 5424 0848\
+      8954 2408 4c8b 4c24 0c4c 894c 240c 488d\
+      3c24 488d 3424 e805 0000 0048 83c4 14c3\
+      4883 ec28 488b 0f48 894c 2414 4c8b 5708\
+      4c89 5424 1c48 8b57 0c48 8954 2420 4c8b\
+      064c 8904 2448 8b46 0848 8944 2408 488b\
+      560c 4889 5424 0c48 8b44 2414 4889 4424\
+      1448 8b54 241c 4889 5424 1c4c 8b4c 2420\
+      4c89 4c24 2048 8b34 2448 8934 244c 8b44\
+      2408 4c89 4424 0848 8b44 240c 4889 4424\
+      0c48 8b4c 2414 4833 0c24 488b 7424 1c48\
+      3374 2408 480b ce48 8b44 2420 4833 4424\
+      0c48 0bc8 4883 f900 0f94 c048 83c4 28c3\
+    ");
+  }
+
+  #[test] fn large_uninit() {
+    let mut compiler = Compiler::new(());
+    let f = intern("f");
+    let ty = || Box::new(Spanned::dummy(TypeKind::Uninit(Box::new(Spanned::dummy(
+      TypeKind::Array(
+        Box::new(Spanned::dummy(TypeKind::UInt(Size::S32))),
+        Box::new(Spanned::dummy(ExprKind::Int(5.into())))))))));
+    let proc = |name, kind, args, stmts| Spanned::dummy(ItemKind::Proc {
+      intrinsic: None,
+      inline: false,
+      kind,
+      name: Spanned::dummy(name),
+      tyargs: 0,
+      args,
+      outs: Box::new([]),
+      rets: Box::new([]),
+      variant: None,
+      body: Block { stmts, expr: None },
+    });
+
+    // proc f(x: uninit (array u32 5)) {}
+    let x = VarId::default();
+    compiler.add(&proc(f, ProcKind::Proc, Box::new([
+      Spanned::dummy((ArgAttr::empty(), ArgKind::Lam(TuplePatternKind::Typed(
+        Box::new(Spanned::dummy(TuplePatternKind::Name(false, intern("x"), x))), ty()))))
+    ]), vec![]), Default::default(), ()).unwrap();
+
+    // main() { let a: uninit (array u32 5) = uninit; f(a); }
+    let a = VarId::default();
+    compiler.add(&proc(intern("main"), ProcKind::Main, Box::new([]), vec![
+      Spanned::dummy(StmtKind::Let {
+        lhs: Spanned::dummy(TuplePatternKind::Typed(
+          Box::new(Spanned::dummy(TuplePatternKind::Name(false, intern("a"), a))), ty())),
+        rhs: Spanned::dummy(ExprKind::Uninit),
+      }),
+      Spanned::dummy(StmtKind::Expr(ExprKind::Call {
+        f: Spanned::dummy(f),
+        tys: vec![],
+        args: vec![Spanned::dummy(ExprKind::Var(a))],
+        variant: None,
+      })),
+    ]), Default::default(), ()).unwrap();
+    compiler.interpret(&mut Default::default()).unwrap();
+    let code = compiler.finish().unwrap();
+    // println!("code = {:#?}", code);
+    assert_runs_like("large_uninit", &code, &Ok(()), b"");
+    let mut out = Vec::new();
+    code.write_elf(&mut out).unwrap();
+    assert_eq_hex("large_uninit", &out, "\
+      7f45 4c46 0201 0100 0000 0000 0000 0000\
+      0200 3e00 0100 0000 7800 4000 0000 0000\
+      4000 0000 0000 0000 0000 0000 0000 0000\
+      0000 0000 4000 3800 0100 4000 0000 0000\
+      0100 0000 0700 0000 7800 0000 0000 0000\
+      7800 4000 0000 0000 0000 0000 0000 0000\
+      6800 0000 0000 0000 6800 0000 0000 0000\
+      0000 2000 0000 0000 e813 0000 00b8 3c00\
+      0000 33ff 0f05 0000 0000 0000 0000 0000\
+      4883 ec28 33c0 4889 0424 33d2 4889 5424\
+      0845 33c9 4c89 4c24 0c48 8b3c 2448 897c\
+      2414 488b 4c24 0848 894c 241c 4c8b 5424\
+      0c4c 8954 2420 e805 0000 0048 83c4 28c3\
+      c300 0000 0000 0000 0000 0000 0000 0000\
+    ");
+  }
+
+  #[test] fn div_rem() {
+    use crate::FileSpan;
+    let mut compiler = Compiler::new(());
+    let [div, rem] = [intern("div"), intern("rem")];
+    let proc = |name, ty: &dyn Fn() -> TypeKind, op: &dyn Fn(VarId, VarId) -> ExprKind| {
+      let mut fresh = VarId::default();
+      let [x, y] = [(); 2].map(|_| fresh.fresh());
+      let arg = |name, v| Spanned::dummy((ArgAttr::empty(), ArgKind::Lam(TuplePatternKind::Typed(
+        Box::new(Spanned::dummy(TuplePatternKind::Name(false, intern(name), v))),
+        Box::new(Spanned::dummy(ty()))))));
+      Spanned::dummy(ItemKind::Proc {
+        intrinsic: None,
+        inline: false,
+        kind: ProcKind::Proc,
+        name: Spanned::dummy(name),
+        tyargs: 0,
+        args: Box::new([arg("x", x), arg("y", y)]),
+        outs: Box::new([]),
+        rets: Box::new([
+          Spanned::dummy(TuplePatternKind::Typed(
+            Box::new(Spanned::dummy(TuplePatternKind::Name(false, Symbol::UNDER, fresh.fresh()))),
+            Box::new(Spanned::dummy(ty())),
+          ))
+        ]),
+        variant: None,
+        body: Block { stmts: vec![], expr: Some(Box::new(Spanned::dummy(op(x, y)))) },
+      })
+    };
+    let var = |v| Box::new(Spanned::dummy(ExprKind::Var(v)));
+    // proc div(x y: u32): u32 { {x // y} bxor x }
+    compiler.add(&proc(div, &|| TypeKind::UInt(Size::S32),
+      &|x, y| ExprKind::Binop(Binop::BitXor,
+        Box::new(Spanned::dummy(ExprKind::Binop(Binop::Div, var(x), var(y)))), var(x))),
+      Default::default(), ()).unwrap();
+    // proc rem(x y: u16): u16 { {x bxor y} % y }
+    compiler.add(&proc(rem, &|| TypeKind::UInt(Size::S16),
+      &|x, y| ExprKind::Binop(Binop::Mod,
+        Box::new(Spanned::dummy(ExprKind::Binop(Binop::BitXor, var(x), var(y)))), var(y))),
+      Default::default(), ()).unwrap();
+
+    // main() { div(7, 2); rem(3, 4); }
+    // (the span of each statement starts at its first argument)
+    let call = |f, a: u32, b: u32| Spanned {
+      span: FileSpan {file: Default::default(), span: (a as usize..a as usize + 1).into()},
+      k: StmtKind::Expr(ExprKind::Call {
+        f: Spanned::dummy(f),
+        tys: vec![],
+        args: vec![Spanned::dummy(ExprKind::Int(a.into())), Spanned::dummy(ExprKind::Int(b.into()))],
+        variant: None,
+      })
+    };
+    compiler.add(
+      &Spanned::dummy(ItemKind::Proc {
+        intrinsic: None,
+        inline: false,
+        kind: ProcKind::Main,
+        name: Spanned::dummy(intern("main")),
+        tyargs: 0,
+        args: Box::new([]),
+        outs: Box::new([]),
+        rets: Box::new([]),
+        variant: None,
+        body: Block { stmts: vec![call(div, 7, 2), call(rem, 3, 4)], expr: None },
+      }),
+      Default::default(), ()).unwrap();
+    let mut interp = compiler.interpreter();
+    let mut call = |f, args: &[&[u8]]| interp.call(f, args).unwrap().unwrap();
+    assert_eq!(call(div, &[&7_u32.to_le_bytes(), &2_u32.to_le_bytes()]), [4_u32.to_le_bytes()]);
+    assert_eq!(call(div, &[&7_u32.to_le_bytes(), &0_u32.to_le_bytes()]), [7_u32.to_le_bytes()]);
+    assert_eq!(call(rem, &[&3_u16.to_le_bytes(), &4_u16.to_le_bytes()]), [3_u16.to_le_bytes()]);
+    assert_eq!(call(rem, &[&5_u16.to_le_bytes(), &0_u16.to_le_bytes()]), [5_u16.to_le_bytes()]);
+    compiler.interpret(&mut Default::default()).unwrap();
+    let code = compiler.finish().unwrap();
+    // println!("code = {:#?}", code);
+    assert_runs_like("div_rem", &code, &Ok(()), b"");
+    let mut out = Vec::new();
+    code.write_elf(&mut out).unwrap();
+    assert_eq_hex("div_rem", &out, "\
+      7f45 4c46 0201 0100 0000 0000 0000 0000\
+      0200 3e00 0100 0000 7800 4000 0000 0000\
+      4000 0000 0000 0000 0000 0000 0000 0000\
+      0000 0000 4000 3800 0100 4000 0000 0000\
+      0100 0000 0700 0000 7800 0000 0000 0000\
+      7800 4000 0000 0000 0000 0000 0000 0000\
+      9800 0000 0000 0000 9800 0000 0000 0000\
+      0000 2000 0000 0000 e813 0000 00b8 3c00\
+      0000 33ff 0f05 0000 0000 0000 0000 0000\
+      bf07 0000 00be 0200 0000 e811 0000 00bf\
+      0300 0000 be04 0000 00e8 3200 0000 c300\
+      41ba 0100 0000 83fe 0048 8bce 410f 44ca\
+      488b c733 d2f7 f133 d283 fe00 0f44 c233\
+      c7c3 0000 0000 0000 0000 0000 0000 0000\
+      6633 fe0f b7c7 0fb7 febe 0100 0000 83ff\
+      004c 8bc7 440f 44c6 33d2 41f7 f083 ff00\
+      0f44 d048 8bc2 c300 0000 0000 0000 0000\
+    ");
+
+    // The debug file loads the same segment, and has the sections appended to it
+    let mut debug = Vec::new();
+    code.write_elf_debug(&mut debug, |_| Some(1)).unwrap();
+    assert_eq!(debug[0x40..out.len()], out[0x40..]);
+    let shoff = u64::from_le_bytes(debug[0x28..0x30].try_into().unwrap());
+    let shnum = u16::from_le_bytes(debug[0x3c..0x3e].try_into().unwrap());
+    assert_eq!(debug.len() as u64, shoff + 0x40 * u64::from(shnum));
+    // and the code of each statement of `main` is mapped to its span (and the return to the
+    // span of the body)
+    let main = code.func_names.enum_iter().find(|p| *p.1 == intern("main")).unwrap().0;
+    let spans = code.funcs[main].1.spans.iter().map(|(off, sp)| (*off, sp.span.start));
+    assert_eq!(spans.collect::<Vec<_>>(), [(0, 7), (15, 3), (30, 0)]);
+  }
+
+  #[test] fn signed_div_rem() {
+    let mut compiler = Compiler::new(());
+    let [div, rem] = [intern("div"), intern("rem")];
+    let proc = |name, sz: Size, op: Binop| {
+      let mut fresh = VarId::default();
+      let [x, y] = [(); 2].map(|_| fresh.fresh());
+      let arg = |name, v| Spanned::dummy((ArgAttr::empty(), ArgKind::Lam(TuplePatternKind::Typed(
+        Box::new(Spanned::dummy(TuplePatternKind::Name(false, intern(name), v))),
+        Box::new(Spanned::dummy(TypeKind::Int(sz)))))));
+      let var = |v| Box::new(Spanned::dummy(ExprKind::Var(v)));
+      Spanned::dummy(ItemKind::Proc {
+        intrinsic: None,
+        inline: false,
+        kind: ProcKind::Proc,
+        name: Spanned::dummy(name),
+        tyargs: 0,
+        args: Box::new([arg("x", x), arg("y", y)]),
+        outs: Box::new([]),
+        rets: Box::new([
+          Spanned::dummy(TuplePatternKind::Typed(
+            Box::new(Spanned::dummy(TuplePatternKind::Name(false, Symbol::UNDER, fresh.fresh()))),
+            Box::new(Spanned::dummy(TypeKind::Int(sz))),
+          ))
+        ]),
+        variant: None,
+        body: Block { stmts: vec![], expr: Some(Box::new(Spanned::dummy(ExprKind::As(
+          Box::new(Spanned::dummy(ExprKind::Binop(op, var(x), var(y)))),
+          Box::new(Spanned::dummy(TypeKind::Int(sz))))))) },
+      })
+    };
+    // proc div(x y: i32): i32 { {x // y} as i32 }
+    compiler.add(&proc(div, Size::S32, Binop::Div), Default::default(), ()).unwrap();
+    // proc rem(x y: i8): i8 { {x % y} as i8 }
+    compiler.add(&proc(rem, Size::S8, Binop::Mod), Default::default(), ()).unwrap();
+    let mut interp = compiler.interpreter();
+    let mut call = |f, x: &[u8], y: &[u8]| interp.call(f, &[x, y]).unwrap().unwrap();
+    assert_eq!(call(div, &(-7_i32).to_le_bytes(), &2_i32.to_le_bytes()), [(-3_i32).to_le_bytes()]);
+    assert_eq!(call(div, &7_i32.to_le_bytes(), &(-2_i32).to_le_bytes()), [(-3_i32).to_le_bytes()]);
+    assert_eq!(call(rem, &(-7_i8).to_le_bytes(), &2_i8.to_le_bytes()), [(-1_i8).to_le_bytes()]);
+    assert_eq!(call(rem, &7_i8.to_le_bytes(), &(-2_i8).to_le_bytes()), [1_i8.to_le_bytes()]);
+    // main() { div(-7, 2); rem(7, -2); }
+    let call = |f, a: i32, b: i32| Spanned::dummy(StmtKind::Expr(ExprKind::Call {
+      f: Spanned::dummy(f),
+      tys: vec![],
+      args: vec![Spanned::dummy(ExprKind::Int(a.into())), Spanned::dummy(ExprKind::Int(b.into()))],
+      variant: None,
+    }));
+    compiler.add(
+      &Spanned::dummy(ItemKind::Proc {
+        intrinsic: None,
+        inline: false,
+        kind: ProcKind::Main,
+        name: Spanned::dummy(intern("main")),
+        tyargs: 0,
+        args: Box::new([]),
+        outs: Box::new([]),
+        rets: Box::new([]),
+        variant: None,
+        body: Block { stmts: vec![call(div, -7, 2), call(rem, 7, -2)], expr: None },
+      }),
+      Default::default(), ()).unwrap();
+    compiler.interpret(&mut Default::default()).unwrap();
+    let code = compiler.finish().unwrap();
+    assert_runs_like("signed_div_rem", &code, &Ok(()), b"");
+    let mut out = Vec::new();
+    code.write_elf(&mut out).unwrap();
+    assert_eq_hex("signed_div_rem", &out, "\
+      7f45 4c46 0201 0100 0000 0000 0000 0000\
+      0200 3e00 0100 0000 7800 4000 0000 0000\
+      4000 0000 0000 0000 0000 0000 0000 0000\
+      0000 0000 4000 3800 0100 4000 0000 0000\
+      0100 0000 0700 0000 7800 0000 0000 0000\
+      7800 4000 0000 0000 0000 0000 0000 0000\
+      a800 0000 0000 0000 a800 0000 0000 0000\
+      0000 2000 0000 0000 e813 0000 00b8 3c00\
+      0000 33ff 0f05 0000 0000 0000 0000 0000\
+      bff9 ffff ffbe 0200 0000 e811 0000 00bf\
+      0700 0000 befe 0000 00e8 4200 0000 c300\
+      488b c741 ba01 0000 0048 8bfe 83c7 0183\
+      ff01 488b ce41 0f46 ca99 f7f9 4c8b c041\
+      f7d8 83fe ff41 0f44 c033 d283 fe00 0f44\
+      c2c3 0000 0000 0000 0000 0000 0000 0000\
+      0fbe c70f befe ba01 0000 0083 ff00 4c8b\
+      cf44 0f44 ca99 41f7 f983 ff00 0f44 d048\
+      8bc2 c300 0000 0000 0000 0000 0000 0000\
+    ");
+  }
+
+  #[test] fn unprovable_with_proofs() {
+    use crate::{ItemContext, infer::TypeError, types::hir};
+    struct Unprovable(Vec<&'static str>);
+    impl ItemContext<()> for &mut Unprovable {
+      type Printer = ();
+      fn print(&mut self) {}
+      fn emit_type_errors<'a>(&mut self, _: &mut (),
+        errs: Vec<hir::Spanned<'a, TypeError<'a>>>,
+        _: &impl crate::DisplayCtx<'a>,
+      ) -> Result<bool, std::convert::Infallible> {
+        for err in errs {
+          if let TypeError::Unprovable(what) = err.k { self.0.push(what) }
+          else { panic!("unexpected type error") }
+        }
+        Ok(false)
+      }
+    }
+
+    let mut compiler = Compiler::new(());
+    compiler.proofs = true;
+    let proc = |name, sz: Size, op: Binop| {
+      let mut fresh = VarId::default();
+      let [x, y] = [(); 2].map(|_| fresh.fresh());
+      let ty = || Box::new(Spanned::dummy(TypeKind::Int(sz)));
+      let arg = |name, v| Spanned::dummy((ArgAttr::empty(), ArgKind::Lam(TuplePatternKind::Typed(
+        Box::new(Spanned::dummy(TuplePatternKind::Name(false, intern(name), v))), ty()))));
+      let var = |v| Box::new(Spanned::dummy(ExprKind::Var(v)));
+      Spanned::dummy(ItemKind::Proc {
+        intrinsic: None,
+        inline: false,
+        kind: ProcKind::Proc,
+        name: Spanned::dummy(intern(name)),
+        tyargs: 0,
+        args: Box::new([arg("x", x), arg("y", y)]),
+        outs: Box::new([]),
+        rets: Box::new([Spanned::dummy(TuplePatternKind::Typed(
+          Box::new(Spanned::dummy(TuplePatternKind::Name(false, Symbol::UNDER, fresh.fresh()))),
+          ty()))]),
+        variant: None,
+        body: Block { stmts: vec![], expr: Some(Box::new(Spanned::dummy(ExprKind::As(
+          Box::new(Spanned::dummy(ExprKind::Binop(op, var(x), var(y)))), ty())))) },
+      })
+    };
+    let mut errs = Unprovable(vec![]);
+    // proc div(x y: i32): i32 { {x // y} as i32 }
+    compiler.add(&proc("div", Size::S32, Binop::Div), Default::default(), &mut errs).unwrap();
+    assert_eq!(errs.0, ["signed division"]);
+    // proc sub(x y: i16): i16 { {x - y} as i16 }
+    errs.0.clear();
+    compiler.add(&proc("sub", Size::S16, Binop::Sub), Default::default(), &mut errs).unwrap();
+    assert_eq!(errs.0, ["16-bit integers"; 4]);
+    // proc sub(x y: i32): i32 { {x - y} as i32 } is fine
+    errs.0.clear();
+    compiler.add(&proc("sub", Size::S32, Binop::Sub), Default::default(), &mut errs).unwrap();
+    assert!(errs.0.is_empty());
+  }
+
+  #[test] fn div_nonzero() {
+    let mut compiler = Compiler::new(());
+    let div = intern("div");
+    // proc div(x y: u32) (ghost h: $ y != 0 $): u32 { x // (y h) }
+    let mut fresh = VarId::default();
+    let [x, y, h] = [(); 3].map(|_| fresh.fresh());
+    let u32 = || Box::new(Spanned::dummy(TypeKind::UInt(Size::S32)));
+    let arg = |attr, name, v, ty| Spanned::dummy((attr, ArgKind::Lam(TuplePatternKind::Typed(
+      Box::new(Spanned::dummy(TuplePatternKind::Name(false, intern(name), v))), ty))));
+    let var = |v| Spanned::dummy(ExprKind::Var(v));
+    let int = |n: u32| Spanned::dummy(ExprKind::Int(n.into()));
+    let ne_zero = |e| Spanned::dummy(ExprKind::Binop(Binop::Ne, Box::new(e), Box::new(int(0))));
+    compiler.add(&Spanned::dummy(ItemKind::Proc {
+      intrinsic: None,
+      inline: false,
+      kind: ProcKind::Proc,
+      name: Spanned::dummy(div),
+      tyargs: 0,
+      args: Box::new([
+        arg(ArgAttr::empty(), "x", x, u32()),
+        arg(ArgAttr::empty(), "y", y, u32()),
+        arg(ArgAttr::GHOST, "h", h,
+          Box::new(Spanned::dummy(TypeKind::Pure(Box::new(ne_zero(var(y))))))),
+      ]),
+      outs: Box::new([]),
+      rets: Box::new([Spanned::dummy(TuplePatternKind::Typed(
+        Box::new(Spanned::dummy(TuplePatternKind::Name(false, Symbol::UNDER, fresh.fresh()))),
+        u32()))]),
+      variant: None,
+      body: Block { stmts: vec![], expr: Some(Box::new(Spanned::dummy(
+        ExprKind::DivNonzero(Binop::Div, Box::new((var(x), var(y), var(h))))))) },
+    }), Default::default(), ()).unwrap();
+    let mut interp = compiler.interpreter();
+    let mut call = |a: u32, b: u32|
+      interp.call(div, &[&a.to_le_bytes(), &b.to_le_bytes()]).unwrap().unwrap();
+    assert_eq!(call(7, 2), [3_u32.to_le_bytes()]);
+    assert_eq!(call(9, 3), [3_u32.to_le_bytes()]);
+    // main() { div(7, 2, (assert {2 != 0})); }
+    compiler.add(
+      &Spanned::dummy(ItemKind::Proc {
+        intrinsic: None,
+        inline: false,
+        kind: ProcKind::Main,
+        name: Spanned::dummy(intern("main")),
+        tyargs: 0,
+        args: Box::new([]),
+        outs: Box::new([]),
+        rets: Box::new([]),
+        variant: None,
+        body: Block {
+          stmts: vec![Spanned::dummy(StmtKind::Expr(ExprKind::Call {
+            f: Spanned::dummy(div),
+            tys: vec![],
+            args: vec![int(7), int(2),
+              Spanned::dummy(ExprKind::Assert(Box::new(ne_zero(int(2)))))],
+            variant: None,
+          }))],
+          expr: None,
+        },
+      }),
+      Default::default(), ()).unwrap();
+    compiler.interpret(&mut Default::default()).unwrap();
+    let code = compiler.finish().unwrap();
+    assert_runs_like("div_nonzero", &code, &Ok(()), b"");
+    let mut out = Vec::new();
+    code.write_elf(&mut out).unwrap();
+    // The division is not guarded by a check for a zero divisor
+    assert_eq_hex("div_nonzero", &out, "\
+      7f45 4c46 0201 0100 0000 0000 0000 0000\
+      0200 3e00 0100 0000 7800 4000 0000 0000\
+      4000 0000 0000 0000 0000 0000 0000 0000\
+      0000 0000 4000 3800 0100 4000 0000 0000\
+      0100 0000 0700 0000 7800 0000 0000 0000\
+      7800 4000 0000 0000 0000 0000 0000 0000\
+      3800 0000 0000 0000 3800 0000 0000 0000\
+      0000 2000 0000 0000 e813 0000 00b8 3c00\
+      0000 33ff 0f05 0000 0000 0000 0000 0000\
+      bf07 0000 00be 0200 0000 e801 0000 00c3\
+      488b c733 d2f7 f6c3 0000 0000 0000 0000\
+    ");
+  }
+
+  #[test] fn div_by_zero() {
+    let mut compiler = Compiler::new(());
+    let div = intern("div");
+    // proc div(x y: u32): u32 { x // y }
+    let mut fresh = VarId::default();
+    let [x, y] = [(); 2].map(|_| fresh.fresh());
+    let u32 = || Box::new(Spanned::dummy(TypeKind::UInt(Size::S32)));
+    let arg = |name, v| Spanned::dummy((ArgAttr::empty(), ArgKind::Lam(TuplePatternKind::Typed(
+      Box::new(Spanned::dummy(TuplePatternKind::Name(false, intern(name), v))), u32()))));
+    let var = |v| Box::new(Spanned::dummy(ExprKind::Var(v)));
+    compiler.add(&Spanned::dummy(ItemKind::Proc {
+      intrinsic: None,
+      inline: false,
+      kind: ProcKind::Proc,
+      name: Spanned::dummy(div),
+      tyargs: 0,
+      args: Box::new([arg("x", x), arg("y", y)]),
+      outs: Box::new([]),
+      rets: Box::new([Spanned::dummy(TuplePatternKind::Typed(
+        Box::new(Spanned::dummy(TuplePatternKind::Name(false, Symbol::UNDER, fresh.fresh()))),
+        u32()))]),
+      variant: None,
+      body: Block { stmts: vec![],
+        expr: Some(Box::new(Spanned::dummy(ExprKind::Binop(Binop::Div, var(x), var(y))))) },
+    }), Default::default(), ()).unwrap();
+    // main() { let r = div(7, 0); assert {r = 0}; }
+    let r = fresh.fresh();
+    compiler.add(
+      &Spanned::dummy(ItemKind::Proc {
+        intrinsic: None,
+        inline: false,
+        kind: ProcKind::Main,
+        name: Spanned::dummy(intern("main")),
+        tyargs: 0,
+        args: Box::new([]),
+        outs: Box::new([]),
+        rets: Box::new([]),
+        variant: None,
+        body: Block {
+          stmts: vec![
+            Spanned::dummy(StmtKind::Let {
+              lhs: Spanned::dummy(TuplePatternKind::Typed(
+                Box::new(Spanned::dummy(TuplePatternKind::Name(false, intern("r"), r))), u32())),
+              rhs: Spanned::dummy(ExprKind::Call {
+                f: Spanned::dummy(div),
+                tys: vec![],
+                args: vec![
+                  Spanned::dummy(ExprKind::Int(7.into())),
+                  Spanned::dummy(ExprKind::Int(0.into())),
+                ],
+                variant: None,
+              }),
+            }),
+            Spanned::dummy(StmtKind::Expr(ExprKind::Assert(Box::new(Spanned::dummy(
+              ExprKind::Binop(Binop::Eq, var(r), Box::new(Spanned::dummy(ExprKind::Int(0.into()))))
+            ))))),
+          ],
+          expr: None,
+        },
+      }),
+      Default::default(), ()).unwrap();
+    // Division by zero is defined, so the compiled code must agree with the interpreter
+    let res = compiler.interpret(&mut Default::default());
+    assert!(res.is_ok());
+    let code = compiler.finish().unwrap();
+    assert_runs_like("div_by_zero", &code, &res, b"");
+  }
+
+  #[test] fn c_export() {
+    let mut compiler = Compiler::new(());
+    let [tbl, get, mix] = [intern("tbl"), intern("get"), intern("mix")];
+    let arr = || Box::new(Spanned::dummy(TypeKind::Array(
+      Box::new(Spanned::dummy(TypeKind::UInt(Size::S32))),
+      Box::new(Spanned::dummy(ExprKind::Int(4.into()))))));
+    let u8 = || Box::new(Spanned::dummy(TypeKind::UInt(Size::S8)));
+    let pat = |x, v| Box::new(Spanned::dummy(TuplePatternKind::Name(false, x, v)));
+    let proc = |name, args, ret, expr| Spanned::dummy(ItemKind::Proc {
+      intrinsic: None,
+      inline: false,
+      kind: ProcKind::Proc,
+      name: Spanned::dummy(name),
+      tyargs: 0,
+      args,
+      outs: Box::new([]),
+      rets: Box::new([Spanned::dummy(TuplePatternKind::Typed(
+        pat(Symbol::UNDER, VarId::default()), ret))]),
+      variant: None,
+      body: Block { stmts: vec![], expr: Some(expr) },
+    });
+
+    // const tbl: array u32 4 := [1, 2, 3, 4];
+    compiler.add(&Spanned::dummy(ItemKind::Const(None,
+      Spanned::dummy(TuplePatternKind::Typed(pat(tbl, VarId::default()), arr())),
+      Spanned::dummy(ExprKind::List(
+        (1..=4_u32).map(|n| Spanned::dummy(ExprKind::Int(n.into()))).collect())))),
+      Default::default(), ()).unwrap();
+    // proc get(): array u32 4 { tbl }
+    compiler.add(&proc(get, Box::new([]), arr(), Box::new(Spanned::dummy(ExprKind::Const(tbl)))),
+      Default::default(), ()).unwrap();
+    // proc mix(a b c d e f g h: u8): u8 { {a bxor b} bxor {g bxor h} }
+    let mut fresh = VarId::default();
+    let vs = [(); 8].map(|_| fresh.fresh());
+    let args = vs.iter().zip(["a", "b", "c", "d", "e", "f", "g", "h"]).map(|(&v, x)|
+      Spanned::dummy((ArgAttr::empty(), ArgKind::Lam(TuplePatternKind::Typed(
+        pat(intern(x), v), u8()))))).collect();
+    let var = |i: usize| Box::new(Spanned::dummy(ExprKind::Var(vs[i])));
+    let xor = |e1, e2| Box::new(Spanned::dummy(ExprKind::Binop(Binop::BitXor, e1, e2)));
+    compiler.add(&proc(mix, args, u8(), xor(xor(var(0), var(1)), xor(var(6), var(7)))),
+      Default::default(), ()).unwrap();
+
+    let code = compiler.finish_with_exports(&[get, mix]).unwrap();
+    let mut out = Vec::new();
+    code.write_object(&mut out).unwrap();
+    assert_links_with_c("c_export", &code, "\
+      #include <stdint.h>\n\
+      #include <stdio.h>\n\
+      void get(uint32_t out[4]);\n\
+      uint8_t mix(uint8_t a, uint8_t b, uint8_t c, uint8_t d,\n\
+        uint8_t e, uint8_t f, uint8_t g, uint8_t h);\n\
+      int main(void) {\n\
+        uint32_t t[4];\n\
+        get(t);\n\
+        printf(\"%u %u %u %u %u\\n\", t[0], t[1], t[2], t[3],\n\
+          mix(0x01, 0x02, 0xff, 0xff, 0xff, 0xff, 0x40, 0x80));\n\
+        return 0;\n\
+      }\n", b"1 2 3 4 195\n");
+    assert_eq_hex("c_export", &out, "\
+      7f45 4c46 0201 0100 0000 0000 0000 0000\
+      0100 3e00 0100 0000 0000 0000 0000 0000\
+      0000 0000 0000 0000 d001 0000 0000 0000\
+      0000 0000 4000 0000 0000 4000 0900 0800\
+      4883 ec10 488b 3425 0000 0000 4889 3424\
+      4c8b 0425 0800 0000 4c89 4424 0848 8b34\
+      2448 8937 488b 4c24 0848 894f 0848 83c4\
+      10c3 0000 0000 0000 0000 0000 0000 0000\
+      488b c74c 0fb6 4424 0848 0fb6 5424 1040\
+      32c6 4432 c241 32c0 c300 0000 0000 0000\
+      0100 0000 0200 0000 0300 0000 0400 0000\
+      0000 0000 0000 0000 0000 0000 0000 0000\
+      0000 0000 0000 0000 0000 0000 0300 0100\
+      0000 0000 0000 0000 0000 0000 0000 0000\
+      0000 0000 0300 0200 0000 0000 0000 0000\
+      0000 0000 0000 0000 0000 0000 0300 0300\
+      0000 0000 0000 0000 0000 0000 0000 0000\
+      0100 0000 1200 0100 0000 0000 0000 0000\
+      3200 0000 0000 0000 0500 0000 1200 0100\
+      4000 0000 0000 0000 1900 0000 0000 0000\
+      0067 6574 006d 6978 0000 0000 0000 0000\
+      0800 0000 0000 0000 0b00 0000 0200 0000\
+      0000 0000 0000 0000 1400 0000 0000 0000\
+      0b00 0000 0200 0000 0800 0000 0000 0000\
+      002e 7465 7874 002e 726f 6461 7461 002e\
+      6273 7300 2e73 796d 7461 6200 2e73 7472\
+      7461 6200 2e72 656c 612e 7465 7874 002e\
+      6e6f 7465 2e47 4e55 2d73 7461 636b 002e\
+      7368 7374 7274 6162 0000 0000 0000 0000\
+      0000 0000 0000 0000 0000 0000 0000 0000\
+      0000 0000 0000 0000 0000 0000 0000 0000\
+      0000 0000 0000 0000 0000 0000 0000 0000\
+      0000 0000 0000 0000 0000 0000 0000 0000\
+      0100 0000 0100 0000 0600 0000 0000 0000\
+      0000 0000 0000 0000 4000 0000 0000 0000\
+      6000 0000 0000 0000 0000 0000 0000 0000\
+      1000 0000 0000 0000 0000 0000 0000 0000\
+      0700 0000 0100 0000 0200 0000 0000 0000\
+      0000 0000 0000 0000 a000 0000 0000 0000\
+      1000 0000 0000 0000 0000 0000 0000 0000\
+      1000 0000 0000 0000 0000 0000 0000 0000\
+      0f00 0000 0800 0000 0300 0000 0000 0000\
+      0000 0000 0000 0000 b000 0000 0000 0000\
+      0000 0000 0000 0000 0000 0000 0000 0000\
+      1000 0000 0000 0000 0000 0000 0000 0000\
+      1400 0000 0200 0000 0000 0000 0000 0000\
+      0000 0000 0000 0000 b000 0000 0000 0000\
+      9000 0000 0000 0000 0500 0000 0400 0000\
+      0800 0000 0000 0000 1800 0000 0000 0000\
+      1c00 0000 0300 0000 0000 0000 0000 0000\
+      0000 0000 0000 0000 4001 0000 0000 0000\
+      0900 0000 0000 0000 0000 0000 0000 0000\
+      0100 0000 0000 0000 0000 0000 0000 0000\
+      2400 0000 0400 0000 4000 0000 0000 0000\
+      0000 0000 0000 0000 5001 0000 0000 0000\
+      3000 0000 0000 0000 0400 0000 0100 0000\
+      0800 0000 0000 0000 1800 0000 0000 0000\
+      2f00 0000 0100 0000 0000 0000 0000 0000\
+      0000 0000 0000 0000 8001 0000 0000 0000\
+      0000 0000 0000 0000 0000 0000 0000 0000\
+      0100 0000 0000 0000 0000 0000 0000 0000\
+      3f00 0000 0300 0000 0000 0000 0000 0000\
+      0000 0000 0000 0000 8001 0000 0000 0000\
+      4900 0000 0000 0000 0000 0000 0000 0000\
+      0100 0000 0000 0000 0000 0000 0000 0000\
+    ");
+  }
+
+  #[test] fn inline_call() {
+    use crate::mir::Terminator;
+    let mut compiler = Compiler::new(());
+    let [g, f, wrap] = [intern("g"), intern("f"), intern("wrap")];
+    let proc = |name, inline, op: &dyn Fn(VarId, VarId) -> ExprKind| {
+      let mut fresh = VarId::default();
+      let [x, y] = [(); 2].map(|_| fresh.fresh());
+      let ty = || Box::new(Spanned::dummy(TypeKind::UInt(Size::S32)));
+      let arg = |name, v| Spanned::dummy((ArgAttr::empty(), ArgKind::Lam(TuplePatternKind::Typed(
+        Box::new(Spanned::dummy(TuplePatternKind::Name(false, intern(name), v))), ty()))));
+      Spanned::dummy(ItemKind::Proc {
+        intrinsic: None,
+        inline,
+        kind: ProcKind::Proc,
+        name: Spanned::dummy(name),
+        tyargs: 0,
+        args: Box::new([arg("x", x), arg("y", y)]),
+        outs: Box::new([]),
+        rets: Box::new([Spanned::dummy(TuplePatternKind::Typed(
+          Box::new(Spanned::dummy(TuplePatternKind::Name(false, Symbol::UNDER, fresh.fresh()))),
+          ty()))]),
+        variant: None,
+        body: Block { stmts: vec![], expr: Some(Box::new(Spanned::dummy(op(x, y)))) },
+      })
+    };
+    let var = |v| Box::new(Spanned::dummy(ExprKind::Var(v)));
+    // inline proc g(x y: u32): u32 { x bxor y }
+    compiler.add(&proc(g, true, &|x, y| ExprKind::Binop(Binop::BitXor, var(x), var(y))),
+      Default::default(), ()).unwrap();
+    // proc f(x y: u32): u32 { g(y, x) band x }
+    compiler.add(&proc(f, false, &|x, y| ExprKind::Binop(Binop::BitAnd,
+      Box::new(Spanned::dummy(ExprKind::Call {
+        f: Spanned::dummy(g),
+        tys: vec![],
+        args: vec![Spanned::dummy(ExprKind::Var(y)), Spanned::dummy(ExprKind::Var(x))],
+        variant: None,
+      })), var(x))),
+      Default::default(), ()).unwrap();
+    // proc wrap(x y: u32): u32 { f(x, y) }
+    compiler.add(&proc(wrap, false, &|x, y| ExprKind::Call {
+      f: Spanned::dummy(f),
+      tys: vec![],
+      args: vec![Spanned::dummy(ExprKind::Var(x)), Spanned::dummy(ExprKind::Var(y))],
+      variant: None,
+    }), Default::default(), ()).unwrap();
+    let has_call = |p| compiler.mir[&p].body.blocks.0.iter()
+      .any(|bl| matches!(bl.term, Some(Terminator::Call {..})));
+    // Only calls to procedures declared `inline` are inlined
+    assert!(!has_call(f) && has_call(wrap));
+    let mut interp = compiler.interpreter();
+    let res = interp.call(f, &[&6_u32.to_le_bytes(), &3_u32.to_le_bytes()]).unwrap().unwrap();
+    assert_eq!(res, [4_u32.to_le_bytes()]);
+  }
+
+  #[test] fn asm_listing() {
+    let mut compiler = Compiler::new(());
+    let [tbl, get] = [intern("tbl"), intern("get")];
+    let arr = || Box::new(Spanned::dummy(TypeKind::Array(
+      Box::new(Spanned::dummy(TypeKind::UInt(Size::S32))),
+      Box::new(Spanned::dummy(ExprKind::Int(4.into()))))));
+    let pat = |x| Box::new(Spanned::dummy(TuplePatternKind::Name(false, x, VarId::default())));
+    // const tbl: array u32 4 := [1, 2, 3, 4];
+    compiler.add(&Spanned::dummy(ItemKind::Const(None,
+      Spanned::dummy(TuplePatternKind::Typed(pat(tbl), arr())),
+      Spanned::dummy(ExprKind::List(
+        (1..=4_u32).map(|n| Spanned::dummy(ExprKind::Int(n.into()))).collect())))),
+      Default::default(), ()).unwrap();
+    // proc get(): array u32 4 { tbl }
+    compiler.add(&Spanned::dummy(ItemKind::Proc {
+      intrinsic: None,
+      inline: false,
+      kind: ProcKind::Proc,
+      name: Spanned::dummy(get),
+      tyargs: 0,
+      args: Box::new([]),
+      outs: Box::new([]),
+      rets: Box::new([Spanned::dummy(TuplePatternKind::Typed(pat(Symbol::UNDER), arr()))]),
+      variant: None,
+      body: Block { stmts: vec![], expr: Some(Box::new(Spanned::dummy(ExprKind::Const(tbl)))) },
+    }), Default::default(), ()).unwrap();
+    let code = compiler.finish_with_exports(&[get]).unwrap();
+    let mut out = Vec::new();
+    code.write_asm(&mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "\
+; .text
+
+_start:
+.bb0:
+  400078:  mov eax, 0x3c
+  40007d:  xor edi, edi
+  40007f:  syscall
+
+get:
+.bb0:
+  400090:  sub rsp, 0x10
+  400094:  mov rsi, qword ptr [tbl]
+  40009c:  mov qword ptr [rsp], rsi
+  4000a0:  mov r8, qword ptr [tbl + 0x8]
+  4000a8:  mov qword ptr [rsp + 0x8], r8
+  4000ad:  mov rsi, qword ptr [rsp]
+  4000b1:  mov qword ptr [rdi], rsi
+  4000b4:  mov rcx, qword ptr [rsp + 0x8]
+  4000b9:  mov qword ptr [rdi + 0x8], rcx
+  4000bd:  add rsp, 0x10
+  4000c1:  ret
+
+; .rodata
+tbl:
+  4000d0:  db 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00
+  4000d8:  db 0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00
+");
+    let mut out = Vec::new();
+    code.write_vcode(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("get:\n  ; args []\n  ; rets [Boxed { reg: rdi, sz: 16 }]\n"));
+    assert!(out.contains("    %1 <- mov.64 [const[0]]\n"));
+  }
+
+  #[test] fn c_export_large_then_small_ret() {
+    let mut compiler = Compiler::new(());
+    let split = intern("split");
+    let arr = || Box::new(Spanned::dummy(TypeKind::Array(
+      Box::new(Spanned::dummy(TypeKind::UInt(Size::S32))),
+      Box::new(Spanned::dummy(ExprKind::Int(4.into()))))));
+    let u8 = || Box::new(Spanned::dummy(TypeKind::UInt(Size::S8)));
+    let pat = |x, v| Box::new(Spanned::dummy(TuplePatternKind::Name(false, x, v)));
+    let mut fresh = VarId::default();
+    // proc split(): array u32 4, u8 { ([1, 2, 3, 4], 7) }
+    compiler.add(&Spanned::dummy(ItemKind::Proc {
+      intrinsic: None,
+      inline: false,
+      kind: ProcKind::Proc,
+      name: Spanned::dummy(split),
+      tyargs: 0,
+      args: Box::new([]),
+      outs: Box::new([]),
+      rets: Box::new([
+        Spanned::dummy(TuplePatternKind::Typed(pat(Symbol::UNDER, fresh.fresh()), arr())),
+        Spanned::dummy(TuplePatternKind::Typed(pat(Symbol::UNDER, fresh.fresh()), u8())),
+      ]),
+      variant: None,
+      body: Block { stmts: vec![], expr: Some(Box::new(Spanned::dummy(ExprKind::List(vec![
+        Spanned::dummy(ExprKind::List(
+          (1..=4_u32).map(|n| Spanned::dummy(ExprKind::Int(n.into()))).collect())),
+        Spanned::dummy(ExprKind::Int(7.into()))])))) },
+    }), Default::default(), ()).unwrap();
+    let mut interp = compiler.interpreter();
+    let res = interp.call(split, &[]).unwrap().unwrap();
+    assert_eq!(res, [[1_u32, 2, 3, 4].iter().flat_map(|n| n.to_le_bytes()).collect(), vec![7]]);
+    let code = compiler.finish_with_exports(&[split]).unwrap();
+    let mut out = Vec::new();
+    code.write_vcode(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    // The large return goes through the pointer in rdi, which leaves rax for the small one
+    assert!(out.contains("split:\n  ; args []\n  ; rets [Boxed { reg: rdi, sz: 16 }, Reg(rax"),
+      "{}", out);
+  }
+
+  #[test] fn bad_exports() {
+    use crate::LinkError;
+    let proc = |name, tyargs| Spanned::dummy(ItemKind::Proc {
+      intrinsic: None,
+      inline: false,
+      kind: ProcKind::Proc,
+      name: Spanned::dummy(name),
+      tyargs,
+      args: Box::new([]),
+      outs: Box::new([]),
+      rets: Box::new([]),
+      variant: None,
+      body: Block { stmts: vec![], expr: None },
+    });
+    let [f, g] = [intern("f"), intern("g")];
+    let mut compiler = Compiler::new(());
+    // proc f<T>() {}
+    compiler.add(&proc(f, 1), Default::default(), ()).unwrap();
+    assert!(matches!(compiler.finish_with_exports(&[f]),
+      Err(LinkError::GenericExport(h)) if h == f));
+    // The compiler was reset, so `f` is no longer defined
+    compiler.add(&proc(g, 0), Default::default(), ()).unwrap();
+    assert!(matches!(compiler.finish_with_exports(&[g, f]),
+      Err(LinkError::UnknownExport(h)) if h == f));
+  }
+
+  #[test] fn asm_negative_imm() {
+    let mut compiler = Compiler::new(());
+    let neg = intern("neg");
+    // proc neg(): i64 { -1 }
+    compiler.add(&Spanned::dummy(ItemKind::Proc {
+      intrinsic: None,
+      inline: false,
+      kind: ProcKind::Proc,
+      name: Spanned::dummy(neg),
+      tyargs: 0,
+      args: Box::new([]),
+      outs: Box::new([]),
+      rets: Box::new([Spanned::dummy(TuplePatternKind::Typed(
+        Box::new(Spanned::dummy(TuplePatternKind::Name(false, Symbol::UNDER, VarId::default()))),
+        Box::new(Spanned::dummy(TypeKind::Int(Size::S64)))))]),
+      variant: None,
+      body: Block {
+        stmts: vec![], expr: Some(Box::new(Spanned::dummy(ExprKind::Int((-1).into()))))
+      },
+    }), Default::default(), ()).unwrap();
+    let code = compiler.finish_with_exports(&[neg]).unwrap();
+    let mut out = Vec::new();
+    code.write_asm(&mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    // The sign-extended 32-bit immediate is printed as a signed value
+    assert!(out.contains(":  mov rax, -0x1\n"), "{}", out);
+  }
 }
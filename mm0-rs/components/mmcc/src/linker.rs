@@ -2,7 +2,7 @@
 
 use std::collections::{HashMap, HashSet};
 
-use crate::build_vcode::VCodeCtx;
+use crate::build_vcode::{VCode, VCodeCtx, proc_abi};
 use crate::codegen::FUNCTION_ALIGN;
 use crate::mir_opt::storage::{Allocations, AllocId};
 use crate::regalloc::{PCode, regalloc_vcode};
@@ -10,14 +10,43 @@ use crate::types::global::{TyKind, ExprKind};
 use crate::types::entity::{ConstTc, Entity, ProcTc};
 use crate::types::mir::{
   Cfg, ConstKind, Constant, Place, Proc, Terminator, Ty, VarId, Visitor};
-use crate::types::vcode::{GlobalId, ProcId, ConstRef};
+use crate::types::vcode::{CallConv, GlobalId, ProcId, ConstRef};
 use crate::types::{IdxVec, Size};
-use crate::Symbol;
+use crate::{Symbol, intern};
 
-type GenericCall = (Symbol, Box<[Ty]>);
+/// A function together with the type arguments it is instantiated at.
+pub(crate) type GenericCall = (Symbol, Box<[Ty]>);
 
 type ConstVal = (u32, ConstRef);
 
+/// The maximum number of nested instantiations of generic functions. This is only reached
+/// by polymorphic recursion, where a generic function calls itself at ever larger types.
+const MAX_MONO_DEPTH: u32 = 64;
+
+/// An error produced during linking.
+#[derive(Copy, Clone, Debug)]
+pub enum LinkError {
+  /// Instantiating this generic function exceeded the maximum depth of nested instantiations,
+  /// which is usually caused by polymorphic recursion.
+  MonoDepth(Symbol),
+  /// An exported symbol does not refer to a procedure.
+  UnknownExport(Symbol),
+  /// An exported procedure is generic, so it has no single compiled instance to export.
+  GenericExport(Symbol),
+}
+
+impl std::fmt::Display for LinkError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      LinkError::MonoDepth(g) => write!(f, "Instantiating '{}' requires more than {} nested \
+        instantiations of generic functions (is it polymorphically recursive?)", g, MAX_MONO_DEPTH),
+      LinkError::UnknownExport(g) => write!(f, "Exported symbol '{}' is not a procedure", g),
+      LinkError::GenericExport(g) =>
+        write!(f, "Exported procedure '{}' must not be generic", g),
+    }
+  }
+}
+
 #[derive(Debug, Default)]
 pub(crate) struct ConstData {
   pub(crate) map: HashMap<Symbol, ConstVal>,
@@ -59,9 +88,21 @@ impl ConstData {
 struct Collector<'a> {
   names: &'a HashMap<Symbol, Entity>,
   mir: &'a HashMap<Symbol, Proc>,
-  implications: HashMap<Symbol, Option<HashSet<GenericCall>>>,
-  funcs: (HashMap<Symbol, ProcId>, IdxVec<ProcId, Symbol>),
+  /// The monomorphized instances of generic functions, indexed by their generated names.
+  instances: HashMap<Symbol, Proc>,
+  /// The number of instances of each generic function, used for generating names.
+  num_instances: HashMap<Symbol, u32>,
+  /// The number of generic instances currently being collected (on the DFS stack).
+  mono_depth: u32,
+  /// The first error encountered during collection.
+  error: Option<LinkError>,
+  funcs: (HashMap<GenericCall, ProcId>, IdxVec<ProcId, Symbol>),
   postorder: Vec<ProcId>,
+  /// The functions which are currently being collected (on the DFS stack).
+  active: HashSet<ProcId>,
+  /// The functions which are the target of a call from a function that (transitively)
+  /// depends on them, i.e. the targets of back edges in the call graph.
+  recursive: HashSet<ProcId>,
   consts: ConstData,
 }
 
@@ -70,25 +111,19 @@ impl<'a> Collector<'a> {
     Self {
       names,
       mir,
-      implications: Default::default(),
+      instances: Default::default(),
+      num_instances: Default::default(),
+      mono_depth: 0,
+      error: None,
       funcs: Default::default(),
       consts: Default::default(),
       postorder: Default::default(),
+      active: Default::default(),
+      recursive: Default::default(),
     }
   }
 
-  fn collect_generics(&mut self, args: &[Ty], calls: &HashSet<GenericCall>) {
-    for &(g, ref tys) in calls {
-      let args: Box<[_]> = tys.iter().map(|ty| ty.subst(args)).collect();
-      self.collect_func(g, &args);
-    }
-  }
-
-  fn collect_cfg(&mut self, body: &Cfg, args: &[Ty]) -> HashSet<GenericCall> {
-    if !args.is_empty() {
-      unimplemented!("functions with type args")
-    }
-    let mut calls = HashSet::new();
+  fn collect_cfg(&mut self, body: &Cfg) {
     for (_, bl) in body.blocks() {
       struct ConstVisitor<'a, 'b>(&'b mut Collector<'a>);
       impl Visitor for ConstVisitor<'_, '_> {
@@ -99,41 +134,50 @@ impl<'a> Collector<'a> {
       }
       ConstVisitor(self).visit_basic_block(bl);
       if let Terminator::Call { f, tys, .. } = bl.terminator() {
-        if tys.iter().any(|ty| ty.has_tyvar()) {
-          calls.insert((*f, tys.clone()));
-        } else {
-          self.collect_func(*f, tys);
-        }
+        assert!(!tys.iter().any(|ty| ty.has_tyvar()), "call to {} is not monomorphic", f);
+        self.collect_func(*f, tys);
       }
     }
-    calls
   }
 
   fn collect_func(&mut self, f: Symbol, args: &[Ty]) -> Option<ProcId> {
-    if !args.is_empty() {
-      unimplemented!("functions with type args")
+    let call = (f, args.into());
+    if let Some(&id) = self.funcs.0.get(&call) {
+      if self.active.contains(&id) { self.recursive.insert(id); }
+      return Some(id)
     }
-    if let Some(&id) = self.funcs.0.get(&f) { return Some(id) }
     if_chain! {
       if let Some(Entity::Proc(tc)) = self.names.get(&f);
       if let ProcTc::Typed(ty) = &tc.k;
       if ty.intrinsic.is_some();
       then { return None }
     }
-    let id = self.funcs.1.push(f);
-    self.funcs.0.insert(f, id);
-    if let Some(imps) = self.implications.get_mut(&f) {
-      let calls = imps.take().expect("cycle in collector?");
-      self.collect_generics(args, &calls);
-      self.implications.insert(f, Some(calls));
-    } else if let Some(proc) = self.mir.get(&f) {
-      let calls = self.collect_cfg(&proc.body, args);
-      if !calls.is_empty() {
-        self.implications.insert(f, None);
-        self.collect_generics(args, &calls);
+    let name = if args.is_empty() { f } else {
+      if self.mono_depth >= MAX_MONO_DEPTH {
+        self.error.get_or_insert(LinkError::MonoDepth(f));
+        return None
+      }
+      // Identifiers cannot start with a digit, so this does not clash with any user function
+      let n = self.num_instances.entry(f).or_default();
+      let name = intern(&format!("{}_{}", n, f));
+      *n += 1;
+      name
+    };
+    let id = self.funcs.1.push(name);
+    self.funcs.0.insert(call, id);
+    self.active.insert(id);
+    if let Some(proc) = self.mir.get(&f) {
+      if args.is_empty() {
+        self.collect_cfg(&proc.body)
+      } else {
+        let inst = proc.instantiate(self.names, name, args);
+        self.mono_depth += 1;
+        self.collect_cfg(&inst.body);
+        self.mono_depth -= 1;
+        self.instances.insert(name, inst);
       }
-      self.implications.insert(f, Some(calls));
     }
+    self.active.remove(&id);
     self.postorder.push(id);
     Some(id)
   }
@@ -225,34 +269,58 @@ pub struct LinkedCode {
   pub(crate) init: (Cfg, Box<PCode>),
   pub(crate) func_names: IdxVec<ProcId, Symbol>,
   pub(crate) funcs: IdxVec<ProcId, (u32, Box<PCode>)>,
+  /// The code of the start routine and the procedures before register allocation,
+  /// for use by [`LinkedCode::write_vcode`].
+  pub(crate) vcode: (Box<VCode>, IdxVec<ProcId, Box<VCode>>),
   pub(crate) postorder: Vec<ProcId>,
+  /// The procedures which are exported with the C calling convention,
+  /// for use by [`LinkedCode::write_object`].
+  pub(crate) exports: Vec<ProcId>,
   pub(crate) text_size: u32,
 }
 
 impl LinkedCode {
   pub(crate) fn link(
     names: &HashMap<Symbol, Entity>,
-    mir: HashMap<Symbol, Proc>,
+    mut mir: HashMap<Symbol, Proc>,
     init: Cfg,
     allocs: &Allocations,
-    globals: &[(Symbol, bool, VarId, Ty)]
-  ) -> Self {
+    globals: &[(Symbol, bool, VarId, Ty)],
+    exports: &[Symbol],
+  ) -> Result<Self, LinkError> {
     let mut coll = Collector::new(names, &mir);
-    coll.collect_cfg(&init, &[]);
-    let mut func_abi = IdxVec::from_default(coll.funcs.1.len());
-    let mut func_code = IdxVec::from_default(coll.funcs.1.len());
-    for &f in &coll.postorder {
-      let sym = coll.funcs.1[f];
+    coll.collect_cfg(&init);
+    let exports = exports.iter().map(|&f| match mir.get(&f) {
+      None => Err(LinkError::UnknownExport(f)),
+      Some(proc) if proc.tyargs != 0 => Err(LinkError::GenericExport(f)),
+      Some(_) => Ok(coll.collect_func(f, &[]).expect("impossible")),
+    }).collect::<Result<Vec<_>, _>>()?;
+    if let Some(e) = coll.error.take() { return Err(e) }
+    let cc = |f| if exports.contains(&f) { CallConv::C } else { CallConv::Mmc };
+    let Collector {instances, funcs: (func_mono, func_names), postorder, recursive, consts, ..} =
+      coll;
+    mir.extend(instances);
+    let mut func_abi = IdxVec::from_default(func_names.len());
+    let mut func_code = IdxVec::from_default(func_names.len());
+    let mut func_virt = IdxVec::from_default(func_names.len());
+    // Recursive calls are compiled before the callee, so we compute their ABI up front.
+    // (Calls into a function assume it clobbers every caller-saved register in this case.)
+    for &f in &recursive {
+      func_abi[f] = proc_abi(names, &mir[&func_names[f]], cc(f));
+    }
+    for &f in &postorder {
+      let sym = func_names[f];
       if let Some(proc) = mir.get(&sym) {
-        let (abi, code) = regalloc_vcode(
-          names, &coll.funcs.0, &func_abi, &coll.consts, &proc.body,
+        let (virt, code) = regalloc_vcode(
+          names, &func_mono, &func_abi, &consts, &proc.body,
           proc.allocs.as_deref().expect("optimized already"),
-          VCodeCtx::Proc(&proc.rets));
+          VCodeCtx::Proc(proc, cc(f)));
         // println!("mir {} = {:#?}", sym, proc);
-        // println!("abi {} = {:#?}", sym, abi);
+        // println!("abi {} = {:#?}", sym, virt.abi);
         // println!("code {} = {:#?}", sym, code);
-        func_abi[f] = abi;
+        func_abi[f] = virt.abi.clone();
         func_code[f] = Some(code);
+        func_virt[f] = Some(virt);
       }
     }
 
@@ -266,8 +334,8 @@ impl LinkedCode {
       global_size += size;
       Some((g, off, size))
     }).collect();
-    let init_code = regalloc_vcode(
-      names, &coll.funcs.0, &func_abi, &coll.consts, &init, allocs, VCodeCtx::Start(globals)).1;
+    let (init_virt, init_code) = regalloc_vcode(
+      names, &func_mono, &func_abi, &consts, &init, allocs, VCodeCtx::Start(globals));
 
     let mut pos = (TEXT_START + init_code.len + FUNCTION_ALIGN - 1) & !(FUNCTION_ALIGN - 1);
     let funcs = func_code.0.into_iter().map(|code| {
@@ -277,16 +345,18 @@ impl LinkedCode {
       (cur, code)
     }).collect();
 
-    Self {
-      consts: coll.consts,
+    Ok(Self {
+      consts,
       globals: globals_out,
       global_size,
       init: (init, init_code),
-      func_names: coll.funcs.1,
+      func_names,
       funcs,
-      postorder: coll.postorder,
+      vcode: (init_virt, func_virt.0.into_iter().map(|v| v.expect("impossible")).collect()),
+      postorder,
+      exports,
       text_size: pos - TEXT_START,
       mir,
-    }
+    })
   }
 }
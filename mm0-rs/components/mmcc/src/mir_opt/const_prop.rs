@@ -0,0 +1,337 @@
+//! The constant propagation pass, which evaluates operations on known values at compile time
+//! and replaces known conditions of branches by constants.
+//!
+//! The values of ghost variables are tracked as well, but this pass only rewrites relevant
+//! statements and operands, so the ghost parts of the program are left intact. The dead code
+//! left behind is removed by the next run of ghost analysis, which marks the unused values as
+//! irrelevant.
+//!
+//! An `if` on a known condition is not replaced by a jump, because the hypothesis of the branch
+//! that is taken has no proof in the MIR. The `if` is kept with a constant condition, and code
+//! generation jumps directly to the branch that is taken. The blocks that can only be entered
+//! through the other branch, which are found using the dominator tree, are marked unreachable, so
+//! that they are kept for the proof but no code is generated for them, and the stores that are
+//! only used in these blocks become ghost in the next run of ghost analysis.
+
+use num::{BigInt, Signed, Zero};
+use super::super::types::{self, IntTy, Size};
+use super::ghost::Reachability;
+#[allow(clippy::wildcard_imports)] use super::*;
+
+/// A known value of a variable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Value {
+  Bool(bool),
+  Int(IntTy, BigInt),
+}
+
+impl Value {
+  fn to_const(&self) -> Constant {
+    match *self {
+      Value::Bool(b) => Constant::bool(b),
+      Value::Int(ity, ref n) => Constant::int(ity, n.clone()),
+    }
+  }
+
+  /// Reinterpret this value at type `ty`, if the type is integral or boolean and the value is in
+  /// range.
+  fn at_ty(self, ty: &TyKind) -> Option<Value> {
+    match (ty, self) {
+      (TyKind::Sn(_, ty), v) => v.at_ty(ty),
+      (TyKind::Bool, v @ Value::Bool(_)) => Some(v),
+      (&TyKind::Int(ity), Value::Int(_, n)) if ity.contains(&n) => Some(Value::Int(ity, n)),
+      _ => None,
+    }
+  }
+
+  /// Get the value as an integer of type `ity`, wrapping if necessary.
+  fn as_int(&self, ity: IntTy) -> Option<BigInt> {
+    if let Value::Int(_, n) = self { truncate(ity, n.clone()) } else { None }
+  }
+}
+
+/// Truncate `n` to the integral type `ity`, or return `None` if `n` is a negative `nat`.
+fn truncate(ity: IntTy, n: BigInt) -> Option<BigInt> {
+  if ity == IntTy::UInt(Size::Inf) { return if n.is_negative() { None } else { Some(n) } }
+  Some(types::Unop::As(ity).apply_int(&n)?.into_owned())
+}
+
+/// The map of variables with known values at a program point.
+type Consts = im::HashMap<VarId, Value>;
+
+/// The state of constant propagation at a program point: `None` if the program point has not
+/// been reached yet, otherwise the set of variables with known values.
+#[derive(Clone, Debug, Default)]
+struct ConstDom(Option<Consts>);
+
+impl Domain for ConstDom {
+  fn join(&mut self, other: &Self) -> bool {
+    match (&mut self.0, &other.0) {
+      (_, None) => false,
+      (None, Some(_)) => { self.0.clone_from(&other.0); true }
+      (Some(vars), Some(other)) => {
+        let len = vars.len();
+        vars.retain(|v, val| other.get(v) == Some(val));
+        vars.len() != len
+      }
+    }
+  }
+}
+
+fn constant(c: &Constant) -> Option<Value> {
+  match (&c.k, c.ety.0.as_deref()) {
+    (ConstKind::Bool, _) => Some(Value::Bool(c.as_bool()?)),
+    (ConstKind::Int, Some(ExprKind::Int(n))) => Some(Value::Int(c.ety.1.as_int_ty()?, n.clone())),
+    (ConstKind::As(c), _) => Some(Value::Int(c.1, constant(&c.0)?.as_int(c.1)?)),
+    _ => None,
+  }
+}
+
+fn operand(st: &Consts, o: &Operand) -> Option<Value> {
+  match o {
+    Operand::Copy(p) | Operand::Move(p) if p.proj.is_empty() => st.get(&p.local).cloned(),
+    Operand::Const(c) => constant(c),
+    _ => None,
+  }
+}
+
+fn binop(op: Binop, v1: &Value, v2: &Value) -> Option<Value> {
+  use types::Binop as B;
+  let ity = match (op.split(), v1, v2) {
+    ((op, None), &Value::Bool(b1), &Value::Bool(b2)) =>
+      return Some(Value::Bool(op.apply_bool_bool(b1, b2))),
+    ((_, None), _, _) => return None,
+    ((_, Some(ity)), _, _) => ity,
+  };
+  let (op, n1) = (op.split().0, v1.as_int(ity)?);
+  let n = match op {
+    B::Lt | B::Le | B::Eq | B::Ne => return Some(Value::Bool(op.apply_int_bool(&n1, &v2.as_int(ity)?))),
+    B::Shl | B::Shr => {
+      let n2 = v2.as_int(IntTy::UInt(ity.size()))?;
+      match ity.size().bits() {
+        Some(bits) if n2 >= bits.into() =>
+          if op == B::Shr && n1.is_negative() { -BigInt::from(1) } else { BigInt::zero() },
+        None if n2 > u16::MAX.into() => return None,
+        _ => op.apply_int_int(&n1, &n2)?,
+      }
+    }
+    _ => op.apply_int_int(&n1, &v2.as_int(ity)?)?,
+  };
+  Some(Value::Int(ity, truncate(ity, n)?))
+}
+
+/// Evaluate `rv` at type `ty`, if it is a constant.
+fn rvalue(st: &Consts, ty: &TyKind, rv: &RValue) -> Option<Value> {
+  let val = match rv {
+    RValue::Use(o) |
+    RValue::Cast(_, o, _) => operand(st, o)?,
+    RValue::Pun(_, p) if p.proj.is_empty() => st.get(&p.local)?.clone(),
+    RValue::Unop(op, o) => match (*op, operand(st, o)?) {
+      (Unop::Not, Value::Bool(b)) => Value::Bool(!b),
+      (Unop::Neg(ity), v) => Value::Int(ity, truncate(ity, -v.as_int(ity)?)?),
+      (Unop::BitNot(ity), v) => Value::Int(ity, truncate(ity, !v.as_int(ity)?)?),
+      (Unop::As(from, to), v) => Value::Int(to, truncate(to, v.as_int(from)?)?),
+      _ => return None,
+    },
+    &RValue::Binop(op, ref o1, ref o2) => binop(op, &operand(st, o1)?, &operand(st, o2)?)?,
+    &RValue::Eq(_, invert, ref o1, ref o2) => match (operand(st, o1)?, operand(st, o2)?) {
+      (Value::Bool(b1), Value::Bool(b2)) => Value::Bool((b1 == b2) != invert),
+      (Value::Int(_, n1), Value::Int(_, n2)) => Value::Bool((n1 == n2) != invert),
+      _ => return None,
+    },
+    _ => return None,
+  };
+  val.at_ty(ty)
+}
+
+fn apply_statement(st: &mut Consts, stmt: &Statement) {
+  match stmt {
+    Statement::Let(LetKind::Let(v, _), _, ty, rv) => match rvalue(st, ty, rv) {
+      Some(val) => { st.insert(*v, val); }
+      None => { st.remove(v); }
+    },
+    Statement::Let(LetKind::Own([(x, _), (y, _)]), _, _, _) => { st.remove(x); st.remove(y); }
+    Statement::Assign(lhs, ty, o, vars) => {
+      let val = if lhs.proj.is_empty() { operand(st, o).and_then(|val| val.at_ty(ty)) } else { None };
+      for r in &**vars {
+        let new = if r.from == lhs.local { val.clone() } else { st.get(&r.from).cloned() };
+        match new {
+          Some(val) => { st.insert(r.to, val); }
+          None => { st.remove(&r.to); }
+        }
+      }
+    }
+    Statement::LabelGroup(..) | Statement::PopLabelGroup |
+    Statement::DominatedBlock(..) | Statement::Span(_) => {}
+  }
+}
+
+fn apply_terminator(st: &mut Consts, term: &Terminator) {
+  match term {
+    Terminator::Jump(_, args, _) => {
+      let vals = args.iter().map(|(v, _, o)| (*v, operand(st, o))).collect::<Vec<_>>();
+      for (v, val) in vals {
+        match val {
+          Some(val) => { st.insert(v, val); }
+          None => { st.remove(&v); }
+        }
+      }
+    }
+    Terminator::Assert(Operand::Copy(p) | Operand::Move(p), _, _, _) if p.proj.is_empty() => {
+      st.insert(p.local, Value::Bool(true));
+    }
+    Terminator::Call { rets, .. } => for (_, v) in &**rets { st.remove(v); }
+    Terminator::Jump1(_) |
+    Terminator::Assert(..) |
+    Terminator::Return(..) |
+    Terminator::Unreachable(_) |
+    Terminator::Dead |
+    Terminator::If(..) |
+    Terminator::Exit(_) => {}
+  }
+}
+
+fn subst_operand(st: &Consts, o: &mut Operand) {
+  if let Operand::Copy(p) | Operand::Move(p) = o {
+    if p.proj.is_empty() {
+      if let Some(val) = st.get(&p.local) { *o = val.to_const().into() }
+    }
+  }
+}
+
+fn subst_rvalue(st: &Consts, rv: &mut RValue) {
+  match rv {
+    RValue::Use(o) |
+    RValue::Unop(_, o) |
+    RValue::Cast(_, o, _) => subst_operand(st, o),
+    RValue::Binop(_, o1, o2) |
+    RValue::Eq(_, _, o1, o2) => { subst_operand(st, o1); subst_operand(st, o2) }
+    RValue::List(os) |
+    RValue::Array(os) => for o in &mut **os { subst_operand(st, o) },
+    RValue::Pun(..) |
+    RValue::Borrow(_) |
+    RValue::Ghost(_) |
+    RValue::Mm0(..) |
+    RValue::Typeof(_) => {}
+  }
+}
+
+impl Cfg {
+  /// Perform constant propagation and folding on the relevant parts of the CFG, replacing
+  /// operations on known values by their results, and the condition of an `if` by a constant
+  /// if it is known. The blocks that can then only be entered through an untaken branch are
+  /// marked unreachable in `reachable`.
+  pub fn const_prop(&mut self, reachable: &mut BlockVec<Reachability>) {
+    struct ConstProp {
+      /// A variable which is known to have a given value on entry to a block, because the block
+      /// is the target of an `if` on that variable, and it has no other predecessors.
+      facts: BlockVec<Option<(VarId, bool)>>,
+    }
+
+    impl Analysis for ConstProp {
+      type Dir = Forward;
+      type Doms = BlockVec<ConstDom>;
+
+      fn bottom(&mut self, cfg: &Cfg) -> Self::Doms {
+        let mut doms = BlockVec::bottom(cfg.blocks.len());
+        doms[BlockId::ENTRY] = ConstDom(Some(Consts::new()));
+        doms
+      }
+
+      fn apply_statement(&mut self, _: &Self::Doms, _: Location, stmt: &Statement, d: &mut ConstDom) {
+        if let Some(st) = &mut d.0 { apply_statement(st, stmt) }
+      }
+
+      fn apply_terminator(&mut self, _: &Self::Doms, _: BlockId, term: &Terminator, d: &mut ConstDom) {
+        if let Some(st) = &mut d.0 { apply_terminator(st, term) }
+      }
+
+      fn apply_trans_for_block(&mut self,
+        ds: &Self::Doms, id: BlockId, bl: &BasicBlock, d: &mut ConstDom
+      ) {
+        if let (Some(st), Some((v, b))) = (&mut d.0, self.facts[id]) { st.insert(v, Value::Bool(b)); }
+        self.do_apply_trans_for_block(ds, id, bl, d)
+      }
+    }
+
+    let ifs = self.blocks().filter_map(|(_, bl)| match bl.term {
+      Some(Terminator::If(Operand::Copy(ref p) | Operand::Move(ref p), [(_, tgt1), (_, tgt2)]))
+        if p.proj.is_empty() => Some((p.local, tgt1, tgt2)),
+      _ => None
+    }).collect::<Vec<_>>();
+    let preds = self.compute_predecessors();
+    let mut facts = BlockVec::<Option<(VarId, bool)>>::from_default(preds.len());
+    for (v, tgt1, tgt2) in ifs {
+      for (b, tgt) in [(true, tgt1), (false, tgt2)] {
+        if preds[tgt].len() == 1 { facts[tgt] = Some((v, b)) }
+      }
+    }
+    let mut analysis = ConstProp { facts };
+    let doms = analysis.iterate_to_fixpoint(self);
+
+    for (id, d) in doms.enum_iter() {
+      let mut st = if let (true, Some(st)) = (reachable[id].reach(), &d.0) { st.clone() } else { continue };
+      if let Some((v, b)) = analysis.facts[id] { st.insert(v, Value::Bool(b)); }
+      let bl = &mut self.blocks[id];
+      for stmt in &mut bl.stmts {
+        match stmt {
+          Statement::Let(_, true, ty, rv) => match rvalue(&st, ty, rv) {
+            Some(val) => if !matches!(rv, RValue::Use(Operand::Const(_))) {
+              *rv = RValue::Use(val.to_const().into())
+            },
+            None => subst_rvalue(&st, rv),
+          }
+          Statement::Assign(_, _, o, vars) if vars.iter().any(|v| v.rel) => subst_operand(&st, o),
+          _ => {}
+        }
+        apply_statement(&mut st, stmt);
+      }
+      match bl.term.as_mut().expect("terminated") {
+        Terminator::Jump(_, args, _) |
+        Terminator::Return(_, args) =>
+          for (_, r, o) in &mut **args { if *r { subst_operand(&st, o) } },
+        Terminator::Call { args, .. } =>
+          for (r, o) in &mut **args { if *r { subst_operand(&st, o) } },
+        Terminator::Assert(o, _, _, _) |
+        Terminator::If(o, _) => subst_operand(&st, o),
+        Terminator::Jump1(_) |
+        Terminator::Unreachable(_) |
+        Terminator::Dead |
+        Terminator::Exit(_) => {}
+      }
+    }
+    self.eliminate_dead_branches(reachable)
+  }
+
+  /// Mark the blocks which can only be entered through the branch of an `if` on a constant that
+  /// is not taken as unreachable.
+  ///
+  /// The untaken target `u` of the `if` is only entered through this edge if every other
+  /// predecessor of `u` is dominated by `u` (or is not reachable), and in this case the same is
+  /// true of every block dominated by `u`.
+  fn eliminate_dead_branches(&mut self, reachable: &mut BlockVec<Reachability>) {
+    let untaken = self.blocks().filter_map(|(id, bl)| match bl.term {
+      Some(Terminator::If(Operand::Const(ref c), [(_, tgt1), (_, tgt2)]))
+        if reachable[id].reach() && tgt1 != tgt2 =>
+          Some((id, if c.as_bool()? { tgt2 } else { tgt1 })),
+      _ => None
+    }).collect::<Vec<_>>();
+    if untaken.is_empty() { return }
+    let preds = self.compute_predecessors().clone();
+    let dom = self.dominator_tree_uncached();
+    let dominates = |u, mut id| loop {
+      if id == u { break true }
+      match dom.try_idom(id) { Some(id2) => id = id2, None => break false }
+    };
+    for (id, u) in untaken {
+      if u == BlockId::ENTRY || !reachable[u].reach() || !preds[u].iter()
+        .all(|&(_, p)| p == id || !reachable[p].reach() || dominates(u, p)) { continue }
+      for (id2, bl) in self.blocks.enum_iter_mut() {
+        if reachable[id2].reach() && dominates(u, id2) {
+          reachable[id2] = Reachability::Unreachable;
+          bl.reachable = false;
+        }
+      }
+    }
+  }
+}
@@ -101,8 +101,9 @@ impl Cfg {
 
   /// Edit the CFG in place to apply the results of reachability analysis.
   pub fn apply_reachability_analysis(&mut self, reachable: &BlockVec<Reachability>) {
+    let mut folded = false;
     for id in (0..self.blocks.len()).map(BlockId::from_usize) {
-      let mut bl = &mut self.blocks[id];
+      let bl = &mut self.blocks[id];
       match reachable[id] {
         Reachability::Dead => { *bl = BasicBlock::DEAD; continue }
         Reachability::Unreachable => { bl.reachable = false; continue }
@@ -113,22 +114,30 @@ impl Cfg {
           *reach = reachable[*tgt].reach(),
         Some(&mut Terminator::If(_, [(_, tgt1), (_, tgt2)])) => {
           let reach1 = reachable[tgt1].reach();
-          if reach1 == reachable[tgt2].reach() { continue }
-          let_unchecked!(Some(Terminator::If(_, [mut vtgt1, mut vtgt2])) = bl.term.take(), {
-            if reach1 { mem::swap(&mut vtgt1, &mut vtgt2) }
-            let (_, ty1) = &self.ctxs.head(self[vtgt1.1].ctx).2;
-            let (e2, ty2) = &self.ctxs.head(self[vtgt2.1].ctx).2;
-            bl = &mut self.blocks[id];
-            bl.stmts.push(Statement::Let(
-              LetKind::Let(vtgt2.0, e2.clone()), false, ty2.clone(),
-              Constant::contra(ty1.clone(), tgt1, vtgt1.0).into()
-            ));
-            bl.term = Some(Terminator::Jump1(tgt2));
-          });
+          if reach1 != reachable[tgt2].reach() { self.select_branch(id, reach1); folded = true }
         }
         _ => {}
       }
     }
+    if folded { self.compute_predecessors(); }
+  }
+
+  /// Replace the `If` terminator of block `id` with a jump to the branch selected by `cond`.
+  /// The hypothesis of the selected branch is proved by contradiction using the other branch,
+  /// which must be unreachable. This invalidates the cached predecessor information.
+  fn select_branch(&mut self, id: BlockId, cond: bool) {
+    let bl = &mut self.blocks[id];
+    let_unchecked!(Some(Terminator::If(_, [mut taken, mut untaken])) = bl.term.take(), {
+      if !cond { mem::swap(&mut taken, &mut untaken) }
+      let (e, ty) = self.ctxs.head(self[taken.1].ctx).2.clone();
+      let bl = &mut self.blocks[id];
+      bl.stmts.push(Statement::Let(
+        LetKind::Let(taken.0, e), false, ty.clone(),
+        Constant::contra(ty, untaken.1, untaken.0).into()
+      ));
+      bl.term = Some(Terminator::Jump1(taken.1));
+    });
+    self.invalidate_cache()
   }
 
   /// This function returns true if the entry block can reach a `Return(_)`, i.e. it can terminate
@@ -275,7 +284,8 @@ impl Cfg {
               d.apply_operand(rhs)
             }
           }
-          Statement::LabelGroup(..) | Statement::PopLabelGroup | Statement::DominatedBlock(..) => {}
+          Statement::LabelGroup(..) | Statement::PopLabelGroup |
+          Statement::DominatedBlock(..) | Statement::Span(_) => {}
         }
       }
 
@@ -344,7 +354,8 @@ impl Cfg {
         match stmt {
           Statement::Let(LetKind::Let(v, _) | LetKind::Own([_, (v, _)]), r, _, _) => *r = get(*v),
           Statement::Assign(_, _, _, vs) => for v in &mut **vs { v.rel = get(v.to) }
-          Statement::LabelGroup(..) | Statement::PopLabelGroup | Statement::DominatedBlock(..) => {}
+          Statement::LabelGroup(..) | Statement::PopLabelGroup |
+          Statement::DominatedBlock(..) | Statement::Span(_) => {}
         }
       }
     }
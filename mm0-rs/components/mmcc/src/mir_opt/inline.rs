@@ -0,0 +1,199 @@
+//! The inlining pass, which replaces calls to procedures declared `inline` (and, if enabled,
+//! to small procedures) with a copy of the body of the callee.
+//!
+//! The variables and blocks of the callee are renamed apart from those of the caller, and its
+//! contexts are rebuilt on top of the context at the call site. The call becomes a jump to the
+//! copy of the entry block which binds the arguments, and each `return` in the callee becomes a
+//! jump to the block after the call which binds the return values. The copied block tree is
+//! placed next to the block after the call, so the proof of the caller contains a proof of the
+//! inlined body in place of the appeal to the callee's specification.
+
+use std::collections::HashMap;
+
+use crate::{Symbol, u32_as_usize};
+use super::types::{Idx, IdxVec, mir};
+#[allow(clippy::wildcard_imports)] use mir::*;
+
+/// The default value of [`Compiler::inline_threshold`](crate::Compiler::inline_threshold).
+/// Only procedures declared `inline` are inlined by default, so that each call in the source
+/// remains a call in the generated code and its proof.
+pub(crate) const INLINE_THRESHOLD: usize = 0;
+
+/// The renaming from the variables, blocks, and contexts of a callee to those of the caller.
+struct Renamer {
+  /// The mapping of variables, which sends every callee variable to a fresh variable.
+  alpha: Alpha,
+  /// The offset to add to the callee's block IDs.
+  blocks: u32,
+  /// `ctxs[buf][i]` is the caller context corresponding to `CtxId(buf, i)` in the callee.
+  ctxs: IdxVec<CtxBufId, Vec<CtxId>>,
+}
+
+impl Renamer {
+  fn var(&mut self, v: VarId) -> VarId { self.alpha.alpha(&v) }
+  fn block(&self, id: BlockId) -> BlockId { BlockId(id.0 + self.blocks) }
+  fn ctx(&self, CtxId(buf, i): CtxId) -> CtxId { self.ctxs[buf][u32_as_usize(i)] }
+}
+
+/// Renaming of variables, blocks and contexts, in place.
+impl VisitorMut for Renamer {
+  fn visit_var(&mut self, v: &mut VarId) { *v = self.var(*v) }
+  fn visit_block_id(&mut self, id: &mut BlockId) { *id = self.block(*id) }
+  fn visit_ctx(&mut self, ctx: &mut CtxId) { *ctx = self.ctx(*ctx) }
+  fn visit_ty(&mut self, ty: &mut Ty) { *ty = self.alpha.alpha(ty) }
+  fn visit_expr(&mut self, e: &mut Expr) { *e = self.alpha.alpha(e) }
+}
+
+impl BlockTree {
+  /// Call `f` on every block which is a root in the tree, that is, every `One(id)` node.
+  fn foreach_root(&self, f: &mut impl FnMut(BlockId)) {
+    match self {
+      BlockTree::LabelGroup(p) => for t in &p.1 { t.foreach_root(f) },
+      BlockTree::Many(trees) => for t in trees { t.foreach_root(f) },
+      &BlockTree::One(bl) => f(bl),
+    }
+  }
+
+  /// Insert the list of trees `new` immediately after the node `One(root)`, so that they can
+  /// jump anywhere `root` can. Returns false if `root` is not in the tree.
+  fn insert_after(&mut self, root: BlockId, new: &mut Vec<BlockTree>) -> bool {
+    match self {
+      BlockTree::LabelGroup(p) => Self::insert_after_list(&mut p.1, root, new),
+      BlockTree::Many(trees) => Self::insert_after_list(trees, root, new),
+      BlockTree::One(bl) if *bl == root => {
+        let trees = std::iter::once(BlockTree::One(root)).chain(new.drain(..)).collect();
+        *self = BlockTree::Many(trees);
+        true
+      }
+      BlockTree::One(_) => false,
+    }
+  }
+
+  fn insert_after_list(
+    trees: &mut Vec<BlockTree>, root: BlockId, new: &mut Vec<BlockTree>
+  ) -> bool {
+    for i in 0..trees.len() {
+      if matches!(trees[i], BlockTree::One(bl) if bl == root) {
+        let j = i + 1;
+        trees.splice(j..j, new.drain(..));
+        return true
+      }
+      if trees[i].insert_after(root, new) { return true }
+    }
+    false
+  }
+}
+
+impl Cfg {
+  /// Call `f` on all the blocks in the development of `root`, meaning all the blocks reachable
+  /// from `root` by edges other than `Jump`. In the proof, these blocks are proved together with
+  /// `root`, which is their root in the block tree.
+  fn develop(&self, root: BlockId, mut f: impl FnMut(BlockId)) {
+    let mut stack = vec![root];
+    while let Some(id) = stack.pop() {
+      f(id);
+      match self[id].term {
+        Some(Terminator::Jump1(tgt) |
+          Terminator::Assert(_, _, true, tgt) |
+          Terminator::Call { reach: true, tgt, .. }) => stack.push(tgt),
+        Some(Terminator::If(_, [(_, bl1), (_, bl2)])) => { stack.push(bl1); stack.push(bl2) }
+        _ => {}
+      }
+    }
+  }
+
+  /// A rough measure of the size of the code generated for this CFG, used by the inliner.
+  fn inline_cost(&self) -> usize {
+    self.blocks.0.iter().filter(|bl| bl.reachable && !bl.is_dead())
+      .map(|bl| bl.stmts.iter().filter(|s| s.relevant()).count() + 1)
+      .sum()
+  }
+
+  /// Inline a copy of `callee` at the call in block `id`, where `root` is the root of `id`
+  /// in the block tree. `base` is the context at the call site, and `args`, `tgt` and `rets`
+  /// are the corresponding fields of the `Call` terminator.
+  #[allow(clippy::too_many_arguments)]
+  fn inline_call(&mut self, callee: &Proc, id: BlockId, root: BlockId, base: CtxId,
+    args: Box<[(bool, Operand)]>, tgt: BlockId, rets: &[(bool, VarId)]
+  ) {
+    let mut alpha = Alpha::default();
+    for v in 0..callee.body.max_var.0 { alpha.push(VarId(v), VarId(self.max_var.0 + v)) }
+    self.max_var.0 += callee.body.max_var.0;
+    let mut r = Renamer {
+      alpha,
+      blocks: u32::try_from(self.blocks.len()).expect("overflow"),
+      ctxs: IdxVec::with_capacity(callee.body.ctxs.len()),
+    };
+    for buf in callee.body.ctxs.bufs() {
+      // The root buffer is its own parent; its copy instead extends the call site context
+      let mut ctx = if r.ctxs.is_empty() { base } else { r.ctx(buf.parent) };
+      let mut ids = Vec::with_capacity(buf.vars.len() + 1);
+      ids.push(ctx);
+      for &(v, rel, ref ety) in &buf.vars {
+        let mut ety = ety.clone();
+        r.visit_ety(&mut ety);
+        ctx = self.ctxs.extend(ctx, r.var(v), rel, ety);
+        ids.push(ctx);
+      }
+      r.ctxs.push(ids);
+    }
+    for bl in &callee.body.blocks.0 {
+      let mut bl = bl.clone();
+      bl.relevance = None;
+      r.visit_basic_block(&mut bl);
+      if let Some(Terminator::Return(outs, args)) = &mut bl.term {
+        let args = outs.iter().map(|&z| Operand::Move(Place::local(z)))
+          .chain(std::mem::take(args).into_vec().into_iter().map(|(_, _, o)| o))
+          .zip(rets).map(|(o, &(rel, v))| (v, rel, o))
+          .collect();
+        bl.term = Some(Terminator::Jump(tgt, args, None));
+      }
+      self.blocks.push(bl);
+    }
+    let entry = r.block(BlockId::ENTRY);
+    let args = callee.args.iter().zip(args.into_vec())
+      .map(|(arg, (rel, o))| (r.var(arg.var), rel, o)).collect();
+    let bl = &mut self[id];
+    bl.stmts.push(Statement::DominatedBlock(entry, base));
+    bl.term = Some(Terminator::Jump(entry, args, None));
+    let mut tree = callee.body.tree.clone();
+    r.visit_block_tree(&mut tree);
+    let mut new = if let BlockTree::Many(trees) = tree { trees } else { vec![tree] };
+    new.push(BlockTree::One(tgt));
+    assert!(self.tree.insert_after(root, &mut new), "block {:?} not in tree", root);
+  }
+}
+
+impl Proc {
+  /// Inline calls to procedures in `mir` which are declared `inline` or whose cost (as measured by
+  /// [`Cfg::inline_cost`]) is at most `threshold`, if it is nonzero. Only the calls in the
+  /// original body are considered; the callees have already had their own calls inlined.
+  pub(crate) fn inline_calls(&mut self, mir: &HashMap<Symbol, Proc>, threshold: usize) {
+    let cfg = &mut self.body;
+    let mut roots = BlockVec::<Option<BlockId>>::from_default(cfg.blocks.len());
+    cfg.tree.foreach_root(&mut |root| cfg.develop(root, |id| roots[id] = Some(root)));
+    let mut changed = false;
+    for id in (0..roots.len()).map(BlockId::from_usize) {
+      let bl = &cfg[id];
+      let (callee, tgt, base) = match (&bl.term, bl.stmts.last(), roots[id]) {
+        (&Some(Terminator::Call { f, ref tys, ref args, reach: true, tgt, ref rets,
+          variant: None, .. }), Some(&Statement::DominatedBlock(tgt2, base)), Some(_))
+        if tys.is_empty() && tgt == tgt2 => match mir.get(&f) {
+          Some(callee) if callee.tyargs == 0 && callee.variant.is_none() &&
+            callee.args.len() == args.len() && callee.rets.len() == rets.len() &&
+            (callee.inline || (threshold != 0 && callee.body.inline_cost() <= threshold)) =>
+            (callee, tgt, base),
+          _ => continue
+        },
+        _ => continue
+      };
+      let root = roots[id].expect("checked");
+      if let Some(Terminator::Call { args, rets, .. }) = cfg[id].term.take() {
+        cfg.inline_call(callee, id, root, base, args, tgt, &rets);
+      }
+      cfg.develop(tgt, |i| roots[i] = Some(tgt));
+      changed = true;
+    }
+    if changed { cfg.invalidate_cache() }
+  }
+}
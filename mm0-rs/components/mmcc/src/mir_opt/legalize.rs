@@ -33,13 +33,16 @@ impl Replace<Statement> for RValue {
 struct Legalizer<'a> {
   max_var: &'a mut VarId,
   stmts: &'a [Statement],
+  bounded_ints: &'a HashMap<VarId, (IntTy, Place)>,
   infinite_vars: HashMap<VarId, usize>,
   buffer: VecPatch<Statement, RValue>,
   generated: HashMap<(VarId, Predicate), PackedOp>,
 }
 
 impl<'a> Legalizer<'a> {
-  fn new(max_var: &'a mut VarId, stmts: &'a [Statement]) -> Self {
+  fn new(max_var: &'a mut VarId, stmts: &'a [Statement],
+    bounded_ints: &'a HashMap<VarId, (IntTy, Place)>
+  ) -> Self {
     let mut infinite_vars = HashMap::default();
     for (i, s) in stmts.iter().enumerate() {
       s.foreach_def(|v, r, _, ty| if r {
@@ -51,6 +54,7 @@ impl<'a> Legalizer<'a> {
     Self {
       max_var,
       stmts,
+      bounded_ints,
       infinite_vars,
       buffer: Default::default(),
       generated: Default::default()
@@ -81,9 +85,17 @@ impl<'a> Legalizer<'a> {
               Some(PackedOp::Copy(v))
             }
             RValue::Binop(op, o1, o2) => {
-              let op = op.as_(ity)?;
-              let o1 = self.try_legalize_operand(o1, Predicate::As(ity))?.unpack();
-              let o2 = self.try_legalize_operand(o2, Predicate::As(ity))?.unpack();
+              let (op, o1, o2) = if let Binop::Div(_, nz) | Binop::Mod(_, nz) = *op {
+                // Division does not commute with truncation, so we can only do it at type
+                // `ity` if the arguments are already in range
+                let op = if let Binop::Div(..) = *op { Binop::Div(ity, nz) }
+                  else { Binop::Mod(ity, nz) };
+                (op, self.exact_operand(o1, ity)?, self.exact_operand(o2, ity)?)
+              } else {
+                (op.as_(ity)?,
+                  self.try_legalize_operand(o1, Predicate::As(ity))?.unpack(),
+                  self.try_legalize_operand(o2, Predicate::As(ity))?.unpack())
+              };
               let v = self.max_var.fresh();
               self.buffer.insert(i+1, Statement::Let(
                 LetKind::Let(v, e.as_ref().map(|e|
@@ -116,6 +128,30 @@ impl<'a> Legalizer<'a> {
     }
   }
 
+  /// If `o` is an integer known to be in the range of a bounded type `ity`,
+  /// returns `ity` and an operand of that type with the same value.
+  fn exact_source(&self, o: &Operand) -> Option<(IntTy, Operand)> {
+    let p = o.place().ok()?;
+    if !p.proj.is_empty() { return None }
+    let (ity, p) = self.bounded_ints.get(&p.local)?;
+    Some((*ity, Operand::Copy(p.clone())))
+  }
+
+  /// Returns an operand of type `ity` with the same value as `o`,
+  /// if `o` is known to be in the range of `ity`.
+  fn exact_operand(&self, o: &Operand, ity: IntTy) -> Option<Operand> {
+    match o.place() {
+      Ok(_) => match self.exact_source(o)? {
+        (ity2, o) if ity2 == ity => Some(o),
+        _ => None,
+      },
+      Err(c) => match c.ety.0.as_deref() {
+        Some(ExprKind::Int(n)) if ity.contains(n) => Some(Operand::Const(Box::new(c.as_(ity)))),
+        _ => None,
+      }
+    }
+  }
+
   fn is_infinite_var(&self, o: &Operand) -> bool {
     if let Ok(p) = o.place() {
       if p.proj.is_empty() { return self.infinite_vars.contains_key(&p.local) }
@@ -147,6 +183,15 @@ impl<'a> Legalizer<'a> {
               })
             }
           },
+          &RValue::Binop(op @ (Binop::Eq(ity) | Binop::Ne(ity)), ref o1, ref o2)
+          if ity.size() == Size::Inf => if_chain! {
+            if let Some((ity, o1)) = self.exact_source(o1);
+            if let Some(o2) = self.exact_operand(o2, ity);
+            then {
+              let op = if let Binop::Eq(_) = op { Binop::Eq(ity) } else { Binop::Ne(ity) };
+              self.buffer.replace(i, RValue::Binop(op, o1, o2))
+            }
+          },
           // TODO: Compile equations like x + y < 17
           // RValue::Binop(op, _, _) if match op {
           //   Binop::Lt(ity) | Binop::Le(ity) |
@@ -167,8 +212,31 @@ impl Cfg {
   /// (In the future, more expressions with unbounded intermediates may be turned into compilable
   /// operations here.)
   pub fn legalize(&mut self) {
+    // Integer variables which are known to be in the range of a bounded type, either because
+    // they have that type or because they are casts of such a variable. These are needed to
+    // compile division and comparisons, and can be used in later blocks, so we collect them
+    // all up front.
+    let mut bounded_ints = HashMap::new();
+    for (_, bl) in self.blocks() {
+      for s in &bl.stmts {
+        s.foreach_def(|v, r, _, ty| if_chain! {
+          if r;
+          if let Some(ity) = ty.as_int_ty();
+          if ity.size() != Size::Inf;
+          then { bounded_ints.insert(v, (ity, v.into())); }
+        });
+        if_chain! {
+          if let Statement::Let(LetKind::Let(v, _), true, ty, RValue::Cast(CastKind::Int, o, tyin))
+            = s;
+          if let (Some(ity), Some(ityin)) = (ty.as_int_ty(), tyin.as_int_ty());
+          if ity.size() == Size::Inf && ityin.size() != Size::Inf;
+          if let Ok(p) = o.place();
+          then { bounded_ints.insert(*v, (ityin, p.clone())); }
+        }
+      }
+    }
     for (_, bl) in self.blocks.enum_iter_mut() {
-      Legalizer::new(&mut self.max_var, &bl.stmts).legalize_all().apply(&mut bl.stmts);
+      Legalizer::new(&mut self.max_var, &bl.stmts, &bounded_ints).legalize_all().apply(&mut bl.stmts);
     }
   }
 }
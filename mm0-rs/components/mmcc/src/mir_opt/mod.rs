@@ -9,9 +9,12 @@ use entity::Entity;
 #[allow(clippy::wildcard_imports)] use mir::*;
 pub(crate) use dominator::DominatorTree;
 
+pub(crate) mod const_prop;
 pub(crate) mod dominator;
 pub(crate) mod ghost;
+pub(crate) mod inline;
 pub(crate) mod legalize;
+pub(crate) mod mono;
 pub(crate) mod storage;
 
 /// A space-optimized `Option<BlockId>`.
@@ -511,7 +514,8 @@ impl Proc {
   /// Perform MIR analysis and optimize the given procedure.
   pub(crate) fn optimize(&mut self, names: &HashMap<Symbol, Entity>) {
     self.body.optimize(&self.rets);
-    if self.allocs.is_none() {
+    // Generic procedures are laid out when they are monomorphized
+    if self.allocs.is_none() && self.tyargs == 0 {
       self.allocs = Some(Rc::new(self.body.storage(names)))
     }
   }
@@ -523,7 +527,7 @@ impl Cfg {
     // println!("opt 0:\n{:#?}", self);
     self.compute_predecessors();
     // println!("compute_predecessors:\n{:#?}", self);
-    let reachable = self.reachability_analysis();
+    let mut reachable = self.reachability_analysis();
     // println!("reachable: {:#?}", reachable);
     self.apply_reachability_analysis(&reachable);
     // println!("reachability_analysis:\n{:#?}", self);
@@ -531,7 +535,9 @@ impl Cfg {
     // println!("ghost_analysis:\n{:#?}", self);
     self.legalize();
     // println!("legalize:\n{:#?}", self);
-    // Do ghost analysis again because legalize produces dead values
+    self.const_prop(&mut reachable);
+    // println!("const_prop:\n{:#?}", self);
+    // Do ghost analysis again because legalize and const_prop produce dead values
     self.do_ghost_analysis(&reachable, rets);
     // println!("ghost_analysis 2:\n{:#?}", self);
   }
@@ -0,0 +1,52 @@
+//! The monomorphization pass, which produces a copy of a generic procedure with the type
+//! variables replaced by concrete types, so that it can be laid out and compiled.
+
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{Symbol, u32_as_usize};
+use super::types::{Spanned, entity::Entity, mir};
+#[allow(clippy::wildcard_imports)] use mir::*;
+
+/// Substitution of types for type variables, in place.
+struct Subst<'a>(&'a [Ty]);
+
+impl VisitorMut for Subst<'_> {
+  fn visit_ty(&mut self, ty: &mut Ty) { *ty = ty.ty_subst(self.0) }
+  fn visit_expr(&mut self, e: &mut Expr) { *e = e.ty_subst(self.0) }
+}
+
+impl Cfg {
+  /// Substitute `tyargs` for the type variables in this CFG.
+  fn subst_mut(&mut self, tyargs: &[Ty]) {
+    let mut subst = Subst(tyargs);
+    for buf in self.ctxs.bufs_mut() {
+      for (_, _, ety) in &mut buf.vars { subst.visit_ety(ety) }
+    }
+    for bl in &mut self.blocks.0 { subst.visit_basic_block(bl) }
+  }
+}
+
+impl Proc {
+  /// Construct the monomorphized version `name` of this (generic) procedure, by substituting
+  /// `tyargs` for the type variables, and compute the storage allocations for the result.
+  pub(crate) fn instantiate(&self,
+    names: &HashMap<Symbol, Entity>, name: Symbol, tyargs: &[Ty]
+  ) -> Proc {
+    assert_eq!(u32_as_usize(self.tyargs), tyargs.len());
+    let mut body = self.body.clone();
+    body.subst_mut(tyargs);
+    let allocs = Some(Rc::new(body.storage(names)));
+    Proc {
+      kind: self.kind,
+      inline: self.inline,
+      name: Spanned { span: self.name.span.clone(), k: name },
+      tyargs: 0,
+      args: self.args.iter().map(|arg| arg.ty_subst(tyargs)).collect(),
+      outs: self.outs.clone(),
+      rets: self.rets.iter().map(|arg| arg.ty_subst(tyargs)).collect(),
+      variant: self.variant.ty_subst(tyargs),
+      body,
+      allocs,
+    }
+  }
+}
@@ -363,7 +363,8 @@ impl Cfg {
             }
             if tgt == AllocId::ZERO { *r = false }
           }
-          Statement::LabelGroup(..) | Statement::PopLabelGroup | Statement::DominatedBlock(..) => {}
+          Statement::LabelGroup(..) | Statement::PopLabelGroup |
+          Statement::DominatedBlock(..) | Statement::Span(_) => {}
         }
         s.foreach_def(|v, r, e, ty| if r { live.insert(v, (e, ty)); })
       }
@@ -50,9 +50,13 @@ impl<C> Compiler<C> {
   }
 
   /// Forward declare a procedure, for use before elaborating the body of a recursive procedure
-  /// or a group of mutually recursive procedures.
+  /// or a group of mutually recursive procedures. Forward declaring the same procedure twice
+  /// (with the same span) is not an error.
   pub fn forward_declare_proc(&mut self, span: &FileSpan, name: Symbol
   ) -> Result<(), DeclarationError> {
+    if let Some(Entity::Proc(Spanned {span: sp2, k: ProcTc::ForwardDeclared})) = self.names.get(&name) {
+      if sp2 == span { return Ok(()) }
+    }
     self.add_item(span, name,
       || Entity::Proc(Spanned {span: span.clone(), k: ProcTc::ForwardDeclared}))
   }
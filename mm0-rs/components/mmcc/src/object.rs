@@ -0,0 +1,181 @@
+//! Relocatable object output, for linking compiled procedures into a C program.
+//!
+//! [`LinkedCode::write_object`] writes an `ET_REL` ELF file containing the code of every
+//! procedure (but not the start routine), the constants and the globals, with relocations for all
+//! references to constants and globals. The procedures passed as exports to
+//! [`Compiler::finish_with_exports`](crate::Compiler::finish_with_exports) are compiled using the
+//! C calling convention (see [`CallConv::C`](crate::types::vcode::CallConv::C)) and become global
+//! symbols, while all other procedures are local to the object file.
+//!
+//! Like [`LinkedCode::write_elf_debug`], this file is not covered by the proof.
+
+use std::io::{self, Write};
+use byteorder::{LE, WriteBytesExt};
+use crate::{LinkedCode, TEXT_START, u32_as_usize};
+use crate::codegen::{BSS_ALIGN, FUNCTION_ALIGN, InstSink, Reloc, RelocTarget, align_to};
+use crate::debug_info::{Section, StrTab, push_symbol, write_zeros,
+  SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE, SHT_NOBITS, SHT_PROGBITS, SHT_STRTAB, SHT_SYMTAB,
+  STB_GLOBAL, STT_FUNC, STT_OBJECT, SYM_SIZE,
+  SEC_BSS, SEC_RODATA, SEC_STRTAB, SEC_SYMTAB, SEC_TEXT};
+
+const SHT_RELA: u32 = 4;
+const SHF_INFO_LINK: u64 = 0x40;
+const STB_LOCAL: u8 = 0;
+const STT_SECTION: u8 = 3;
+const R_X86_64_32S: u64 = 11;
+
+/// The size of an entry in a relocation table.
+const RELA_SIZE: u64 = 24;
+
+// Indexes into the section header table, after the ones shared with `debug_info`.
+// (`.rela.text` is at index 6 and `.note.GNU-stack` at index 7.)
+const SEC_SHSTRTAB: u16 = 8;
+const NUM_SECTIONS: u16 = 9;
+
+// Indexes into the symbol table of the section symbols. (The `.text` symbol is at index 1.)
+const SYM_RODATA: u64 = 2;
+const SYM_BSS: u64 = 3;
+
+impl LinkedCode {
+  /// Construct the `.symtab` and `.strtab` sections for a relocatable object. Procedures and
+  /// globals are local symbols, except for the exported procedures, which are global.
+  /// Procedure addresses are relative to `text_base`.
+  /// Returns the sections and the index of the first global symbol.
+  fn object_symtab(&self, text_base: u32) -> (Vec<u8>, StrTab, u32) {
+    let mut strtab = StrTab::new();
+    let mut symtab = vec![0; 24];
+    for sec in [SEC_TEXT, SEC_RODATA, SEC_BSS] {
+      push_symbol(&mut symtab, 0, (STB_LOCAL << 4) | STT_SECTION, sec, 0, 0)
+    }
+    let mut push = |symtab: &mut Vec<u8>, name: &str, info, shndx, value, size| {
+      let name = strtab.push(name);
+      push_symbol(symtab, name, info, shndx, value, size)
+    };
+    for (f, &(start, ref code)) in self.funcs.enum_iter() {
+      if !self.exports.contains(&f) {
+        push(&mut symtab, self.func_names[f].as_str(), (STB_LOCAL << 4) | STT_FUNC, SEC_TEXT,
+          (start - text_base).into(), code.len.into());
+      }
+    }
+    for &(g, off, size) in &self.globals.0 {
+      push(&mut symtab, g.as_str(), (STB_LOCAL << 4) | STT_OBJECT, SEC_BSS,
+        off.into(), size.into());
+    }
+    // `symtab` consists of whole symbol table entries, so the division is exact
+    #[allow(clippy::integer_division)]
+    let first_global = (symtab.len() as u64 / SYM_SIZE).try_into().expect("overflow");
+    for &f in &self.exports {
+      let (start, ref code) = self.funcs[f];
+      push(&mut symtab, self.func_names[f].as_str(), (STB_GLOBAL << 4) | STT_FUNC, SEC_TEXT,
+        (start - text_base).into(), code.len.into());
+    }
+    (symtab, strtab, first_global)
+  }
+
+  /// Write the code of all procedures as a relocatable object file (`ET_REL`), which can be
+  /// linked into a C program. The procedures exported by
+  /// [`Compiler::finish_with_exports`](crate::Compiler::finish_with_exports) can be called from
+  /// C, with the prototypes described in [`CallConv::C`](crate::types::vcode::CallConv::C).
+  ///
+  /// The start routine is not included, so the initializers of globals are not run, and globals
+  /// start out zeroed. Constants and globals are referenced using 32-bit absolute addresses, so
+  /// the object must be linked into a non-position-independent executable (`-no-pie`).
+  pub fn write_object(&self, w: &mut impl Write) -> io::Result<()> {
+    const HEADER: [u8; 0x28] = [
+      0x7f, b'E', b'L', b'F', // ELF magic
+      2, // EI_CLASS = 2 = 64-bit
+      1, // EI_DATA = 1 = little endian
+      1, // EI_VERSION = 1
+      0, // EI_OSABI = 0 = System V
+      0, // EI_ABIVERSION = 0
+      0, 0, 0, 0, 0, 0, 0, // EI_PAD
+      1, 0, // e_type = 1 = ET_REL (relocatable file)
+      0x3e, 0, // e_machine = 0x3e = AMD x86-64
+      1, 0, 0, 0, // e_version = 1
+      0, 0, 0, 0, 0, 0, 0, 0, // e_entry = 0 (no entry point)
+      0, 0, 0, 0, 0, 0, 0, 0, // e_phoff = 0 (no program header)
+    ];
+
+    let text_base = self.funcs.0.first().map_or(TEXT_START + self.text_size, |&(start, _)| start);
+    let mut text = vec![];
+    let mut ctx = InstSink::new(self, 0, Some(vec![]));
+    self.write_funcs(&mut ctx, &mut text)?;
+    let relocs = ctx.relocs.take().expect("impossible");
+    debug_assert_eq!(text.len() as u64, u64::from(TEXT_START + self.text_size - text_base));
+
+    let mut rela = vec![];
+    for Reloc { addr, target, addend } in relocs {
+      let sym = match target {
+        RelocTarget::Rodata => SYM_RODATA,
+        RelocTarget::Bss => SYM_BSS,
+      };
+      rela.write_u64::<LE>((addr - text_base).into())?;
+      rela.write_u64::<LE>((sym << 32) | R_X86_64_32S)?;
+      rela.write_i64::<LE>(addend.into())?;
+    }
+    let (symtab, strtab, first_global) = self.object_symtab(text_base);
+
+    let mut shstrtab = StrTab::new();
+    let mut sections = Vec::with_capacity(NUM_SECTIONS.into());
+    sections.push(Section::default());
+    let mut offset = 0x40;
+    let mut push = |name, sec: Section, len: usize, align: u64| {
+      offset = (offset + align - 1) & !(align - 1);
+      let size = len as u64;
+      if sec.ty != SHT_NOBITS { offset += size }
+      sections.push(Section { name, offset: offset - size, size, align, ..sec });
+    };
+    push(shstrtab.push(".text"), Section {
+      ty: SHT_PROGBITS, flags: SHF_ALLOC | SHF_EXECINSTR, ..Default::default()
+    }, text.len(), FUNCTION_ALIGN.into());
+    // In the executable the constants follow the padded code, so they are aligned as well
+    push(shstrtab.push(".rodata"), Section {
+      ty: SHT_PROGBITS, flags: SHF_ALLOC, ..Default::default()
+    }, self.consts.rodata.len(), FUNCTION_ALIGN.into());
+    push(shstrtab.push(".bss"), Section {
+      ty: SHT_NOBITS, flags: SHF_ALLOC | SHF_WRITE, ..Default::default()
+    }, u32_as_usize(self.global_size), BSS_ALIGN);
+    push(shstrtab.push(".symtab"), Section {
+      ty: SHT_SYMTAB, link: SEC_STRTAB.into(), info: first_global, entsize: SYM_SIZE,
+      ..Default::default()
+    }, symtab.len(), 8);
+    push(shstrtab.push(".strtab"), Section { ty: SHT_STRTAB, ..Default::default() },
+      strtab.0.len(), 1);
+    push(shstrtab.push(".rela.text"), Section {
+      ty: SHT_RELA, flags: SHF_INFO_LINK, link: SEC_SYMTAB.into(), info: SEC_TEXT.into(),
+      entsize: RELA_SIZE, ..Default::default()
+    }, rela.len(), 8);
+    // An empty `.note.GNU-stack` section marks that the code does not need an executable stack
+    push(shstrtab.push(".note.GNU-stack"), Section { ty: SHT_PROGBITS, ..Default::default() },
+      0, 1);
+    let name = shstrtab.push(".shstrtab");
+    push(name, Section { ty: SHT_STRTAB, ..Default::default() }, shstrtab.0.len(), 1);
+    debug_assert_eq!(sections.len(), usize::from(NUM_SECTIONS));
+    debug_assert_eq!(sections[usize::from(SEC_SHSTRTAB)].name, name);
+    let shoff = align_to::<8>(offset);
+
+    w.write_all(&HEADER)?;
+    w.write_u64::<LE>(shoff)?; // e_shoff
+    w.write_u32::<LE>(0)?; // e_flags = 0
+    w.write_u16::<LE>(0x40)?; // e_ehsize = 0x40 bytes
+    w.write_u16::<LE>(0)?; // e_phentsize = 0 (no program header)
+    w.write_u16::<LE>(0)?; // e_phnum = 0
+    w.write_u16::<LE>(0x40)?; // e_shentsize = 0x40 (section header table stride)
+    w.write_u16::<LE>(NUM_SECTIONS)?; // e_shnum
+    w.write_u16::<LE>(SEC_SHSTRTAB)?; // e_shstrndx
+    // end of ELF header, now at offset 0x40
+
+    let contents: [&[u8]; NUM_SECTIONS as usize] =
+      [&[], &text, &self.consts.rodata, &[], &symtab, &strtab.0, &rela, &[], &shstrtab.0];
+    let mut pos = 0x40;
+    for (sec, data) in sections.iter().zip(contents) {
+      if sec.ty == SHT_NOBITS || data.is_empty() { continue }
+      write_zeros(w, sec.offset - pos)?;
+      w.write_all(data)?;
+      pos = sec.offset + data.len() as u64;
+    }
+    write_zeros(w, shoff - pos)?;
+    for sec in &sections { sec.write(w)? }
+    Ok(())
+  }
+}
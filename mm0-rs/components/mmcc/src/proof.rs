@@ -80,6 +80,7 @@ impl<'a> ElfProof<'a> {
   }
 
   /// An iterator over the functions, in function dependency order.
+  /// (Within a group of mutually recursive functions the order is arbitrary.)
   #[must_use] pub fn proc_proofs(&self) -> ProcIter<'_> {
     ProcIter {
       code: self.code,
@@ -14,11 +14,11 @@ use regalloc2::{Allocation, Edit, Function, ProgPoint, SpillSlot};
 
 use crate::arch::{AMode, Inst, callee_saved, non_callee_saved, MACHINE_ENV, Offset, PAMode, PInst,
   PRegMem, PRegMemImm, PRegSet, PShiftIndex, RSP, PReg, RegMem, RegMemImm};
-use crate::linker::ConstData;
+use crate::linker::{ConstData, GenericCall};
 use crate::mir_opt::storage::Allocations;
 use crate::types::{IdxVec, Size};
 use crate::types::mir::{self, Cfg};
-use crate::{Entity, Idx, Symbol};
+use crate::{Entity, FileSpan, Idx, Symbol};
 use crate::build_vcode::{VCode, VCodeCtx, build_vcode};
 use crate::types::vcode::{self, IsReg, InstId, ProcAbi, ProcId, SpillId, BlockId, ChunkVec};
 
@@ -107,6 +107,8 @@ pub(crate) struct PCode {
   pub(crate) block_params: ChunkVec<BlockId, (mir::VarId, PRegMem)>,
   pub(crate) stack_size: u32,
   pub(crate) saved_regs: Vec<PReg>,
+  /// The source spans of the code, with the offset of the first instruction of each.
+  pub(crate) spans: Vec<(u32, FileSpan)>,
   pub(crate) len: u32,
 }
 
@@ -137,6 +139,7 @@ impl std::fmt::Debug for PCode {
 struct PCodeBuilder {
   code: Box<PCode>,
   fwd_jumps: Vec<(u32, PInstId)>,
+  spans: Vec<(PInstId, FileSpan)>,
 }
 
 impl std::ops::Deref for PCodeBuilder {
@@ -215,7 +218,7 @@ impl PCodeBuilder {
   }
 
   fn finish(self, saved_regs: Vec<PReg>) -> Box<PCode> {
-    let Self {mut code, fwd_jumps, ..} = self;
+    let Self {mut code, fwd_jumps, spans} = self;
     code.saved_regs = saved_regs;
     for (pos, i) in fwd_jumps {
       let inst = &mut code.insts[i];
@@ -228,11 +231,13 @@ impl PCodeBuilder {
     code.len = 0;
     let mut iter = code.blocks.0.iter();
     let mut cur = iter.next().expect("nonempty").0;
+    let mut spans = spans.into_iter().peekable();
     for (id, inst) in code.insts.enum_iter() {
       if id == cur {
         code.block_addr.push(code.len);
         if let Some(n) = iter.next() { cur = n.0 }
       }
+      while let Some((_, span)) = spans.next_if(|p| p.0 == id) { code.spans.push((code.len, span)) }
       code.len += u32::from(inst.len());
     }
     code
@@ -291,13 +296,13 @@ fn get_clobbers(vcode: &VCode, out: &regalloc2::Output) -> PRegSet {
 #[allow(clippy::similar_names)]
 pub(crate) fn regalloc_vcode(
   names: &HashMap<Symbol, Entity>,
-  func_mono: &HashMap<Symbol, ProcId>,
+  func_mono: &HashMap<GenericCall, ProcId>,
   funcs: &IdxVec<ProcId, ProcAbi>,
   consts: &ConstData,
   cfg: &Cfg,
   allocs: &Allocations,
   ctx: VCodeCtx<'_>,
-) -> (ProcAbi, Box<PCode>) {
+) -> (Box<VCode>, Box<PCode>) {
   // simplelog::SimpleLogger::init(simplelog::LevelFilter::Debug, simplelog::Config::default());
   let mut vcode = build_vcode(names, func_mono, funcs, consts, cfg, allocs, ctx);
   // eprintln!("{:#?}", vcode);
@@ -323,17 +328,20 @@ pub(crate) fn regalloc_vcode(
   let mut code = PCodeBuilder {
     code: Box::new(PCode {
       insts: IdxVec::new(),
-      block_map: vcode.block_map,
+      block_map: vcode.block_map.clone(),
       blocks: IdxVec::from(vec![]),
       block_addr: IdxVec::from(vec![0]),
       block_params: [[]].into_iter().collect(),
       stack_size: stack_size_no_ret,
       saved_regs: vec![],
+      spans: vec![],
       len: 0,
     }),
     fwd_jumps: vec![],
+    spans: vec![],
   };
   let mut bb = BlockBuilder::new(&vcode.blocks.0);
+  let mut spans = vcode.spans.iter().peekable();
   code.push_prologue(stack_size_no_ret, saved_regs.iter().copied());
   for (i, inst) in vcode.insts.enum_iter() {
     ar.next_inst();
@@ -342,6 +350,10 @@ pub(crate) fn regalloc_vcode(
       code.block_params.push_new();
       code.code.block_addr.push(code.len);
     };
+    while let Some((_, span)) = spans.next_if(|p| p.0 == i) {
+      let id = PInstId::from_usize(code.insts.len());
+      code.spans.push((id, span.clone()))
+    }
     code.apply_edits(&mut edits, &mut ar, ProgPoint::before(i));
     match *inst {
       Inst::Fallthrough { dst } => {
@@ -361,11 +373,14 @@ pub(crate) fn regalloc_vcode(
         let (_, dst) = (ar.reg(), ar.reg());
         code.push(PInst::Unop { op, sz, dst });
       }
-      // Inst::DivRem { sz, ref src2, .. } => {
-      //   let (_, src, _, _) = (ar.next(), ar.rm(src2), ar.next(), ar.next());
-      //   code.push(PInst::Cdx { sz });
-      //   code.push(PInst::DivRem { sz, src });
-      // }
+      Inst::DivRem { sz, signed, ref src2, .. } => {
+        let (_, _, src, _, _) = (ar.next(), ar.next(), ar.rm(src2), ar.next(), ar.next());
+        code.push(PInst::DivRem { sz, signed, src });
+      }
+      Inst::Cdx { sz, .. } => {
+        ar.next(); ar.next();
+        code.push(PInst::Cdx { sz });
+      }
       Inst::Mul { sz, ref src2, .. } => {
         let (_, src, _, _) = (ar.next(), ar.rm(src2), ar.next(), ar.next());
         code.push(PInst::Mul { sz, src });
@@ -438,5 +453,5 @@ pub(crate) fn regalloc_vcode(
     code.apply_edits(&mut edits, &mut ar, ProgPoint::after(i));
   }
   bb.finish_block(&mut code);
-  (vcode.abi, code.finish(saved_regs))
+  (Box::new(vcode), code.finish(saved_regs))
 }
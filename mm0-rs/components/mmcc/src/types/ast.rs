@@ -418,6 +418,9 @@ pub enum ExprKind {
   Unop(Unop, Box<Expr>),
   /// A binary operation.
   Binop(Binop, Box<Expr>, Box<Expr>),
+  /// A division `{x // (y h)}` or remainder `{x % (y h)}` (the operation is `Div` or `Mod`),
+  /// where `h: y != 0` proves that the divisor is nonzero, so that it need not be checked.
+  DivNonzero(Binop, Box<(Expr, Expr, Expr)>),
   /// `(sn x)` constructs the unique member of the type `(sn x)`.
   /// `(sn y h)` is also a member of `(sn x)` if `h` proves `y = x`.
   Sn(Box<Expr>, Option<Box<Expr>>),
@@ -566,6 +569,15 @@ impl ExprKind {
         write!(f, " {} ", op)?;
         e2.k.debug_indent(i, f)
       }
+      ExprKind::DivNonzero(op, args) => {
+        let (e1, e2, h) = &**args;
+        e1.k.debug_indent(i, f)?;
+        write!(f, " {} (", op)?;
+        e2.k.debug_indent(i, f)?;
+        write!(f, ", ")?;
+        h.k.debug_indent(i, f)?;
+        write!(f, ")")
+      }
       ExprKind::Sn(e, h) => {
         write!(f, "sn(")?;
         e.k.debug_indent(i, f)?;
@@ -861,6 +873,9 @@ pub enum ItemKind {
     /// The compiler will ensure this matches an existing intrinsic, and intrinsics cannot be
     /// called until they are declared using an `intrinsic` declaration.
     intrinsic: Option<super::entity::IntrinsicProc>,
+    /// True if this procedure was declared in an `inline` group, meaning that calls to it should
+    /// be inlined into the caller regardless of its size.
+    inline: bool,
     /// The type of declaration: `func`, `proc`, or `intrinsic`.
     kind: ProcKind,
     /// The name of the procedure.
@@ -92,6 +92,9 @@ make_prims! {
     /// * `(continue e)` jumps to the start of the nearest enclosing loop.
     /// * `(continue lab e)` jumps to the start of the loop with label `lab`.
     Continue: "continue",
+    /// `{x // y}` returns the quotient of the arguments, rounding toward zero.
+    /// Division by zero is defined and returns `0`.
+    Div: "//",
     /// `{x = y}` returns true if `x` is equal to `y`
     Eq: "=",
     /// `(ghost x)` returns the same thing as `x` but in the type `(ghost A)`.
@@ -105,6 +108,9 @@ make_prims! {
     Max: "max",
     /// `{x min y}` returns the minimum of the arguments
     Min: "min",
+    /// `{x % y}` returns the remainder of the division of `x` by `y`,
+    /// with the sign of `x`. `{x % 0}` is defined and returns `x`.
+    Mod: "%",
     /// * `{x * y}` returns the integer product of the arguments
     /// * `(* x)` is a deref operation `*x: T` where `x: &T`.
     MulDeref: "*",
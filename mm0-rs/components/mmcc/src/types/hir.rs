@@ -410,6 +410,12 @@ pub enum Binop {
   Mul(IntTy),
   /// Integer subtraction
   Sub(IntTy),
+  /// Integer division, rounding toward zero. Division by zero returns `0`.
+  /// The `bool` is true if the divisor is known to be nonzero, so that it need not be checked.
+  Div(IntTy, bool),
+  /// Integer remainder, with the sign of the dividend. `x % 0` returns `x`.
+  /// The `bool` is true if the divisor is known to be nonzero, so that it need not be checked.
+  Mod(IntTy, bool),
   /// Maximum
   Max(IntTy),
   /// Minimum
@@ -445,6 +451,10 @@ impl std::fmt::Debug for Binop {
       Binop::Add(ity) => write!(f, "+[{}]", ity),
       Binop::Mul(ity) => write!(f, "*[{}]", ity),
       Binop::Sub(ity) => write!(f, "-[{}]", ity),
+      Binop::Div(ity, false) => write!(f, "//[{}]", ity),
+      Binop::Div(ity, true) => write!(f, "//[{}, nonzero]", ity),
+      Binop::Mod(ity, false) => write!(f, "%[{}]", ity),
+      Binop::Mod(ity, true) => write!(f, "%[{}, nonzero]", ity),
       Binop::Max(ity) => write!(f, "max[{}]", ity),
       Binop::Min(ity) => write!(f, "min[{}]", ity),
       Binop::And => write!(f, "and"),
@@ -473,6 +483,8 @@ impl super::Binop {
       Add => Binop::Add(ity),
       Mul => Binop::Mul(ity),
       Sub => Binop::Sub(ity),
+      Div => Binop::Div(ity, false),
+      Mod => Binop::Mod(ity, false),
       Max => Binop::Max(ity),
       Min => Binop::Min(ity),
       And => Binop::And,
@@ -490,6 +502,34 @@ impl super::Binop {
   }
 }
 
+impl Binop {
+  /// The inverse of [`as_hir`](super::Binop::as_hir): split this operation into the underlying
+  /// [`types::Binop`](super::Binop) and the integral type, which is `None` for `And`/`Or`.
+  #[must_use] pub fn split(self) -> (super::Binop, Option<IntTy>) {
+    use super::Binop as B;
+    match self {
+      Binop::Add(ity) => (B::Add, Some(ity)),
+      Binop::Mul(ity) => (B::Mul, Some(ity)),
+      Binop::Sub(ity) => (B::Sub, Some(ity)),
+      Binop::Div(ity, _) => (B::Div, Some(ity)),
+      Binop::Mod(ity, _) => (B::Mod, Some(ity)),
+      Binop::Max(ity) => (B::Max, Some(ity)),
+      Binop::Min(ity) => (B::Min, Some(ity)),
+      Binop::And => (B::And, None),
+      Binop::Or => (B::Or, None),
+      Binop::BitAnd(ity) => (B::BitAnd, Some(ity)),
+      Binop::BitOr(ity) => (B::BitOr, Some(ity)),
+      Binop::BitXor(ity) => (B::BitXor, Some(ity)),
+      Binop::Shl(ity) => (B::Shl, Some(ity)),
+      Binop::Shr(ity) => (B::Shr, Some(ity)),
+      Binop::Lt(ity) => (B::Lt, Some(ity)),
+      Binop::Le(ity) => (B::Le, Some(ity)),
+      Binop::Eq(ity) => (B::Eq, Some(ity)),
+      Binop::Ne(ity) => (B::Ne, Some(ity)),
+    }
+  }
+}
+
 /// An expression.
 pub type Expr<'a> = Spanned<'a, (ExprKind<'a>, ty::ExprTy<'a>)>;
 
@@ -518,6 +558,9 @@ pub enum ExprKind<'a> {
   Unop(Unop, Box<Expr<'a>>),
   /// A binary operation.
   Binop(Binop, Box<Expr<'a>>, Box<Expr<'a>>),
+  /// A division or remainder `{x // (y h)}` with operands `[x, y, h]`, where `h: y != 0`.
+  /// The operation is a `Div` or `Mod` whose divisor is marked as known to be nonzero.
+  DivNonzero(Binop, Box<[Expr<'a>; 3]>),
   /// Equality, or disequality if `inverted = true`.
   Eq(ty::Ty<'a>, bool, Box<Expr<'a>>, Box<Expr<'a>>),
   /// `(sn x)` constructs the unique member of the type `(sn x)`.
@@ -652,6 +695,15 @@ impl ExprKind<'_> {
         write!(f, " {:?} ", op)?;
         e2.k.0.debug_indent(i, f)
       }
+      ExprKind::DivNonzero(op, args) => {
+        let [e1, e2, h] = &**args;
+        e1.k.0.debug_indent(i, f)?;
+        write!(f, " {:?} (", op)?;
+        e2.k.0.debug_indent(i, f)?;
+        write!(f, ", ")?;
+        h.k.0.debug_indent(i, f)?;
+        write!(f, ")")
+      }
       ExprKind::Eq(_, false, e1, e2) => {
         e1.k.0.debug_indent(i, f)?;
         write!(f, " == ")?;
@@ -880,6 +932,8 @@ pub enum ItemKind<'a> {
   Proc {
     /// The type of declaration: `func`, `proc`, or `intrinsic`.
     kind: ProcKind,
+    /// True if calls to this procedure should always be inlined.
+    inline: bool,
     /// The name of the procedure.
     name: Spanned<'a, Symbol>,
     /// The number of type arguments
@@ -912,7 +966,10 @@ impl Debug for ItemKind<'_> {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     use itertools::Itertools;
     match self {
-      &Self::Proc { kind, ref name, tyargs, ref args, ref rets, ref variant, ref body, .. } => {
+      &Self::Proc {
+        kind, inline, ref name, tyargs, ref args, ref rets, ref variant, ref body, ..
+      } => {
+        if inline { write!(f, "#[inline] ")? }
         write!(f, "{}", match kind {
           ProcKind::Func => "func",
           ProcKind::Proc => "proc",
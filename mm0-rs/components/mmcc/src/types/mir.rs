@@ -6,7 +6,7 @@ use bit_vec::BitVec;
 use num::BigInt;
 use smallvec::SmallVec;
 #[cfg(feature = "memory")] use mm0_deepsize_derive::DeepSizeOf;
-use crate::{Symbol, mir_opt::storage::Allocations, u32_as_usize};
+use crate::{FileSpan, Symbol, mir_opt::storage::Allocations, u32_as_usize};
 use super::{IntTy, Size, ProofId, LambdaId, IdxVec, Spanned, ast::ProcKind, ast, global, hir,
   super::mir_opt::DominatorTree};
 pub use {ast::TyVarId, hir::{Unop, Binop}};
@@ -63,6 +63,37 @@ impl<T: HasAlpha> HasAlpha for global::Mm0Expr<T> {
   }
 }
 
+/// A trait for substituting types for the type variables in a type or expression.
+/// The type variable `TyKind::Var(i)` is replaced by `tyargs[i]`.
+pub trait HasTySubst {
+  /// Applies the substitution, producing a copy of the value.
+  #[must_use] fn ty_subst(&self, tyargs: &[Ty]) -> Self;
+}
+
+impl<T: HasTySubst> HasTySubst for Box<[T]> {
+  fn ty_subst(&self, tyargs: &[Ty]) -> Self {
+    self.iter().map(|e| e.ty_subst(tyargs)).collect()
+  }
+}
+
+impl<T: HasTySubst> HasTySubst for Option<T> {
+  fn ty_subst(&self, tyargs: &[Ty]) -> Self {
+    self.as_ref().map(|e| e.ty_subst(tyargs))
+  }
+}
+
+impl<T: HasTySubst, U: HasTySubst> HasTySubst for (T, U) {
+  fn ty_subst(&self, tyargs: &[Ty]) -> Self {
+    (self.0.ty_subst(tyargs), self.1.ty_subst(tyargs))
+  }
+}
+
+impl<T: HasTySubst> HasTySubst for global::Mm0Expr<T> {
+  fn ty_subst(&self, tyargs: &[Ty]) -> Self {
+    Self { subst: self.subst.ty_subst(tyargs), expr: self.expr }
+  }
+}
+
 mk_id! {
   /// A variable ID. We use a different numbering here to avoid confusion with `VarId`s from HIR.
   VarId(Debug("v"))
@@ -339,10 +370,45 @@ impl TyKind {
 
   /// Substitute into the type arguments of a type.
   #[must_use] pub fn subst(self: &Ty, tyargs: &[Ty]) -> Ty {
-    if !tyargs.is_empty() {
-      unimplemented!("type substitution")
-    }
-    self.clone()
+    if tyargs.is_empty() { self.clone() } else { self.ty_subst(tyargs) }
+  }
+}
+
+impl HasTySubst for Ty {
+  fn ty_subst(&self, tyargs: &[Ty]) -> Self {
+    macro_rules! s {($e:expr) => {$e.ty_subst(tyargs)}}
+    if !self.has_tyvar() { return self.clone() }
+    Rc::new(match &**self {
+      &TyKind::Var(v) => return tyargs[u32_as_usize(v)].clone(),
+      TyKind::Unit | TyKind::True | TyKind::False | TyKind::Bool |
+      TyKind::Int(_) | TyKind::Input | TyKind::Output => unreachable!(),
+      TyKind::Array(ty, n) => TyKind::Array(s!(ty), s!(n)),
+      TyKind::Own(ty) => TyKind::Own(s!(ty)),
+      &TyKind::Ref(lft, ref ty) => TyKind::Ref(lft, s!(ty)),
+      TyKind::RefSn(e) => TyKind::RefSn(s!(e)),
+      TyKind::Sn(a, ty) => TyKind::Sn(s!(a), s!(ty)),
+      TyKind::Struct(args) => TyKind::Struct(s!(args)),
+      &TyKind::All(v, ref pat, ref ty) => TyKind::All(v, s!(pat), s!(ty)),
+      TyKind::Imp(p, q) => TyKind::Imp(s!(p), s!(q)),
+      TyKind::Wand(p, q) => TyKind::Wand(s!(p), s!(q)),
+      TyKind::Not(p) => TyKind::Not(s!(p)),
+      TyKind::And(ps) => TyKind::And(s!(ps)),
+      TyKind::Or(ps) => TyKind::Or(s!(ps)),
+      TyKind::If(c, t, e) => TyKind::If(s!(c), s!(t), s!(e)),
+      TyKind::Ghost(ty) => TyKind::Ghost(s!(ty)),
+      TyKind::Uninit(ty) => TyKind::Uninit(s!(ty)),
+      TyKind::Pure(e) => TyKind::Pure(s!(e)),
+      TyKind::User(f, tys, es) => TyKind::User(*f, s!(tys), s!(es)),
+      TyKind::Heap(e, v, ty) => TyKind::Heap(s!(e), s!(v), s!(ty)),
+      TyKind::HasTy(e, ty) => TyKind::HasTy(s!(e), s!(ty)),
+      TyKind::Moved(ty) => TyKind::Moved(s!(ty)),
+    })
+  }
+}
+
+impl HasTySubst for Arg {
+  fn ty_subst(&self, tyargs: &[Ty]) -> Self {
+    Self { attr: self.attr, var: self.var, ty: self.ty.ty_subst(tyargs) }
   }
 }
 
@@ -402,7 +468,7 @@ impl HasAlpha for TyKind {
 }
 
 /// The type of variant, or well founded order that recursions decrease.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 #[cfg_attr(feature = "memory", derive(DeepSizeOf))]
 pub enum VariantType {
   /// This variant is a nonnegative natural number which decreases to 0.
@@ -417,10 +483,20 @@ pub enum VariantType {
 
 /// A variant is a pure expression, together with a
 /// well founded order that decreases on all calls.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 #[cfg_attr(feature = "memory", derive(DeepSizeOf))]
 pub struct Variant(pub Expr, pub VariantType);
 
+impl HasTySubst for Variant {
+  fn ty_subst(&self, tyargs: &[Ty]) -> Self {
+    Self(self.0.ty_subst(tyargs), match &self.1 {
+      VariantType::Down => VariantType::Down,
+      VariantType::UpLt(e) => VariantType::UpLt(e.ty_subst(tyargs)),
+      VariantType::UpLe(e) => VariantType::UpLe(e.ty_subst(tyargs)),
+    })
+  }
+}
+
 /// A place expression.
 pub type EPlace = Rc<EPlaceKind>;
 
@@ -479,6 +555,19 @@ impl EPlaceKind {
   }
 }
 
+impl HasTySubst for EPlace {
+  fn ty_subst(&self, tyargs: &[Ty]) -> Self {
+    macro_rules! s {($e:expr) => {$e.ty_subst(tyargs)}}
+    if !self.has_tyvar() { return self.clone() }
+    Rc::new(match &**self {
+      EPlaceKind::Var(_) => unreachable!(),
+      EPlaceKind::Index(a, ty, i) => EPlaceKind::Index(s!(a), s!(ty), s!(i)),
+      EPlaceKind::Slice(a, ty, [i, l]) => EPlaceKind::Slice(s!(a), s!(ty), [s!(i), s!(l)]),
+      EPlaceKind::Proj(a, ty, i) => EPlaceKind::Proj(s!(a), s!(ty), *i),
+    })
+  }
+}
+
 impl HasAlpha for EPlaceKind {
   fn alpha(&self, a: &mut Alpha) -> Self {
     macro_rules! a {($e:expr) => {$e.alpha(a)}}
@@ -587,7 +676,7 @@ impl ExprKind {
       ExprKind::Sizeof(ty) => ty.has_tyvar(),
       ExprKind::Ref(p) => p.has_tyvar(),
       ExprKind::Call { tys, args, .. } =>
-        tys.iter().all(|e| e.has_tyvar()) || args.iter().any(|e| e.has_tyvar()),
+        tys.iter().any(|e| e.has_tyvar()) || args.iter().any(|e| e.has_tyvar()),
     }
   }
 }
@@ -626,6 +715,35 @@ impl std::fmt::Debug for ExprKind {
   }
 }
 
+impl HasTySubst for Expr {
+  #[allow(clippy::many_single_char_names)]
+  fn ty_subst(&self, tyargs: &[Ty]) -> Self {
+    macro_rules! s {($e:expr) => {$e.ty_subst(tyargs)}}
+    if !self.has_tyvar() { return self.clone() }
+    Rc::new(match &**self {
+      ExprKind::Unit | ExprKind::Var(_) | ExprKind::Const(_) |
+      ExprKind::Bool(_) | ExprKind::Int(_) => unreachable!(),
+      ExprKind::Unop(op, e) => ExprKind::Unop(*op, s!(e)),
+      ExprKind::Binop(op, e1, e2) => ExprKind::Binop(*op, s!(e1), s!(e2)),
+      ExprKind::Index(a, i) => ExprKind::Index(s!(a), s!(i)),
+      ExprKind::Slice(a, i, l) => ExprKind::Slice(s!(a), s!(i), s!(l)),
+      ExprKind::Proj(a, i) => ExprKind::Proj(s!(a), *i),
+      ExprKind::UpdateIndex(a, i, v) => ExprKind::UpdateIndex(s!(a), s!(i), s!(v)),
+      ExprKind::UpdateSlice(a, i, l, v) => ExprKind::UpdateSlice(s!(a), s!(i), s!(l), s!(v)),
+      ExprKind::UpdateProj(a, i, v) => ExprKind::UpdateProj(s!(a), *i, s!(v)),
+      ExprKind::List(es) => ExprKind::List(s!(es)),
+      ExprKind::Array(es) => ExprKind::Array(s!(es)),
+      ExprKind::Sizeof(ty) => ExprKind::Sizeof(s!(ty)),
+      ExprKind::Ref(e) => ExprKind::Ref(s!(e)),
+      ExprKind::Mm0(e) => ExprKind::Mm0(s!(e)),
+      &ExprKind::Call {f, ref tys, ref args} =>
+        ExprKind::Call {f, tys: s!(tys), args: s!(args)},
+      ExprKind::If {cond, then, els} => ExprKind::If {
+        cond: s!(cond), then: s!(then), els: s!(els)},
+    })
+  }
+}
+
 impl HasAlpha for ExprKind {
   #[allow(clippy::many_single_char_names)]
   fn alpha(&self, a: &mut Alpha) -> Self {
@@ -775,6 +893,14 @@ impl Contexts {
     self.rev_iter(id).next().expect("not the root context")
   }
 
+  /// An iterator over all the context buffers.
+  pub(crate) fn bufs(&self) -> impl Iterator<Item=&CtxBuf> { self.0 .0.iter() }
+
+  /// An iterator over all the context buffers.
+  pub(crate) fn bufs_mut(&mut self) -> impl Iterator<Item=&mut CtxBuf> {
+    self.0 .0.iter_mut()
+  }
+
   /// Clear all computational relevance settings in the contexts.
   pub fn reset_ghost(&mut self) {
     self.0 .0.iter_mut().for_each(|ctx| ctx.vars.iter_mut().for_each(|v| v.1 = false))
@@ -961,6 +1087,13 @@ impl Cfg {
     self.dominator_tree.get_or_insert(preds)
   }
 
+  /// Clear the cached predecessor and dominator information. This must be called after any
+  /// change to the edges of the CFG.
+  pub fn invalidate_cache(&mut self) {
+    self.predecessors = None;
+    self.dominator_tree = None;
+  }
+
   /// Retrieve the predecessor information for this CFG.
   /// Panics if [`compute_predecessors`](Cfg::compute_predecessors) is not called first.
   #[inline] #[must_use] pub fn predecessors(&self) -> &Predecessors {
@@ -1238,6 +1371,14 @@ impl Constant {
     Self { ety: (Some(Rc::new(ExprKind::Bool(b))), Rc::new(TyKind::Bool)), k: ConstKind::Bool }
   }
 
+  /// Returns the value of a boolean constant, or `None` if this is not a boolean literal.
+  #[must_use] pub fn as_bool(&self) -> Option<bool> {
+    match (&self.k, self.ety.0.as_deref()) {
+      (ConstKind::Bool, Some(&ExprKind::Bool(b))) => Some(b),
+      _ => None,
+    }
+  }
+
   /// Returns an integral constant.
   #[must_use] pub fn int(ty: IntTy, n: BigInt) -> Self {
     Self { ety: (Some(Rc::new(ExprKind::Int(n))), Rc::new(TyKind::Int(ty))), k: ConstKind::Int }
@@ -1447,6 +1588,7 @@ impl Binop {
       Binop::BitAnd(_) => Some(Binop::BitAnd(ity)),
       Binop::BitOr(_) => Some(Binop::BitOr(ity)),
       Binop::BitXor(_) => Some(Binop::BitXor(ity)),
+      Binop::Div(..) | Binop::Mod(..) |
       Binop::Max(_) | Binop::Min(_) | Binop::And | Binop::Or | Binop::Shl(_) | Binop::Shr(_) |
       Binop::Le(_) | Binop::Lt(_) | Binop::Eq(_) | Binop::Ne(_) => None,
     }
@@ -1597,6 +1739,9 @@ pub enum Statement {
   /// is the derived context).
   /// This is a ghost operation which does nothing to the state.
   DominatedBlock(BlockId, CtxId),
+  /// Marks the start of the code for a source statement, for debugging information.
+  /// This is a ghost operation which does nothing to the state.
+  Span(FileSpan),
 }
 
 impl std::fmt::Debug for Statement {
@@ -1613,6 +1758,7 @@ impl std::fmt::Debug for Statement {
       Self::LabelGroup(bls, _) => write!(f, "label_group({:?})", bls.iter().format(", ")),
       Self::PopLabelGroup => write!(f, "pop_label_group"),
       Self::DominatedBlock(bl, _) => write!(f, "dominated_block({:?})", bl),
+      Self::Span(sp) => write!(f, "span({:?})", sp),
     }
   }
 }
@@ -1623,7 +1769,8 @@ impl Statement {
     match self {
       &Self::Let(_, r, _, _) => r,
       Self::Assign(_, _, _, vars) => vars.iter().any(|v| v.rel),
-      Self::LabelGroup(..) | Self::PopLabelGroup | Self::DominatedBlock(..) => false,
+      Self::LabelGroup(..) | Self::PopLabelGroup |
+      Self::DominatedBlock(..) | Self::Span(_) => false,
     }
   }
 
@@ -1633,7 +1780,7 @@ impl Statement {
       Self::Let(LetKind::Let(..), ..) => 1,
       Self::Let(LetKind::Own(..), ..) => 2,
       Self::Assign(_, _, _, vars) => vars.len(),
-      Self::LabelGroup(..) | Self::PopLabelGroup | Self::DominatedBlock(..) => 0,
+      Self::LabelGroup(..) | Self::PopLabelGroup | Self::DominatedBlock(..) | Self::Span(_) => 0,
     }
   }
 
@@ -1649,7 +1796,7 @@ impl Statement {
         vars.iter().for_each(|Rename {to, rel, ety: (e, ty), ..}| {
           f(*to, *rel, e.as_ref(), ty)
         }),
-      Self::LabelGroup(..) | Self::PopLabelGroup | Self::DominatedBlock(..) => {}
+      Self::LabelGroup(..) | Self::PopLabelGroup | Self::DominatedBlock(..) | Self::Span(_) => {}
     }
   }
 
@@ -1716,7 +1863,10 @@ pub enum Terminator {
     tgt: BlockId,
     /// The list of variables returned from the call, which are introduced into the context of the
     /// target block.
-    rets: Box<[(bool, VarId)]>
+    rets: Box<[(bool, VarId)]>,
+    /// The variant, a ghost value to prove that this call decreases the variant of the target.
+    /// This is required on calls that participate in a cycle in the call graph.
+    variant: Option<Operand>,
   },
   /// Successfully exit the program.
   /// The operand should be a proof of the postcondition of the program.
@@ -1754,7 +1904,7 @@ impl std::fmt::Debug for Terminator {
         "if {:?} then {:?}. {:?} else {:?}. {:?}", cond, v1, bl1, v2, bl2),
       Self::Assert(cond, v, true, bl) => write!(f, "assert {:?} -> {:?}. {:?}", cond, v, bl),
       Self::Assert(cond, _, false, _) => write!(f, "assert {:?} -> !", cond),
-      Self::Call { f: func, tys, args, reach, tgt, rets, .. } => {
+      Self::Call { f: func, tys, args, reach, tgt, rets, variant, .. } => {
         write!(f, "call {}", func)?;
         if !tys.is_empty() { write!(f, "<{:?}>", tys.iter().format(", "))? }
         write!(f, "(")?;
@@ -1763,7 +1913,9 @@ impl std::fmt::Debug for Terminator {
           if first { first = false } else { write!(f, ", ")? }
           write!(f, "{}{:?}", if r {""} else {"ghost "}, o)?
         }
-        write!(f, ") -> ")?;
+        write!(f, ")")?;
+        if let Some(var) = variant { write!(f, " variant {:?}", var)? }
+        write!(f, " -> ")?;
         if *reach {
           for &(r, v) in &**rets { write!(f, "{}{:?}. ", if r {""} else {"ghost "}, v)? }
           write!(f, "{:?}", tgt)
@@ -1835,7 +1987,8 @@ pub(crate) trait Visitor {
         if needed { self.visit_place(lhs); self.visit_operand(rhs) }
       }
       Statement::Let(_, r, _, rv) => if *r { self.visit_rvalue(rv) }
-      Statement::LabelGroup(..) | Statement::PopLabelGroup | Statement::DominatedBlock(..) => {}
+      Statement::LabelGroup(..) | Statement::PopLabelGroup |
+      Statement::DominatedBlock(..) | Statement::Span(_) => {}
     }
   }
 
@@ -1873,6 +2026,178 @@ impl<F: FnMut(VarId)> Visitor for UseVisitor<F> {
   fn visit_var(&mut self, v: VarId) { (self.0)(v) }
 }
 
+/// A visitor that can modify the MIR in place. Unlike [`Visitor`], this visits every variable,
+/// block, context, type and expression in the statement, including those in ghost positions.
+/// The default implementations of the leaf methods (`visit_var`, `visit_block_id`, `visit_ctx`,
+/// `visit_ty` and `visit_expr`) do nothing, and the rest recurse into the subterms.
+pub(crate) trait VisitorMut {
+  fn visit_var(&mut self, _: &mut VarId) {}
+  fn visit_block_id(&mut self, _: &mut BlockId) {}
+  fn visit_ctx(&mut self, _: &mut CtxId) {}
+  fn visit_ty(&mut self, _: &mut Ty) {}
+  fn visit_expr(&mut self, _: &mut Expr) {}
+
+  fn visit_ety(&mut self, (e, ty): &mut ExprTy) {
+    if let Some(e) = e { self.visit_expr(e) }
+    self.visit_ty(ty)
+  }
+
+  fn visit_place(&mut self, p: &mut Place) {
+    self.visit_var(&mut p.local);
+    for (ty, proj) in &mut p.proj {
+      self.visit_ty(ty);
+      match proj {
+        Projection::Proj(..) | Projection::Deref => {}
+        Projection::Index(i, h) => { self.visit_var(i); self.visit_var(h) }
+        Projection::Slice(i, l, h) => { self.visit_var(i); self.visit_var(l); self.visit_var(h) }
+      }
+    }
+  }
+
+  fn visit_constant(&mut self, c: &mut Constant) {
+    self.visit_ety(&mut c.ety);
+    match &mut c.k {
+      ConstKind::Contra(bl, v) => { self.visit_block_id(bl); self.visit_var(v) }
+      ConstKind::As(c) => self.visit_constant(&mut c.0),
+      _ => {}
+    }
+  }
+
+  fn visit_operand(&mut self, o: &mut Operand) {
+    match o {
+      Operand::Copy(p) | Operand::Move(p) | Operand::Ref(p) => self.visit_place(p),
+      Operand::Const(c) => self.visit_constant(c),
+    }
+  }
+
+  fn visit_cast_kind(&mut self, ck: &mut CastKind) {
+    match ck {
+      CastKind::Int | CastKind::Wand(None) => {}
+      CastKind::Subtype(o) | CastKind::Mem(o) | CastKind::Wand(Some(o)) => self.visit_operand(o),
+    }
+  }
+
+  fn visit_rvalue(&mut self, rv: &mut RValue) {
+    match rv {
+      RValue::Use(o) | RValue::Unop(_, o) | RValue::Ghost(o) | RValue::Typeof(o) =>
+        self.visit_operand(o),
+      RValue::Binop(_, o1, o2) => { self.visit_operand(o1); self.visit_operand(o2) }
+      RValue::Eq(ty, _, o1, o2) => {
+        self.visit_ty(ty);
+        self.visit_operand(o1);
+        self.visit_operand(o2)
+      }
+      RValue::Pun(pk, p) => {
+        match pk {
+          PunKind::Sn(o) => if let Some(o) = o { self.visit_operand(o) },
+          PunKind::And(os) => for o in os { self.visit_operand(o) },
+          PunKind::Ptr => {}
+          PunKind::DropAs(ck) => self.visit_cast_kind(&mut ck.1),
+        }
+        self.visit_place(p)
+      }
+      RValue::Cast(ck, o, ty) => {
+        self.visit_cast_kind(ck);
+        self.visit_operand(o);
+        self.visit_ty(ty)
+      }
+      RValue::List(os) | RValue::Array(os) | RValue::Mm0(_, os) =>
+        for o in &mut **os { self.visit_operand(o) },
+      RValue::Borrow(p) => self.visit_place(p),
+    }
+  }
+
+  fn visit_stmt(&mut self, s: &mut Statement) {
+    match s {
+      Statement::Let(lk, _, ty, rv) => {
+        match lk {
+          LetKind::Let(v, e) => {
+            self.visit_var(v);
+            if let Some(e) = e { self.visit_expr(e) }
+          }
+          LetKind::Own([(x, ty1), (h, ty2)]) => {
+            self.visit_var(x);
+            self.visit_ty(ty1);
+            self.visit_var(h);
+            self.visit_ty(ty2)
+          }
+        }
+        self.visit_ty(ty);
+        self.visit_rvalue(rv)
+      }
+      Statement::Assign(lhs, ty, rhs, vars) => {
+        self.visit_place(lhs);
+        self.visit_ty(ty);
+        self.visit_operand(rhs);
+        for r in &mut **vars {
+          self.visit_var(&mut r.from);
+          self.visit_var(&mut r.to);
+          self.visit_ety(&mut r.ety)
+        }
+      }
+      Statement::LabelGroup(bls, ctx) => {
+        for bl in bls { self.visit_block_id(bl) }
+        self.visit_ctx(ctx)
+      }
+      Statement::PopLabelGroup | Statement::Span(_) => {}
+      Statement::DominatedBlock(bl, ctx) => { self.visit_block_id(bl); self.visit_ctx(ctx) }
+    }
+  }
+
+  fn visit_terminator(&mut self, term: &mut Terminator) {
+    match term {
+      Terminator::Jump(bl, args, variant) => {
+        self.visit_block_id(bl);
+        for (v, _, o) in &mut **args { self.visit_var(v); self.visit_operand(o) }
+        if let Some(o) = variant { self.visit_operand(o) }
+      }
+      Terminator::Jump1(bl) => self.visit_block_id(bl),
+      Terminator::Return(outs, args) => {
+        for v in &mut **outs { self.visit_var(v) }
+        for (v, _, o) in &mut **args { self.visit_var(v); self.visit_operand(o) }
+      }
+      Terminator::Unreachable(o) | Terminator::Exit(o) => self.visit_operand(o),
+      Terminator::If(o, [(v1, bl1), (v2, bl2)]) => {
+        self.visit_operand(o);
+        self.visit_var(v1);
+        self.visit_block_id(bl1);
+        self.visit_var(v2);
+        self.visit_block_id(bl2)
+      }
+      Terminator::Assert(o, v, _, bl) => {
+        self.visit_operand(o);
+        self.visit_var(v);
+        self.visit_block_id(bl)
+      }
+      Terminator::Call { tys, args, tgt, rets, variant, .. } => {
+        for ty in &mut **tys { self.visit_ty(ty) }
+        for (_, o) in &mut **args { self.visit_operand(o) }
+        self.visit_block_id(tgt);
+        for (_, v) in &mut **rets { self.visit_var(v) }
+        if let Some(o) = variant { self.visit_operand(o) }
+      }
+      Terminator::Dead => {}
+    }
+  }
+
+  fn visit_basic_block(&mut self, bl: &mut BasicBlock) {
+    self.visit_ctx(&mut bl.ctx);
+    for s in &mut bl.stmts { self.visit_stmt(s) }
+    if let Some(t) = &mut bl.term { self.visit_terminator(t) }
+  }
+
+  fn visit_block_tree(&mut self, tree: &mut BlockTree) {
+    match tree {
+      BlockTree::LabelGroup(p) => {
+        for bl in &mut p.0 { self.visit_block_id(bl) }
+        for t in &mut p.1 { self.visit_block_tree(t) }
+      }
+      BlockTree::Many(trees) => for t in trees { self.visit_block_tree(t) },
+      BlockTree::One(bl) => self.visit_block_id(bl),
+    }
+  }
+}
+
 /// A basic block, which consists of an initial context (containing the logical parameters to the
 /// block), followed by a list of statements, and ending with a terminator. The terminator is
 /// optional only during MIR construction, and represents an "unfinished" block.
@@ -2032,6 +2357,8 @@ impl Default for BlockTree {
 pub struct Proc {
   /// The type of declaration: `func`, `proc`, or `intrinsic`.
   pub kind: ProcKind,
+  /// True if calls to this procedure should always be inlined.
+  pub inline: bool,
   /// The name of the procedure.
   pub name: Spanned<Symbol>,
   /// The number of type arguments
@@ -2043,6 +2370,8 @@ pub struct Proc {
   pub outs: Box<[u32]>,
   /// The return values of the procedure. (Functions and procedures return multiple values in MMC.)
   pub rets: Vec<Arg>,
+  /// The variant, which must decrease on recursive calls to this procedure.
+  pub variant: Option<Variant>,
   /// The body of the procedure.
   pub body: Cfg,
   /// The result of the allocation pass, created once optimization is done.
@@ -12,7 +12,7 @@ pub mod vcode;
 use std::borrow::Cow;
 use std::marker::PhantomData;
 use std::ops::{Index, IndexMut, Range};
-use num::{BigInt, Signed};
+use num::{BigInt, Signed, Zero};
 #[cfg(feature = "memory")] use mm0_deepsize_derive::DeepSizeOf;
 use crate::{FileSpan, Symbol};
 
@@ -464,6 +464,10 @@ pub enum Binop {
   Mul,
   /// Integer subtraction
   Sub,
+  /// Integer division, rounding toward zero. Division by zero returns `0`.
+  Div,
+  /// Integer remainder, with the sign of the dividend. `x % 0` returns `x`.
+  Mod,
   /// Maximum
   Max,
   /// Minimum
@@ -500,6 +504,8 @@ impl Binop {
       Binop::Add => "+",
       Binop::Mul => "*",
       Binop::Sub => "-",
+      Binop::Div => "//",
+      Binop::Mod => "%",
       Binop::Max => "max",
       Binop::Min => "min",
       Binop::And => "and",
@@ -519,7 +525,7 @@ impl Binop {
   /// Returns the type of this binop.
   #[must_use] pub fn ty(self) -> BinopType {
     match self {
-      Binop::Add | Binop::Mul | Binop::Sub |
+      Binop::Add | Binop::Mul | Binop::Sub | Binop::Div | Binop::Mod |
       Binop::Max | Binop::Min |
       Binop::BitAnd | Binop::BitOr | Binop::BitXor => BinopType::IntIntInt,
       Binop::Shl | Binop::Shr => BinopType::IntNatInt,
@@ -531,7 +537,7 @@ impl Binop {
   /// Returns true if this integral function returns a `nat` on nonnegative inputs.
   #[must_use] pub fn preserves_nat(self) -> bool {
     match self {
-      Binop::Add | Binop::Mul |
+      Binop::Add | Binop::Mul | Binop::Div | Binop::Mod |
       Binop::Max | Binop::Min |
       Binop::BitAnd | Binop::BitOr | Binop::BitXor |
       Binop::Shl | Binop::Shr => true,
@@ -547,6 +553,7 @@ impl Binop {
       Binop::Add | Binop::Mul |
       Binop::Max | Binop::Min |
       Binop::Shl | Binop::Sub => false,
+      Binop::Div | Binop::Mod |
       Binop::BitAnd | Binop::BitOr | Binop::BitXor | Binop::Shr => true,
       Binop::Lt | Binop::Le | Binop::Eq | Binop::Ne |
       Binop::And | Binop::Or => panic!("not an int -> int binop"),
@@ -561,6 +568,8 @@ impl Binop {
       Binop::Add => Some(n1 + n2),
       Binop::Mul => Some(n1 * n2),
       Binop::Sub => Some(n1 - n2),
+      Binop::Div => Some(if n2.is_zero() { BigInt::zero() } else { n1 / n2 }),
+      Binop::Mod => Some(if n2.is_zero() { n1.clone() } else { n1 % n2 }),
       Binop::Max => Some(n1.max(n2).clone()),
       Binop::Min => Some(n1.min(n2).clone()),
       Binop::BitAnd => Some(n1 & n2),
@@ -581,7 +590,7 @@ impl Binop {
       Binop::Le => n1 <= n2,
       Binop::Eq => n1 == n2,
       Binop::Ne => n1 != n2,
-      Binop::Add | Binop::Mul | Binop::Sub |
+      Binop::Add | Binop::Mul | Binop::Sub | Binop::Div | Binop::Mod |
       Binop::Max | Binop::Min |
       Binop::BitAnd | Binop::BitOr | Binop::BitXor |
       Binop::Shl | Binop::Shr |
@@ -593,7 +602,7 @@ impl Binop {
   /// Panics if it is not a `(bool, bool) -> bool` function.
   #[must_use] pub fn apply_bool_bool(self, b1: bool, b2: bool) -> bool {
     match self {
-      Binop::Add | Binop::Mul | Binop::Sub |
+      Binop::Add | Binop::Mul | Binop::Sub | Binop::Div | Binop::Mod |
       Binop::Max | Binop::Min |
       Binop::BitAnd | Binop::BitOr | Binop::BitXor |
       Binop::Shl | Binop::Shr |
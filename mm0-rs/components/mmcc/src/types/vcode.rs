@@ -2,7 +2,7 @@
 
 use std::{collections::HashMap, fmt::{Debug, Display}, iter::FromIterator};
 
-use crate::{Idx, types::{IdxVec, mir}, arch::PReg};
+use crate::{FileSpan, Idx, types::{IdxVec, mir}, arch::PReg};
 
 use mm0_util::u32_as_usize;
 pub(crate) use regalloc2::{RegClass, InstRange, Operand, Inst as InstId};
@@ -223,8 +223,29 @@ pub(crate) enum ArgAbi {
   },
 }
 
+/// The calling convention used by a procedure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum CallConv {
+  /// The internal calling convention, used for procedures that are only called from MMC code.
+  /// Return values are passed first, then the arguments, in the registers
+  /// `rax, rdi, rsi, rdx, rcx, r8, r9`, and anything left over goes on the stack.
+  Mmc,
+  /// The System V x86-64 calling convention (`extern "C"`), used for procedures exported from
+  /// a relocatable object. The procedure can be called from C with the prototype obtained by:
+  ///
+  /// * The first return value that fits in a register is returned in `rax`.
+  /// * Any other return value `T` becomes a leading `T *` out-parameter, in order.
+  /// * Arguments that fit in a register are passed by value, in order.
+  ///   Larger arguments `T` are passed by reference, as `const T *`.
+  /// * Ghost arguments and return values are omitted.
+  ///
+  /// The first six parameters go in `rdi, rsi, rdx, rcx, r8, r9`, and the rest on the stack in
+  /// 8 byte slots.
+  C,
+}
+
 /// The representation of a monomorphized function's calling convention.
-#[derive(Default, Debug)]
+#[derive(Clone, Default, Debug)]
 pub(crate) struct ProcAbi {
   /// The arguments of the procedure.
   pub(crate) args: Box<[ArgAbi]>,
@@ -251,6 +272,8 @@ pub struct VCode<I> {
   pub(crate) operands: ChunkVec<InstId, Operand>,
   pub(crate) num_vregs: usize,
   pub(crate) spills: IdxVec<SpillId, u32>,
+  /// The source spans of the MIR statements, with the first instruction generated for each.
+  pub(crate) spans: Vec<(InstId, FileSpan)>,
 }
 
 impl<I> Default for VCode<I> {
@@ -266,6 +289,7 @@ impl<I> Default for VCode<I> {
       operands: Default::default(),
       num_vregs: 0,
       spills: vec![0, 0].into(), // INCOMING, OUTGOING
+      spans: vec![],
     }
   }
 }
@@ -294,6 +318,16 @@ impl<I> VCode<I> {
     self.blocks.0.last_mut().expect("no blocks").1 = InstId::new(self.insts.len());
   }
 
+  /// Mark the next instruction as the start of the code for the source statement at `span`.
+  /// A previous span with no instructions is replaced.
+  pub fn set_span(&mut self, span: &FileSpan) {
+    let inst = InstId::new(self.insts.len());
+    match self.spans.last_mut() {
+      Some((i, sp)) if *i == inst => sp.clone_from(span),
+      _ => self.spans.push((inst, span.clone())),
+    }
+  }
+
   /// Make space in the outgoing argument stack region.
   pub fn mk_outgoing_spill(&mut self, sz: u32) {
     self.grow_spill(SpillId::OUTGOING, sz)
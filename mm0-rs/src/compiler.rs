@@ -21,7 +21,8 @@ use annotate_snippets::{
 use typed_arena::Arena;
 use clap::ArgMatches;
 use mm1_parser::{parse, ErrorLevel, ParseError};
-use crate::elab::{ElabError, ElabErrorKind, ElabResult, ElaborateBuilder};
+use crate::elab::{ElabError, ElabErrorKind, ElabResult, ElaborateBuilder, FileReader,
+  lisp::eval::Debugger};
 use crate::{ArcList, FileRef, FileSpan, FrozenEnv, LinedString, MutexExt, Position, Range, Span};
 use crate::mmb::import::elab as mmb_elab;
 use crate::mmu::import::elab as mmu_elab;
@@ -37,6 +38,14 @@ lazy_static! {
 
 static QUIET: AtomicBool = AtomicBool::new(false);
 
+/// The command line options that are passed to the elaborator of each file.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct ElabOptions {
+  /// The options for the output of the MMC compiler.
+  #[cfg(feature = "mmc")]
+  pub(crate) mmc: crate::mmc::Options,
+}
+
 /// The cached [`Environment`](crate::elab::Environment) representing a
 /// completed parse, or an incomplete parse.
 #[derive(DeepSizeOf)]
@@ -343,8 +352,13 @@ fn log_msg(#[allow(unused_mut)] mut s: String) {
 /// which will later be joined when the result is required.
 /// (**Note**: This can result in deadlock if the import graph has a cycle.)
 ///
+/// The `opts` are passed to the elaborator of this file and its imports. If `debugger` is set,
+/// it is attached to lisp evaluation in this file (but not its imports).
+///
 /// [`Ast`]: crate::parser::Ast
-async fn elaborate(path: FileRef, rd: ArcList<FileRef>) -> io::Result<ElabResult<()>> {
+async fn elaborate(path: FileRef, rd: ArcList<FileRef>, opts: ElabOptions,
+  debugger: Option<Box<dyn Debugger>>
+) -> io::Result<ElabResult<()>> {
   let (path, file) = VFS.get_or_insert(path)?;
   {
     let mut g = file.parsed.lock().await;
@@ -393,12 +407,20 @@ async fn elaborate(path: FileRef, rd: ArcList<FileRef>) -> io::Result<ElabResult
           if rd.contains(&p) {
             send.send(ElabResult::ImportCycle(rd.clone())).expect("failed to send");
           } else {
-            POOL.spawn_ok(elaborate_and_send(p.clone(), send, rd.clone()));
+            POOL.spawn_ok(elaborate_and_send(p.clone(), send, rd.clone(), opts));
             deps.push(p);
           }
           Ok(recv)
         },
         recv_goal: None,
+        recv_progress: None,
+        recv_input: Some(FileReader::new(|p| {
+          let file = VFS.get_or_insert(p)?.1;
+          Ok(file.text.try_ascii().cloned().ok_or("expected a text file")?)
+        })),
+        debugger,
+        #[cfg(feature = "mmc")]
+        mmc_options: opts.mmc,
       }.elab();
     let (cyc, _, errors, env) = fut.await;
     (cyc, errors, env)
@@ -436,26 +458,35 @@ async fn elaborate(path: FileRef, rd: ArcList<FileRef>) -> io::Result<ElabResult
 /// See [`elaborate`] for details on elaboration. This function encapsulates
 /// the `async fn` into a [`BoxFuture`], in order to avoid a recursion between
 /// this function and [`elaborate`] resulting in infinite sized futures.
-fn elaborate_and_send(path: FileRef, send: FSender<ElabResult<()>>, rd: ArcList<FileRef>) ->
-  BoxFuture<'static, ()> {
-  async {
-    if let Ok(env) = elaborate(path, rd).await {
+fn elaborate_and_send(path: FileRef,
+  send: FSender<ElabResult<()>>, rd: ArcList<FileRef>, opts: ElabOptions
+) -> BoxFuture<'static, ()> {
+  async move {
+    if let Ok(env) = elaborate(path, rd, opts, None).await {
       drop(send.send(env));
     }
   }.boxed()
 }
 
-/// Elaborate a file, and return the completed [`FrozenEnv`] result, along with the
-/// file contents.
-pub(crate) fn elab_for_result(path: FileRef) -> io::Result<(FileContents, Option<FrozenEnv>)> {
+/// Elaborate a file with the given options, and return the completed [`FrozenEnv`] result,
+/// along with the file contents.
+pub(crate) fn elab_for_result(path: FileRef, opts: ElabOptions
+) -> io::Result<(FileContents, Option<FrozenEnv>)> {
   let (path, file) = VFS.get_or_insert(path)?;
-  let env = match block_on(elaborate(path, Default::default()))? {
+  let env = match block_on(elaborate(path, Default::default(), opts, None))? {
     ElabResult::Ok(_, _, env) => Some(env),
     _ => None
   };
   Ok((file.text.clone(), env))
 }
 
+/// Elaborate a file with a lisp [`Debugger`] attached, and return the result.
+#[cfg(feature = "server")]
+pub(crate) fn elab_with_debugger(path: FileRef, debugger: Box<dyn Debugger>
+) -> io::Result<ElabResult<()>> {
+  block_on(elaborate(path, Default::default(), Default::default(), Some(debugger)))
+}
+
 /// Main entry point for `mm0-rs compile` subcommand.
 ///
 /// # Arguments
@@ -469,7 +500,14 @@ pub(crate) fn elab_for_result(path: FileRef) -> io::Result<(FileContents, Option
 pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
   let path = args.value_of("INPUT").expect("required arg");
   let path: FileRef = fs::canonicalize(path)?.into();
-  let (file, env) = elab_for_result(path.clone())?;
+  let opts = ElabOptions {
+    #[cfg(feature = "mmc")]
+    mmc: crate::mmc::Options {
+      dump: args.value_of("mmc_dump").and_then(crate::mmc::DumpKind::parse),
+      debug_elf: args.is_present("mmc_debug_elf"),
+    },
+  };
+  let (file, env) = elab_for_result(path.clone(), opts)?;
   let env = env.unwrap_or_else(|| std::process::exit(1));
   QUIET.store(args.is_present("quiet"), Ordering::Relaxed);
   if let Some(s) = args.value_of_os("output") {
@@ -0,0 +1,418 @@
+//! A [Debug Adapter Protocol] server for stepping through MM1 lisp evaluation.
+//!
+//! This is started as `mm0-rs debug-adapter` by an editor such as VS Code, which talks to it
+//! over stdin/stdout. The client `launch`es an MM1 file, which is elaborated on a separate
+//! thread with a [`Debugger`] attached to the lisp evaluator. Evaluation can be paused at
+//! function applications, either because of a breakpoint on a line or on a function name,
+//! a pause request, or a step command. While evaluation is paused, the elaboration thread
+//! waits for commands from the main thread, which it uses to answer the client's queries
+//! about the stack frames, local variables, goals and evaluation stack.
+//!
+//! [Debug Adapter Protocol]: https://microsoft.github.io/debug-adapter-protocol/
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use clap::ArgMatches;
+use serde_json::{json, Value};
+use crate::elab::{ElabResult, lisp::eval::{Debugger, DebugView}};
+use crate::{FileRef, FileSpan, LinedString, MutexExt, Span};
+
+/// The single thread reported to the client.
+const THREAD_ID: i64 = 1;
+
+/// The output half of the connection, which is shared between the main thread and
+/// the elaboration thread.
+struct Output {
+  w: Box<dyn Write + Send>,
+  seq: i64,
+}
+
+impl Output {
+  fn send(&mut self, mut msg: Value) {
+    self.seq += 1;
+    msg["seq"] = self.seq.into();
+    let s = msg.to_string();
+    drop(write!(self.w, "Content-Length: {}\r\n\r\n{}", s.len(), s).and_then(|()| self.w.flush()))
+  }
+
+  fn event(&mut self, event: &str, body: Value) {
+    self.send(json!({"type": "event", "event": event, "body": body}))
+  }
+
+  fn response(&mut self, req: &Value, res: Result<Value, String>) {
+    let mut msg = json!({
+      "type": "response",
+      "request_seq": req["seq"],
+      "command": req["command"],
+      "success": res.is_ok(),
+    });
+    match res {
+      Ok(body) => msg["body"] = body,
+      Err(e) => msg["message"] = e.into(),
+    }
+    self.send(msg)
+  }
+}
+
+/// The breakpoints set by the client.
+#[derive(Default)]
+struct Breakpoints {
+  /// The line breakpoints in each file, using zero-based line numbers.
+  lines: HashMap<PathBuf, Vec<u32>>,
+  /// The function breakpoints, by name.
+  functions: Vec<String>,
+}
+
+/// The state shared between the main thread and the elaboration thread.
+struct Shared {
+  out: Mutex<Output>,
+  bps: Mutex<Breakpoints>,
+  /// True if evaluation is currently paused (and so is waiting for [`Command`]s).
+  paused: AtomicBool,
+  /// Set by the client to request that evaluation pause at the next opportunity.
+  pause_req: AtomicBool,
+  /// True if line numbers are one-based in the protocol (the default).
+  lines_start_at_1: AtomicBool,
+  /// True if column numbers are one-based in the protocol (the default).
+  columns_start_at_1: AtomicBool,
+}
+
+/// How evaluation should proceed after a pause.
+#[derive(Copy, Clone, Debug)]
+enum Step {
+  /// Run until the next breakpoint.
+  Continue,
+  /// Stop at the next function application.
+  In,
+  /// Stop at the next function application at the given depth or shallower.
+  Over(usize),
+  /// Stop at the next function application shallower than the given depth.
+  Out(usize),
+}
+
+/// A request from the main thread to the paused elaboration thread.
+enum Command {
+  StackTrace(Sender<Value>),
+  Scopes(usize, Sender<Value>),
+  Variables(usize, Sender<Value>),
+  /// Resume evaluation. The function is given the current depth and returns the step mode.
+  Resume(fn(usize) -> Step),
+}
+
+/// The [`Debugger`] attached to the lisp evaluator, which runs on the elaboration thread.
+struct DapDebugger {
+  shared: Arc<Shared>,
+  recv: Receiver<Command>,
+  step: Step,
+  /// The call depth of the last pause.
+  depth: usize,
+  /// The reason for the last pause, as reported to the client.
+  reason: &'static str,
+  /// The last line a breakpoint was hit on, so that we don't stop again on the same line.
+  last_line: Option<(FileRef, u32)>,
+  /// The source text of files, for converting spans to line numbers.
+  files: HashMap<FileRef, Option<LinedString>>,
+}
+
+impl DapDebugger {
+  fn source(&mut self, file: &FileRef) -> Option<&LinedString> {
+    self.files.entry(file.clone())
+      .or_insert_with(|| std::fs::read_to_string(file.path()).ok().map(Into::into))
+      .as_ref()
+  }
+
+  fn line_of(&mut self, file: &FileRef, pos: usize) -> Option<u32> {
+    Some(self.source(file)?.to_pos(pos).line)
+  }
+
+  fn check_breakpoints(&mut self, file: &FileRef, sp: Span, name: Option<&[u8]>
+  ) -> Option<&'static str> {
+    let shared = self.shared.clone();
+    let bps = shared.bps.ulock();
+    if let Some(name) = name {
+      if bps.functions.iter().any(|f| f.as_bytes() == name) { return Some("function breakpoint") }
+    }
+    let lines = match bps.lines.get(file.path()) {
+      Some(lines) if !lines.is_empty() => lines,
+      _ => { self.last_line = None; return None }
+    };
+    let line = self.line_of(file, sp.start)?;
+    if matches!(&self.last_line, Some((f, l)) if f == file && *l == line) { return None }
+    self.last_line = None;
+    if lines.contains(&line) {
+      self.last_line = Some((file.clone(), line));
+      return Some("breakpoint")
+    }
+    None
+  }
+
+  fn stack_trace(&mut self, frames: &[FileSpan], names: &[String]) -> Value {
+    let l1 = self.shared.lines_start_at_1.load(Ordering::Relaxed);
+    let c1 = self.shared.columns_start_at_1.load(Ordering::Relaxed);
+    let frames = frames.iter().zip(names).enumerate().map(|(i, (loc, name))| {
+      let (line, column) = self.source(&loc.file).map_or((0, 0), |src| {
+        let pos = src.to_pos(loc.span.start);
+        (pos.line + u32::from(l1), pos.character + u32::from(c1))
+      });
+      json!({
+        "id": i,
+        "name": name,
+        "line": line,
+        "column": column,
+        "source": {"name": loc.file.rel(), "path": loc.file.path()},
+      })
+    }).collect::<Vec<_>>();
+    json!({"stackFrames": frames, "totalFrames": frames.len()})
+  }
+}
+
+/// The kinds of variable lists shown for each stack frame. Variable references are
+/// `1 + SCOPES.len() * frame + kind`, because `0` means "no variables".
+const SCOPES: [&str; 3] = ["Locals", "Goals", "Stack"];
+
+fn variables(view: &DebugView<'_, '_>, reference: usize) -> Value {
+  let r = reference.saturating_sub(1);
+  #[allow(clippy::integer_division)]
+  let (frame, kind) = (r / SCOPES.len(), r % SCOPES.len());
+  let numbered = |prefix: &str, values: Vec<String>| values.into_iter().enumerate()
+    .map(|(i, value)| (format!("{}{}", prefix, i), value)).collect();
+  let values = match kind {
+    0 => view.locals(frame),
+    1 if frame == 0 => numbered("?g", view.goals()),
+    2 if frame == 0 => numbered("#", view.stack()),
+    _ => vec![],
+  };
+  let vars = values.into_iter().map(|(name, value)| json!({
+    "name": name,
+    "value": value,
+    "variablesReference": 0,
+  })).collect::<Vec<_>>();
+  json!({"variables": vars})
+}
+
+impl Debugger for DapDebugger {
+  fn should_break(&mut self, depth: usize, file: &FileRef, sp: Span, name: Option<&[u8]>) -> bool {
+    let reason = if self.shared.pause_req.swap(false, Ordering::Relaxed) { Some("pause") } else {
+      match self.step {
+        Step::In => Some("step"),
+        Step::Over(d) if depth <= d => Some("step"),
+        Step::Out(d) if depth < d => Some("step"),
+        _ => None,
+      }
+    };
+    match reason.or_else(|| self.check_breakpoints(file, sp, name)) {
+      Some(reason) => { self.reason = reason; self.depth = depth; true }
+      None => false
+    }
+  }
+
+  fn pause(&mut self, view: DebugView<'_, '_>) {
+    let (frames, names): (Vec<_>, Vec<_>) =
+      view.frames().into_iter().map(|f| (f.loc, f.name)).unzip();
+    if self.reason == "step" {
+      if let Some(line) = self.line_of(&frames[0].file, frames[0].span.start) {
+        self.last_line = Some((frames[0].file.clone(), line))
+      }
+    }
+    self.shared.paused.store(true, Ordering::SeqCst);
+    self.shared.out.ulock().event("stopped", json!({
+      "reason": self.reason,
+      "threadId": THREAD_ID,
+      "allThreadsStopped": true,
+    }));
+    loop {
+      match self.recv.recv() {
+        Ok(Command::StackTrace(send)) => drop(send.send(self.stack_trace(&frames, &names))),
+        Ok(Command::Scopes(frame, send)) => {
+          let n = if frame == 0 { SCOPES.len() } else { 1 };
+          let scopes = SCOPES[..n].iter().enumerate().map(|(i, name)| json!({
+            "name": name,
+            "variablesReference": 1 + SCOPES.len() * frame + i,
+            "expensive": false,
+          })).collect::<Vec<_>>();
+          drop(send.send(json!({"scopes": scopes})))
+        }
+        Ok(Command::Variables(reference, send)) => drop(send.send(variables(&view, reference))),
+        Ok(Command::Resume(f)) => { self.step = f(self.depth); break }
+        // The main thread has exited, so there is nobody to resume us
+        Err(_) => std::process::exit(0),
+      }
+    }
+    self.shared.paused.store(false, Ordering::SeqCst);
+  }
+}
+
+fn read_message(r: &mut impl BufRead) -> io::Result<Option<Value>> {
+  let mut len = None;
+  let mut line = String::new();
+  loop {
+    line.clear();
+    if r.read_line(&mut line)? == 0 { return Ok(None) }
+    let line = line.trim_end();
+    if line.is_empty() { break }
+    if let Some(n) = line.strip_prefix("Content-Length:") {
+      len = Some(n.trim().parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?)
+    }
+  }
+  let len = len.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+  let mut buf = vec![0; len];
+  r.read_exact(&mut buf)?;
+  Ok(Some(serde_json::from_slice(&buf)?))
+}
+
+/// Get a writer for the protocol stream. Anything else printed to stdout (such as the
+/// diagnostics printed by the compiler) would corrupt the stream, so on unix we move the
+/// protocol to a duplicate of stdout, and point stdout at stderr instead.
+fn protocol_output() -> io::Result<Box<dyn Write + Send>> {
+  #[cfg(unix)] unsafe {
+    use std::os::unix::io::FromRawFd;
+    let fd = libc::dup(1);
+    if fd < 0 || libc::dup2(2, 1) < 0 { return Err(io::Error::last_os_error()) }
+    Ok(Box::new(std::fs::File::from_raw_fd(fd)))
+  }
+  #[cfg(not(unix))] Ok(Box::new(io::stdout()))
+}
+
+/// Elaborate `path` with a debugger attached, and report the results to the client.
+fn run(shared: &Arc<Shared>, path: FileRef, recv: Receiver<Command>) {
+  let dbg = DapDebugger {
+    shared: shared.clone(), recv,
+    step: Step::Continue,
+    depth: 0,
+    reason: "",
+    last_line: None,
+    files: HashMap::new(),
+  };
+  let output = |s: String|
+    shared.out.ulock().event("output", json!({"category": "console", "output": s}));
+  match crate::compiler::elab_with_debugger(path.clone(), Box::new(dbg)) {
+    Err(e) => output(format!("error: {}\n", e)),
+    Ok(ElabResult::Ok(_, errors, _)) => {
+      let src = std::fs::read_to_string(path.path()).ok().map(LinedString::from);
+      for e in errors.iter().flat_map(|e| &**e) {
+        let pos = src.as_ref().map_or_else(Default::default, |src| src.to_pos(e.pos.start));
+        output(format!("{}:{}:{}: {}: {}\n", path.path().display(), pos.line + 1, pos.character + 1,
+          e.level, e.kind.msg()))
+      }
+    }
+    Ok(ElabResult::Canceled) => output("elaboration canceled\n".into()),
+    Ok(ElabResult::ImportCycle(_)) => output("import cycle\n".into()),
+  }
+  let mut out = shared.out.ulock();
+  out.event("exited", json!({"exitCode": 0}));
+  out.event("terminated", json!({}));
+}
+
+/// Send a command to the paused elaboration thread, and wait for the response.
+fn query(shared: &Shared, cmd: Option<&Sender<Command>>, f: impl FnOnce(Sender<Value>) -> Command
+) -> Result<Value, String> {
+  if !shared.paused.load(Ordering::SeqCst) { return Err("not paused".into()) }
+  let (send, recv) = channel();
+  cmd.and_then(|cmd| cmd.send(f(send)).ok())
+    .and_then(|()| recv.recv().ok())
+    .ok_or_else(|| "not paused".into())
+}
+
+/// Main entry point for `mm0-rs debug-adapter` subcommand.
+pub fn main(_: &ArgMatches<'_>) -> io::Result<()> {
+  let shared = Arc::new(Shared {
+    out: Mutex::new(Output { w: protocol_output()?, seq: 0 }),
+    bps: Default::default(),
+    paused: AtomicBool::new(false),
+    pause_req: AtomicBool::new(false),
+    lines_start_at_1: AtomicBool::new(true),
+    columns_start_at_1: AtomicBool::new(true),
+  });
+  let stdin = io::stdin();
+  let mut stdin = stdin.lock();
+  let mut program = None;
+  let mut send_cmd: Option<Sender<Command>> = None;
+  while let Some(req) = read_message(&mut stdin)? {
+    if req["type"] != "request" { continue }
+    let args = &req["arguments"];
+    let command = req["command"].as_str().unwrap_or_default();
+    let resume = |f: fn(usize) -> Step| {
+      if let Some(cmd) = &send_cmd {
+        if shared.paused.load(Ordering::SeqCst) { drop(cmd.send(Command::Resume(f))) }
+      }
+      Ok(json!({"allThreadsContinued": true}))
+    };
+    let res = match command {
+      "initialize" => {
+        for (flag, key) in [
+          (&shared.lines_start_at_1, "linesStartAt1"),
+          (&shared.columns_start_at_1, "columnsStartAt1"),
+        ] {
+          flag.store(args[key].as_bool().unwrap_or(true), Ordering::Relaxed)
+        }
+        let mut out = shared.out.ulock();
+        out.response(&req, Ok(json!({
+          "supportsConfigurationDoneRequest": true,
+          "supportsFunctionBreakpoints": true,
+        })));
+        out.event("initialized", json!({}));
+        continue
+      }
+      "launch" => match args["program"].as_str().map(std::fs::canonicalize) {
+        None => Err("missing 'program' argument".into()),
+        Some(Err(e)) => Err(format!("cannot open program: {}", e)),
+        Some(Ok(p)) => { program = Some(FileRef::from(p)); Ok(json!({})) }
+      },
+      "setBreakpoints" => {
+        let path = args["source"]["path"].as_str()
+          .map(|p| std::fs::canonicalize(p).unwrap_or_else(|_| p.into()));
+        let base = u64::from(shared.lines_start_at_1.load(Ordering::Relaxed));
+        let lines = args["breakpoints"].as_array().map_or_else(Vec::new, |bps| bps.iter()
+          .filter_map(|bp| bp["line"].as_u64()?.checked_sub(base)?.try_into().ok())
+          .collect::<Vec<u32>>());
+        let res = lines.iter().map(|&l| json!({"verified": true, "line": u64::from(l) + base}))
+          .collect::<Vec<_>>();
+        if let Some(path) = path { shared.bps.ulock().lines.insert(path, lines); }
+        Ok(json!({"breakpoints": res}))
+      }
+      "setFunctionBreakpoints" => {
+        let names = args["breakpoints"].as_array().map_or_else(Vec::new, |bps| bps.iter()
+          .filter_map(|bp| Some(bp["name"].as_str()?.to_owned())).collect::<Vec<_>>());
+        let res = names.iter().map(|_| json!({"verified": true})).collect::<Vec<_>>();
+        shared.bps.ulock().functions = names;
+        Ok(json!({"breakpoints": res}))
+      }
+      "setExceptionBreakpoints" => Ok(json!({"breakpoints": []})),
+      "configurationDone" => match program.take() {
+        Some(path) => {
+          let (send, recv) = channel();
+          send_cmd = Some(send);
+          let shared = shared.clone();
+          std::thread::spawn(move || run(&shared, path, recv));
+          Ok(json!({}))
+        }
+        None => Err("no program launched".into())
+      },
+      "threads" => Ok(json!({"threads": [{"id": THREAD_ID, "name": "elaborator"}]})),
+      "stackTrace" => query(&shared, send_cmd.as_ref(), Command::StackTrace),
+      "scopes" => {
+        let frame = args["frameId"].as_u64().and_then(|n| n.try_into().ok()).unwrap_or(0);
+        query(&shared, send_cmd.as_ref(), |send| Command::Scopes(frame, send))
+      }
+      "variables" => {
+        let r = args["variablesReference"].as_u64().and_then(|n| n.try_into().ok()).unwrap_or(0);
+        query(&shared, send_cmd.as_ref(), |send| Command::Variables(r, send))
+      }
+      "continue" => resume(|_| Step::Continue),
+      "next" => resume(Step::Over),
+      "stepIn" => resume(|_| Step::In),
+      "stepOut" => resume(Step::Out),
+      "pause" => { shared.pause_req.store(true, Ordering::Relaxed); Ok(json!({})) }
+      "disconnect" | "terminate" => {
+        shared.out.ulock().response(&req, Ok(json!({})));
+        std::process::exit(0)
+      }
+      _ => Err(format!("unsupported request '{}'", command)),
+    };
+    shared.out.ulock().response(&req, res);
+  }
+  Ok(())
+}
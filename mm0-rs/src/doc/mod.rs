@@ -594,7 +594,7 @@ impl<'a, W: Write> BuildDoc<'a, W> {
       let fe = FormatEnv {source: self.source, env: &self.env};
       match *s {
         StmtTrace::Global(_) |
-        StmtTrace::OutputString(_) => {}
+        StmtTrace::OutputString(_) | StmtTrace::InputString(_) => {}
         StmtTrace::Sort(a) => {
           let ad = &self.env.data[a];
           write!(file, "    <div id=\"")?;
@@ -655,7 +655,7 @@ impl<'a, W: Write> BuildDoc<'a, W> {
 pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
   let path = args.value_of("INPUT").expect("required arg");
   let path: FileRef = fs::canonicalize(path)?.into();
-  let (fc, old) = crate::compiler::elab_for_result(path.clone())?;
+  let (fc, old) = crate::compiler::elab_for_result(path.clone(), Default::default())?;
   let old = old.unwrap_or_else(|| std::process::exit(1));
   println!("writing docs");
   let mut env = Environment::new();
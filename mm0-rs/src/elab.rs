@@ -16,12 +16,12 @@ pub mod refine;
 pub mod proof;
 pub mod inout;
 pub mod verify;
+pub mod tactic;
 
 
 use std::collections::HashMap;
 use std::mem;
 use std::ops::{Deref, DerefMut};
-use std::path::PathBuf;
 use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
 use std::{future::Future, pin::Pin, task::{Context, Poll}};
 use std::time::{Duration, Instant};
@@ -36,11 +36,11 @@ use lisp::LispVal;
 use local_context::try_get_span_opt;
 use crate::{ArcList, ArcString, AtomId, BoxError, Coe, DeclKey, DocComment, EnvMergeIter,
   Environment, ErrorLevel, Expr, ExprNode, FileRef, FileSpan, FrozenEnv,
-  FrozenLispVal, LocalContext, Modifiers, NotaInfo, ObjectKind, Prec,
+  FrozenLispVal, LinedString, LocalContext, Modifiers, NotaInfo, ObjectKind, Prec,
   Proof, ProofNode, Remap, Remapper, SortId, Span, Term, TermId, Thm, ThmId};
 
 #[cfg(feature = "server")]
-use {crate::LinedString, lsp_types::{Diagnostic, DiagnosticRelatedInformation, Location}};
+use lsp_types::{Diagnostic, DiagnosticRelatedInformation, Location};
 
 /// An error payload.
 #[derive(Debug, DeepSizeOf)]
@@ -217,6 +217,57 @@ impl std::fmt::Debug for GoalListener {
   }
 }
 
+/// A progress event, passed to a [`ProgressListener`] as elaboration moves through the file.
+#[derive(Copy, Clone, Debug)]
+pub enum ElabProgress<'a> {
+  /// Elaboration is about to start on a statement.
+  Stmt {
+    /// The index of the statement in the file
+    idx: usize,
+    /// The total number of statements in the file
+    total: usize,
+    /// The byte offset of the start of the statement
+    pos: usize,
+  },
+  /// Elaboration is waiting for an imported file to be elaborated.
+  Import(&'a FileRef),
+}
+
+/// A function that gets called on elaboration progress events.
+#[allow(clippy::type_complexity)]
+pub struct ProgressListener(Box<dyn for<'a> FnMut(&'a Elaborator, ElabProgress<'a>)>);
+
+impl ProgressListener {
+  /// Creates a new [`ProgressListener`] from a callback.
+  pub fn new(f: impl for<'a> FnMut(&'a Elaborator, ElabProgress<'a>) + 'static) -> Self {
+    Self(Box::new(f))
+  }
+}
+
+impl std::fmt::Debug for ProgressListener {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    "ProgressListener".fmt(f)
+  }
+}
+
+/// A function that gets called to read the file named by `input-file` for an
+/// `input string` command, returning the file's text.
+#[allow(clippy::type_complexity)]
+pub struct FileReader(Box<dyn FnMut(FileRef) -> Result<Arc<LinedString>, BoxError>>);
+
+impl FileReader {
+  /// Creates a new [`FileReader`] from a callback.
+  pub fn new(f: impl FnMut(FileRef) -> Result<Arc<LinedString>, BoxError> + 'static) -> Self {
+    Self(Box::new(f))
+  }
+}
+
+impl std::fmt::Debug for FileReader {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    "FileReader".fmt(f)
+  }
+}
+
 /// The [`Elaborator`] struct contains the working data for elaboration, and is the
 /// main interface to MM1 operations (along with [`Evaluator`](lisp::eval::Evaluator),
 /// which a lisp execution context).
@@ -257,6 +308,15 @@ pub struct Elaborator {
   arena: lisp::LispArena,
   /// A listener for goal view events.
   recv_goal: Option<GoalListener>,
+  /// A listener for elaboration progress events.
+  recv_progress: Option<ProgressListener>,
+  /// A function for reading the file named by `input-file`.
+  recv_input: Option<FileReader>,
+  /// A debugger for lisp evaluation.
+  debugger: Option<Box<dyn lisp::eval::Debugger>>,
+  /// The options for the output of the MMC compiler.
+  #[cfg(feature = "mmc")]
+  pub(crate) mmc_options: crate::mmc::Options,
 }
 
 impl Deref for Elaborator {
@@ -302,6 +362,11 @@ impl Elaborator {
       reporting: ReportMode::new(),
       arena: Default::default(),
       recv_goal,
+      recv_progress: None,
+      recv_input: None,
+      debugger: None,
+      #[cfg(feature = "mmc")]
+      mmc_options: Default::default(),
     }
   }
 
@@ -316,6 +381,9 @@ impl Elaborator {
     if self.reporting.active(e.level) {self.errors.push(e)}
   }
 
+  /// The errors that have been reported so far.
+  #[must_use] pub fn errors(&self) -> &[ElabError] { &self.errors }
+
   fn push_spans(&mut self) {
     self.env.spans.push(mem::take(&mut self.spans));
   }
@@ -327,6 +395,13 @@ impl Elaborator {
     }
   }
 
+  fn call_progress_listener(&mut self, p: ElabProgress<'_>) {
+    if let Some(mut listener) = self.recv_progress.take() {
+      listener.0(self, p);
+      self.recv_progress = Some(listener);
+    }
+  }
+
   fn name_of(&mut self, stmt: &Stmt) -> LispVal {
     match &stmt.k {
       StmtKind::Annot(_, s) => self.name_of(s),
@@ -650,6 +725,17 @@ pub struct ElaborateBuilder<'a, F> {
   /// to transfer an [`Environment`] containing the elaborated theorems, as well as any
   /// extra data `T`, which is collected and passed through the function.
   pub recv_goal: Option<GoalListener>,
+  /// A listener which is called before each statement is elaborated, and when elaboration
+  /// blocks on an import, to report the progress of elaboration through the file.
+  pub recv_progress: Option<ProgressListener>,
+  /// A function which is called when an `input string` command needs the text of the
+  /// file named by `input-file`. If it is `None`, such commands report an error.
+  pub recv_input: Option<FileReader>,
+  /// A debugger to attach to lisp evaluation in this file.
+  pub debugger: Option<Box<dyn lisp::eval::Debugger>>,
+  /// The options for the output of the MMC compiler, such as the files written by `mmc-finish`.
+  #[cfg(feature = "mmc")]
+  pub mmc_options: crate::mmc::Options,
 }
 
 impl<'a, T: Send, F> ElaborateBuilder<'a, F>
@@ -762,11 +848,17 @@ where F: FnMut(FileRef) -> Result<Receiver<ElabResult<T>>, BoxError> {
           let ast = elab.ast.clone();
           while let Some(s) = ast.stmts.get(*idx) {
             if elab.cancel.load(Ordering::Relaxed) {break}
-            match elab.elab_stmt(String::new(), s, s.span) {
+            elab.call_progress_listener(ElabProgress::Stmt {
+              idx: *idx, total: ast.stmts.len(), pos: s.span.start});
+            let start = Instant::now();
+            let res = elab.elab_stmt(String::new(), s, s.span);
+            elab.spans.elab_time = start.elapsed();
+            match res {
               Ok(ElabStmt::Ok) => {}
               Ok(ElabStmt::Import(sp)) => {
                 if let Some((file, recv)) = recv.remove(&sp) {
                   elab.spans.insert(sp, ObjectKind::Import(file.clone()));
+                  elab.call_progress_listener(ElabProgress::Import(&file));
                   *progress = UnfinishedStmt::Import(sp, file, recv);
                   elab.push_spans();
                   continue 'l
@@ -791,12 +883,14 @@ where F: FnMut(FileRef) -> Result<Receiver<ElabResult<T>>, BoxError> {
     let mut recv = HashMap::new();
     let mut elab = Elaborator::new(self.ast.clone(),
       self.path, self.mm0_mode, self.check_proofs, self.cancel, self.recv_goal);
+    elab.recv_progress = self.recv_progress;
+    elab.recv_input = self.recv_input;
+    elab.debugger = self.debugger;
+    #[cfg(feature = "mmc")] { elab.mmc_options = self.mmc_options }
     elab.arena.install_thread_local();
     for &(sp, ref f) in &self.ast.imports {
       (|| -> Result<_> {
-        let f = std::str::from_utf8(f).map_err(|e| ElabError::new_e(sp, e))?;
-        let path = elab.path.path().parent().map_or_else(|| PathBuf::from(f), |p| p.join(f));
-        let r: FileRef = path.canonicalize().map_err(|e| ElabError::new_e(sp, e))?.into();
+        let r = elab.path.resolve_import(f).map_err(|e| ElabError::new_e(sp, e))?;
         let tok = recv_dep(r.clone()).map_err(|e| ElabError::new_e(sp, e))?;
         recv.insert(sp, (r, tok));
         Ok(())
@@ -190,6 +190,34 @@ impl ProofNode {
       }
     }
   }
+
+  /// The number of nodes in this [`ProofNode`], not counting [`Ref`](ProofNode::Ref)
+  /// nodes (which point to shared heap elements that are counted separately).
+  #[must_use] pub fn size(&self) -> usize {
+    fn sum(args: &[ProofNode]) -> usize { args.iter().map(ProofNode::size).sum() }
+    match self {
+      ProofNode::Ref(_) => 0,
+      ProofNode::Dummy(_, _) => 1,
+      ProofNode::Term {args, ..} | ProofNode::Cong {args, ..} => 1 + sum(args),
+      ProofNode::Hyp(_, e) | ProofNode::Refl(e) | ProofNode::Sym(e) => 1 + e.size(),
+      ProofNode::Thm {args, res, ..} => 1 + sum(args) + res.size(),
+      ProofNode::Conv(p) => 1 + p.0.size() + p.1.size() + p.2.size(),
+      ProofNode::Unfold {args, res, ..} => 1 + sum(args) + res.0.size() + res.1.size(),
+    }
+  }
+
+  /// Returns true if `f` returns true on any theorem applied in this [`ProofNode`].
+  /// This does not follow [`Ref`](ProofNode::Ref) nodes.
+  pub fn any_thm(&self, f: &mut impl FnMut(ThmId) -> bool) -> bool {
+    match self {
+      ProofNode::Ref(_) | ProofNode::Dummy(_, _) | ProofNode::Term {..} => false,
+      ProofNode::Hyp(_, e) | ProofNode::Refl(e) | ProofNode::Sym(e) => e.any_thm(f),
+      ProofNode::Cong {args, ..} => args.iter().any(|e| e.any_thm(f)),
+      &ProofNode::Thm {thm, ref args, ..} => f(thm) || args.iter().any(|e| e.any_thm(f)),
+      ProofNode::Conv(p) => p.1.any_thm(f) || p.2.any_thm(f),
+      ProofNode::Unfold {res, ..} => res.1.any_thm(f),
+    }
+  }
 }
 
 impl From<&ExprNode> for ProofNode {
@@ -223,6 +251,19 @@ pub struct Proof {
   pub head: ProofNode,
 }
 
+impl Proof {
+  /// The size of the proof, counted as the number of [`ProofNode`]s after
+  /// deduplication (so each shared subterm on the heap is counted once).
+  #[must_use] pub fn size(&self) -> usize {
+    self.heap.iter().chain(&*self.hyps).map(ProofNode::size).sum::<usize>() + self.head.size()
+  }
+
+  /// Returns true if `f` returns true on any theorem applied in this proof.
+  pub fn any_thm(&self, mut f: impl FnMut(ThmId) -> bool) -> bool {
+    self.heap.iter().chain(&*self.hyps).any(|e| e.any_thm(&mut f)) || self.head.any_thm(&mut f)
+  }
+}
+
 /// The proof of the axiom or theorem.
 #[derive(Clone, Debug, DeepSizeOf)]
 pub enum ThmKind {
@@ -269,15 +310,15 @@ pub struct Thm {
   pub kind: ThmKind,
 }
 
-/// An `output string` directive, which is anonymous and hence stored directly
-/// in the [`StmtTrace`] list.
+/// An `input string` or `output string` directive, which is anonymous and hence
+/// stored directly in the [`StmtTrace`] list.
 #[derive(Clone, Debug, DeepSizeOf)]
 pub struct OutputString {
   /// The span of the full statement.
   pub span: FileSpan,
   /// The heap of expressions used in the `exprs`.
   pub heap: Box<[ExprNode]>,
-  /// The expressions to output.
+  /// The expressions to input or output.
   pub exprs: Box<[ExprNode]>,
 }
 
@@ -292,7 +333,9 @@ pub enum StmtTrace {
   /// A global lisp declaration in a `do` block, i.e. `do { (def foo 1) };`
   Global(AtomId),
   /// An `output string` directive.
-  OutputString(Box<OutputString>)
+  OutputString(Box<OutputString>),
+  /// An `input string` directive.
+  InputString(Box<OutputString>),
 }
 
 /// A declaration is either a [`Term`] or a [`Thm`]. This is done because in MM1
@@ -1052,6 +1095,21 @@ impl Environment {
   #[must_use] pub fn thm(&self, a: AtomId) -> Option<ThmId> {
     if let Some(DeclKey::Thm(i)) = self.data[a].decl { Some(i) } else { None }
   }
+
+  /// Determine for each theorem whether it depends on `sorry`, that is, whether it
+  /// or some theorem it transitively references is a `theorem` with no proof.
+  #[must_use] pub fn uses_sorry(&self) -> ThmVec<bool> {
+    let mut out = ThmVec::default();
+    for td in &self.thms.0 {
+      let b = match &td.kind {
+        ThmKind::Axiom => false,
+        ThmKind::Thm(None) => true,
+        ThmKind::Thm(Some(pf)) => pf.any_thm(|t| out.get(t).copied().unwrap_or(false)),
+      };
+      out.push(b);
+    }
+    out
+  }
 }
 
 /// Adding an item (sort, term, theorem, atom) can result in a redeclaration error,
@@ -1290,6 +1348,7 @@ impl Environment {
       },
       StmtTrace::Global(_) => {}
       StmtTrace::OutputString(ref e) => self.stmts.push(StmtTrace::OutputString(e.remap(remap))),
+      StmtTrace::InputString(ref e) => self.stmts.push(StmtTrace::InputString(e.remap(remap))),
     }
     Ok(())
   }
@@ -40,7 +40,7 @@ use num::BigInt;
 use crate::{mk_lisp_kind, ArcString, AtomData, AtomId, AtomVec, DeclKey, DocComment, Environment,
   FileSpan, LinedString, LispData, LispKind, LispVal, MergeStrategy, MergeStrategyInner, ParserEnv, Sort,
   SortId, SortVec, Span, StmtTrace, Term, TermId, TermVec, Thm, ThmId, ThmVec,
-  lisp::{print::FormatEnv, Annot, InferTarget, LispRef, LispWeak, Proc, Syntax}};
+  lisp::{print::FormatEnv, Annot, InferTarget, LispKey, LispRef, LispWeak, Proc, Syntax}};
 use super::{ObjectKind, Remap, Remapper, Spans};
 
 /// A "frozen" environment, which is a thread-safe read only
@@ -97,6 +97,8 @@ impl FrozenEnv {
   #[must_use] pub fn thm(&self, t: ThmId) -> &Thm { &self.thms()[t] }
   /// Accessor for [`Environment::stmts`]
   #[must_use] pub fn stmts(&self) -> &[StmtTrace] { &unsafe { self.thaw() }.stmts }
+  /// Accessor for [`Environment::uses_sorry`]
+  #[must_use] pub fn uses_sorry(&self) -> ThmVec<bool> { unsafe { self.thaw() }.uses_sorry() }
   /// Parse a string into an atom.
   #[must_use] pub fn get_atom(&self, s: &[u8]) -> Option<AtomId> { unsafe { self.thaw() }.atoms.get(s).copied() }
   /// Accessor for [`Environment::pe`]
@@ -366,6 +368,8 @@ impl Remap for FrozenLispKind {
       FrozenLispKind::Annot(sp, m) => LispVal::new(LispKind::Annot(sp.clone(), m.remap(r))),
       FrozenLispKind::Proc(f) => LispVal::proc(f.remap(r)),
       FrozenLispKind::AtomMap(m) => LispVal::new(LispKind::AtomMap(m.remap(r))),
+      FrozenLispKind::HashMap(m) => LispVal::new(LispKind::HashMap(
+        m.iter().map(|(k, v)| (k.remap(r), v.remap(r))).collect())),
       FrozenLispKind::Ref(m) => match r.refs.entry(m as *const _) {
         Entry::Occupied(e) => e.get().clone(),
         Entry::Vacant(e) => {
@@ -406,6 +410,7 @@ impl Remap for FrozenProc {
       Proc::MatchCont(_) => Proc::MatchCont(Rc::new(Cell::new(false))),
       Proc::RefineCallback => Proc::RefineCallback,
       Proc::MergeMap(m) => Proc::MergeMap(unsafe {freeze_merge_strategy(m)}.remap(r)),
+      Proc::Macro(f) => Proc::Macro(f.remap(r)),
       Proc::ProofThunk(x, m) => Proc::ProofThunk(x.remap(r), RefCell::new(
         match &*unsafe { m.try_borrow_unguarded() }.expect("failed to deref ref") {
           Ok(e) => Ok(e.remap(r)),
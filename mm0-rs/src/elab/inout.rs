@@ -1,11 +1,12 @@
 //! Support for the `input` and `output` commands.
 
 use std::io;
+use std::sync::Arc;
 use super::proof::{Dedup, NodeHasher, ProofKind, build};
-use crate::{DeclKey, SortId, TermId, Type, Expr, ExprNode,
-  TermKind, OutputString, StmtTrace, Environment, FileSpan, BoxError};
+use crate::{AtomId, DeclKey, SortId, TermId, Type, Expr, ExprNode,
+  TermKind, OutputString, StmtTrace, Environment, FileSpan, BoxError, LinedString};
 use super::{ElabError, Elaborator, Span, HashMap, Result as EResult, SExpr,
-  lisp::{InferTarget, LispVal}, local_context::try_get_span, FrozenEnv};
+  lisp::{InferTarget, LispKind, LispVal}, local_context::try_get_span, FrozenEnv};
 
 /// The elaboration data used by input/output commands. This caches precomputed
 /// evaluations of `output string` commands.
@@ -330,7 +331,8 @@ impl Elaborator {
     let_unchecked!(Some((s, map)) = &mut self.inout.string, Ok((*s, map)))
   }
 
-  fn elab_output_string(&mut self, sp: Span, hs: &[SExpr]) -> EResult<()> {
+  /// Elaborate the arguments of an `input string` or `output string` command.
+  fn elab_string_exprs(&mut self, sp: Span, hs: &[SExpr]) -> EResult<OutputString> {
     let (sorts, _) = self.get_string_handler(sp)?;
     let fsp = self.fspan(sp);
     let mut es = Vec::with_capacity(hs.len());
@@ -351,8 +353,58 @@ impl Elaborator {
       .collect::<EResult<Vec<_>>>()?;
     let (mut ids, heap) = build(&de);
     let exprs = is.into_iter().map(|i| ids[i].take()).collect();
-    self.stmts.push(StmtTrace::OutputString(
-      Box::new(OutputString {span: fsp, heap, exprs})));
+    Ok(OutputString {span: fsp, heap, exprs})
+  }
+
+  fn elab_output_string(&mut self, sp: Span, hs: &[SExpr]) -> EResult<()> {
+    let os = self.elab_string_exprs(sp, hs)?;
+    self.stmts.push(StmtTrace::OutputString(Box::new(os)));
+    Ok(())
+  }
+
+  /// Get the text that `input string` is checked against: the contents of the file named
+  /// by the `input-file` global (relative to the current file) if it is set, or else the
+  /// text of the current file, if it is an MM0 file. The input file is read through the
+  /// [`FileReader`](super::FileReader), so that it is tracked as a dependency of this file.
+  fn input_text(&mut self, sp: Span) -> EResult<(String, Arc<LinedString>)> {
+    let file = match &self.data[AtomId::INPUT_FILE].lisp {
+      Some(data) => data.val.unwrapped(|e| if let LispKind::String(s) = e { Ok(s.clone()) } else {
+        Err(ElabError::new_e(sp, "input-file: expected a string"))
+      })?,
+      None if self.path.has_extension("mm0") =>
+        return Ok((self.path.rel().into(), self.ast.source.clone())),
+      None => return Err(ElabError::new_e(sp,
+        "input string: set 'input-file' to the path of the MM0 file to check against")),
+    };
+    let name = String::from_utf8_lossy(&file).into_owned();
+    let reader = self.recv_input.as_mut().ok_or_else(|| ElabError::new_e(sp,
+      "input string: reading input files is not supported here"))?;
+    let text = self.path.resolve_import(&file).map_err(Into::into).and_then(|p| reader.0(p))
+      .map_err(|e| ElabError::new_e(sp, format!("input string: could not read '{}': {}", name, e)))?;
+    Ok((name, text))
+  }
+
+  fn elab_input_string(&mut self, sp: Span, hs: &[SExpr]) -> EResult<()> {
+    let os = self.elab_string_exprs(sp, hs)?;
+    let mut w = StringWriter::<Vec<u8>>::default();
+    let terms = &self.inout.string.as_ref().expect("string handler should be initialized").1;
+    self.env.write_output_string(terms, &mut w, &os.heap, &os.exprs).map_err(|e| match e {
+      OutputError::IoError(e) => panic!("{}", e),
+      OutputError::String(e) => ElabError::new_e(sp, e),
+    })?;
+    if w.hex.is_some() {
+      return Err(ElabError::new_e(sp, "input string: expression has an odd number of hex digits"))
+    }
+    let (name, text) = self.input_text(sp)?;
+    let text = text.as_bytes();
+    if w.w != text {
+      let i = w.w.iter().zip(text).take_while(|(a, b)| a == b).count();
+      let line = text[..i].split(|&c| c == b'\n').count();
+      return Err(ElabError::new_e(sp, format!(
+        "input string does not match '{}': first difference at byte {} (line {}), \
+         expected {} bytes, got {}", name, i, line, text.len(), w.w.len())))
+    }
+    self.stmts.push(StmtTrace::InputString(Box::new(os)));
     Ok(())
   }
 
@@ -398,11 +450,20 @@ impl Elaborator {
     }
   }
 
-  /// Elaborate an `input` command. This is not implemented, as it needs to work with the
-  /// final MM0 file, which is not available. More design work is needed.
-  #[allow(clippy::unused_self)]
-  pub fn elab_input(&mut self, _: Span, kind: Span, _: &[SExpr]) -> EResult<()> {
-    Err(ElabError::new_e(kind, "unsupported input kind"))
+  /// Elaborate an `input` command. Since the MM0 file that will be checked against is
+  /// not known during elaboration, `input string` is checked against the file given by
+  /// the `input-file` global (see [`input_text`](Self::input_text)). Other input kinds
+  /// are passed to the `elab-input` callback, if it is defined.
+  pub fn elab_input(&mut self, sp: Span, kind: Span, hs: &[SExpr]) -> EResult<()> {
+    if self.span(kind) == b"string" { return self.elab_input_string(sp, hs) }
+    let proc = match &self.data[AtomId::ELAB_INPUT].lisp {
+      Some(e) => e.val.clone(),
+      None => return Err(ElabError::new_e(kind, "unsupported input kind")),
+    };
+    let mut args = vec![LispVal::atom(self.env.get_atom(self.ast.span(kind)))];
+    for f in hs { args.push(self.eval_lisp(false, f)?) }
+    self.call_func(sp, &proc, args)?;
+    Ok(())
   }
 }
 
@@ -429,3 +490,102 @@ impl FrozenEnv {
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+  use std::sync::{Arc, Mutex};
+  use futures::channel::oneshot::Receiver;
+  use crate::elab::{ElabResult, ElaborateBuilder, FileReader};
+  use crate::{BoxError, FileRef, FrozenEnv, LinedString};
+
+  const PREAMBLE: &str = "delimiter $ ( ) $;
+    sort hex; sort char; sort string;
+    term x0: hex; term x1: hex; term x2: hex; term x3: hex;
+    term x4: hex; term x5: hex; term x6: hex; term x7: hex;
+    term x8: hex; term x9: hex; term xa: hex; term xb: hex;
+    term xc: hex; term xd: hex; term xe: hex; term xf: hex;
+    term ch: hex > hex > char;
+    term s0: string; term s1: char > string; term sadd: string > string > string;
+    do { (def input-file \"input.mm0\") };\n";
+
+  /// Elaborates `src` as `test.mm1` in a fresh directory containing `input.mm0` with
+  /// contents `input`. Returns the error messages, the files passed to the
+  /// [`FileReader`], and the final environment.
+  fn elab_input(name: &str, input: &str, src: &str) -> (Vec<String>, Vec<FileRef>, FrozenEnv) {
+    let dir = std::env::temp_dir().join(format!("mm0-rs-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).expect("failed to create test directory");
+    std::fs::write(dir.join("input.mm0"), input).expect("failed to write input file");
+    let path = FileRef::from(dir.canonicalize().expect("bad directory").join("test.mm1"));
+    let text = Arc::new(LinedString::from(format!("{}{}", PREAMBLE, src)));
+    let ast = Arc::new(mm1_parser::parse(text, None).1);
+    assert!(ast.errors.is_empty());
+    let read = Arc::new(Mutex::new(vec![]));
+    let (_, _, errs, env) = futures::executor::block_on(ElaborateBuilder {
+      ast: &ast,
+      path,
+      mm0_mode: false,
+      check_proofs: true,
+      report_upstream_errors: false,
+      cancel: Arc::default(),
+      old: None,
+      recv_dep: |_| -> Result<Receiver<ElabResult<()>>, BoxError> { Err("no imports".into()) },
+      recv_goal: None,
+      recv_progress: None,
+      recv_input: Some({
+        let read = read.clone();
+        FileReader::new(move |p| {
+          let text = std::fs::read_to_string(p.path())?;
+          read.lock().expect("poisoned").push(p);
+          Ok(Arc::new(text.into()))
+        })
+      }),
+      debugger: None,
+      #[cfg(feature = "mmc")]
+      mmc_options: Default::default(),
+    }.elab());
+    std::fs::remove_dir_all(&dir).expect("failed to remove test directory");
+    let read = std::mem::take(&mut *read.lock().expect("poisoned"));
+    (errs.into_iter().map(|e| e.kind.msg()).collect(), read, env)
+  }
+
+  #[test]
+  fn input_string() {
+    let (errs, read, env) = elab_input("input-ok", "a\n",
+      "input string: $ sadd (s1 (ch x6 x1)) (s1 (ch x0 xa)) $;");
+    assert_eq!(errs, Vec::<String>::new());
+    assert_eq!(read.len(), 1);
+    assert!(read[0].path().ends_with("input.mm0"));
+    assert!(env.stmts().iter().any(|s| matches!(s, crate::StmtTrace::InputString(_))));
+
+    let (errs, _, _) = elab_input("input-bad", "ab",
+      "input string: $ sadd (s1 (ch x6 x1)) (s1 (ch x0 xa)) $;");
+    assert_eq!(errs, ["input string does not match 'input.mm0': first difference at byte 1 \
+      (line 1), expected 2 bytes, got 2"]);
+  }
+
+  #[test]
+  fn input_string_mmb() {
+    use crate::mmb::{export::Exporter, import};
+    let (errs, _, env) = elab_input("input-mmb", "a",
+      "input string: $ s1 (ch x6 x1) $;");
+    assert_eq!(errs, Vec::<String>::new());
+    let file = FileRef::from(PathBuf::from("test.mmb"));
+    let mut out = std::io::Cursor::new(vec![]);
+    let mut report = |_, msg: &str| panic!("{}", msg);
+    let mut ex = Exporter::new(file.clone(), None, &env, &mut report, &mut out);
+    ex.run(true).expect("export failed");
+    ex.finish().expect("export failed");
+    let (res, env2) = import::elab(&file, out.get_ref());
+    assert!(res.is_ok());
+    assert_eq!(env2.terms.len(), env.terms().len());
+    let is = env2.stmts.iter().find_map(|s| match s {
+      crate::StmtTrace::InputString(is) => Some(is),
+      _ => None
+    }).expect("input string was not exported");
+    let mut w = super::StringWriter {w: vec![], hex: None};
+    let (_, terms) = env2.new_string_handler().expect("string handler");
+    env2.write_output_string(&terms, &mut w, &is.heap, &is.exprs).expect("bad input string");
+    assert_eq!(w.w, b"a");
+  }
+}
@@ -11,7 +11,7 @@ pub mod print;
 pub mod pretty;
 
 use std::ops::{Deref, DerefMut};
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
 use std::rc::{Rc, Weak};
 use std::cell::{Cell, RefCell};
 use std::sync::{Arc, Mutex};
@@ -20,7 +20,7 @@ use num::BigInt;
 use owning_ref::{OwningRef, StableAddress, CloneStableAddress};
 use crate::{ast::Atom, ArcString, AtomId, FileSpan, MergeStrategy, MergeStrategyInner, Modifiers,
   MutexExt, Remap, Remapper, SliceExt, Span, StackList};
-use parser::Ir;
+use parser::Code;
 pub use super::math_parser::{QExpr, QExprKind};
 
 macro_rules! str_enum {
@@ -121,6 +121,20 @@ str_enum! {
     ///   overwriting the originals but preserving any keys not in `new`.
     ///   * This can also be used as `(merge-map strat)` where `strat` is a subsidiary merge strategy.
     SetMergeStrategy: "set-merge-strategy",
+    /// `(try e handler)` evaluates `e`, and if it throws an error, it instead returns
+    /// `(handler err)`, where `err` is the list `('error msg)` tagged with the span of the
+    /// error. Timeouts, cancellation and stack overflows are not caught. The goals and
+    /// metavariables (and their assignments) are reset to what they were at the start of the
+    /// `try`, but other side effects of `e` (such as mutation of other refs) are not undone.
+    Try: "try",
+    /// `(defmacro (m args) body)` defines a global macro `m`. A use `(m e1 e2 ...)` of the macro
+    /// is replaced, before compilation, by the result of calling `(fn (args) body)` on the
+    /// unevaluated expressions `'e1`, `'e2`, ... . `(defmacro m f)` uses the procedure `f`
+    /// as the expansion function. The expansion is hygienic: local variables bound in the
+    /// expansion are not visible to the expressions that came from the arguments, and
+    /// identifiers introduced by the expansion refer to global definitions, even if a local
+    /// variable with the same name is in scope where the macro is used.
+    DefMacro: "defmacro",
   }
 }
 
@@ -132,6 +146,7 @@ impl Syntax {
       Atom::Quote => Ok(Syntax::Quote),
       Atom::Unquote => Ok(Syntax::Unquote),
       Atom::Nfx => Err(b":nfx"),
+      Atom::Gen(_) => Err(s),
     }
   }
 }
@@ -275,6 +290,9 @@ macro_rules! mk_lisp_kind {
       /// A map from atoms to values. This can be used as a mutable map if it is behind a
       /// [`Ref`](Self::Ref).
       AtomMap(HashMap<AtomId, $val>),
+      /// A map from immutable lisp values to values, using structural equality on keys.
+      /// This can be used as a mutable map if it is behind a [`Ref`](Self::Ref).
+      HashMap(HashMap<LispKey, $val>),
       /// A mutable reference. This is the only way to have mutable values in
       /// client code.
       Ref($ref_),
@@ -671,6 +689,23 @@ impl LispKind {
   pub fn is_map(&self) -> bool {
     self.unwrapped(|e| matches!(e, LispKind::AtomMap(_)))
   }
+  /// Returns true if this value is a hash map.
+  pub fn is_hash_map(&self) -> bool {
+    self.unwrapped(|e| matches!(e, LispKind::HashMap(_)))
+  }
+  /// Returns true if this value is immutable, that is, it contains no refs, procedures,
+  /// maps, metavariables or goals. Only immutable values can be used as hash map keys.
+  pub fn is_immutable(&self) -> bool {
+    match self {
+      LispKind::Atom(_) | LispKind::Number(_) | LispKind::String(_) |
+      LispKind::Bool(_) | LispKind::Syntax(_) | LispKind::Undef => true,
+      LispKind::List(es) => es.iter().all(|e| e.is_immutable()),
+      LispKind::DottedList(es, r) => es.iter().all(|e| e.is_immutable()) && r.is_immutable(),
+      LispKind::Annot(_, e) => e.is_immutable(),
+      LispKind::Proc(_) | LispKind::AtomMap(_) | LispKind::HashMap(_) |
+      LispKind::Ref(_) | LispKind::MVar(..) | LispKind::Goal(_) => false,
+    }
+  }
   /// Returns true if this value is not `#undef` or a reference to `#undef`.
   pub fn is_def(&self) -> bool {
     self.unwrapped(|e| !matches!(e, LispKind::Undef))
@@ -832,12 +867,74 @@ impl PartialEq<LispKind> for LispKind {
           }
         }
       }
-      _ => false // Goal, Proc, MVar, AtomMap all have only reference equality
+      _ => false // Goal, Proc, MVar, AtomMap, HashMap all have only reference equality
     }))
   }
 }
 impl Eq for LispKind {}
 
+impl LispKind {
+  /// Hash an immutable value, consistently with `==`.
+  fn hash_structural<H: Hasher>(&self, state: &mut H) {
+    match self {
+      LispKind::Atom(a) => {1_u8.hash(state); a.hash(state)}
+      LispKind::Number(n) => {2_u8.hash(state); n.hash(state)}
+      LispKind::String(s) => {3_u8.hash(state); s.hash(state)}
+      LispKind::Bool(b) => {4_u8.hash(state); b.hash(state)}
+      LispKind::Syntax(s) => {5_u8.hash(state); s.to_str().hash(state)}
+      LispKind::Undef => 6_u8.hash(state),
+      LispKind::List(_) | LispKind::DottedList(..) => {7_u8.hash(state); self.hash_list(state)}
+      LispKind::Annot(_, e) => e.hash_structural(state),
+      LispKind::Proc(_) | LispKind::AtomMap(_) | LispKind::HashMap(_) |
+      LispKind::Ref(_) | LispKind::MVar(..) | LispKind::Goal(_) => {}
+    }
+  }
+
+  /// Hash the elements of a list, such that `(a b . (c d))` and `(a b c d)` hash the same.
+  fn hash_list<H: Hasher>(&self, state: &mut H) {
+    match self {
+      LispKind::List(es) => {
+        for e in &**es {e.hash_structural(state)}
+        0_u8.hash(state)
+      }
+      LispKind::DottedList(es, r) => {
+        for e in &**es {e.hash_structural(state)}
+        r.hash_list(state)
+      }
+      LispKind::Annot(_, e) => e.hash_list(state),
+      _ => {u8::MAX.hash(state); self.hash_structural(state)}
+    }
+  }
+}
+
+/// A key in a [`LispKind::HashMap`]. This is a wrapper around an immutable [`LispVal`]
+/// (see [`LispKind::is_immutable`]), which is hashed and compared structurally.
+#[derive(Clone, Debug, EnvDebug, DeepSizeOf)]
+pub struct LispKey(LispVal);
+
+impl LispKey {
+  /// Construct a hash map key from a lisp value, if it is immutable.
+  #[must_use] pub fn new(e: LispVal) -> Option<LispKey> {
+    if e.is_immutable() {Some(LispKey(e))} else {None}
+  }
+  /// Get the lisp value underlying this key.
+  #[must_use] pub fn val(&self) -> &LispVal { &self.0 }
+}
+
+impl PartialEq for LispKey {
+  fn eq(&self, other: &LispKey) -> bool { self.0 == other.0 }
+}
+impl Eq for LispKey {}
+
+impl Hash for LispKey {
+  fn hash<H: Hasher>(&self, state: &mut H) { self.0.hash_structural(state) }
+}
+
+impl Remap for LispKey {
+  type Target = Self;
+  fn remap(&self, r: &mut Remapper) -> Self { LispKey(self.0.remap(r)) }
+}
+
 /// An annotation, which is a tag placed on lisp values that is ignored by all
 /// the basic functions.
 #[derive(Clone, Debug, EnvDebug, DeepSizeOf)]
@@ -893,13 +990,14 @@ pub enum Proc {
     pos: ProcPos,
     /// The local environment, that was captured at the time of the lambda's
     /// creation. This allows `fn`'s to be closures and not just named functions.
+    /// Only the variables that are used in the body are captured.
     env: Box<[LispVal]>,
     /// The procedure's specification, a poor man's function signature.
     /// As the language is untyped, the only real information we have here
     /// is how many arguments are expected.
     spec: ProcSpec,
     /// The code of the procedure.
-    code: Arc<[Ir]>
+    code: Arc<Code>
   },
   /// A match continuation, which is passed to client code in the variable `k`
   /// of `(match e [pat (=> k) code])`. It is a *delimited* continuation, which means
@@ -918,6 +1016,9 @@ pub enum Proc {
   RefineCallback,
   /// A partially applied `(merge-map f)` invocation.
   MergeMap(MergeStrategy),
+  /// A macro, created by `defmacro` or `(macro f)`, with the given expansion function.
+  /// When called as a regular function it performs the expansion.
+  Macro(LispVal),
   /// A delayed proof, generated by a call to `get-decl`, which returns a lisp
   /// data structure reflecting the requested definition, but delays the proof
   /// unless forced by calling this thunk. The unevaluated form of the thunk
@@ -965,6 +1066,10 @@ impl Proc {
       Proc::MatchCont(_) |
       Proc::ProofThunk(_, _) => ProcSpec::AtLeast(0),
       Proc::MergeMap(_) => ProcSpec::Exact(2),
+      Proc::Macro(f) => f.unwrapped(|f| match f {
+        LispKind::Proc(f) => f.spec(),
+        _ => ProcSpec::AtLeast(0),
+      }),
       Proc::RefineCallback => ProcSpec::AtLeast(1),
       #[cfg(feature = "mmc")]
       Proc::MmcCompiler(_) => ProcSpec::AtLeast(1),
@@ -1112,6 +1217,61 @@ str_enum! {
     /// (list->string '(98 97 114)) -- "bar"
     /// ```
     ListToString: "list->string",
+    /// `(number->string n)` converts a number to a string in decimal, and
+    /// `(number->string n radix)` converts it to a string in the given radix, which
+    /// must be between 2 and 36. Digits above 9 are written using lowercase letters.
+    /// ```metamath-zero
+    /// (number->string 255 16)    -- "ff"
+    /// (number->string (- 5) 2)   -- "-101"
+    /// ```
+    NumberToString: "number->string",
+    /// `(string-upcase s)` converts all ASCII letters in the string to uppercase.
+    /// ```metamath-zero
+    /// (string-upcase "foo-Bar") -- "FOO-BAR"
+    /// ```
+    StringUpcase: "string-upcase",
+    /// `(string-downcase s)` converts all ASCII letters in the string to lowercase.
+    /// ```metamath-zero
+    /// (string-downcase "foo-Bar") -- "foo-bar"
+    /// ```
+    StringDowncase: "string-downcase",
+    /// `(string-index pat s)` returns the index of the first occurrence of the string `pat`
+    /// in `s`, or `#f` if it does not occur. `(string-index pat s start)` returns the index
+    /// of the first occurrence at or after `start`.
+    /// ```metamath-zero
+    /// (string-index "o" "hello world")   -- 4
+    /// (string-index "o" "hello world" 5) -- 7
+    /// (string-index "x" "hello world")   -- #f
+    /// ```
+    StringIndex: "string-index",
+    /// `(string-split sep s)` splits the string `s` at every occurrence of the
+    /// (nonempty) string `sep`, returning a list of strings.
+    /// ```metamath-zero
+    /// (string-split "," "a,b,,c") -- ("a" "b" "" "c")
+    /// ```
+    StringSplit: "string-split",
+    /// `(string-join sep l)` stringifies all the elements of the list `l`, and appends them
+    /// with the string `sep` between each element.
+    /// ```metamath-zero
+    /// (string-join ", " '(a "b" 1)) -- "a, b, 1"
+    /// ```
+    StringJoin: "string-join",
+    /// `(string-replace from to s)` replaces all occurrences of the (nonempty) string `from`
+    /// in `s` by the string `to`.
+    /// ```metamath-zero
+    /// (string-replace "o" "0" "foo bar") -- "f00 bar"
+    /// ```
+    StringReplace: "string-replace",
+    /// `(format fmt e1 e2 e3)` returns the string `fmt` with each `{}` placeholder replaced
+    /// by the next argument, stringified as in `string-append`. The placeholder `{pp}`
+    /// instead pretty-prints the argument as in `pp`, using declared math notations,
+    /// and `{{` and `}}` are escapes for literal braces.
+    /// ```metamath-zero
+    /// (format "{} + {} = {}" 2 2 4)  -- "2 + 2 = 4"
+    /// (format "{{{}}}" 'foo)         -- "{foo}"
+    /// (format "goal: {pp}" $ ph -> ph $) -- "goal: ph -> ph"
+    /// ```
+    Format: "format",
     /// `(not e1 e2 e3)` returns `#f` if any argument is truthy, and `#t` otherwise.
     /// It is not short-circuiting.
     Not: "not",
@@ -1187,7 +1347,8 @@ str_enum! {
     IsAtomMap: "atom-map?",
     /// `(atom-map! [k1 v1] [k2 v2] ...)` creates a new mutable atom map, a key-value store.
     NewAtomMap: "atom-map!",
-    /// * `(lookup m k)` gets the value stored in the atom map `m` at `k`, or `#undef` if not present.
+    /// * `(lookup m k)` gets the value stored in the atom map or hash map `m` at `k`,
+    ///   or `#undef` if not present.
     /// * `(lookup m k v)` will return `v` instead if the key is not present,
     ///   unless `v` is a procedure, in which case it will be called with no arguments on lookup failure.
     Lookup: "lookup",
@@ -1200,6 +1361,36 @@ str_enum! {
     ///   with the value `v` inserted at key `k`.
     /// * `(insert m k)` returns `k` erased from `m`.
     InsertNew: "insert",
+    /// `(remove! m k)` erases the key `k` from the mutable map `m`, and returns the
+    /// value that was stored there, or `#undef` if it was not present.
+    Remove: "remove!",
+    /// `(hash-map? m)` is true if the argument is a hash map.
+    IsHashMap: "hash-map?",
+    /// `(hash-map! [k1 v1] [k2 v2] ...)` creates a new mutable hash map. Unlike atom maps,
+    /// the keys can be any immutable values (numbers, strings, atoms, lists of these, etc.),
+    /// and they are compared using `==`; using a mutable value as a key is an error.
+    /// The other map functions like `lookup` and `insert!` work on both kinds of map.
+    NewHashMap: "hash-map!",
+    /// `(hash-set! k1 k2 ...)` creates a new mutable hash map that maps each `ki` to `#t`.
+    /// This is the representation of sets used by `map-union`, `map-intersect` and `map-diff`.
+    NewHashSet: "hash-set!",
+    /// `(map-size m)` returns the number of keys in the atom map or hash map `m`.
+    MapSize: "map-size",
+    /// `(map->list m)` returns the list of `(k v)` pairs in the atom map or hash map `m`,
+    /// sorted by key. Atoms are sorted by name, and keys of different kinds are ordered
+    /// atoms, numbers, strings, booleans, syntax forms, `#undef`, lists.
+    MapToList: "map->list",
+    /// `(map-has? m k)` is true if the atom map or hash map `m` contains the key `k`.
+    MapHas: "map-has?",
+    /// `(map-union m1 m2 ...)` returns a new immutable hash map containing the keys of all the
+    /// hash maps `mi`. If a key is present in more than one map, the value from the last one is used.
+    MapUnion: "map-union",
+    /// `(map-intersect m1 m2)` returns a new immutable hash map containing the entries of `m1`
+    /// whose keys are also in `m2`.
+    MapIntersect: "map-intersect",
+    /// `(map-diff m1 m2)` returns a new immutable hash map containing the entries of `m1`
+    /// whose keys are not in `m2`.
+    MapDiff: "map-diff",
     /// This function is intended for use in `set-merge-strategy`, and will merge atom-maps.
     ///
     /// * `(merge-map old new)` will add all keys in the atom-map `new` to `old`, returning
@@ -1208,6 +1399,9 @@ str_enum! {
     /// * `(merge-map f old new)` or `((merge-map f) old new)` will use
     ///   `(f oldval newval)` to resolve keys that are present in both maps.
     MergeMap: "merge-map",
+    /// `(macro f)` returns a macro whose expansion function is `f`. This is usually used
+    /// via `defmacro`; macros only expand when they are referenced by a global definition.
+    Macro: "macro",
     /// `(set-timeout n)` sets the timeout for running individual theorems and
     /// `do` blocks to `n` milliseconds. The default is 5 seconds.
     SetTimeout: "set-timeout",
@@ -1342,6 +1536,64 @@ str_enum! {
     /// effect as the top level command `output string: e1 e2 ...;` but this command is only
     /// triggered on a compile, while `eval-string` works also in server mode.
     EvalString: "eval-string",
+    /// `(register-eq s eq refl trans [mp])` registers the equality scheme used by `rw` and
+    /// `simp` for sort `s`. `eq` is a binary term on `s`, and `refl`, `trans`, `mp` are
+    /// theorems of the form `eq a a`, `(h1: eq a b) (h2: eq b c): eq a c` and
+    /// `(h1: eq a b) (h2: b): a` respectively. `mp` is only needed for provable sorts,
+    /// in order to rewrite goals.
+    RegisterEq: "register-eq",
+    /// `(register-congr t thm)` registers `thm` as the congruence theorem for term `t`,
+    /// which should have the form `(h1: eq a1 b1) ... (hn: eq an bn):
+    /// eq (t a1 ... an) (t b1 ... bn)`. Arguments without a hypothesis are not rewritten.
+    RegisterCongr: "register-congr",
+    /// `(register-simp thm)` adds `thm` to the global `simp-lemmas`, the default lemma set
+    /// of `simp`. It is intended for use in annotations: with the usual definition
+    /// `(def (annotate e s) (e s))`, a theorem can be marked using `@register-simp`.
+    RegisterSimp: "register-simp",
+    /// * `(rw lemmas)` rewrites the first goal bottom-up using the list of theorems `lemmas`,
+    ///   which must have no hypotheses and a conclusion of the form `eq a b` for a
+    ///   registered equality `eq`. Each subterm is rewritten at most once. It returns a proof
+    ///   term for the goal, to be passed to `refine`, which leaves the rewritten goal as
+    ///   a new goal (or closes it if it has the form `eq a a`), and reports which lemmas fired.
+    /// * `(rw lemmas e)` rewrites expression `e` instead, and returns `(e2 p used)`
+    ///   where `p` proves `eq e e2` (or is `#undef` if nothing was rewritten)
+    ///   and `used` is the list of lemmas that fired.
+    Rw: "rw",
+    /// `(simp)`, `(simp lemmas)`, `(simp lemmas e)` are the same as `rw`, but rewrite
+    /// to a fixed point. If `lemmas` is omitted or `#undef`, the theorems in the
+    /// global `simp-lemmas` (see `register-simp`) are used.
+    Simp: "simp",
+    /// `(auto lemmas [depth])` searches for a proof of the first goal by backward chaining,
+    /// using the hypotheses in the local context and the list of theorems `lemmas`, with
+    /// theorem applications nested at most `depth` deep (default 5). It returns the proof
+    /// as an s-expression to be passed to `refine`, and fails if no proof was found.
+    Auto: "auto",
+    /// `(unfold t [occs])` unfolds the definition `t` in the first goal, replacing the goal
+    /// with the unfolded statement. `occs` is a list of the occurrences of `t` to unfold,
+    /// numbered from 1 in pre-order; by default all occurrences are unfolded.
+    Unfold: "unfold",
+    /// `(fold t [occs])` is the reverse of `unfold`: it folds instances of the body of
+    /// the definition `t` in the first goal back into applications of `t`.
+    Fold: "fold",
+    /// `(conv-unfold t e [occs])` and `(conv-fold t e [occs])` are the same as `unfold`
+    /// and `fold` but act on the expression `e`, returning a conversion proof from `e`
+    /// to the result. Conversions use the syntax of elaborated proofs: an expression
+    /// `e` proves `e = e`, `(t c1 ... cn)` is congruence, `(:sym c)` is symmetry and
+    /// `(:unfold t es c)` unfolds `(t es)`.
+    ConvUnfold: "conv-unfold",
+    /// `(conv-fold t e [occs])` folds the definition `t` in `e`. See `conv-unfold`.
+    ConvFold: "conv-fold",
+    /// `(conv-at e path c)` applies the conversion `c` inside `e` at the subterm
+    /// given by `path`, a list of argument positions (numbered from 1), returning a
+    /// conversion on `e`. The left side of `c` must be the subterm at `path`.
+    ConvAt: "conv-at",
+    /// `(conv-lhs c)` returns the left side of the conversion `c`.
+    ConvLhs: "conv-lhs",
+    /// `(conv-rhs c)` returns the right side of the conversion `c`.
+    ConvRhs: "conv-rhs",
+    /// `(conv-goal c)` changes the first goal using the conversion `c`, whose left side
+    /// must be the goal, replacing it with the right side of `c`.
+    ConvGoal: "conv-goal",
     /// `(mmc-init)` returns a new compiler object, which is itself a procedure that can
     /// be called to compile MMC functions. See [`Compiler::call`].
     ///
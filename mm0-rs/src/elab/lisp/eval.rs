@@ -18,9 +18,9 @@ use crate::elab::local_context::{try_get_span, try_get_span_from, AwaitingProof,
 use crate::elab::{
   refine::{RStack, RState, RefineResult},
   ElabErrorKind, ReportMode, Result};
-use super::parser::{Ir, MVarPattern};
+use super::parser::{Code, Ir, MVarPattern};
 use super::print::FormatEnv;
-use super::{Arc, BuiltinProc, Cell, InferTarget, LispKind, LispRef, LispVal, Modifiers, Proc,
+use super::{Arc, BuiltinProc, Cell, InferTarget, LispKey, LispKind, LispRef, LispVal, Modifiers, Proc,
   ProcPos, ProcSpec, QExpr, Rc, RefCell, Uncons};
 
 #[derive(Debug)]
@@ -32,6 +32,25 @@ struct MergeMapData {
   k: Option<AtomId>,
 }
 
+#[derive(Debug)]
+struct TryData {
+  /// The error handler
+  handler: LispVal,
+  /// The span of the `try` keyword
+  sp: Span,
+  /// The number of local variable slots (of all frames) at the start of the block
+  ctx: usize,
+  /// The goals at the start of the block
+  goals: Vec<LispVal>,
+  /// The metavariables at the start of the block
+  mvars: Vec<LispVal>,
+  /// The values of the metavariable and goal references at the start of the block,
+  /// so that assignments made by a failed tactic can be undone
+  assignments: Vec<(LispVal, LispVal)>,
+  /// The location at which to continue after the handler returns
+  tgt: usize,
+}
+
 #[derive(Debug)]
 enum Stack {
   Undef,
@@ -49,6 +68,7 @@ enum Stack {
   AddThmProc(Box<AwaitingProof>),
   Refine(Span, Vec<RStack>),
   Focus(Span, Vec<LispVal>),
+  Try(Box<TryData>),
 }
 
 impl From<bool> for Stack {
@@ -119,10 +139,38 @@ impl crate::EnvDisplay for Stack {
       Stack::AddThmProc(ap) => write!(f, "(add-thm {})", fe.to(&ap.atom())),
       Stack::Refine(_, rs) => write!(f, "(refine {})", fe.to(rs)),
       Stack::Focus(_, es) => write!(f, "(focus {})", fe.to(es)),
+      Stack::Try(t) => write!(f, "(try {})", fe.to(&t.handler)),
     }
   }
 }
 
+/// The map types that can be stored in a [`LispKind`]: atom maps and hash maps.
+trait LispMap: Clone + Sized {
+  fn project(e: &LispKind) -> Option<&Self>;
+  fn project_mut(e: &mut LispKind) -> Option<&mut Self>;
+  fn inject(self) -> LispKind;
+}
+
+impl LispMap for HashMap<AtomId, LispVal> {
+  fn project(e: &LispKind) -> Option<&Self> {
+    if let LispKind::AtomMap(m) = e {Some(m)} else {None}
+  }
+  fn project_mut(e: &mut LispKind) -> Option<&mut Self> {
+    if let LispKind::AtomMap(m) = e {Some(m)} else {None}
+  }
+  fn inject(self) -> LispKind { LispKind::AtomMap(self) }
+}
+
+impl LispMap for HashMap<LispKey, LispVal> {
+  fn project(e: &LispKind) -> Option<&Self> {
+    if let LispKind::HashMap(m) = e {Some(m)} else {None}
+  }
+  fn project_mut(e: &mut LispKind) -> Option<&mut Self> {
+    if let LispKind::HashMap(m) = e {Some(m)} else {None}
+  }
+  fn inject(self) -> LispKind { LispKind::HashMap(self) }
+}
+
 impl LispKind {
   fn as_ref_mut<T>(&self, f: impl FnOnce(&mut LispVal) -> T) -> Option<T> {
     match self {
@@ -132,33 +180,34 @@ impl LispKind {
     }
   }
 
-  fn make_map_mut<T>(&self, f: impl FnOnce(&mut HashMap<AtomId, LispVal>) -> T) -> (Option<T>, Option<LispVal>) {
+  fn make_map_mut<M: LispMap, T>(&self, f: impl FnOnce(&mut M) -> T) -> (Option<T>, Option<LispVal>) {
     match self {
-      LispKind::AtomMap(m) => {
-        let mut m = m.clone();
-        (Some(f(&mut m)), Some(LispVal::new(LispKind::AtomMap(m))))
-      }
       LispKind::Annot(sp, e) => match e.make_map_mut(f) {
         (r, None) => (r, None),
         (r, Some(e)) => (r, Some(LispVal::new(LispKind::Annot(sp.clone(), e)))),
       },
       LispKind::Ref(m) => (m.get_mut(|e| e.as_map_mut(f)), None),
-      _ => (None, None)
+      e => match M::project(e) {
+        Some(m) => {
+          let mut m = m.clone();
+          (Some(f(&mut m)), Some(LispVal::new(m.inject())))
+        }
+        None => (None, None)
+      }
     }
   }
 }
 impl LispVal {
-  fn as_map_mut<T>(&mut self, f: impl FnOnce(&mut HashMap<AtomId, LispVal>) -> T) -> Option<T> {
+  fn as_map_mut<M: LispMap, T>(&mut self, f: impl FnOnce(&mut M) -> T) -> Option<T> {
     match self.get_mut() {
       None => {
         let (r, new) = self.make_map_mut(f);
         if let Some(e) = new {*self = e}
         r
       }
-      Some(LispKind::AtomMap(m)) => Some(f(m)),
       Some(LispKind::Annot(_, e)) => Self::as_map_mut(e, f),
       Some(LispKind::Ref(m)) => m.get_mut(|e| Self::as_map_mut(e, f)),
-      Some(_) => None
+      Some(e) => M::project_mut(e).map(f),
     }
   }
 }
@@ -204,15 +253,15 @@ impl Elaborator {
   }
 
   /// Evaluate a compiled lisp expression.
-  pub fn evaluate<'b>(&'b mut self, sp: Span, ir: &'b [Ir]) -> Result<LispVal> {
+  pub fn evaluate<'b>(&'b mut self, sp: Span, code: &'b Code) -> Result<LispVal> {
     // an easy and common special case
-    if let [Ir::Const(e)] = ir { return Ok(e.clone()) }
-    Evaluator::new(self, sp, ir).run()
+    if let [Ir::Const(e)] = &*code.ir { return Ok(e.clone()) }
+    Evaluator::new(self, sp, &code.ir, &code.locals).run()
   }
 
   /// Shorthand to call a lisp function from the top level.
   pub fn call_func(&mut self, sp: Span, f: &LispVal, es: Vec<LispVal>) -> Result<LispVal> {
-    let mut eval = Evaluator::new(self, sp, &[]);
+    let mut eval = Evaluator::new(self, sp, &[], &[]);
     eval.app(false, &(sp, sp), f, es)?;
     eval.run()
   }
@@ -234,7 +283,7 @@ impl Elaborator {
   pub fn apply_merge(&mut self,
     sp: Span, strat: Option<&MergeStrategyInner>, old: LispVal, new: LispVal
   ) -> Result<LispVal> {
-    let mut eval = Evaluator::new(self, sp, &[]);
+    let mut eval = Evaluator::new(self, sp, &[], &[]);
     eval.run_apply_merge(sp, strat, old, new)
   }
 
@@ -277,6 +326,83 @@ impl Elaborator {
     })
   }
 
+  fn as_hash_map<T>(&self, e: &LispKind, f: impl FnOnce(&HashMap<LispKey, LispVal>) -> SResult<T>) -> SResult<T> {
+    e.unwrapped(|e| match e {
+      LispKind::HashMap(m) => f(m),
+      _ => Err(format!("not a hash map: {}", self.print(e)))
+    })
+  }
+
+  fn as_key(&self, e: &LispVal) -> SResult<LispKey> {
+    LispKey::new(e.clone()).ok_or_else(|| format!("expected an immutable key, got {}", self.print(e)))
+  }
+
+  /// Compare two immutable values, consistently with `==`. Values of different kinds are
+  /// ordered atoms, numbers, strings, booleans, syntax forms, `#undef`, lists; atoms are
+  /// ordered by name and lists lexicographically.
+  fn cmp_key(&self, e1: &LispVal, e2: &LispVal) -> std::cmp::Ordering {
+    fn rank(e: &LispKind) -> u8 {
+      match e {
+        LispKind::Atom(_) => 1,
+        LispKind::Number(_) => 2,
+        LispKind::String(_) => 3,
+        LispKind::Bool(_) => 4,
+        LispKind::Syntax(_) => 5,
+        LispKind::Undef => 6,
+        LispKind::List(_) | LispKind::DottedList(..) => 7,
+        _ => 0,
+      }
+    }
+    e1.unwrapped(|k1| e2.unwrapped(|k2| match (k1, k2) {
+      (&LispKind::Atom(a), &LispKind::Atom(b)) => self.data[a].name.cmp(&self.data[b].name),
+      (LispKind::Number(a), LispKind::Number(b)) => a.cmp(b),
+      (LispKind::String(a), LispKind::String(b)) => a[..].cmp(&b[..]),
+      (LispKind::Bool(a), LispKind::Bool(b)) => a.cmp(b),
+      (LispKind::Syntax(a), LispKind::Syntax(b)) => a.to_str().cmp(b.to_str()),
+      (LispKind::List(_) | LispKind::DottedList(..),
+       LispKind::List(_) | LispKind::DottedList(..)) => {
+        let (mut u1, mut u2) = (Uncons::from(e1.clone()), Uncons::from(e2.clone()));
+        loop {
+          match (u1.next(), u2.next()) {
+            (Some(a), Some(b)) => match self.cmp_key(&a, &b) {
+              std::cmp::Ordering::Equal => {}
+              ord => return ord
+            }
+            (None, None) => return match (u1.is_empty(), u2.is_empty()) {
+              (true, true) => std::cmp::Ordering::Equal,
+              (true, false) => std::cmp::Ordering::Less,
+              (false, true) => std::cmp::Ordering::Greater,
+              // both are improper lists, compare the tails
+              (false, false) => self.cmp_key(&u1.as_lisp(), &u2.as_lisp()),
+            },
+            (None, Some(_)) => return std::cmp::Ordering::Less,
+            (Some(_), None) => return std::cmp::Ordering::Greater,
+          }
+        }
+      }
+      _ => rank(k1).cmp(&rank(k2))
+    }))
+  }
+
+  /// Insert `v` at key `k` in the atom map or hash map `m`, or remove the key if `v` is
+  /// [`None`], and return the old value. Returns [`None`] if `m` is not a map.
+  fn map_insert(&mut self,
+    m: &mut LispVal, k: &LispVal, v: Option<LispVal>
+  ) -> Option<SResult<Option<LispVal>>> {
+    if m.is_hash_map() {
+      m.as_map_mut(|m: &mut HashMap<LispKey, LispVal>| {
+        let k = self.as_key(k)?;
+        Ok(match v { Some(v) => m.insert(k, v), None => m.remove(&k) })
+      })
+    } else {
+      m.as_map_mut(|m: &mut HashMap<AtomId, LispVal>| {
+        let k = self.as_string_atom(k)
+          .ok_or_else(|| format!("expected an atom, got {}", self.print(k)))?;
+        Ok(match v { Some(v) => m.insert(k, v), None => m.remove(&k) })
+      })
+    }
+  }
+
   fn to_string(&self, e: &LispKind) -> ArcString {
     match e {
       LispKind::Ref(m) => m.get(|e| self.to_string(e)),
@@ -448,6 +574,12 @@ impl Elaborator {
   }
 }
 
+/// Returns the index of the first occurrence of `pat` in `s`.
+fn find_bytes(s: &[u8], pat: &[u8]) -> Option<usize> {
+  if pat.is_empty() { return Some(0) }
+  s.windows(pat.len()).position(|w| w == pat)
+}
+
 fn set_report_mode(fe: FormatEnv<'_>, mode: &mut ReportMode, args: &[LispVal]) -> SResult<()> {
   if args.len() == 1 {
     if let Some(b) = args[0].as_bool() {
@@ -467,12 +599,110 @@ fn set_report_mode(fe: FormatEnv<'_>, mode: &mut ReportMode, args: &[LispVal]) -
   } else {Err("invalid arguments".into())}
 }
 
+/// A debugger for lisp evaluation, which can be attached to an [`Elaborator`] to
+/// pause evaluation at function calls and inspect the state of the evaluator.
+pub trait Debugger: Send {
+  /// Called before every function application. `depth` is the number of stack frames,
+  /// `file` and `sp` give the location of the application, and `name` is the name of the
+  /// function being called, if it has one. Returns true if evaluation should pause here,
+  /// in which case [`pause`](Self::pause) will be called.
+  fn should_break(&mut self, depth: usize, file: &FileRef, sp: Span, name: Option<&[u8]>) -> bool;
+
+  /// Called when evaluation is paused. Evaluation resumes when this function returns.
+  fn pause(&mut self, view: DebugView<'_, '_>);
+}
+
+impl std::fmt::Debug for dyn Debugger {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    "Debugger".fmt(f)
+  }
+}
+
+/// A stack frame of a paused evaluation, for display in a debugger.
+#[derive(Debug)]
+pub struct DebugFrame {
+  /// The name of the function executing in this frame.
+  pub name: String,
+  /// The location that is being evaluated in this frame. For all but the innermost frame
+  /// this is the function application which is currently in progress.
+  pub loc: FileSpan,
+}
+
+/// A read-only view of a paused [`Evaluator`], passed to [`Debugger::pause`].
+#[derive(Debug)]
+pub struct DebugView<'a, 'b> {
+  ev: &'a Evaluator<'b>,
+  sp: Span,
+}
+
+impl DebugView<'_, '_> {
+  fn frame_name(&self, pos: Option<&ProcPos>) -> String {
+    match pos {
+      None => "(top level)".into(),
+      Some(ProcPos::Named(_, _, a)) => self.ev.data[*a].name.to_string(),
+      Some(ProcPos::Unnamed(_)) => "[fn]".into(),
+      Some(ProcPos::Builtin(p)) => p.to_string(),
+    }
+  }
+
+  /// The stack frames of the evaluation, innermost first.
+  #[must_use] pub fn frames(&self) -> Vec<DebugFrame> {
+    let cs = &self.ev.call_stack;
+    let mut out = Vec::with_capacity(cs.len() + 1);
+    out.push(DebugFrame {
+      name: self.frame_name(cs.last().map(|f| &f.pos)),
+      loc: FileSpan { file: self.ev.file.clone(), span: self.sp },
+    });
+    // The span of a call stack entry is the location of the call, in the calling function
+    for (i, frame) in cs.iter().enumerate().rev() {
+      let name = self.frame_name(i.checked_sub(1).map(|j| &cs[j].pos));
+      out.push(DebugFrame { name, loc: frame.span.clone() })
+    }
+    out
+  }
+
+  /// The names and values of the local variables in stack frame `i` (counting from the
+  /// innermost frame). Variables without a known name are identified by their slot index.
+  #[must_use] pub fn locals(&self, i: usize) -> Vec<(String, String)> {
+    let cs = &self.ev.call_stack;
+    // `j` is the index of the call stack entry for the function running in frame `i`,
+    // or `None` for the top level code.
+    let (ctx, j, ip) = if i == 0 {
+      (&self.ev.ctx[self.ev.bp..], cs.len().checked_sub(1), self.ev.ip)
+    } else if let Some(j) = cs.len().checked_sub(i) {
+      let end = cs.get(j + 1).map_or(self.ev.bp, |f| f.parent_bp);
+      (&self.ev.ctx[cs[j].parent_bp..end], j.checked_sub(1), cs[j].parent_ip)
+    } else { return vec![] };
+    let locals = match j {
+      None => self.ev.locals,
+      Some(j) => cs[j].arc.as_ref().map_or(&[][..], |code| &code.locals),
+    };
+    ctx.iter().enumerate().map(|(i, e)| {
+      let name = match Code::local_name(locals, ip, i) {
+        Some(x) => format!("{}", self.ev.data[x].name),
+        None => format!("x{}", i),
+      };
+      (name, format!("{}", self.ev.print(e)))
+    }).collect()
+  }
+
+  /// The entries of the evaluation stack, innermost first.
+  #[must_use] pub fn stack(&self) -> Vec<String> {
+    self.ev.stack.iter().rev().map(|e| format!("{}", self.ev.print(e))).collect()
+  }
+
+  /// The current proof goals.
+  #[must_use] pub fn goals(&self) -> Vec<String> {
+    self.ev.lc.goals.iter().map(|e| format!("{}", self.ev.format_env().pp(e, 80))).collect()
+  }
+}
+
 #[derive(Debug)]
 struct CallStack<'a> {
   parent_code: &'a [Ir],
-  parent_ctx: Vec<LispVal>,
+  parent_bp: usize,
   parent_ip: usize,
-  arc: Option<Arc<[Ir]>>,
+  arc: Option<Arc<Code>>,
   span: FileSpan,
   pos: ProcPos,
 }
@@ -484,10 +714,15 @@ struct CallStack<'a> {
 pub struct Evaluator<'a> {
   /// The elaborator, which is used to mediate all access to the elaboration context.
   elab: &'a mut Elaborator,
-  /// The values assigned to local variables in the current context.
+  /// The slots for the local variables of all the functions on the call stack. Local
+  /// variable `i` of the function currently being evaluated is `ctx[bp + i]`.
   ctx: Vec<LispVal>,
+  /// The start of the local variable slots of the function currently being evaluated.
+  bp: usize,
   /// The function currently being evaluated.
   code: &'a [Ir],
+  /// The local variable names of the top level code (see [`Code::locals`]).
+  locals: &'a [(usize, usize, AtomId)],
   /// The instruction pointer.
   ip: usize,
   /// The file that contains the location we are currently evaluating.
@@ -523,15 +758,19 @@ macro_rules! stack_match {
 }
 
 impl<'a> Evaluator<'a> {
-  fn new(elab: &'a mut Elaborator, orig_span: Span, code: &'a [Ir]) -> Evaluator<'a> {
+  fn new(elab: &'a mut Elaborator, orig_span: Span,
+    code: &'a [Ir], locals: &'a [(usize, usize, AtomId)]
+  ) -> Evaluator<'a> {
     // println!("new:\n{}", elab.print(&IrList(1, code)));
     let file = elab.path.clone();
     Evaluator {
       elab,
       ctx: vec![],
+      bp: 0,
       file: file.clone(),
       orig: FileSpan { file, span: orig_span },
       code,
+      locals,
       ip: 0,
       stack: vec![],
       call_stack: vec![],
@@ -597,7 +836,7 @@ impl<'a> Evaluator<'a> {
       Ok(()) => { self.stack.push(Stack::Undef); Ok(()) }
       Err((ap, proc)) => {
         let sp = try_get_span(&fsp, &proc);
-        self.call(tail, &[Ir::AddThm], None, fsp, ProcPos::Builtin(BuiltinProc::AddThm), vec![]);
+        self.call(tail, &[Ir::AddThm], None, fsp, ProcPos::Builtin(BuiltinProc::AddThm), &[]);
         self.stack.push(Stack::AddThmProc(ap));
         self.app(false, &(sp, sp), &proc, vec![])
       }
@@ -620,7 +859,7 @@ impl<'a> Evaluator<'a> {
       LispKind::Undef => Ok(Some(old)),
       LispKind::AtomMap(newmap) => {
         if newmap.is_empty() { return Ok(Some(old)) }
-        let mut opt = Some(old.as_map_mut(mem::take).ok_or_else(||
+        let mut opt = Some(old.as_map_mut(mem::take::<HashMap<AtomId, LispVal>>).ok_or_else(||
           self.err(Some((sp, false)), "merge-map: not an atom-map"))?);
         let oldmap = opt.as_mut().expect("impossible");
         let mut todo = vec![];
@@ -636,12 +875,12 @@ impl<'a> Evaluator<'a> {
         }
         if todo.is_empty() {
           Ok(Some({
-            if old.is_ref() && old.as_map_mut(|m| *m = opt.take().expect("impossible")).is_some() { old }
+            if old.is_ref() && old.as_map_mut(|m: &mut HashMap<_, _>| *m = opt.take().expect("impossible")).is_some() { old }
             else { LispVal::new(LispKind::AtomMap(opt.take().expect("impossible"))) }
           }))
         } else {
           let fsp = self.fspan(sp);
-          self.call(false, &[Ir::MergeMap], None, fsp, ProcPos::Builtin(BuiltinProc::MergeMap), vec![]);
+          self.call(false, &[Ir::MergeMap], None, fsp, ProcPos::Builtin(BuiltinProc::MergeMap), &[]);
           self.stack.push(Stack::MergeMap(Box::new(MergeMapData {
             old, strat, it: todo.into_iter(), map: opt.expect("impossible"), k: None
           })));
@@ -669,7 +908,7 @@ impl<'a> Evaluator<'a> {
       let_unchecked!(data as Some(Stack::MergeMap(data)) = self.stack.pop());
       let MergeMapData { mut old, map, .. } = *data;
       let mut opt = Some(map);
-      if !old.is_ref() || old.as_map_mut(|m| *m = opt.take().expect("impossible")).is_none() {
+      if !old.is_ref() || old.as_map_mut(|m: &mut HashMap<_, _>| *m = opt.take().expect("impossible")).is_none() {
         old = LispVal::new(LispKind::AtomMap(opt.take().expect("impossible")))
       }
       self.stack.push(old.into());
@@ -733,7 +972,7 @@ macro_rules! make_builtins {
     }
 
     impl<'a> Evaluator<'a> {
-      #[allow(clippy::unwrap_used)]
+      #[allow(clippy::unwrap_used, clippy::mutable_key_type)]
       fn evaluate_builtin(&mut $self, $tail: bool, sp: &(Span, Span), f: BuiltinProc, mut $args: Vec<LispVal>) -> Result<()> {
         macro_rules! print {($sp:expr, $x:expr) => {{
           let msg = $x; $self.info($sp, false, f.to_str(), msg)
@@ -979,6 +1218,106 @@ make_builtins! { self, tail, sp1, sp2, args,
     }
     LispVal::string(out.into()).into()
   },
+  NumberToString: AtLeast(1) => {
+    let radix = match args.get(1) {
+      None if args.len() == 1 => 10,
+      Some(e) if args.len() == 2 => try1!(self.with_int(e, |n| match n.to_u32() {
+        Some(r @ 2..=36) => Ok(r),
+        _ => Err(format!("radix out of range: {}", n))
+      })),
+      _ => try1!(Err("expected one or two arguments"))
+    };
+    let s = try1!(self.with_int(&args[0], |n| Ok(n.to_str_radix(radix))));
+    LispVal::string(s.into()).into()
+  },
+  StringUpcase: Exact(1) =>
+    LispVal::string(try1!(self.as_string(&args[0])).to_ascii_uppercase().into()).into(),
+  StringDowncase: Exact(1) =>
+    LispVal::string(try1!(self.as_string(&args[0])).to_ascii_lowercase().into()).into(),
+  StringIndex: AtLeast(2) => {
+    let pat = try1!(self.as_string(&args[0]));
+    let s = try1!(self.as_string(&args[1]));
+    let start = match args.get(2) {
+      None => 0,
+      Some(e) if args.len() == 3 => try1!(self.with_int(e, |n| match n.to_usize() {
+        Some(i) if i <= s.len() => Ok(i),
+        _ => Err(format!("index out of range: start {}, length {}", n, s.len()))
+      })),
+      _ => try1!(Err("expected two or three arguments"))
+    };
+    match find_bytes(&s[start..], &pat) {
+      Some(i) => LispVal::number((start + i).into()).into(),
+      None => LispVal::bool(false).into(),
+    }
+  },
+  StringSplit: Exact(2) => {
+    let sep = try1!(self.as_string(&args[0]));
+    if sep.is_empty() { try1!(Err("string-split: empty separator")) }
+    let s = try1!(self.as_string(&args[1]));
+    let mut s = &*s;
+    let mut out = vec![];
+    while let Some(i) = find_bytes(s, &sep) {
+      out.push(LispVal::string(s[..i].into()));
+      s = &s[i + sep.len()..];
+    }
+    out.push(LispVal::string(s.into()));
+    LispVal::list(out).into()
+  },
+  StringJoin: Exact(2) => {
+    let sep = try1!(self.as_string(&args[0]));
+    let mut u = Uncons::New(args[1].clone());
+    let mut out = Vec::new();
+    for (i, e) in (&mut u).enumerate() {
+      if i != 0 { out.extend_from_slice(&sep) }
+      out.extend_from_slice(&self.to_string(&e))
+    }
+    if !u.is_empty() {
+      try1!(Err(format!("string-join: not a list: {}", self.print(&args[1]))))
+    }
+    LispVal::string(out.into()).into()
+  },
+  StringReplace: Exact(3) => {
+    let from = try1!(self.as_string(&args[0]));
+    if from.is_empty() { try1!(Err("string-replace: empty search string")) }
+    let to = try1!(self.as_string(&args[1]));
+    let s = try1!(self.as_string(&args[2]));
+    let mut s = &*s;
+    let mut out = Vec::with_capacity(s.len());
+    while let Some(i) = find_bytes(s, &from) {
+      out.extend_from_slice(&s[..i]);
+      out.extend_from_slice(&to);
+      s = &s[i + from.len()..];
+    }
+    out.extend_from_slice(s);
+    LispVal::string(out.into()).into()
+  },
+  Format: AtLeast(1) => {
+    let fmt = try1!(self.as_string(&args[0]));
+    let mut it = args[1..].iter();
+    let mut out = Vec::with_capacity(fmt.len());
+    let mut s = &*fmt;
+    while let Some(i) = s.iter().position(|&c| c == b'{' || c == b'}') {
+      out.extend_from_slice(&s[..i]);
+      s = &s[i..];
+      let (pp, n) = match s {
+        [b'{', b'{', ..] => { out.push(b'{'); s = &s[2..]; continue }
+        [b'}', b'}', ..] => { out.push(b'}'); s = &s[2..]; continue }
+        [b'{', b'}', ..] => (false, 2),
+        [b'{', b'p', b'p', b'}', ..] => (true, 4),
+        _ => try1!(Err(format!("format: bad format string: {}", fmt)))
+      };
+      s = &s[n..];
+      let e = try1!(it.next().ok_or("format: not enough arguments"));
+      if pp {
+        out.extend_from_slice(format!("{}", self.format_env().pp(e, 80)).as_bytes())
+      } else {
+        out.extend_from_slice(&self.to_string(e))
+      }
+    }
+    out.extend_from_slice(s);
+    if it.next().is_some() { try1!(Err("format: too many arguments")) }
+    LispVal::string(out.into()).into()
+  },
   Not: AtLeast(0) => (!args.iter().any(|e| e.truthy())).into(),
   And: AtLeast(0) => args.iter().all(|e| e.truthy()).into(),
   Or: AtLeast(0) => args.iter().any(|e| e.truthy()).into(),
@@ -1007,7 +1346,7 @@ make_builtins! { self, tail, sp1, sp2, args,
       return self.app(tail, &(sp1, sp), &proc, vec![])
     }
     let fsp = self.fspan(sp1);
-    self.call(tail, &[Ir::Map], None, fsp, ProcPos::Builtin(BuiltinProc::Map), vec![]);
+    self.call(tail, &[Ir::Map], None, fsp, ProcPos::Builtin(BuiltinProc::Map), &[]);
     self.stack.push(proc.into());
     self.stack.push(Stack::MapProc2(vec![]));
     self.stack.push(Stack::MapProc1(sp1, it.map(Uncons::from).collect()));
@@ -1068,11 +1407,16 @@ make_builtins! { self, tail, sp1, sp2, args,
     LispVal::new_ref(LispVal::new(LispKind::AtomMap(m))).into()
   },
   Lookup: AtLeast(2) => {
-    match self.as_string_atom(&args[1]) {
+    let e = if args[0].is_hash_map() {
+      let k = try1!(self.as_key(&args[1]));
+      Some(self.as_hash_map(&args[0], |m| Ok(m.get(&k).cloned())))
+    } else {
+      self.as_string_atom(&args[1]).map(|k| self.as_map(&args[0], |m| Ok(m.get(&k).cloned())))
+    };
+    match e {
       None => Stack::Undef,
-      Some(k) => {
-        let e = try1!(self.as_map(&args[0], |m| Ok(m.get(&k).cloned())));
-        if let Some(e) = e {e} else {
+      Some(e) => {
+        if let Some(e) = try1!(e) {e} else {
           let v = args.get(2).cloned().unwrap_or_else(LispVal::undef);
           if v.is_proc() {
             let sp = v.fspan().map_or(sp2, |fsp| fsp.span);
@@ -1084,33 +1428,87 @@ make_builtins! { self, tail, sp1, sp2, args,
     }
   },
   Insert: AtLeast(2) => {
-    try1!(try1!(args[0].as_ref_mut(|r| {
-      r.as_map_mut(|m| -> SResult<_> {
-        let k = self.as_string_atom(&args[1])
-          .ok_or_else(|| format!("expected an atom, got {}", self.print(&args[1])))?;
-        match args.get(2) {
-          Some(v) => {m.insert(k, v.clone());}
-          None => {m.remove(&k);}
-        }
-        Ok(())
-      })
-    }).unwrap_or(None).ok_or("expected a mutable map")));
+    try1!(try1!(args[0].as_ref_mut(|r| self.map_insert(r, &args[1], args.get(2).cloned()))
+      .unwrap_or(None).ok_or("expected a mutable map")));
     Stack::Undef
   },
   InsertNew: AtLeast(2) => {
     let mut it = args.into_iter();
     let mut m = it.next().unwrap();
     let k = it.next().unwrap();
-    let k = self.as_string_atom(&k)
-      .ok_or_else(|| format!("expected an atom, got {}", self.print(&k)));
-    try1!(try1!(m.as_map_mut(|m| -> SResult<_> {
-      match it.next() {
-        Some(v) => {m.insert(k?, v);}
-        None => {m.remove(&k?);}
+    try1!(try1!(self.map_insert(&mut m, &k, it.next()).ok_or("expected a map")));
+    m.into()
+  },
+  Remove: Exact(2) => {
+    let old = try1!(try1!(args[0].as_ref_mut(|r| self.map_insert(r, &args[1], None))
+      .unwrap_or(None).ok_or("expected a mutable map")));
+    old.map_or(Stack::Undef, Stack::Val)
+  },
+  IsHashMap: Exact(1) => args[0].is_hash_map().into(),
+  NewHashMap: AtLeast(0) => {
+    let mut m = HashMap::new();
+    for e in args {
+      let mut u = Uncons::from(e);
+      let k = try1!(self.as_key(&try1!(u.next().ok_or("invalid arguments"))));
+      let ret = u.next();
+      if !u.exactly(0) {try1!(Err("invalid arguments"))}
+      if let Some(v) = ret {m.insert(k, v);} else {m.remove(&k);}
+    }
+    LispVal::new_ref(LispVal::new(LispKind::HashMap(m))).into()
+  },
+  NewHashSet: AtLeast(0) => {
+    let mut m = HashMap::new();
+    for e in &args { m.insert(try1!(self.as_key(e)), LispVal::bool(true)); }
+    LispVal::new_ref(LispVal::new(LispKind::HashMap(m))).into()
+  },
+  MapSize: Exact(1) => {
+    let n = try1!(args[0].unwrapped(|e| match e {
+      LispKind::AtomMap(m) => Ok(m.len()),
+      LispKind::HashMap(m) => Ok(m.len()),
+      _ => Err(format!("not a map: {}", self.print(e)))
+    }));
+    LispVal::number(n.into()).into()
+  },
+  MapToList: Exact(1) => {
+    let mut es = try1!(args[0].unwrapped(|e| match e {
+      LispKind::AtomMap(m) => Ok(m.iter()
+        .map(|(&a, v)| (LispVal::atom(a), v.clone())).collect::<Vec<_>>()),
+      LispKind::HashMap(m) => Ok(m.iter().map(|(k, v)| (k.val().clone(), v.clone())).collect()),
+      _ => Err(format!("not a map: {}", self.print(e)))
+    }));
+    es.sort_by(|(k1, _), (k2, _)| self.cmp_key(k1, k2));
+    LispVal::list(es.into_iter().map(|(k, v)| LispVal::list(vec![k, v])).collect::<Vec<_>>()).into()
+  },
+  MapHas: Exact(2) => {
+    if args[0].is_hash_map() {
+      let k = try1!(self.as_key(&args[1]));
+      try1!(self.as_hash_map(&args[0], |m| Ok(m.contains_key(&k))))
+    } else {
+      match self.as_string_atom(&args[1]) {
+        None => false,
+        Some(k) => try1!(self.as_map(&args[0], |m| Ok(m.contains_key(&k)))),
       }
-      Ok(())
-    }).ok_or("expected a map")));
-    Stack::Undef
+    }.into()
+  },
+  MapUnion: AtLeast(1) => {
+    let mut res = HashMap::new();
+    for e in &args {
+      try1!(self.as_hash_map(e, |m| {
+        res.extend(m.iter().map(|(k, v)| (k.clone(), v.clone())));
+        Ok(())
+      }))
+    }
+    LispVal::new(LispKind::HashMap(res)).into()
+  },
+  MapIntersect: Exact(2) => {
+    let res = try1!(self.as_hash_map(&args[0], |m1| self.as_hash_map(&args[1], |m2|
+      Ok(m1.iter().filter(|(k, _)| m2.contains_key(k)).map(|(k, v)| (k.clone(), v.clone())).collect()))));
+    LispVal::new(LispKind::HashMap(res)).into()
+  },
+  MapDiff: Exact(2) => {
+    let res = try1!(self.as_hash_map(&args[0], |m1| self.as_hash_map(&args[1], |m2|
+      Ok(m1.iter().filter(|(k, _)| !m2.contains_key(k)).map(|(k, v)| (k.clone(), v.clone())).collect()))));
+    LispVal::new(LispKind::HashMap(res)).into()
   },
   MergeMap: AtLeast(0) => {
     let mut it = args.drain(..);
@@ -1124,6 +1522,10 @@ make_builtins! { self, tail, sp1, sp2, args,
       } else { LispVal::proc(Proc::MergeMap(arg1.into_merge_strategy())) }
     } else { LispVal::proc(Proc::MergeMap(None)) }.into()
   },
+  Macro: Exact(1) => {
+    if !args[0].is_proc() { try1!(Err("expected a function")) }
+    LispVal::proc(Proc::Macro(args.pop().unwrap())).into()
+  },
   SetTimeout: Exact(1) => {
     match try1!(args[0].as_int(BigInt::to_u64).ok_or("expected a number")) {
       None | Some(0) => {self.timeout = None; self.cur_timeout = None},
@@ -1197,7 +1599,7 @@ make_builtins! { self, tail, sp1, sp2, args,
     try1!(xarg.as_atom().ok_or("expected an atom"));
     let fsp = self.fspan(sp1);
     let x_sp = try_get_span(&fsp, &xarg);
-    self.call(tail, &[Ir::Have], None, fsp, ProcPos::Builtin(BuiltinProc::Have), vec![]);
+    self.call(tail, &[Ir::Have], None, fsp, ProcPos::Builtin(BuiltinProc::Have), &[]);
     let mut rstack = vec![RStack::DeferGoals(mem::take(&mut self.lc.goals))];
     let state = match (args.next().unwrap(), args.next()) {
       (p, None) => {
@@ -1238,20 +1640,13 @@ make_builtins! { self, tail, sp1, sp2, args,
   },
   NewDummy: AtLeast(1) => {
     if args.len() > 2 {try1!(Err("expected 1 or 2 armuments"))}
-    let (x, s) = match args.get(1) {
-      None => {
-        let mut i = 1;
-        let x = loop {
-          let a = self.get_atom(format!("_{}", i).as_bytes());
-          if !self.lc.vars.contains_key(&a) {break a}
-          i += 1;
-        };
-        (x, &args[0])
-      }
-      Some(s) => (try1!(args[0].as_atom().ok_or("expected an atom")), s)
-    };
+    let s = args.last().expect("nonempty");
     let sort = try1!(s.as_atom().and_then(|s| self.data[s].sort).ok_or("expected a sort"));
-    self.lc.vars.insert(x, (true, InferSort::Bound(sort)));
+    let x = if args.len() == 1 { self.fresh_dummy(sort) } else {
+      let x = try1!(args[0].as_atom().ok_or("expected an atom"));
+      self.lc.vars.insert(x, (true, InferSort::Bound(sort)));
+      x
+    };
     LispVal::atom(x).into()
   },
   SetReporting: AtLeast(1) => {
@@ -1279,6 +1674,81 @@ make_builtins! { self, tail, sp1, sp2, args,
     let bytes = self.eval_string(&fsp, &args)?;
     LispVal::string(bytes.into()).into()
   },
+  RegisterEq: AtLeast(4) => {
+    if args.len() > 5 {try1!(Err("expected 4 or 5 arguments"))}
+    let xs = try1!(args.iter().map(|e| e.as_atom().ok_or("expected an atom"))
+      .collect::<Result<Vec<_>, _>>());
+    try1!(self.register_eq(xs[0], xs[1], xs[2], xs[3], xs.get(4).copied()));
+    Stack::Undef
+  },
+  RegisterCongr: Exact(2) => {
+    let t = try1!(args[0].as_atom().ok_or("expected an atom"));
+    let thm = try1!(args[1].as_atom().ok_or("expected an atom"));
+    try1!(self.register_congr(t, thm));
+    Stack::Undef
+  },
+  RegisterSimp: Exact(1) => {
+    let thm = try1!(args[0].as_atom().ok_or("expected an atom"));
+    try1!(self.register_simp(thm));
+    Stack::Undef
+  },
+  Rw: AtLeast(1) => {
+    let (ret, msg) = try1!(self.rewrite_tactic(false, &args));
+    if let Some(msg) = msg { print!(sp1, msg) }
+    ret.into()
+  },
+  Simp: AtLeast(0) => {
+    let (ret, msg) = try1!(self.rewrite_tactic(true, &args));
+    if let Some(msg) = msg { print!(sp1, msg) }
+    ret.into()
+  },
+  Auto: AtLeast(1) => {
+    if args.len() > 2 {try1!(Err("expected 1 or 2 arguments"))}
+    let lemmas = try1!(Uncons::from(args[0].clone())
+      .map(|e| e.as_atom().ok_or("expected a list of theorems"))
+      .collect::<Result<Vec<_>, _>>());
+    let depth = match args.get(1) {
+      None => 5,
+      Some(e) => try1!(e.as_int(BigInt::to_usize).ok_or("expected a number")
+        .and_then(|n| n.ok_or("depth out of range"))),
+    };
+    try1!(self.auto(&lemmas, depth)).into()
+  },
+  Unfold: AtLeast(1) => {
+    let fsp = self.fspan(sp1);
+    if args.len() > 2 {try1!(Err("expected 1 or 2 arguments"))}
+    try1!(self.conv_tactic(false, None, &args[0], args.get(1)).and_then(|(_, c)| self.conv_goal(fsp, c)));
+    Stack::Undef
+  },
+  Fold: AtLeast(1) => {
+    let fsp = self.fspan(sp1);
+    if args.len() > 2 {try1!(Err("expected 1 or 2 arguments"))}
+    try1!(self.conv_tactic(true, None, &args[0], args.get(1)).and_then(|(_, c)| self.conv_goal(fsp, c)));
+    Stack::Undef
+  },
+  ConvUnfold: AtLeast(2) => {
+    if args.len() > 3 {try1!(Err("expected 2 or 3 arguments"))}
+    let (e2, c) = try1!(self.conv_tactic(false, Some(&args[1]), &args[0], args.get(2)));
+    LispVal::list(vec![e2, c]).into()
+  },
+  ConvFold: AtLeast(2) => {
+    if args.len() > 3 {try1!(Err("expected 2 or 3 arguments"))}
+    let (e2, c) = try1!(self.conv_tactic(true, Some(&args[1]), &args[0], args.get(2)));
+    LispVal::list(vec![e2, c]).into()
+  },
+  ConvAt: Exact(3) => {
+    let path = try1!(Uncons::from(args[1].clone())
+      .map(|e| e.as_int(BigInt::to_usize).flatten().ok_or("expected a list of numbers"))
+      .collect::<Result<Vec<_>, _>>());
+    try1!(self.conv_at(&args[0], &path, args[2].clone())).into()
+  },
+  ConvLhs: Exact(1) => try1!(self.conv_side(&args[0], false)).into(),
+  ConvRhs: Exact(1) => try1!(self.conv_side(&args[0], true)).into(),
+  ConvGoal: Exact(1) => {
+    let fsp = self.fspan(sp1);
+    try1!(self.conv_goal(fsp, args[0].clone()));
+    Stack::Undef
+  },
   #[cfg(feature = "mmc")]
   MmcInit: Exact(0) => LispVal::proc(Proc::MmcCompiler(
     RefCell::new(Box::new(crate::mmc::Compiler::new(self)))
@@ -1294,6 +1764,53 @@ impl<'a> Evaluator<'a> {
     try_get_span_from(&self.orig, fsp)
   }
 
+  /// The implementation of `(rw)` and `(simp)`. Returns the result value, and in goal mode
+  /// a message listing the lemmas that fired.
+  fn rewrite_tactic(&self, simp: bool, args: &[LispVal]) -> SResult<(LispVal, Option<String>)> {
+    if args.len() > 2 { return Err("expected at most 2 arguments".into()) }
+    let lemmas = match args.first() {
+      Some(e) if e.is_def() => Uncons::from(e.clone())
+        .map(|e| e.as_atom().ok_or("expected a list of theorems"))
+        .collect::<Result<Vec<_>, _>>()?,
+      _ if simp => self.simp_lemmas()?,
+      _ => return Err("expected a list of theorems".into()),
+    };
+    let timeout = self.cur_timeout;
+    if let Some(e) = args.get(1) {
+      let res = self.rewrite(&lemmas, simp, timeout, e)?;
+      let used = LispVal::list(res.used.into_iter().map(|(a, _)| LispVal::atom(a)).collect::<Vec<_>>());
+      Ok((LispVal::list(vec![res.expr, res.proof.unwrap_or_else(LispVal::undef), used]), None))
+    } else {
+      let (p, used) = self.rewrite_goal(&lemmas, simp, timeout)?;
+      let used = used.into_iter().map(|(a, n)| {
+        let a = &self.data[a].name;
+        if n == 1 { format!("{}", a) } else { format!("{} (x{})", a, n) }
+      }).collect::<Vec<_>>();
+      let msg = if used.is_empty() { "closed by reflexivity".into() }
+        else { format!("used {}", used.join(", ")) };
+      Ok((p, Some(msg)))
+    }
+  }
+
+  /// The implementation of `(unfold)`, `(fold)`, `(conv-unfold)` and `(conv-fold)`.
+  /// The conversion acts on `e`, or on the first goal if `e` is `None`.
+  fn conv_tactic(&mut self,
+    fold: bool, e: Option<&LispVal>, t: &LispVal, occs: Option<&LispVal>
+  ) -> SResult<(LispVal, LispVal)> {
+    let t = t.as_atom().ok_or("expected an atom")?;
+    let occs = match occs {
+      Some(e) if e.is_def() => Some(Uncons::from(e.clone())
+        .map(|e| e.as_int(BigInt::to_usize).flatten().ok_or("expected a list of numbers"))
+        .collect::<Result<Vec<_>, _>>()?),
+      _ => None,
+    };
+    let e = match e {
+      Some(e) => e.clone(),
+      None => self.lc.goals.iter().find_map(|g| g.goal_type()).ok_or("no goals")?,
+    };
+    if fold { self.conv_fold(t, occs.as_deref(), &e) } else { self.conv_unfold(t, occs.as_deref(), &e) }
+  }
+
   #[allow(unused)]
   fn respan(&self, sp: Span) -> Span { self.try_get_span(Some(&self.fspan(sp))) }
 
@@ -1318,10 +1835,10 @@ impl<'a> Evaluator<'a> {
   fn call(&mut self,
     tail: bool,
     code: &'a [Ir],
-    arc: Option<Arc<[Ir]>>,
+    arc: Option<Arc<Code>>,
     span: FileSpan,
     pos: ProcPos,
-    ctx: Vec<LispVal>,
+    env: &[LispVal],
   ) {
     // if self.check_proofs {
     //   println!("calling (tail = {}):\n{}with:", tail, self.print(&IrList(1, code)));
@@ -1333,8 +1850,10 @@ impl<'a> Evaluator<'a> {
     if let Some(fsp) = pos.fspan() { self.file = fsp.file.clone() }
     if tail {
       if let Some(frame) = self.call_stack.last_mut() {
+        // The new function reuses the local variable slots of the current one
         self.code = code;
-        self.ctx = ctx;
+        self.ctx.truncate(self.bp);
+        self.ctx.extend_from_slice(env);
         self.ip = 0;
         frame.arc = arc;
         frame.span = span;
@@ -1347,15 +1866,17 @@ impl<'a> Evaluator<'a> {
       arc, span, pos,
       parent_code: mem::replace(&mut self.code, code),
       parent_ip: mem::take(&mut self.ip),
-      parent_ctx: mem::replace(&mut self.ctx, ctx),
-    })
+      parent_bp: mem::replace(&mut self.bp, self.ctx.len()),
+    });
+    self.ctx.extend_from_slice(env);
   }
 
   fn ret(&mut self) {
     let frame = self.call_stack.pop().expect("underflow");
     self.file = frame.span.file;
     self.code = frame.parent_code;
-    self.ctx = frame.parent_ctx;
+    self.ctx.truncate(self.bp);
+    self.bp = frame.parent_bp;
     self.ip = frame.parent_ip;
     // println!("returning to:\n  ip = {}\n{}", self.ip, self.print(&IrList(1, self.code)));
   }
@@ -1390,9 +1911,9 @@ impl<'a> Evaluator<'a> {
           // the IR inside the Ret instruction above, so that it won't get deallocated
           // while in use. Rust doesn't reason about other owners of an Arc though, so...
           #[allow(clippy::useless_transmute)]
-          let code2 = unsafe { std::mem::transmute::<&[Ir], &[Ir]>(&**code) };
+          let code2 = unsafe { std::mem::transmute::<&[Ir], &[Ir]>(&code.ir) };
           let fsp = self.fspan(sp.0);
-          self.call(tail, code2, Some(code.clone()), fsp, pos.clone(), (**env).into());
+          self.call(tail, code2, Some(code.clone()), fsp, pos.clone(), env);
           match spec {
             ProcSpec::Exact(_) => self.ctx.extend(args),
             ProcSpec::AtLeast(nargs) => {
@@ -1408,7 +1929,7 @@ impl<'a> Evaluator<'a> {
               Some(Stack::MatchCont(n, tgt, e, a)) => {
                 a.set(false);
                 if Rc::ptr_eq(&a, valid) {
-                  self.ctx.truncate(n);
+                  self.ctx.truncate(self.bp + n);
                   self.ip = tgt;
                   self.stack.push(e.into());
                   break
@@ -1425,6 +1946,7 @@ impl<'a> Evaluator<'a> {
           let old = args.pop().expect("impossible");
           self.push_merge_map(sp.0, strat.clone(), old, &new)?
         }
+        Proc::Macro(f) => self.app(tail, sp, f, args)?,
         Proc::RefineCallback => {
           self.stack.push(Stack::Refine(sp.0, vec![]));
           let p = args.pop().expect("impossible");
@@ -1464,7 +1986,7 @@ impl<'a> Evaluator<'a> {
   fn insert_call_refine(&mut self, tail: bool, sp: Span) {
     let fsp = self.fspan(sp);
     self.call(tail, &[Ir::RefineResume], None, fsp,
-      ProcPos::Builtin(BuiltinProc::Refine), vec![]);
+      ProcPos::Builtin(BuiltinProc::Refine), &[]);
     if !tail {
       let len = self.stack.len();
       self.stack.swap(len - 1, len - 2);
@@ -1569,7 +2091,7 @@ impl<'a> Evaluator<'a> {
           if ok {
             self.ctx.extend(vars.into_vec());
             if let Some(n) = cont {
-              assert!(self.ctx.len() == n);
+              assert!(self.ctx.len() == self.bp + n);
               let valid = Rc::new(Cell::new(true));
               self.ctx.push(LispVal::proc(Proc::MatchCont(valid.clone())));
               self.stack.push(Stack::MatchCont(n, tgt, e, valid));
@@ -1640,13 +2162,35 @@ impl<'a> Evaluator<'a> {
         Ir::FocusStart(_) | Ir::RefineGoal(_) | Ir::FocusFinish |
         Ir::SetMergeStrategy(..) | Ir::LocalDef(_) | Ir::GlobalDef(..) | Ir::SetDoc(..) |
         Ir::Lambda(..) | Ir::Branch(..) | Ir::TestPatternResume | Ir::BranchFail(_) |
-        Ir::Map | Ir::Have | Ir::RefineResume | Ir::AddThm | Ir::MergeMap
+        Ir::Map | Ir::Have | Ir::RefineResume | Ir::AddThm | Ir::MergeMap |
+        Ir::TryStart(..) | Ir::TryEnd
         => panic!("unexpected in pattern mode"),
       };
       self.ip += 1;
     }
   }
 
+  /// Check with the attached debugger, if any, whether we should pause before the
+  /// function application at `sp`, and pause if so.
+  fn debug_hook(&mut self, sp: Span, func: Result<BuiltinProc, &LispVal>) {
+    let mut dbg = if let Some(dbg) = self.elab.debugger.take() { dbg } else { return };
+    let name = match func {
+      Ok(p) => Some(p.to_str().as_bytes()),
+      Err(f) => f.unwrapped(|f| match f {
+        LispKind::Proc(Proc::Builtin(p)) => Some(p.to_str().as_bytes()),
+        LispKind::Proc(Proc::Lambda {pos: ProcPos::Named(_, _, a), ..}) => Some(&*self.data[*a].name),
+        _ => None,
+      }),
+    };
+    if dbg.should_break(self.call_stack.len(), &self.file, sp, name) {
+      let start = Instant::now();
+      dbg.pause(DebugView { ev: self, sp });
+      // The time spent paused does not count toward the timeout
+      if let Some(t) = &mut self.elab.cur_timeout { *t += start.elapsed() }
+    }
+    self.elab.debugger = Some(dbg);
+  }
+
   fn heartbeat(&mut self) -> Result<()> {
     // self.iters = self.iters.wrapping_add(1);
     if self.cur_timeout.map_or(false, |t| t < Instant::now()) {
@@ -1740,7 +2284,9 @@ impl<'a> Evaluator<'a> {
     Ok(())
   }
 
-  fn lambda(&mut self, name: u8, &(sp, spec, ref code): &(Span, ProcSpec, Arc<[Ir]>)) {
+  fn lambda(&mut self, name: u8,
+    &(sp, spec, ref code, ref caps): &(Span, ProcSpec, Arc<Code>, Box<[usize]>)
+  ) {
     let pos = if name == u8::MAX {
       ProcPos::Unnamed(self.fspan(sp))
     } else {
@@ -1750,7 +2296,7 @@ impl<'a> Evaluator<'a> {
     };
     self.stack.push(LispVal::proc(Proc::Lambda {
       pos,
-      env: self.ctx.clone().into(),
+      env: caps.iter().map(|&i| self.ctx[self.bp + i].clone()).collect(),
       spec,
       code: code.clone(),
     }).into())
@@ -1840,8 +2386,46 @@ impl<'a> Evaluator<'a> {
     Ok(())
   }
 
-  #[allow(clippy::never_loop, clippy::many_single_char_names)]
+  /// Handle an error thrown during evaluation, by unwinding the stack to the innermost
+  /// enclosing `try` block and calling its handler. The error is returned if there is no
+  /// such block, or if it is a timeout, cancellation or stack overflow.
+  fn catch(&mut self, err: ElabError) -> Result<()> {
+    if !self.stack.iter().any(|s| matches!(s, Stack::Try(_))) || self.heartbeat().is_err() {
+      return Err(err)
+    }
+    loop {
+      match self.stack.pop().expect("underflow") {
+        Stack::Try(t) => {
+          let TryData {handler, sp, ctx, goals, mvars, assignments, tgt} = *t;
+          self.ctx.truncate(ctx);
+          for (r, val) in assignments { r.as_ref_(|e| *e = val); }
+          self.lc.mvars = mvars;
+          self.lc.set_goals(goals);
+          self.ip = tgt;
+          let fsp = FileSpan {file: self.path.clone(), span: err.pos};
+          let e = LispVal::list(vec![
+            LispVal::atom(AtomId::ERROR),
+            LispVal::string(err.kind.msg().into())
+          ]).span(fsp);
+          return self.app(false, &(sp, sp), &handler, vec![e])
+        }
+        Stack::Ret => self.ret(),
+        _ => {}
+      }
+    }
+  }
+
   fn run(&mut self) -> Result<LispVal> {
+    loop {
+      match self.run_core() {
+        Err(e) => self.catch(e)?,
+        ret => return ret
+      }
+    }
+  }
+
+  #[allow(clippy::never_loop, clippy::many_single_char_names)]
+  fn run_core(&mut self) -> Result<LispVal> {
     macro_rules! throw {($sp:expr, $e:expr) => {{
       let err = $e;
       return Err(self.err(Some(($sp, false)), err))
@@ -1889,9 +2473,9 @@ impl<'a> Evaluator<'a> {
           }
           Ir::Undef => self.stack.push(Stack::Undef),
           Ir::Dup => self.stack.push(self.stack.last().expect("underflow").dup()),
-          Ir::AssertScope(n) => assert!(self.ctx.len() == n),
-          Ir::EndScope(n) => self.ctx.truncate(n),
-          Ir::Local(i) => self.stack.push(self.ctx[i].clone().into()),
+          Ir::AssertScope(n) => assert!(self.ctx.len() == self.bp + n),
+          Ir::EndScope(n) => self.ctx.truncate(self.bp + n),
+          Ir::Local(i) => self.stack.push(self.ctx[self.bp + i].clone().into()),
           Ir::Global(sp, a) => self.global_var(sp, a)?,
           Ir::Const(ref val) => self.stack.push(val.clone().into()),
           Ir::List(sp, n) => {
@@ -1902,10 +2486,12 @@ impl<'a> Evaluator<'a> {
           Ir::App(tail, ref sp, n) => {
             let args = self.popn(n).map(Stack::into_lisp).collect();
             let func = self.pop_lisp();
+            if self.debugger.is_some() { self.debug_hook(sp.0, Err(&func)) }
             self.app(tail, sp, &func, args)?
           }
           Ir::BuiltinApp(tail, func, ref sp, n) => {
             let args = self.popn(n).map(Stack::into_lisp).collect();
+            if self.debugger.is_some() { self.debug_hook(sp.0, Ok(func)) }
             self.heartbeat()?;
             self.evaluate_builtin(tail, sp, func, args)?
           }
@@ -1933,7 +2519,7 @@ impl<'a> Evaluator<'a> {
               self.print(&a)))
           }
           Ir::LocalDef(n) => {
-            assert!(self.ctx.len() == n);
+            assert!(self.ctx.len() == self.bp + n);
             let ret = self.pop_lisp();
             self.ctx.push(ret);
           }
@@ -1967,6 +2553,20 @@ impl<'a> Evaluator<'a> {
           Ir::RefineGoal(ret_val) => self.refine_goal(ret_val)?,
           Ir::AddThm => self.add_thm_resume()?,
           Ir::MergeMap => self.merge_map_resume()?,
+          Ir::TryStart(sp, tgt) => {
+            let handler = self.pop_lisp();
+            let (ctx, goals) = (self.ctx.len(), self.lc.goals.clone());
+            let mvars = self.lc.mvars.clone();
+            let assignments = mvars.iter().chain(&goals)
+              .filter_map(|r| Some((r.clone(), r.as_ref_(|e| e.clone())?))).collect();
+            self.stack.push(Stack::Try(Box::new(TryData {
+              handler, sp, ctx, goals, mvars, assignments, tgt})))
+          }
+          Ir::TryEnd => {
+            let ret = self.stack.pop().expect("underflow");
+            assert!(matches!(self.stack.pop(), Some(Stack::Try(_))), "stack type error");
+            self.stack.push(ret)
+          }
 
           // Listing the instructions explicitly so that we get missing match arm errors
           Ir::PatternResult(_) | Ir::PatternAtom(_) | Ir::PatternQuoteAtom(_) |
@@ -1990,3 +2590,530 @@ impl<'a> Evaluator<'a> {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use std::path::PathBuf;
+  use std::sync::{Arc, Mutex};
+  use futures::channel::oneshot::Receiver;
+  use std::sync::atomic::{AtomicBool, Ordering};
+  use crate::elab::{ElabError, ElabErrorKind, ElabResult, ElaborateBuilder, FrozenEnv};
+  use crate::{BoxError, ErrorLevel, FileRef, LinedString, Span};
+  use super::{DebugView, Debugger};
+
+  /// An elaboration message, together with the source text of the
+  /// related (stack trace) locations.
+  type Msg = (ErrorLevel, String, Vec<String>);
+
+  fn elab(src: &str) -> Vec<Msg> { elab_with(src, None) }
+
+  fn elab_errors(src: &str, cancel: Arc<AtomicBool>, debugger: Option<Box<dyn Debugger>>,
+  ) -> Vec<ElabError> {
+    elab_env(src, cancel, debugger).0
+  }
+
+  fn elab_env(src: &str, cancel: Arc<AtomicBool>, debugger: Option<Box<dyn Debugger>>,
+  ) -> (Vec<ElabError>, FrozenEnv) {
+    let text = Arc::new(LinedString::from(src.to_owned()));
+    let ast = Arc::new(mm1_parser::parse(text, None).1);
+    assert!(ast.errors.is_empty());
+    let res = futures::executor::block_on(ElaborateBuilder {
+      ast: &ast,
+      path: FileRef::from(PathBuf::from("test.mm1")),
+      mm0_mode: false,
+      check_proofs: true,
+      report_upstream_errors: false,
+      cancel,
+      old: None,
+      recv_dep: |_| -> Result<Receiver<ElabResult<()>>, BoxError> { Err("no imports".into()) },
+      recv_goal: None,
+      recv_progress: None,
+      recv_input: None,
+      debugger,
+      #[cfg(feature = "mmc")]
+      mmc_options: Default::default(),
+    }.elab());
+    (res.2, res.3)
+  }
+
+  fn elab_with(src: &str, debugger: Option<Box<dyn Debugger>>) -> Vec<Msg> {
+    elab_errors(src, Arc::default(), debugger).into_iter().map(|e| match e.kind {
+      ElabErrorKind::Boxed(msg, info) => (e.level, msg.to_string(),
+        info.unwrap_or_default().into_iter().map(|(fsp, _)| src[fsp.span.start..fsp.span.end].to_owned()).collect()),
+      ElabErrorKind::Upstream(..) => unreachable!(),
+    }).collect()
+  }
+
+  fn infos(src: &str) -> Vec<String> {
+    elab(src).into_iter().map(|(lvl, msg, _)| {
+      assert!(matches!(lvl, ErrorLevel::Info), "unexpected error: {}", msg);
+      msg
+    }).collect()
+  }
+
+  #[test]
+  fn tail_calls() {
+    assert_eq!(infos("do {
+      (set-stack-limit 100)
+      (def (loop-if n) (if (= n 0) 'if (loop-if (- n 1))))
+      (print (loop-if 10000))
+      (def (loop-match n) (match n [0 'match] [_ (loop-match (- n 1))]))
+      (print (loop-match 10000))
+      (def (loop-begin n) (if (= n 0) 'begin (begin (def m (- n 1)) (loop-begin m))))
+      (print (loop-begin 10000))
+      (def (loop-let n) (let ([m (- n 1)]) (if (< m 0) 'let (loop-let m))))
+      (print (loop-let 10000))
+      (def (even? n) (if (= n 0) #t (odd? (- n 1))))
+      (def (odd? n) (if (= n 0) #f (even? (- n 1))))
+      (print (even? 10001))
+      (def (loop-apply n) (if (= n 0) 'apply (apply loop-apply (list (- n 1)))))
+      (print (loop-apply 10000))
+    };"), ["if", "match", "begin", "let", "#f", "apply"]);
+  }
+
+  #[test]
+  fn closures_and_scopes() {
+    assert_eq!(infos("do {
+      (def (adder n) (fn (x) (+ x n)))
+      (def add3 (adder 3))
+      (print (add3 4))
+      (print (let ([x 1] [y 2]) (let ([x 10]) (list x y))))
+      (print (match '(1 2 3) [(a . b) (let ([c a]) (list c b))]))
+      (def (f n) (match n [(? number?) (=> k) (if (= n 0) (k) 'num)] [_ 'other]))
+      (print (list (f 1) (f 0) (f 'a)))
+      (def fs (map (fn (i) (fn () (* i i))) '(1 2 3)))
+      (print (map (fn (g) (g)) fs))
+      (def (fact n) (if (= n 0) 1 (* n (fact (- n 1)))))
+      (print (fact 20))
+    };"), ["7", "(10 2)", "(1 (2 3))", "(num other other)", "(1 4 9)",
+      "2432902008176640000"]);
+  }
+
+  #[test]
+  fn stack_limits() {
+    let msgs = elab("
+      do { (set-stack-limit 50) (def (deep n) (if (= n 0) 0 (+ 1 (deep (- n 1))))) (deep 100) };
+      do { (set-timeout 50) (def (spin) (spin)) (spin) };");
+    assert!(matches!(&*msgs, [
+      (ErrorLevel::Error, m1, _), (ErrorLevel::Error, m2, _)
+    ] if m1 == "stack overflow" && m2 == "timeout"), "{:?}", msgs);
+  }
+
+  #[test]
+  fn stack_span() {
+    let msgs = elab("do {
+      (def (f x) (+ 1 (g x)))
+      (def (g x) (+ 1 (h x)))
+      (def (h x) (if #t (error \"boom\") 0))
+      (f 1)
+    };");
+    assert_eq!(msgs.len(), 1);
+    let (_, msg, trace) = &msgs[0];
+    assert_eq!(msg, "boom");
+    assert_eq!(trace, &["(error \"boom\")", "(h x)", "(g x)"]);
+  }
+
+  /// The info messages of an elaboration, together with the source text they point to.
+  fn infos_at(src: &str) -> Vec<(String, String)> {
+    elab_errors(src, Arc::default(), None).into_iter().map(|e| {
+      assert!(matches!(e.level, ErrorLevel::Info), "unexpected error: {}", e.kind.msg());
+      (e.kind.msg(), src[e.pos.start..e.pos.end].to_owned())
+    }).collect()
+  }
+
+  #[test]
+  fn try_catch() {
+    assert_eq!(infos_at("do {
+      (def (show e) (match e [('error msg) (report-at 'info e msg)]))
+      (try (begin (error \"boom\") 'unreachable) show)
+      (print (try 1 show))
+      (try (+ 1 'a) show)
+      (print (try (error \"x\") (fn (e) 'handled)))
+    };"), [
+      ("boom".into(), "(error \"boom\")".into()),
+      ("1".into(), "(print (try 1 show))".into()),
+      ("expected a integer, got a".into(), "(+ 1 'a)".into()),
+      ("handled".into(), "(print (try (error \"x\") (fn (e) 'handled)))".into()),
+    ]);
+  }
+
+  #[test]
+  fn try_restores_proof_state() {
+    // A failed tactic inside `try` undoes its assignments to goals and metavariables
+    assert_eq!(infos_at("
+      delimiter $ ( ) $;
+      provable sort wff;
+      term imp: wff > wff > wff; infixr imp: $->$ prec 25;
+      axiom ax1 (a b: wff): $ a -> b -> a $;
+      axiom mp (a b: wff): $ a -> b $ > $ a $ > $ b $;
+      theorem t (a b: wff) (h: $ a $): $ b -> a $ =
+      (focus
+        (def (show e) (match e [('error msg) (report-at 'info e msg)]))
+        (def (state) (print (list (map goal-type (get-goals)) (get-mvars))))
+        (try (refine 'ax1) show)
+        (state)
+        (refine '(mp _ _))
+        (state)
+        (try (begin (refine 'ax1) (state) (error \"later\")) show)
+        (state)
+        (refine 'ax1 'h));"), [
+      ("failed to unify: b -> a =?= b -> ?b -> b\nvariable vs term: a != (imp ?b b)".into(),
+        "ax1".into()),
+      ("(((imp b a)) ())".into(), "(state)".into()),
+      ("(((imp ?a (imp b a)) ?a) (?a))".into(), "(state)".into()),
+      ("((a) ())".into(), "(state)".into()),
+      ("later".into(), "(error \"later\")".into()),
+      ("(((imp ?a (imp b a)) ?a) (?a))".into(), "(state)".into()),
+    ]);
+  }
+
+  /// Sets the cancellation flag when `cancel` is called.
+  struct Canceller(Arc<AtomicBool>);
+
+  impl Debugger for Canceller {
+    fn should_break(&mut self, _: usize, _: &FileRef, _: Span, name: Option<&[u8]>) -> bool {
+      if name == Some(b"cancel") { self.0.store(true, Ordering::Relaxed) }
+      false
+    }
+    fn pause(&mut self, _: DebugView<'_, '_>) {}
+  }
+
+  #[test]
+  fn try_fatal_errors() {
+    // Timeouts, stack overflows and cancellation are not caught by `try`
+    let msgs = elab("
+      do { (set-stack-limit 50) (def (deep n) (if (= n 0) 0 (+ 1 (deep (- n 1)))))
+        (try (deep 100) (fn (e) (print 'caught))) };
+      do { (set-timeout 50) (def (spin) (spin)) (try (spin) (fn (e) (print 'caught))) };");
+    assert!(matches!(&*msgs, [
+      (ErrorLevel::Error, m1, _), (ErrorLevel::Error, m2, _)
+    ] if m1 == "stack overflow" && m2 == "timeout"), "{:?}", msgs);
+    let cancel = Arc::<AtomicBool>::default();
+    let errs = elab_errors("do {
+      (def (cancel) 0)
+      (try (begin (cancel) (def (spin) (spin)) (spin)) (fn (e) (print 'caught)))
+    };", Arc::clone(&cancel), Some(Box::new(Canceller(Arc::clone(&cancel)))));
+    let msgs = errs.iter().map(|e| e.kind.msg()).collect::<Vec<_>>();
+    assert_eq!(msgs, ["cancelled"]);
+  }
+
+  #[test]
+  fn hash_maps() {
+    assert_eq!(infos("do {
+      (def m (hash-map! '[(a b) 1] '[2 two] '[\"s\" str] '[#t yes]))
+      (print (list (lookup m '(a . (b))) (lookup m (cons 'a '(b))) (lookup m 2) (lookup m 3)))
+      (print (list (map-has? m \"s\") (map-has? m 's) (map-size m)))
+      (insert! m (cons 'a '(b)) 3)
+      (print (map->list m))
+      (insert! m 2)
+      (print (list (remove! m \"s\") (remove! m \"s\")))
+      (print (map->list m))
+      (print (map->list (hash-set! 'c '(b) 'a 10 (- 1) '(a b) '(a) \"b\")))
+      (print (map->list (map-union (hash-set! 1 2) (hash-map! '[2 x] '[3 y]))))
+      (print (map->list (map-intersect (hash-map! '[1 x] '[2 y] '[3 z]) (hash-set! 2 3 4))))
+      (print (map->list (map-diff (hash-set! 1 2 3) (hash-set! 2))))
+    };"), [
+      "(1 1 two #undef)",
+      "(#t #f 4)",
+      "((2 two) (\"s\" str) (#t yes) ((a b) 3))",
+      "(str #undef)",
+      "((#t yes) ((a b) 3))",
+      "((a #t) (c #t) (-1 #t) (10 #t) (\"b\" #t) ((a) #t) ((a b) #t) ((b) #t))",
+      "((1 #t) (2 x) (3 y))",
+      "((2 y) (3 z))",
+      "((1 #t) (3 #t))",
+    ]);
+    // Mutable values cannot be used as keys
+    let msgs = elab("
+      do { (lookup (hash-map!) (ref! 1)) };
+      do { (map-has? (hash-map!) (hash-map!)) };
+      do { (insert! (hash-map!) (list (atom-map!)) 1) };");
+    let msgs = msgs.iter().map(|(lvl, msg, _)| {
+      assert!(matches!(lvl, ErrorLevel::Error));
+      msg.split(',').next().expect("nonempty")
+    }).collect::<Vec<_>>();
+    assert_eq!(msgs, ["expected an immutable key"; 3]);
+  }
+
+  /// The local variables of each stack frame.
+  type Frames = Vec<Vec<(String, String)>>;
+
+  /// Records the local variables of all frames whenever `target` is called.
+  struct Recorder(Arc<Mutex<Vec<Frames>>>);
+
+  impl Debugger for Recorder {
+    fn should_break(&mut self, _: usize, _: &FileRef, _: Span, name: Option<&[u8]>) -> bool {
+      name == Some(b"target")
+    }
+    fn pause(&mut self, view: DebugView<'_, '_>) {
+      let frames = (0..view.frames().len()).map(|i| view.locals(i)).collect();
+      self.0.lock().expect("poisoned").push(frames)
+    }
+  }
+
+  #[test]
+  fn debug_locals() {
+    let out = Arc::default();
+    let msgs = elab_with("do {
+      (def (target) 0)
+      (def (f a b) (let ([c (+ a b)]) (match (list c) [(d) (target)])))
+      (def (g x) (begin ((fn (y) (begin (f x y) 'ok)) 2) 'ok))
+      (let ([t 1]) (g t))
+    };", Some(Box::new(Recorder(Arc::clone(&out)))));
+    assert!(msgs.iter().all(|m| matches!(m.0, ErrorLevel::Info)), "{:?}", msgs);
+    let strs = |v: &[(&str, &str)]| v.iter()
+      .map(|&(x, e)| (x.to_owned(), e.to_owned())).collect::<Vec<_>>();
+    assert_eq!(*out.lock().expect("poisoned"), [vec![
+      strs(&[("a", "1"), ("b", "2"), ("c", "3"), ("d", "3")]),
+      strs(&[("x", "1"), ("y", "2")]),
+      strs(&[("x", "1")]),
+      strs(&[("t", "1")]),
+    ]]);
+  }
+
+  #[test]
+  fn auto_conv() {
+    // `h2` only matches the second hypothesis of `mp` after unfolding `id`,
+    // and the hypotheses are used at the leaves of a depth 1 search.
+    assert_eq!(infos("
+      delimiter $ ( ) $;
+      provable sort wff;
+      term imp: wff > wff > wff; infixr imp: $->$ prec 25;
+      def id (a: wff): wff = $ a $;
+      axiom mp (a b: wff): $ a -> b $ > $ a $ > $ b $;
+      theorem t (a b: wff) (h1: $ (a -> a) -> b $) (h2: $ id (a -> a) $): $ b $ =
+      (focus (def p (auto '(mp) 1)) (print p) (refine p));"),
+      ["(mp h1 (:conv (imp a a) (:sym (:unfold id ((imp a a)) (imp a a))) h2))"]);
+  }
+
+  /// A small theory of natural numbers with registered equality schemes, for testing
+  /// `rw` and `simp`.
+  const NAT: &str = "
+    delimiter $ ( ) $;
+    provable sort wff;
+    sort nat;
+    term iff: wff > wff > wff; infixl iff: $<->$ prec 20;
+    term eq: nat > nat > wff; infixl eq: $=$ prec 50;
+    term d0: nat; prefix d0: $0$ prec max;
+    term suc: nat > nat;
+    term add: nat > nat > nat; infixl add: $+$ prec 64;
+    axiom biid (a: wff): $ a <-> a $;
+    axiom bitr (a b c: wff): $ a <-> b $ > $ b <-> c $ > $ a <-> c $;
+    axiom mpbir (a b: wff): $ a <-> b $ > $ b $ > $ a $;
+    axiom eqid (a: nat): $ a = a $;
+    axiom eqtr (a b c: nat): $ a = b $ > $ b = c $ > $ a = c $;
+    axiom eqeq (a b c d: nat): $ a = b $ > $ c = d $ > $ a = c <-> b = d $;
+    axiom suceq (a b: nat): $ a = b $ > $ suc a = suc b $;
+    axiom addeq (a b c d: nat): $ a = b $ > $ c = d $ > $ a + c = b + d $;
+    do {
+      (register-eq 'wff 'iff 'biid 'bitr 'mpbir)
+      (register-eq 'nat 'eq 'eqid 'eqtr)
+      (register-congr 'eq 'eqeq)
+      (register-congr 'suc 'suceq)
+      (register-congr 'add 'addeq)
+      (def (annotate e s) (e s))
+    };";
+
+  #[test]
+  fn rewriting() {
+    assert_eq!(infos(&format!("{}
+      @register-simp axiom add0 (a: nat): $ a + 0 = a $;
+      axiom addS (a b: nat): $ a + suc b = suc (a + b) $;
+      do {{ (insert! simp-lemmas 'addS #t) }};
+      theorem t1 (a: nat): $ suc (a + 0) = suc a $ = (focus (refine (rw '(add0))));
+      theorem t2 (a: nat): $ a + 0 + 0 = a $ = (focus (refine (rw '(add0))));
+      theorem t3 (a b: nat): $ a + suc (b + 0) = suc (a + b) $ = (focus (refine (simp)));
+      theorem t4 (a: nat): $ suc a = suc a $ = (focus (refine (simp)));
+      do {{
+        (print (rw '(add0) $ suc (0 + 0) $))
+        (print (simp #undef $ suc (0 + suc 0) $))
+        (print (rw '(addS) $ suc 0 $))
+      }};", NAT)), [
+      "used add0", "used add0 (x2)", "used add0, addS", "closed by reflexivity",
+      "((suc (d0)) (suceq (add0)) (add0))",
+      "((suc (suc (d0))) (suceq (eqtr (addS) (suceq (add0)))) (addS add0))",
+      "((suc (d0)) #undef ())",
+    ]);
+    let msgs = elab(&format!("{}
+      axiom add0 (a: nat): $ a + 0 = a $;
+      @register-simp term foo: nat;
+      do {{ (register-congr 'suc 'add0) }};
+      theorem t1 (a: nat): $ a = a $ = (focus (refine (rw '(eqeq))));
+      theorem t2 (a: nat): $ suc a = a $ = (focus (refine (rw '(add0))));", NAT));
+    assert_eq!(msgs.into_iter().map(|(lvl, msg, _)| {
+      assert!(matches!(lvl, ErrorLevel::Error), "unexpected message: {}", msg);
+      msg
+    }).collect::<Vec<_>>(), [
+      "unknown theorem 'foo'",
+      "'add0' is not a congruence theorem for 'suc'",
+      "'eqeq' is not a rewrite lemma of the form a = b",
+      "no lemmas apply to the goal",
+    ]);
+  }
+
+  #[test]
+  fn conversions() {
+    assert_eq!(infos("
+      delimiter $ ( ) $;
+      provable sort wff;
+      sort nat;
+      term imp: wff > wff > wff; infixr imp: $->$ prec 25;
+      term not: wff > wff; prefix not: $~$ prec 40;
+      term all {x: nat} (p: wff x): wff;
+      term tru: wff;
+      def or (a b: wff): wff = $ ~ a -> b $; infixl or: $\\/$ prec 30;
+      def id (a: wff): wff = $ a $;
+      def hasv {.x: nat}: wff = $ all x tru $;
+      axiom ax1 (a: wff): $ a -> a $;
+      do { (def (goal-ty) (goal-type (hd (get-goals)))) };
+      theorem t1 (a: wff): $ id a -> id (id a) $ =
+      (focus
+        (unfold 'id '(2)) (print (goal-ty))
+        (unfold 'id '(2)) (print (goal-ty))
+        (unfold 'id) (print (goal-ty))
+        (refine 'ax1));
+      theorem t2 (a: wff): $ (~ a -> a) -> (~ a -> a) $ =
+      (focus
+        (fold 'or '(2)) (print (goal-ty))
+        (fold 'or) (print (goal-ty))
+        (refine 'ax1));
+      theorem t3 (a b: wff): $ id a -> a $ =
+      (focus
+        (def c (hd (tl (conv-unfold 'id $ id a $))))
+        (print c)
+        (def c2 (conv-at (goal-ty) '(1) c))
+        (print (list c2 (conv-lhs c2) (conv-rhs c2)))
+        (conv-goal c2)
+        (refine 'ax1));
+      theorem t4: $ hasv -> hasv $ =
+      (focus
+        (try (unfold 'hasv '(1 3)) (fn (e) (match e [('error msg) (display msg)])))
+        (unfold 'hasv) (print (goal-ty))
+        (fold 'hasv '(2)) (print (goal-ty))
+        (fold 'hasv) (print (goal-ty))
+        (refine 'ax1));
+      do {
+        (def (show e) (match e [('error msg) (display msg)]))
+        (print (conv-fold 'or '(imp (not a) (imp (not b) b)) '(2)))
+        (try (conv-fold 'or '(imp (not a) (imp (not b) b)) '(3)) show)
+        (try (conv-fold 'id '(imp a (not a))) show)
+      };
+    "), [
+      "(imp (id a) (id a))", "(imp (id a) a)", "(imp a a)",
+      "(imp (imp (not a) a) (or a a))", "(imp (or a a) (or a a))",
+      "(:unfold id (a) a)",
+      "((imp (:unfold id (a) a) a) (imp (id a) a) (imp a a))",
+      // The dummy `_1` created before the failure is removed again
+      "occurrence 3 of 'hasv' not found (there are 2)",
+      "(imp (all _1 (tru)) (all _2 (tru)))",
+      "(imp (all _1 (tru)) (hasv))",
+      "(imp (hasv) (hasv))",
+      "((imp (not a) (or b b)) (imp (not a) (:sym (:unfold or (b b) (imp (not b) b)))))",
+      "occurrence 3 of 'or' not found (there are 2)",
+      "cannot fold 'id', which unfolds to a variable",
+    ]);
+  }
+
+  #[test]
+  fn strings() {
+    assert_eq!(infos("
+      delimiter $ ( ) $;
+      provable sort wff;
+      term imp: wff > wff > wff; infixr imp: $->$ prec 25;
+      do {
+        (display (format \"{} + {} = {}\" 2 2 4))
+        (display (format \"{{{}}} }}{{\" 'foo))
+        (display (format \"{} {pp}\" \"goal:\" $ a -> a -> a $))
+        (display (format \"no args\"))
+        (print (string-split \",\" \"a,b,,c\"))
+        (print (string-split \", \" \", a, \"))
+        (print (string-split \",\" \"\"))
+        (print (list (string-index \"o\" \"hello world\") (string-index \"o\" \"hello world\" 5)
+          (string-index \"x\" \"hello world\") (string-index \"\" \"abc\" 3)))
+        (display (string-replace \"o\" \"0\" \"foo bar\"))
+        (display (string-replace \"aa\" \"a\" \"aaaaa\"))
+        (display (string-join \", \" '(a \"b\" 1)))
+        (print (string-join \", \" ()))
+        (display (string-upcase \"foo-Bar 1\"))
+        (display (string-downcase \"foo-Bar 1\"))
+        (print (list (number->string 255) (number->string 255 16) (number->string (- 5) 2)
+          (number->string 35 36)))
+      };"), ["2 + 2 = 4", "{foo} }{", "goal: a -> a -> a", "no args",
+      r#"("a" "b" "" "c")"#, r#"("" "a" "")"#, r#"("")"#, "(4 7 #f 3)",
+      "f00 bar", "aaa", "a, b, 1", r#""""#, "FOO-BAR 1", "foo-bar 1",
+      r#"("255" "ff" "-101" "z")"#]);
+    let msgs = elab("
+      do { (format \"{} {}\" 1) };
+      do { (format \"{}\" 1 2) };
+      do { (format \"{x}\" 1) };
+      do { (format \"}\") };
+      do { (string-split \"\" \"abc\") };
+      do { (string-replace \"\" \"x\" \"abc\") };
+      do { (string-index \"a\" \"abc\" 4) };
+      do { (string-join \",\" '(a . b)) };
+      do { (number->string 10 37) };");
+    assert_eq!(msgs.into_iter().map(|(lvl, msg, _)| {
+      assert!(matches!(lvl, ErrorLevel::Error), "unexpected message: {}", msg);
+      msg
+    }).collect::<Vec<_>>(), [
+      "format: not enough arguments",
+      "format: too many arguments",
+      "format: bad format string: {x}",
+      "format: bad format string: }",
+      "string-split: empty separator",
+      "string-replace: empty search string",
+      "index out of range: start 4, length 3",
+      "string-join: not a list: (a . b)",
+      "radix out of range: 37",
+    ]);
+  }
+
+  #[test]
+  fn macro_hygiene() {
+    assert_eq!(infos("do {
+      (defmacro (my-or a b) (list 'let (list (list 't a)) (list 'if 't 't b)))
+      (print (let ([t 5]) (my-or #f t)))
+      (defmacro (my-or3 a b c) (list 'my-or a (list 'my-or b c)))
+      (print (let ([t 7]) (my-or3 #f #f t)))
+      (def (helper x) (list 'global x))
+      (defmacro (call-helper e) (list 'helper e))
+      (print (let ([helper (fn (x) 'local)]) (call-helper 1)))
+      (defmacro (with-x e) (list 'let (list (list 'x 1)) (list 'list ''x e)))
+      (print (let ([x 2]) (with-x x)))
+    };"), ["5", "7", "(global 1)", "(x 2)"]);
+    // The renamed variables are local to the parser, and do not leak into the atom table.
+    let (errs, env) = elab_env("do {
+      (defmacro (m e) (list 'let (list (list 'y e)) 'y))
+      (def z (m (m 1)))
+    };", Arc::default(), None);
+    assert!(errs.is_empty());
+    assert!(env.data().iter().all(|d| !d.name().contains(&b' ')));
+  }
+
+  fn errors_at(src: &str) -> Vec<(String, String)> {
+    elab_errors(src, Arc::default(), None).into_iter().map(|e| {
+      assert!(matches!(e.level, ErrorLevel::Error), "unexpected message: {}", e.kind.msg());
+      (e.kind.msg(), src[e.pos.start..e.pos.end].to_owned())
+    }).collect()
+  }
+
+  #[test]
+  fn macro_errors() {
+    // Errors in the arguments point at the argument, and errors in the generated code
+    // or in the expansion itself point at the macro invocation.
+    let strs = |v: &[(&str, &str)]| v.iter()
+      .map(|&(x, e)| (x.to_owned(), e.to_owned())).collect::<Vec<_>>();
+    assert_eq!(errors_at("do {
+      (defmacro (forever) '(forever))
+      (forever)
+    };"), strs(&[("maximum macro expansion depth exceeded", "(forever)")]));
+    assert_eq!(errors_at("
+      do { (defmacro (wrap x) (list 'list 1 x)) (wrap (+ 1 'a)) };
+      do { (defmacro (bad x) (list 'hd x)) (bad 2) };
+      do { (defmacro (oops x) (error \"in macro\")) (oops 3) };"), strs(&[
+      ("expected a integer, got a", "(+ 1 'a)"),
+      ("expected a list, got 2", "(bad 2)"),
+      ("in macro", "(oops 3)"),
+    ]));
+  }
+}
@@ -5,7 +5,9 @@
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
 use std::collections::HashMap;
-use num::{BigInt, ToPrimitive};
+use std::rc::Rc;
+use typed_arena::Arena;
+use num::{BigInt, Signed, ToPrimitive};
 use crate::ast::{SExpr, SExprKind, Atom};
 use crate::ArcString;
 use super::super::{AtomId, Span, DocComment, Elaborator, ElabError, ObjectKind};
@@ -89,12 +91,17 @@ pub enum Ir {
   GlobalDef(Span, Span, AtomId),
   /// Set the doc comment for a variable. Does not touch the stack.
   SetDoc(DocComment, AtomId),
-  /// The `(fn xs e)` syntax form. Create a closure from the current context, and return
-  /// it, using the provided [`ProcSpec`] and code. It can later be called by the
-  /// [`App`](Self::App) instruction.
+  /// The `(fn xs e)` syntax form. Create a closure capturing the listed variables of
+  /// the current context, and return it, using the provided [`ProcSpec`] and code.
+  /// It can later be called by the [`App`](Self::App) instruction.
   /// The `u8` is `u8::MAX` if the lambda is unnamed; else it is a forward reference
-  /// to a `GlobalDef` with the name information. `[] -> [lambda spec [code...]]`
-  Lambda(u8, Box<(Span, ProcSpec, Arc<[Ir]>)>),
+  /// to a `GlobalDef` with the name information.
+  /// `[] -> [lambda spec [code...] (x_i1, ..., x_ik)]`
+  ///
+  /// Inside the code, the captured variables are numbered `0..k` in the order given,
+  /// followed by the arguments.
+  #[allow(clippy::type_complexity)]
+  Lambda(u8, Box<(Span, ProcSpec, Arc<Code>, Box<[usize]>)>),
   /// Start a match branch. Push `n` variables to the match context,
   /// to store the values of variables to match.
   /// The second usize is the address of the next branch.
@@ -131,6 +138,13 @@ pub enum Ir {
   /// Receive the result of a callback in the `add-thm` function.
   /// `[(add-thm ap), ret] -> [#undef]` and evaluate `ap.finish(ret)`.
   AddThm,
+  /// The start of a `(try e handler)` block. The handler is on the stack, and the
+  /// location is the instruction after the matching [`TryEnd`](Self::TryEnd).
+  /// `[h] -> [(try h)]`. If an error is thrown while `(try h)` is on the stack, the stack
+  /// is unwound to this point, `(try h)` is replaced by `h(err)`, and we jump to the location.
+  TryStart(Span, usize),
+  /// The end of a `(try e handler)` block. `[(try h), e] -> [e]`
+  TryEnd,
   /// Implementation of `merge-map` inner loop.
   /// * `[(merge-map {map, k: Some(k), ..}), ret] -> [(merge-map {map', k: None})]` and loop,
   ///   where `map' = map.insert(k, ret)`
@@ -253,9 +267,14 @@ impl Ir {
           ProcSpec::Exact(n) => writeln!(f, "{} [", n)?,
           ProcSpec::AtLeast(n) => writeln!(f, "{}+ [", n)?,
         }
-        Self::fmt_list(&args.2, depth + 1, fe, f)?;
+        Self::fmt_list(&args.2.ir, depth + 1, fe, f)?;
         for _ in 0..depth { f.write_str("  ")? }
-        write!(f, "]")
+        write!(f, "] (")?;
+        for (i, x) in args.3.iter().enumerate() {
+          if i != 0 { f.write_str(", ")? }
+          write!(f, "x{}", x)?
+        }
+        write!(f, ")")
       }
       Ir::Branch(n, ip, None) => write!(f, "branch {} -> {}", n, ip),
       Ir::Branch(n, ip, Some(_)) => write!(f, "branch-cont {} -> {}", n, ip),
@@ -266,6 +285,8 @@ impl Ir {
       Ir::RefineResume => write!(f, "refine-resume"),
       Ir::AddThm => write!(f, "add-thm"),
       Ir::MergeMap => write!(f, "merge-map"),
+      Ir::TryStart(_, ip) => write!(f, "try-start -> {}", ip),
+      Ir::TryEnd => write!(f, "try-end"),
       Ir::PatternResult(false) => write!(f, "> fail"),
       Ir::PatternResult(true) => write!(f, "> skip"),
       Ir::PatternAtom(n) => write!(f, "> var {}", n),
@@ -294,6 +315,35 @@ impl EnvDisplay for Ir {
   }
 }
 
+/// A compiled code block (the body of a lambda, or a top level expression),
+/// together with the names of its local variables for the debugger.
+#[derive(Debug, Default, EnvDebug, DeepSizeOf)]
+pub struct Code {
+  /// The instructions.
+  pub ir: Box<[Ir]>,
+  /// An entry `(ip, i, x)` means that from instruction `ip` onward, local variable slot
+  /// `i` holds the variable `x` (until a later entry for slot `i`).
+  pub locals: Box<[(usize, usize, AtomId)]>,
+}
+
+impl Code {
+  /// The name of local variable slot `i` when the instruction pointer is at `ip`,
+  /// given the [`locals`](Self::locals) of a code block.
+  #[must_use] pub fn local_name(locals: &[(usize, usize, AtomId)], ip: usize, i: usize) -> Option<AtomId> {
+    locals.iter().rev().find(|&&(ip2, j, _)| j == i && ip2 <= ip).map(|x| x.2)
+  }
+}
+
+impl Remap for Code {
+  type Target = Self;
+  fn remap(&self, r: &mut Remapper) -> Self {
+    Code {
+      ir: self.ir.remap(r),
+      locals: self.locals.iter().map(|&(ip, i, x)| (ip, i, x.remap(r))).collect(),
+    }
+  }
+}
+
 /// A formatter for [`Ir`] lists.
 #[derive(Clone, Copy, Debug, EnvDebug, DeepSizeOf)]
 pub struct IrList<'a>(pub usize, pub &'a [Ir]);
@@ -324,7 +374,8 @@ impl Remap for Ir {
       Ir::Const(v) => Ir::Const(unsafe { v.freeze() }.remap(r)),
       &Ir::SetMergeStrategy(sp, a) => Ir::SetMergeStrategy(sp, a.remap(r)),
       &Ir::GlobalDef(sp, sp2, a) => Ir::GlobalDef(sp, sp2, a.remap(r)),
-      &Ir::Lambda(name, ref args) => Ir::Lambda(name, Box::new((args.0, args.1, args.2.remap(r)))),
+      &Ir::Lambda(name, ref args) =>
+        Ir::Lambda(name, Box::new((args.0, args.1, args.2.remap(r), args.3.clone()))),
       &Ir::PatternQuoteAtom(a) => Ir::PatternQuoteAtom(a.remap(r)),
       &Ir::PatternQExprAtom(a) => Ir::PatternQExprAtom(a.remap(r)),
       _ => self.clone()
@@ -367,10 +418,26 @@ impl LocalCtx {
   }
 }
 
+/// The maximum nesting depth of macro expansions, to catch macros that expand to themselves.
+const MAX_MACRO_DEPTH: usize = 256;
+
 struct LispParser<'a> {
   elab: &'a mut Elaborator,
   ctx: LocalCtx,
   code: Vec<Ir>,
+  /// The local variable names of `code` (see [`Code::locals`]).
+  locals: Vec<(usize, usize, AtomId)>,
+  /// The atoms and keywords produced by macro expansion, referenced by [`Atom::Gen`],
+  /// along with the index of the expansion that produced them.
+  gen: Vec<(Result<AtomId, Syntax>, u32)>,
+  /// Maps the names produced by [`rename`](Self::rename) back to the original names.
+  renamed: HashMap<AtomId, AtomId>,
+  /// The names produced by [`rename`](Self::rename), indexed by the original name and scope.
+  fresh: HashMap<(AtomId, u32), AtomId>,
+  /// The number of macro expansions so far.
+  expansions: u32,
+  /// The current nesting depth of macro expansions.
+  depth: usize,
 }
 impl<'a> Deref for LispParser<'a> {
   type Target = Elaborator;
@@ -418,7 +485,33 @@ impl ExprCtx {
 
 impl<'a> LispParser<'a> {
   fn new(elab: &'a mut Elaborator) -> Self {
-    Self { elab, ctx: LocalCtx::new(), code: vec![] }
+    Self {
+      elab, ctx: LocalCtx::new(), code: vec![], locals: vec![],
+      gen: vec![], renamed: HashMap::new(), fresh: HashMap::new(), expansions: 0, depth: 0,
+    }
+  }
+
+  fn into_code(self) -> Code {
+    Code { ir: self.code.into(), locals: self.locals.into() }
+  }
+
+  /// Push a local variable which is bound starting at the current instruction.
+  fn push_local(&mut self, x: AtomId) -> usize {
+    let n = self.ctx.push(x);
+    if x != AtomId::UNDER { self.locals.push((self.code.len(), n, self.unrename(x))) }
+    n
+  }
+
+  /// Start compiling a new code block, returning the old one.
+  fn take_code(&mut self) -> (Vec<Ir>, Vec<(usize, usize, AtomId)>) {
+    (std::mem::take(&mut self.code), std::mem::take(&mut self.locals))
+  }
+
+  /// Finish compiling a code block, restoring the old one.
+  fn replace_code(&mut self,
+    (code, locals): (Vec<Ir>, Vec<(usize, usize, AtomId)>)
+  ) -> (Vec<Ir>, Vec<(usize, usize, AtomId)>) {
+    (std::mem::replace(&mut self.code, code), std::mem::replace(&mut self.locals, locals))
   }
 
   fn push_def(&mut self,
@@ -427,7 +520,8 @@ impl<'a> LispParser<'a> {
     if global && x != AtomId::UNDER {
       for (i, ir) in self.code.iter_mut().rev().enumerate() {
         match ir {
-          Ir::AssertScope(_) | Ir::EndScope(_) => {}
+          Ir::AssertScope(_) | Ir::EndScope(_) |
+          Ir::BuiltinApp(_, BuiltinProc::Macro, _, _) => {}
           Ir::Lambda(name, _) => {
             if let Ok(i) = i.try_into() { *name = i }
             break
@@ -440,14 +534,51 @@ impl<'a> LispParser<'a> {
         self.code.push(Ir::SetDoc(doc, x))
       }
     } else {
-      let n = self.ctx.push(x);
+      let n = self.push_local(x);
       self.code.push(Ir::LocalDef(n));
     }
   }
 
-  fn push_lambda(&mut self, sp: Span, n: usize, spec: ProcSpec, code: Arc<[Ir]>) {
+  /// Push a lambda whose code `code` was compiled in a context with `n` variables
+  /// before the arguments. Only the variables that are actually used in the body
+  /// are captured, so the variables in `code` are renumbered accordingly.
+  /// The arguments are named by the variables following the first `n` in the context.
+  fn push_lambda(&mut self, sp: Span, n: usize, spec: ProcSpec,
+    (mut code, mut locals): (Vec<Ir>, Vec<(usize, usize, AtomId)>)
+  ) {
+    let mut used = vec![false; n];
+    for ir in &code {
+      match *ir {
+        Ir::Local(i) if i < n => used[i] = true,
+        Ir::Lambda(_, ref args) => for &i in &*args.3 { if i < n { used[i] = true } }
+        _ => {}
+      }
+    }
+    let caps = (0..n).filter(|&i| used[i]).collect::<Box<[_]>>();
+    let nargs = match spec { ProcSpec::Exact(k) => k, ProcSpec::AtLeast(k) => k + 1 };
+    let names = caps.iter().map(|&i| self.ctx.ctx[i]).chain(self.ctx.ctx[n..n + nargs].iter().copied());
+    let mut args = names.enumerate()
+      .filter(|&(_, x)| x != AtomId::UNDER).map(|(i, x)| (0, i, self.unrename(x)))
+      .collect::<Vec<_>>();
+    if caps.len() < n {
+      // Captured variable `caps[j]` becomes `j`, and the local variables
+      // of the body are shifted down to follow the captures.
+      let k = caps.len();
+      let map = |i: usize| if i < n { caps.partition_point(|&j| j < i) } else { i - n + k };
+      for ir in &mut code {
+        match ir {
+          Ir::Local(i) | Ir::LocalDef(i) | Ir::AssertScope(i) | Ir::EndScope(i) |
+          Ir::Branch(_, _, Some(i)) => *i = map(*i),
+          Ir::Lambda(_, args) => for i in &mut *args.3 { *i = map(*i) }
+          _ => {}
+        }
+      }
+      for (_, i, _) in &mut locals { *i = map(*i) }
+    }
+    args.append(&mut locals);
+    let code = Code { ir: code.into(), locals: args.into() };
     self.code.push(Ir::AssertScope(n));
-    self.code.push(Ir::Lambda(u8::MAX, Box::new((sp, spec, code))))
+    self.code.push(Ir::Lambda(u8::MAX, Box::new((sp, spec, code.into(), caps))))
   }
 
   fn restore(&mut self, n: usize) {
@@ -496,19 +627,19 @@ impl<'a> LispParser<'a> {
       self.exprs(ExprsCtx::Eval(keep, tail), es)?;
       self.restore(len)
     } else {
-      let mut orig = std::mem::take(&mut self.code);
+      let mut orig = self.take_code();
       self.exprs(ExprsCtx::Eval(true, true), es)?;
       for (i, e) in stack.into_iter().enumerate() {
-        let mut code = if i == lambdas - 1 { std::mem::take(&mut orig) } else { vec![] };
-        std::mem::swap(&mut self.code, &mut code);
+        let code = if i == lambdas - 1 { std::mem::take(&mut orig) } else { Default::default() };
+        let code = self.replace_code(code);
         match e {
           Item::List(xs) => {
             len -= xs.len();
-            self.push_lambda(sp, len, ProcSpec::Exact(xs.len()), code.into())
+            self.push_lambda(sp, len, ProcSpec::Exact(xs.len()), code)
           }
           Item::DottedList(xs, _) => {
             len -= xs.len() + 1;
-            self.push_lambda(sp, len, ProcSpec::AtLeast(xs.len()), code.into())
+            self.push_lambda(sp, len, ProcSpec::AtLeast(xs.len()), code)
           }
         }
       }
@@ -583,12 +714,219 @@ impl<'a> LispParser<'a> {
   }
 
   fn parse_ident_or_syntax(&mut self, sp: Span, a: Atom) -> Result<AtomId, Syntax> {
+    if let Atom::Gen(i) = a {
+      let (x, scope) = self.gen[i as usize];
+      return x.map(|x| self.rename(x, scope))
+    }
     match Syntax::parse(self.ast.clone().span(sp), a) {
       Ok(s) => Err(s),
       Err(s) => Ok(self.get_atom(s))
     }
   }
 
+  /// Get the name used for the identifier `x` when it is produced by macro expansion
+  /// number `scope`. Local variables bound by a macro expansion use this fresh name,
+  /// so that they neither capture nor are captured by the variables written by the user.
+  ///
+  /// The fresh names are not interned: they are ids past the end of the atom table, which
+  /// are only meaningful to this parser and must be mapped back using
+  /// [`unrename`](Self::unrename) before they are used to look up atom data or stored
+  /// in the compiled code.
+  fn rename(&mut self, x: AtomId, scope: u32) -> AtomId {
+    if x == AtomId::UNDER { return x }
+    let n = u32::try_from(self.renamed.len()).expect("too many renamed variables");
+    let renamed = &mut self.renamed;
+    *self.fresh.entry((x, scope)).or_insert_with(|| {
+      let y = AtomId(u32::MAX - n);
+      renamed.insert(y, x);
+      y
+    })
+  }
+
+  /// Get the original name of an identifier renamed by [`rename`](Self::rename).
+  fn unrename(&self, x: AtomId) -> AtomId {
+    self.renamed.get(&x).copied().unwrap_or(x)
+  }
+
+  fn span_atom(&self, sp: Span, a: Atom) -> &[u8] {
+    match a {
+      Atom::Gen(i) => match self.gen[i as usize].0 {
+        Ok(x) => &self.data[x].name,
+        Err(s) => s.to_byte_str(),
+      },
+      _ => self.ast.span_atom(sp, a)
+    }
+  }
+
+  fn is_syntax(&self, sp: Span, a: Atom, stx: Syntax) -> bool {
+    match a {
+      Atom::Gen(i) => self.gen[i as usize].0 == Err(stx),
+      _ => Syntax::parse(self.ast.span(sp), a) == Ok(stx),
+    }
+  }
+
+  /// Returns the lisp value corresponding to a macro, if `x` refers to a global macro
+  /// definition which is not shadowed by a local variable.
+  fn get_macro(&self, x: AtomId) -> Option<LispVal> {
+    if self.ctx.get(x).is_some() { return None }
+    let data = self.data[self.unrename(x)].lisp.as_ref()?;
+    data.val.unwrapped(|e| match e {
+      LispKind::Proc(Proc::Macro(f)) => Some(f.clone()),
+      _ => None
+    })
+  }
+
+  /// Convert a macro argument to a lisp value. The resulting value and its subterms
+  /// are recorded in `orig`, so that the parts that are passed through unchanged by the
+  /// macro can be mapped back to the original syntax, with its spans, after expansion.
+  /// The `arena` holds the syntax of antiquotations in formulas, which is not part of `e`.
+  fn sexpr_to_val<'c>(&mut self, e: &'c SExpr,
+    orig: &mut HashMap<*const LispKind, &'c SExpr>, arena: &'c Arena<SExpr>,
+  ) -> Result<LispVal, ElabError> {
+    let v = match &e.k {
+      &SExprKind::Atom(a) => match self.parse_ident_or_syntax(e.span, a) {
+        Ok(x) => LispVal::atom(self.unrename(x)),
+        Err(s) => LispVal::syntax(s),
+      },
+      SExprKind::List(es) => {
+        let mut vs = Vec::with_capacity(es.len());
+        for e in es { vs.push(self.sexpr_to_val(e, orig, arena)?) }
+        LispVal::list(vs)
+      }
+      SExprKind::DottedList(es, r) => {
+        let mut vs = Vec::with_capacity(es.len());
+        for e in es { vs.push(self.sexpr_to_val(e, orig, arena)?) }
+        LispVal::dotted_list(vs, self.sexpr_to_val(r, orig, arena)?)
+      }
+      SExprKind::Number(n) => LispVal::number(n.clone().into()),
+      SExprKind::String(s) => LispVal::string(s.clone()),
+      &SExprKind::Bool(b) => LispVal::bool(b),
+      SExprKind::Undef => LispVal::undef(),
+      SExprKind::DocComment(_, e) => self.sexpr_to_val(e, orig, arena)?,
+      &SExprKind::Formula(f) => {
+        // `$ a + ,b $` is passed to the macro as `'(add a ,b)`, which evaluates to the same thing
+        let q = self.parse_formula(f)?;
+        let q = self.qexpr_to_val(q, orig, arena)?;
+        LispVal::list(vec![LispVal::syntax(Syntax::Quote), q])
+      }
+    }.span(self.fspan(e.span));
+    orig.insert(Rc::as_ptr(&v.0), e);
+    Ok(v)
+  }
+
+  /// Convert a formula in a macro argument to the quoted s-expression it stands for,
+  /// where antiquotations `,e` become `(unquote e)`. See [`sexpr_to_val`](Self::sexpr_to_val).
+  fn qexpr_to_val<'c>(&mut self, e: QExpr,
+    orig: &mut HashMap<*const LispKind, &'c SExpr>, arena: &'c Arena<SExpr>,
+  ) -> Result<LispVal, ElabError> {
+    let fsp = self.fspan(e.span);
+    let (head, es) = match e.k {
+      QExprKind::IdentApp(sp, es) => {
+        let a = self.elab.env.get_atom(self.ast.clone().span(sp));
+        let head = LispVal::atom(a).span(self.fspan(sp));
+        if es.is_empty() { return Ok(head) }
+        (head, es.into_vec())
+      }
+      QExprKind::App(sp, t, es) =>
+        (LispVal::atom(self.terms[t].atom).span(self.fspan(sp)), es.into_vec()),
+      QExprKind::Unquote(e) => {
+        if self.mm0_mode {
+          self.report(ElabError::warn(e.span, "(MM0 mode) unquotation not allowed"))
+        }
+        let v = self.sexpr_to_val(arena.alloc(e), orig, arena)?;
+        return Ok(LispVal::list(vec![LispVal::syntax(Syntax::Unquote), v]).span(fsp))
+      }
+    };
+    let mut vs = Vec::with_capacity(es.len() + 1);
+    vs.push(head);
+    for e in es { vs.push(self.qexpr_to_val(e, orig, arena)?) }
+    Ok(LispVal::list(vs).span(fsp))
+  }
+
+  fn gen_atom(&mut self, x: Result<AtomId, Syntax>, scope: u32) -> Atom {
+    let i = self.gen.len().try_into().expect("too many generated atoms");
+    self.gen.push((x, scope));
+    Atom::Gen(i)
+  }
+
+  /// Convert the result of a macro expansion back to syntax. Values that came from the
+  /// macro's arguments are mapped back to the original syntax (see
+  /// [`sexpr_to_val`](Self::sexpr_to_val)), while everything else is considered to be
+  /// generated by expansion number `scope`, and is given the span `sp` of the macro invocation.
+  fn val_to_sexpr(&mut self, sp: Span, scope: u32, e: &LispVal,
+    orig: &HashMap<*const LispKind, &SExpr>
+  ) -> Result<SExpr, ElabError> {
+    if let Some(&e) = orig.get(&Rc::as_ptr(&e.0)) { return Ok(e.clone()) }
+    let k = match &**e {
+      LispKind::Annot(_, e) => return self.val_to_sexpr(sp, scope, e, orig),
+      &LispKind::Atom(x) => SExprKind::Atom(self.gen_atom(Ok(x), scope)),
+      &LispKind::Syntax(s) => SExprKind::Atom(self.gen_atom(Err(s), scope)),
+      LispKind::List(_) | LispKind::DottedList(..) => {
+        let mut es = vec![];
+        let mut e = e;
+        let tail = loop {
+          if !es.is_empty() {
+            if let Some(&e2) = orig.get(&Rc::as_ptr(&e.0)) {
+              match &e2.k {
+                SExprKind::List(es2) => { es.extend_from_slice(es2); break None }
+                SExprKind::DottedList(es2, r) => { es.extend_from_slice(es2); break Some((**r).clone()) }
+                _ => break Some(e2.clone())
+              }
+            }
+          }
+          match &**e {
+            LispKind::Annot(_, e2) => e = e2,
+            LispKind::List(es2) => {
+              for e in &**es2 { es.push(self.val_to_sexpr(sp, scope, e, orig)?) }
+              break None
+            }
+            LispKind::DottedList(es2, r) => {
+              for e in &**es2 { es.push(self.val_to_sexpr(sp, scope, e, orig)?) }
+              e = r
+            }
+            _ => break Some(self.val_to_sexpr(sp, scope, e, orig)?)
+          }
+        };
+        match tail {
+          None => SExprKind::List(es),
+          Some(r) if es.is_empty() => return Ok(r),
+          Some(r) => SExprKind::DottedList(es, Box::new(r)),
+        }
+      }
+      LispKind::Number(n) if n.is_negative() => {
+        let minus = self.get_atom(b"-");
+        SExprKind::List(vec![
+          SExpr {span: sp, k: SExprKind::Atom(self.gen_atom(Ok(minus), scope))},
+          SExpr {span: sp, k: SExprKind::Number(n.magnitude().clone())},
+        ])
+      }
+      LispKind::Number(n) => SExprKind::Number(n.magnitude().clone()),
+      LispKind::String(s) => SExprKind::String(s.clone()),
+      &LispKind::Bool(b) => SExprKind::Bool(b),
+      LispKind::Undef => SExprKind::Undef,
+      _ => return Err(ElabError::new_e(sp, format!(
+        "macro expansion produced a value that is not syntax: {}", self.print(e))))
+    };
+    Ok(SExpr {span: sp, k})
+  }
+
+  /// Expand the macro invocation `e`, whose arguments are `args` and which has
+  /// expansion function `f`.
+  fn expand_macro(&mut self, e: &SExpr, f: &LispVal, args: &[SExpr]) -> Result<SExpr, ElabError> {
+    let arena = Arena::new();
+    let mut orig = HashMap::new();
+    let mut vs = Vec::with_capacity(args.len());
+    for arg in args { vs.push(self.sexpr_to_val(arg, &mut orig, &arena)?) }
+    // We hold on to the arguments so that the pointers in `orig` remain valid.
+    let vs2 = vs.clone();
+    let res = self.elab.call_func(e.span, f, vs)?;
+    let scope = self.expansions;
+    self.expansions += 1;
+    let res = self.val_to_sexpr(e.span, scope, &res, &orig);
+    drop(vs2);
+    res
+  }
+
   fn parse_atom(&mut self, sp: Span, a: Atom) -> Result<AtomId, ElabError> {
     self.parse_ident_or_syntax(sp, a).map_err(|_|
       ElabError::new_e(sp, "keyword in invalid position"))
@@ -701,7 +1039,7 @@ impl<'a> LispParser<'a> {
       let mut ds = Vec::with_capacity(ls.len());
       for l in ls {
         let ((sp, x, stk), e2) = self.let_var(l)?;
-        let n = self.ctx.push(x);
+        let n = self.push_local(x);
         self.code.push(Ir::Undef);
         self.code.push(Ir::BuiltinApp(false, BuiltinProc::NewRef, Box::new((sp, sp)), 1));
         self.code.push(Ir::LocalDef(n));
@@ -711,7 +1049,7 @@ impl<'a> LispParser<'a> {
         self.code.push(Ir::Local(n));
         self.def_ir(sp, true, false, e2, stk)?;
         self.code.push(Ir::BuiltinApp(false, BuiltinProc::SetWeak, Box::new((sp, sp)), 2));
-        let m = self.ctx.push(x);
+        let m = self.push_local(x);
         self.code.push(Ir::LocalDef(m));
       }
     } else {
@@ -722,7 +1060,7 @@ impl<'a> LispParser<'a> {
           self.code.push(Ir::Drop(1));
           self.code.push(Ir::Undef);
         } else {
-          let n = self.ctx.push(x);
+          let n = self.push_local(x);
           self.code.push(Ir::LocalDef(n))
         }
       }
@@ -758,12 +1096,12 @@ impl<'a> LispParser<'a> {
           return Ok(())
         }
         [SExpr {span, k: SExprKind::Atom(a)}, ref e] if quote =>
-          if self.ast.span_atom(span, a) == b"unquote" {
+          if self.span_atom(span, a) == b"unquote" {
             finish!(self.pattern(ctx, false, e)?)
           }
         _ if quote => {},
         [ref head, ref args @ ..] => if let SExprKind::Atom(a) = head.k {
-          match self.ast.span_atom(head.span, a) {
+          match self.span_atom(head.span, a) {
             b"quote" => match args {
               [e] => finish!(self.pattern(ctx, true, e)?),
               _ => return Err(ElabError::new_e(head.span, "expected one argument")),
@@ -771,7 +1109,7 @@ impl<'a> LispParser<'a> {
             b"mvar" => match args {
               [] => finish!(self.code.push(Ir::PatternMVar(MVarPattern::Unknown))),
               &[SExpr {span, k: SExprKind::Atom(a)}]
-              if matches!(self.ast.span_atom(span, a), b"___" | b"...") =>
+              if matches!(self.span_atom(span, a), b"___" | b"...") =>
                 finish!(self.code.push(Ir::PatternMVar(MVarPattern::Any))),
               [bd, s] => finish!({
                 self.code.push(Ir::PatternMVar(MVarPattern::Simple));
@@ -916,8 +1254,14 @@ impl<'a> LispParser<'a> {
   fn pattern(&mut self, ctx: &mut LocalCtx, quote: bool, e: &SExpr) -> Result<(), ElabError> {
     match &e.k {
       &SExprKind::Atom(a) => if quote {
-        self.code.push(Ir::PatternQuoteAtom(
-          self.elab.env.get_atom(self.elab.ast.span_atom(e.span, a))))
+        let x = match a {
+          Atom::Gen(i) => match self.gen[i as usize].0 {
+            Ok(x) => x,
+            Err(s) => self.get_atom(s.to_byte_str()),
+          },
+          _ => self.elab.env.get_atom(self.elab.ast.span_atom(e.span, a)),
+        };
+        self.code.push(Ir::PatternQuoteAtom(x))
       } else {
         let x = self.parse_atom(e.span, a)?;
         self.code.push({
@@ -954,7 +1298,7 @@ impl<'a> LispParser<'a> {
     if let Some(e2) = es.get(0) {
       if let SExprKind::List(v) = &e2.k {
         if let [SExpr {span, k: SExprKind::Atom(a)}, ref x] = **v {
-          if self.ast.span_atom(span, a) == b"=>" {
+          if self.span_atom(span, a) == b"=>" {
             cont = self.parse_ident(x)?;
             es = &es[1..];
           }
@@ -964,8 +1308,9 @@ impl<'a> LispParser<'a> {
     let mut ctx = LocalCtx::new();
     self.pattern(&mut ctx, false, e)?;
     let vars = ctx.ctx.len();
-    let start = self.ctx.push_list(&ctx.ctx);
-    let cont = (cont != AtomId::UNDER).then(|| self.ctx.push(cont));
+    let start = self.ctx.len();
+    for &x in &ctx.ctx { self.push_local(x); }
+    let cont = (cont != AtomId::UNDER).then(|| self.push_local(cont));
     self.exprs(ExprsCtx::Eval(keep, tail && cont.is_none()), es)?;
     if cont.is_some() { self.code.push(Ir::drop(keep, 1)) }
     self.restore(start);
@@ -985,7 +1330,10 @@ impl<'a> LispParser<'a> {
   fn eval_atom(&mut self, keep: bool, sp: Span, x: AtomId) {
     match self.ctx.get(x) {
       None => {
-        self.spans.insert(sp, ObjectKind::Global(x));
+        let x = if let Some(&x) = self.renamed.get(&x) { x } else {
+          self.spans.insert(sp, ObjectKind::Global(x));
+          x
+        };
         if keep {
           // Preload the value, if it exists; else look it up at run time
           let data = &self.data[x];
@@ -1017,7 +1365,7 @@ impl<'a> LispParser<'a> {
       &SExprKind::Atom(a) => if ctx.quote {
         push_const!(span!(e.span,
           match self.parse_ident_or_syntax(e.span, a) {
-            Ok(x) => LispVal::atom(x),
+            Ok(x) => LispVal::atom(self.unrename(x)),
             Err(s) => LispVal::syntax(s),
           }
         ))
@@ -1033,7 +1381,7 @@ impl<'a> LispParser<'a> {
         }
         for e in es {
           if let SExprKind::Atom(a) = es[0].k {
-            if Syntax::parse(self.ast.span(e.span), a) == Ok(Syntax::Unquote) {
+            if self.is_syntax(e.span, a, Syntax::Unquote) {
               return Err(ElabError::new_e(e.span, "cannot evaluate an improper list"))
             }
           }
@@ -1059,7 +1407,7 @@ impl<'a> LispParser<'a> {
         loop {
           if let Some(arg) = it.next() {
             if let SExprKind::Atom(a) = arg.k {
-              if Syntax::parse(self.ast.span(arg.span), a) == Ok(Syntax::Unquote) {
+              if self.is_syntax(arg.span, a, Syntax::Unquote) {
                 let r = it.next().ok_or_else(||
                   ElabError::new_e(arg.span, "expected at least one argument"))?;
                 self.expr(ExprCtx::eval(ctx.keep), r)?;
@@ -1077,7 +1425,19 @@ impl<'a> LispParser<'a> {
       } else if let SExprKind::Atom(a) = es[0].k {
         match self.parse_ident_or_syntax(es[0].span, a) {
           Ok(AtomId::UNDER) => return Err(ElabError::new_e(es[0].span, "'_' is not a function")),
-          Ok(x) => {
+          Ok(x) => if let Some(f) = self.get_macro(x) {
+            if !matches!(a, Atom::Gen(_)) {
+              self.spans.insert(es[0].span, ObjectKind::Global(x));
+            }
+            if self.depth >= MAX_MACRO_DEPTH {
+              return Err(ElabError::new_e(e.span, "maximum macro expansion depth exceeded"))
+            }
+            let e2 = self.expand_macro(e, &f, &es[1..])?;
+            self.depth += 1;
+            let res = self.expr_doc(doc, ctx, &e2);
+            self.depth -= 1;
+            return res
+          } else {
             self.eval_atom(true, es[0].span, x);
             let p = self.pop_builtin();
             let n = self.exprs(ExprsCtx::App, &es[1..])?;
@@ -1089,13 +1449,16 @@ impl<'a> LispParser<'a> {
             if !ctx.keep { self.code.push(Ir::Drop(1)) }
           }
           Err(stx) => {
-            self.spans.insert_if(es[0].span, || ObjectKind::Syntax(stx));
+            if !matches!(a, Atom::Gen(_)) {
+              self.spans.insert_if(es[0].span, || ObjectKind::Syntax(stx));
+            }
             match stx {
               Syntax::Begin => { self.exprs(ExprsCtx::Eval(ctx.keep, ctx.tail), &es[1..])?; }
               Syntax::Define if es.len() < 2 => return Err(
                 ElabError::new_e(es[0].span, "expected at least one argument")),
               Syntax::Define => {
-                let (sp, x) = self.def(ctx.tail && !ctx.keep, &es[1], &es[2..])?;
+                let (sp, mut x) = self.def(ctx.tail && !ctx.keep, &es[1], &es[2..])?;
+                if ctx.global { x = self.unrename(x) }
                 if x != AtomId::UNDER && !ctx.mask_def {
                   if ctx.global { self.spans.insert(sp, ObjectKind::Global(x)); }
                   let doc = if doc.is_empty() {None} else {Some(doc.into())};
@@ -1105,16 +1468,33 @@ impl<'a> LispParser<'a> {
                 if ctx.mask_def { self.restore(restore) }
                 return Ok(false)
               }
+              Syntax::DefMacro if es.len() < 2 => return Err(
+                ElabError::new_e(es[0].span, "expected at least one argument")),
+              Syntax::DefMacro if !ctx.global => return Err(
+                ElabError::new_e(es[0].span, "defmacro: macros must be defined at the top level")),
+              Syntax::DefMacro => {
+                let (sp, x) = self.def(false, &es[1], &es[2..])?;
+                let x = self.unrename(x);
+                if x != AtomId::UNDER {
+                  let sp2 = Box::new((e.span, es[0].span));
+                  self.code.push(Ir::BuiltinApp(false, BuiltinProc::Macro, sp2, 1));
+                  self.spans.insert(sp, ObjectKind::Global(x));
+                  let doc = if doc.is_empty() {None} else {Some(doc.into())};
+                  self.push_def(true, e.span, sp, doc, x);
+                }
+                if ctx.keep { self.code.push(Ir::Undef) }
+                return Ok(false)
+              }
               Syntax::Lambda if es.len() < 2 => return Err(
                 ElabError::new_e(es[0].span, "expected at least one argument")),
               Syntax::Lambda => if ctx.keep {
-                let orig = std::mem::take(&mut self.code);
+                let orig = self.take_code();
                 match &es[1].k {
                   SExprKind::List(xs) => {
                     let xs = self.parse_idents(xs)?;
                     let n = self.ctx.push_list(&xs);
                     self.exprs(ExprsCtx::Eval(true, true), &es[2..])?;
-                    let code = std::mem::replace(&mut self.code, orig).into();
+                    let code = self.replace_code(orig);
                     self.push_lambda(es[0].span, n, ProcSpec::Exact(xs.len()), code)
                   }
                   SExprKind::DottedList(xs, y) => {
@@ -1123,14 +1503,14 @@ impl<'a> LispParser<'a> {
                     let n = self.ctx.push_list(&xs);
                     self.ctx.push(y);
                     self.exprs(ExprsCtx::Eval(true, true), &es[2..])?;
-                    let code = std::mem::replace(&mut self.code, orig).into();
+                    let code = self.replace_code(orig);
                     self.push_lambda(es[0].span, n, ProcSpec::AtLeast(xs.len()), code)
                   }
                   _ => {
                     let x = self.parse_ident(&es[1])?;
                     let n = self.ctx.push(x);
                     self.exprs(ExprsCtx::Eval(true, true), &es[2..])?;
-                    let code = std::mem::replace(&mut self.code, orig).into();
+                    let code = self.replace_code(orig);
                     self.push_lambda(es[0].span, n, ProcSpec::AtLeast(0), code)
                   }
                 }
@@ -1144,12 +1524,12 @@ impl<'a> LispParser<'a> {
               Syntax::If if 3 <= es.len() && es.len() <= 4 => {
                 self.expr(ExprCtx::EVAL.mask_def(), &es[1])?;
                 let patch1 = self.new_patch();
-                self.expr(ExprCtx::eval(ctx.keep).mask_def(), &es[2])?;
+                self.expr(ExprCtx::eval(ctx.keep).tail(ctx.tail).mask_def(), &es[2])?;
                 let patch2 = self.new_patch();
                 self.finish_patch(patch1, Ir::JumpUnless);
                 match es.get(3) {
                   None => if ctx.keep { self.code.push(Ir::Undef) },
-                  Some(e) => { self.expr(ExprCtx::eval(ctx.keep).mask_def(), e)?; }
+                  Some(e) => { self.expr(ExprCtx::eval(ctx.keep).tail(ctx.tail).mask_def(), e)?; }
                 }
                 self.finish_patch(patch2, Ir::Jump);
               }
@@ -1163,6 +1543,7 @@ impl<'a> LispParser<'a> {
               Syntax::Letrec => self.let_(true, ctx.keep, ctx.tail, &es[1..])?,
               Syntax::SetMergeStrategy if 2 <= es.len() && es.len() <= 3 => {
                 let a = self.parse_ident(&es[1])?;
+                let a = self.unrename(a);
                 if let Some(e) = es.get(2) { self.expr(ExprCtx::EVAL, e)?; }
                 else { self.code.push(Ir::Undef) }
                 self.code.push(Ir::SetMergeStrategy(es[0].span, a));
@@ -1170,6 +1551,16 @@ impl<'a> LispParser<'a> {
               }
               Syntax::SetMergeStrategy => return Err(
                 ElabError::new_e(es[0].span, "expected one or two arguments")),
+              Syntax::Try if es.len() == 3 => {
+                self.expr(ExprCtx::EVAL.mask_def(), &es[2])?;
+                let patch = self.new_patch();
+                self.expr(ExprCtx::EVAL.mask_def(), &es[1])?;
+                self.code.push(Ir::TryEnd);
+                self.finish_patch(patch, |ip| Ir::TryStart(es[0].span, ip));
+                if !ctx.keep { self.code.push(Ir::Drop(1)) }
+              }
+              Syntax::Try => return Err(
+                ElabError::new_e(es[0].span, "expected two arguments")),
               Syntax::Match if es.len() < 2 => return Err(
                 ElabError::new_e(es[0].span, "expected at least one argument")),
               Syntax::Match => {
@@ -1183,10 +1574,10 @@ impl<'a> LispParser<'a> {
                   _ => unreachable!()
                 };
                 let i = self.ctx.push(AtomId::UNDER);
-                let orig = std::mem::take(&mut self.code);
+                let orig = self.take_code();
                 self.code.push(Ir::Local(i));
                 self.match_(true, true, es[0].span, &es[1..])?;
-                let code = std::mem::replace(&mut self.code, orig).into();
+                let code = self.replace_code(orig);
                 self.push_lambda(es[0].span, i, spec, code);
               }
             }
@@ -1210,33 +1601,33 @@ impl<'a> LispParser<'a> {
 
 impl Elaborator {
   /// Parse a lisp `SExpr` from the surface syntax into an `IR` object suitable for evaluation.
-  pub fn parse_lisp(&mut self, global: bool, e: &SExpr) -> Result<Vec<Ir>, ElabError> {
+  pub fn parse_lisp(&mut self, global: bool, e: &SExpr) -> Result<Code, ElabError> {
     self.parse_lisp_doc(global, e, String::new())
   }
 
   /// Parse a lisp `SExpr` from the surface syntax into an `IR` object suitable for evaluation.
   /// The `doc` argument is an additional doc string, if applicable.
-  pub fn parse_lisp_doc(&mut self, global: bool, e: &SExpr, doc: String) -> Result<Vec<Ir>, ElabError> {
+  pub fn parse_lisp_doc(&mut self, global: bool, e: &SExpr, doc: String) -> Result<Code, ElabError> {
     let mut p = LispParser::new(self);
     p.expr_doc(doc, ExprCtx::EVAL.global(global), e)?;
-    Ok(p.code)
+    Ok(p.into_code())
   }
 
   /// Parse and evaluate a lisp expression being used as a proof. Essentially the same
   /// as evaluating `(refine e)` where `e` is the input expression.
-  pub fn parse_refine_lisp(&mut self, e: &SExpr) -> Result<Vec<Ir>, ElabError> {
+  pub fn parse_refine_lisp(&mut self, e: &SExpr) -> Result<Code, ElabError> {
     let mut p = LispParser::new(self);
     p.expr(ExprCtx::EVAL.mask_def(), e)?;
     p.code.push(Ir::RefineGoal(true));
-    Ok(p.code)
+    Ok(p.into_code())
   }
 
   /// Parse a `QExpr`, the result of parsing a math formula,
   /// into an `IR` object suitable for evaluation. (Usually this will be a `IR::Const`,
   /// but `QExpr`'s can contain antiquotations which require evaluation.)
-  pub fn parse_qexpr(&mut self, e: QExpr) -> Result<Vec<Ir>, ElabError> {
+  pub fn parse_qexpr(&mut self, e: QExpr) -> Result<Code, ElabError> {
     let mut p = LispParser::new(self);
     p.qexpr(true, e)?;
-    Ok(p.code)
+    Ok(p.into_code())
   }
 }
@@ -80,6 +80,7 @@ impl LispKind {
       LispKind::List(es) => es.is_empty(),
       LispKind::DottedList(..) |
       LispKind::AtomMap(..) |
+      LispKind::HashMap(..) |
       LispKind::Goal(..) => false,
       LispKind::Atom(..) |
       LispKind::MVar(..) |
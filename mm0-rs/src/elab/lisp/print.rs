@@ -174,6 +174,7 @@ impl EnvDisplay for LispKind {
       LispKind::Proc(Proc::RefineCallback) => write!(f, "#[refine]"),
       LispKind::Proc(Proc::ProofThunk(x, _)) => write!(f, "#[proof of {}]", fe.to(x)),
       LispKind::Proc(Proc::MergeMap(_)) => write!(f, "#[merge-map]"),
+      LispKind::Proc(Proc::Macro(m)) => write!(f, "#[macro {}]", fe.to(m)),
       #[cfg(feature = "mmc")]
       LispKind::Proc(Proc::MmcCompiler(_)) => write!(f, "#[mmc-compiler]"),
       LispKind::AtomMap(m) => {
@@ -181,6 +182,11 @@ impl EnvDisplay for LispKind {
         for (a, v) in m {write!(f, " [{} {}]", fe.data[*a].name, fe.to(v))?}
         write!(f, ")")
       }
+      LispKind::HashMap(m) => {
+        write!(f, "(hash-map!")?;
+        for (k, v) in m {write!(f, " [{} {}]", fe.to(k.val()), fe.to(v))?}
+        write!(f, ")")
+      }
       LispKind::Ref(m) if m.too_many_readers() => write!(f, "#[ref]"),
       LispKind::Ref(m) => m.get(|e| e.fmt(fe, f)),
       &LispKind::MVar(n, _) => write!(f, "?{}", alphanumber(n)),
@@ -727,6 +727,7 @@ impl NodeHash for ProofHash {
     Ok(Ok(match &**r {
       &LispKind::Atom(a) => match kind {
         ProofKind::Expr | ProofKind::Conv => match nh.var_map.get(&a) {
+          Some(&i) if kind == ProofKind::Conv => ProofHash::Refl(de.reuse(i)),
           Some(&i) => ProofHash::Ref(kind, i),
           None => match nh.lc.vars.get(&a) {
             Some(&(true, InferSort::Bound(sort))) => {
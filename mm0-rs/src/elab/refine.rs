@@ -54,6 +54,17 @@ enum RefineExpr {
     /// The main expression
     e: LispVal
   },
+  /// A conversion `(:conv tgt u p)`, as in an elaborated proof. `tgt` and `u` are
+  /// already elaborated, and `u` proves that `tgt` is convertible to the type of `p`,
+  /// but `p` is a refine script.
+  Conv {
+    /// The (elaborated) type of the result
+    tgt: LispVal,
+    /// The (elaborated) conversion proof
+    u: LispVal,
+    /// The main expression
+    e: LispVal
+  },
   /// An elaborated or "verbatim" term. This term is assumed to be a full proof already
   /// and is not interpreted as a refine script.
   Exact(LispVal),
@@ -261,6 +272,9 @@ pub(crate) enum RState {
   ///   let ty' = RState::RefineExpr(unknown, ty)
   ///   return RStack::TypedAt(tgt, p)(ty')
   /// RState::RefineProof(tgt, '(exact p)) := return coerce(tgt, type_of(p), p)
+  /// RState::RefineProof(tgt, '(:conv ty u p)) :=
+  ///   let elab_p = RState::RefineProof(rhs(u), p)
+  ///   return coerce(tgt, ty, (:conv ty u elab_p))
   /// RState::RefineProof(tgt, func) := return RState::Proc(tgt, func)
   /// ...
   /// ```
@@ -456,7 +470,7 @@ impl LispVal {
   fn sym(p: Self) -> Self {
     Self::list(vec![Self::atom(AtomId::SYM), p])
   }
-  fn apply_conv(c: Self, tgt: Self, p: Self) -> Self {
+  pub(crate) fn apply_conv(c: Self, tgt: Self, p: Self) -> Self {
     if c.is_def() {Self::conv(tgt, c, p)} else {p}
   }
 
@@ -542,6 +556,14 @@ impl Elaborator {
                   Err(ElabError::new_e(sp2.unwrap_or(fsp.span), "':' expected two arguments"))
                 }
               }
+              AtomId::CONV => {
+                return if let (Some(tgt), Some(u), Some(e), true) =
+                  (u.next(), u.next(), u.next(), u.is_empty()) {
+                  Ok(RefineExpr::Conv {tgt, u, e})
+                } else {
+                  Err(ElabError::new_e(sp, ":conv: expected three arguments"))
+                }
+              }
               _ => (InferMode::Regular, e)
             };
             let sp2 = try_get_span(fsp, &t);
@@ -686,14 +708,14 @@ impl Elaborator {
 
   /// Unify expressions `e1` and `e2`. Returns a conversion proof
   /// `u: e1 = e2`, with `#undef` meaning that `e1` and `e2` are equal after unification.
-  fn unify1(&mut self, e1: &LispVal, e2: &LispVal) -> SResult<LispVal> {
+  pub(crate) fn unify1(&mut self, e1: &LispVal, e2: &LispVal) -> SResult<LispVal> {
     self.unify_core(e1, e2).map_err(|e| self.format_env().pretty(|p|
       format!("{}\n{}", p.unify_err(e1, e2).pretty(80), e)))
   }
 
   /// Unify expressions `e1` and `e2`. Returns a conversion proof
   /// `u: e1 = e2`, with `#undef` meaning that `e1` and `e2` are equal after unification.
-  fn unify_core(&mut self, e1: &LispVal, e2: &LispVal) -> SResult<LispVal> {
+  pub(crate) fn unify_core(&mut self, e1: &LispVal, e2: &LispVal) -> SResult<LispVal> {
     // println!("{} =?= {}", self.format_env().pp(e1, 80), self.format_env().pp(e2, 80));
     // (|| {
     if e1.ptr_eq(e2) {return Ok(LispVal::undef())}
@@ -793,7 +815,7 @@ impl Elaborator {
     }
   }
 
-  fn type_target(&self, ty: &Type) -> InferTarget {
+  pub(crate) fn type_target(&self, ty: &Type) -> InferTarget {
     match *ty {
       Type::Bound(s) => InferTarget::Bound(self.sorts[s].atom),
       Type::Reg(s, _) => InferTarget::Reg(self.sorts[s].atom),
@@ -868,6 +890,12 @@ impl Elaborator {
             stack.push(RStack::TypedAt {sp: try_get_span(&fsp, &p), tgt, p: q});
             RState::RefineExpr {tgt: InferTarget::Unknown, e: ty}
           }
+          RefineExpr::Conv {tgt: ty, u, e: q} => {
+            let src = self.conv_side(&u, true).map_err(|e| ElabError::new_e(sp, e))?;
+            stack.push(RStack::Coerce {u: self.unify(sp, &tgt, &ty)?, tgt});
+            stack.push(RStack::Coerce {tgt: ty, u});
+            RState::RefineProof {tgt: src, p: q}
+          }
           RefineExpr::Exact(p) => {
             let e = self.infer_type(sp, &p)?;
             RState::Ret(self.coerce_to(sp, tgt, &e, p)?)
@@ -909,6 +937,8 @@ impl Elaborator {
               tgt: if tgt.bound() {InferTarget::Bound(s)} else {InferTarget::Reg(s)}
             }
           }
+          Ok(RefineExpr::Conv {..}) => return Err(ElabError::new_e(try_get_span(&fsp, &e),
+            ":conv: expected a term, not a proof")),
           Ok(RefineExpr::Exact(e)) => RState::Ret(e),
           Ok(RefineExpr::Proc) => RState::Ret(e),
           Err(err) => (|| -> Result<_> {
@@ -2,6 +2,7 @@
 
 use std::mem::MaybeUninit;
 use std::collections::BTreeMap;
+use std::time::Duration;
 use crate::AtomId;
 use super::local_context::LocalContext;
 use crate::Span;
@@ -28,6 +29,9 @@ pub struct Spans<T> {
   /// The local context as of the end of the proof. This is used to resolve variables
   /// and subproof names.
   pub lc: Option<LocalContext>,
+  /// The wall-clock time spent elaborating this statement. This does not include
+  /// time spent waiting on imports.
+  pub elab_time: Duration,
   /// The actual data associated to spans. They are indexed by span start, and one
   /// start point can contain many spans, even multiple data elements at the same span.
   data: BTreeMap<usize, Vec<(Span, T)>>,
@@ -59,6 +63,7 @@ impl<T> Spans<T> {
       stmt: MaybeUninit::uninit(),
       decl: MaybeUninit::uninit(),
       lc: None,
+      elab_time: Duration::default(),
       data: BTreeMap::new()
     }
   }
@@ -0,0 +1,843 @@
+//! Native tactics, which are exposed as lisp builtins but implemented directly
+//! on the elaborator state for speed.
+//!
+//! This contains the rewriting engine behind `(rw)` and `(simp)`, the proof search
+//! behind `(auto)`, and the conversion tactics `(unfold)`, `(fold)` and `(conv-at)`,
+//! which build definitional unfolding proofs (`:conv`, `:unfold` and `:sym` terms).
+//!
+//! Rewriting is driven by three global atom maps, which are created on demand and merge
+//! across imports:
+//!
+//! * `eq-schemes` maps a sort `s` to a list `(eq refl trans mp)`, registered by
+//!   `(register-eq)`. Here `eq` is a binary term constructor on `s`, and `refl`, `trans`
+//!   and `mp` are theorems of the form `eq a a`, `(h1: eq a b) (h2: eq b c): eq a c` and
+//!   `(h1: eq a b) (h2: b): a`. (`mp` is only needed for provable sorts, to rewrite goals.)
+//! * `congr-lemmas` maps a term `t` to a congruence theorem of the form
+//!   `(h1: eq a1 b1) ... (hn: eq an bn): eq (t a1 ... an) (t b1 ... bn)`,
+//!   registered by `(register-congr)`. Arguments that do not have a hypothesis, such
+//!   as bound variables, are not rewritten.
+//! * `simp-lemmas` is the set of default lemmas for `(simp)`, which maps each lemma to `#t`.
+//!   Theorems are added by `(register-simp)`, usually from the `annotate` function
+//!   of an annotation, but any lisp code can insert into it.
+//!
+//! The rewrite lemmas themselves are theorems with no hypotheses of the form `eq lhs rhs`,
+//! where `lhs` is not a variable and every variable in `rhs` appears in `lhs`.
+//! The resulting proof terms use only the hypotheses of the registered theorems, so they
+//! can be passed directly to `refine`, which reconstructs the intermediate expressions
+//! by unification.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+use crate::{ArcString, AtomId, Expr, ExprNode, FileSpan, MergeStrategyInner, SortId, StmtTrace,
+  TermId, TermKind, ThmId};
+use super::Elaborator;
+use super::environment::LispData;
+use super::local_context::InferSort;
+use super::lisp::{InferTarget, LispKind, LispVal, Uncons, eval::SResult};
+use super::proof::Subst;
+
+/// The maximum number of rewrites performed by a single `rw` or `simp` call,
+/// to catch looping simp sets.
+const MAX_REWRITES: usize = 1 << 16;
+
+/// The maximum recursion depth of the rewriter. Because `simp` re-simplifies the result
+/// of each rewrite, a looping simp set will usually hit this before [`MAX_REWRITES`].
+const MAX_DEPTH: usize = 1024;
+
+/// A pattern, derived from the statement of a theorem, for matching against expressions.
+#[derive(Debug)]
+enum Pat {
+  /// The `n`th variable of the theorem
+  Var(usize),
+  /// An application of a term constructor
+  App(TermId, Box<[Pat]>),
+}
+
+impl Pat {
+  fn new(heap: &[ExprNode], nargs: usize, e: &ExprNode) -> Option<Pat> {
+    match *e {
+      ExprNode::Ref(i) if i < nargs => Some(Pat::Var(i)),
+      ExprNode::Ref(i) => Pat::new(heap, nargs, &heap[i]),
+      ExprNode::Dummy(..) => None,
+      ExprNode::App(t, ref es) => Some(Pat::App(t,
+        es.iter().map(|e| Pat::new(heap, nargs, e)).collect::<Option<_>>()?)),
+    }
+  }
+
+  fn vars(&self, vars: &mut [bool]) {
+    match self {
+      &Pat::Var(i) => vars[i] = true,
+      Pat::App(_, ps) => for p in &**ps { p.vars(vars) }
+    }
+  }
+
+  fn as_var(&self) -> Option<usize> {
+    if let Pat::Var(i) = *self { Some(i) } else { None }
+  }
+}
+
+/// The equality scheme for one sort, registered by `(register-eq)`.
+#[derive(Debug)]
+struct EqScheme {
+  eq: TermId,
+  refl: AtomId,
+  trans: AtomId,
+  mp: Option<AtomId>,
+}
+
+/// A congruence theorem for one term constructor.
+#[derive(Debug)]
+struct Congr {
+  thm: AtomId,
+  /// For each hypothesis, the argument it rewrites and the reflexivity theorem for
+  /// that argument's sort, used when the argument is unchanged.
+  hyps: Rc<[(usize, AtomId)]>,
+}
+
+/// A rewrite lemma `eq lhs rhs`.
+#[derive(Debug)]
+struct Lemma {
+  name: AtomId,
+  nargs: usize,
+  lhs: Pat,
+  rhs: Pat,
+}
+
+/// The result of [`Elaborator::rewrite`].
+#[derive(Debug)]
+pub struct Rewrite {
+  /// The rewritten expression.
+  pub expr: LispVal,
+  /// A proof of `eq e expr`, or `None` if nothing was rewritten.
+  pub proof: Option<LispVal>,
+  /// The lemmas that fired, with the number of times each was used, in order of first use.
+  pub used: Vec<(AtomId, usize)>,
+}
+
+fn eq_sorts(schemes: &HashMap<SortId, EqScheme>) -> HashMap<TermId, SortId> {
+  schemes.iter().map(|(&s, sc)| (sc.eq, s)).collect()
+}
+
+struct Rewriter<'a> {
+  elab: &'a Elaborator,
+  schemes: HashMap<SortId, EqScheme>,
+  congrs: HashMap<TermId, Congr>,
+  lemmas: HashMap<TermId, Vec<Lemma>>,
+  repeat: bool,
+  timeout: Option<Instant>,
+  steps: usize,
+  depth: usize,
+  used: Vec<(AtomId, usize)>,
+}
+
+impl Elaborator {
+  /// Destructure `e` as a term application.
+  fn dest_app(&self, e: &LispVal) -> Option<(TermId, Vec<LispVal>)> {
+    e.unwrapped(|r| match r {
+      LispKind::Atom(a) if !self.lc.vars.contains_key(a) =>
+        self.term(*a).map(|t| (t, vec![])),
+      LispKind::List(es) => {
+        let t = self.term(es.first()?.as_atom()?)?;
+        Some((t, es[1..].to_vec()))
+      }
+      _ => None
+    })
+  }
+
+  /// Get the map stored in one of the rewriting globals, or an empty map if it is not defined.
+  fn rewrite_global(&self, a: AtomId) -> SResult<HashMap<AtomId, LispVal>> {
+    match &self.data[a].lisp {
+      None => Ok(HashMap::new()),
+      Some(data) => data.val.unwrapped(|e| match e {
+        LispKind::AtomMap(m) => Ok(m.clone()),
+        _ => Err(format!("'{}' is not an atom map", self.data[a].name)),
+      })
+    }
+  }
+
+  /// Insert `k := v` into one of the rewriting globals, creating it as a mutable atom map
+  /// with the `merge-map` strategy if it does not exist yet.
+  pub(crate) fn rewrite_register(&mut self, a: AtomId, k: AtomId, v: LispVal) -> SResult<()> {
+    if self.data[a].lisp.is_none() {
+      self.env.stmts.push(StmtTrace::Global(a));
+      self.data[a].lisp = Some(LispData {
+        src: None, doc: None,
+        val: LispVal::new_ref(LispVal::new(LispKind::AtomMap(HashMap::new()))),
+        merge: Some(Rc::new(MergeStrategyInner::AtomMap(None))),
+      });
+    }
+    let name = &self.data[a].name;
+    let val = &self.data[a].lisp.as_ref().expect("just created").val;
+    val.as_ref_(|e| {
+      if let Some(LispKind::AtomMap(m)) = e.get_mut() { m.insert(k, v); return Ok(()) }
+      let mut m = e.unwrapped(|e| if let LispKind::AtomMap(m) = e { Some(m.clone()) } else { None })
+        .ok_or_else(|| format!("'{}' is not an atom map", name))?;
+      m.insert(k, v);
+      *e = LispVal::new(LispKind::AtomMap(m));
+      Ok(())
+    }).unwrap_or_else(|| Err(format!("'{}' is not a mutable atom map", name)))
+  }
+
+  /// Get the equality schemes registered by `(register-eq)`.
+  fn eq_schemes(&self) -> SResult<HashMap<SortId, EqScheme>> {
+    let mut schemes = HashMap::new();
+    for (s, v) in self.rewrite_global(AtomId::EQ_SCHEMES)? {
+      let err = || format!("eq-schemes: invalid entry for sort '{}'", self.data[s].name);
+      let sort = self.data[s].sort.ok_or_else(err)?;
+      let es = v.unwrapped(|e| if let LispKind::List(es) = e {
+        es.iter().map(|e| e.as_atom()).collect::<Option<Vec<_>>>()
+      } else { None }).ok_or_else(err)?;
+      match *es {
+        [eq, refl, trans, ref mp @ ..] if mp.len() <= 1 => {
+          let eq = self.term(eq).ok_or_else(err)?;
+          schemes.insert(sort, EqScheme {eq, refl, trans, mp: mp.first().copied()});
+        }
+        _ => return Err(err()),
+      }
+    }
+    Ok(schemes)
+  }
+
+  fn get_thm(&self, a: AtomId) -> SResult<ThmId> {
+    self.thm(a).ok_or_else(|| format!("unknown theorem '{}'", self.data[a].name))
+  }
+
+  /// Check that `thm` is a congruence theorem for term `t`, and determine which
+  /// hypothesis rewrites which argument.
+  fn congr_info(&self, schemes: &HashMap<SortId, EqScheme>,
+    eqs: &HashMap<TermId, SortId>, term: TermId, thm: AtomId,
+  ) -> SResult<Congr> {
+    let err = || format!("'{}' is not a congruence theorem for '{}'",
+      self.data[thm].name, self.data[self.terms[term].atom].name);
+    let td = &self.thms[self.get_thm(thm)?];
+    let nargs = td.args.len();
+    let (lhs, rhs) = match Pat::new(&td.heap, nargs, &td.ret).ok_or_else(err)? {
+      Pat::App(eq, es) if eqs.contains_key(&eq) && es.len() == 2 => match &*es {
+        [Pat::App(t1, es1), Pat::App(t2, es2)] if *t1 == term && *t2 == term =>
+          (es1.iter().map(Pat::as_var).collect::<Option<Vec<_>>>().ok_or_else(err)?,
+           es2.iter().map(Pat::as_var).collect::<Option<Vec<_>>>().ok_or_else(err)?),
+        _ => return Err(err()),
+      },
+      _ => return Err(err()),
+    };
+    let mut covered = vec![false; lhs.len()];
+    let mut hyps = vec![];
+    for (_, h) in &*td.hyps {
+      let (x, y, sort) = match Pat::new(&td.heap, nargs, h).ok_or_else(err)? {
+        Pat::App(eq, es) => match (eqs.get(&eq), &*es) {
+          (Some(&sort), [Pat::Var(x), Pat::Var(y)]) => (*x, *y, sort),
+          _ => return Err(err()),
+        },
+        Pat::Var(_) => return Err(err()),
+      };
+      let i = (0..lhs.len()).find(|&i| lhs[i] == x && rhs[i] == y).ok_or_else(err)?;
+      let refl = schemes[&sort].refl;
+      covered[i] = true;
+      hyps.push((i, refl));
+    }
+    if (0..lhs.len()).any(|i| !covered[i] && lhs[i] != rhs[i]) { return Err(err()) }
+    Ok(Congr {thm, hyps: hyps.into()})
+  }
+
+  /// Check that `name` is a valid rewrite lemma of the form `eq lhs rhs`.
+  fn lemma_info(&self, eqs: &HashMap<TermId, SortId>, name: AtomId) -> SResult<Lemma> {
+    let err = || format!("'{}' is not a rewrite lemma of the form a = b", self.data[name].name);
+    let td = &self.thms[self.get_thm(name)?];
+    let nargs = td.args.len();
+    if !td.hyps.is_empty() { return Err(err()) }
+    let (lhs, rhs) = match Pat::new(&td.heap, nargs, &td.ret).ok_or_else(err)? {
+      Pat::App(eq, es) if eqs.contains_key(&eq) && es.len() == 2 => {
+        let mut it = Vec::from(es).into_iter();
+        (it.next().expect("len 2"), it.next().expect("len 2"))
+      }
+      _ => return Err(err()),
+    };
+    if let Pat::Var(_) = lhs { return Err(err()) }
+    let (mut l, mut r) = (vec![false; nargs], vec![false; nargs]);
+    lhs.vars(&mut l);
+    rhs.vars(&mut r);
+    if l.iter().zip(&r).any(|(&l, &r)| r && !l) { return Err(err()) }
+    Ok(Lemma {name, nargs, lhs, rhs})
+  }
+
+  /// Register an equality scheme for sort `s`, with equality term `eq` and
+  /// theorems `refl`, `trans` and (optionally) `mp`.
+  pub(crate) fn register_eq(&mut self,
+    s: AtomId, eq: AtomId, refl: AtomId, trans: AtomId, mp: Option<AtomId>
+  ) -> SResult<()> {
+    let sort = self.data[s].sort.ok_or_else(|| format!("unknown sort '{}'", self.data[s].name))?;
+    let t = self.term(eq).ok_or_else(|| format!("unknown term '{}'", self.data[eq].name))?;
+    let td = &self.terms[t];
+    if !(td.args.len() == 2 && td.args.iter().all(|(_, ty)| ty.sort() == sort)) {
+      return Err(format!("'{}' is not a binary relation on '{}'", self.data[eq].name, self.data[s].name))
+    }
+    for &a in [refl, trans].iter().chain(&mp) { self.get_thm(a)?; }
+    let mut v = vec![LispVal::atom(eq), LispVal::atom(refl), LispVal::atom(trans)];
+    v.extend(mp.map(LispVal::atom));
+    self.rewrite_register(AtomId::EQ_SCHEMES, s, LispVal::list(v))
+  }
+
+  /// Register `thm` as the congruence theorem for term `t`.
+  pub(crate) fn register_congr(&mut self, t: AtomId, thm: AtomId) -> SResult<()> {
+    let tid = self.term(t).ok_or_else(|| format!("unknown term '{}'", self.data[t].name))?;
+    let schemes = self.eq_schemes()?;
+    self.congr_info(&schemes, &eq_sorts(&schemes), tid, thm)?;
+    self.rewrite_register(AtomId::CONGR_LEMMAS, t, LispVal::atom(thm))
+  }
+
+  /// Mark theorem `thm` as a simp lemma, by adding it to `simp-lemmas`.
+  pub(crate) fn register_simp(&mut self, thm: AtomId) -> SResult<()> {
+    self.get_thm(thm)?;
+    self.rewrite_register(AtomId::SIMP_LEMMAS, thm, LispVal::bool(true))
+  }
+
+  /// The list of lemmas in `simp-lemmas`.
+  pub(crate) fn simp_lemmas(&self) -> SResult<Vec<AtomId>> {
+    let mut v = self.rewrite_global(AtomId::SIMP_LEMMAS)?.into_iter()
+      .filter(|(_, e)| e.truthy()).map(|(a, _)| a).collect::<Vec<_>>();
+    v.sort_by_key(|&a| self.thm(a));
+    Ok(v)
+  }
+
+  /// Rewrite expression `e` bottom-up using `lemmas`. If `repeat` is true, this rewrites
+  /// to a fixed point (`simp`), otherwise each subterm is rewritten at most once (`rw`).
+  pub(crate) fn rewrite(&self,
+    lemmas: &[AtomId], repeat: bool, timeout: Option<Instant>, e: &LispVal
+  ) -> SResult<Rewrite> {
+    let mut rw = Rewriter::new(self, lemmas, repeat, timeout)?;
+    Ok(match rw.rewrite(e)? {
+      None => Rewrite {expr: e.clone(), proof: None, used: rw.used},
+      Some((expr, p)) => Rewrite {expr, proof: Some(p), used: rw.used},
+    })
+  }
+
+  /// Rewrite the type of the first goal using `lemmas`, and return a proof term for it
+  /// which leaves the rewritten goal as a `_` placeholder (or closes it by reflexivity).
+  pub(crate) fn rewrite_goal(&self,
+    lemmas: &[AtomId], repeat: bool, timeout: Option<Instant>
+  ) -> SResult<(LispVal, Vec<(AtomId, usize)>)> {
+    let ty = self.lc.goals.iter().find_map(|g| g.goal_type()).ok_or("no goals")?;
+    let mut rw = Rewriter::new(self, lemmas, repeat, timeout)?;
+    let res = rw.rewrite(&ty)?;
+    let e = res.as_ref().map_or(&ty, |r| &r.0);
+    let rest = rw.refl_proof(e).unwrap_or_else(|| LispVal::atom(AtomId::UNDER));
+    let p = match res {
+      None if !rest.is_atom() => rest,
+      None => return Err("no lemmas apply to the goal".into()),
+      Some((_, p)) => {
+        let s = rw.sort_of(&ty).ok_or("goal is not a term application")?;
+        let mp = rw.schemes.get(&s).and_then(|sc| sc.mp).ok_or_else(|| format!(
+          "no 'mp' theorem registered for sort '{}'", self.sorts[s].name))?;
+        LispVal::list(vec![LispVal::atom(mp), p, rest])
+      }
+    };
+    Ok((p, rw.used))
+  }
+}
+
+impl<'a> Rewriter<'a> {
+  fn new(elab: &'a Elaborator, lemmas: &[AtomId],
+    repeat: bool, timeout: Option<Instant>,
+  ) -> SResult<Self> {
+    let schemes = elab.eq_schemes()?;
+    let eqs = eq_sorts(&schemes);
+    let mut congrs = HashMap::new();
+    for (t, v) in elab.rewrite_global(AtomId::CONGR_LEMMAS)? {
+      let (tid, thm) = match (elab.term(t), v.as_atom()) {
+        (Some(tid), Some(thm)) => (tid, thm),
+        _ => return Err(format!("congr-lemmas: invalid entry for '{}'", elab.data[t].name)),
+      };
+      congrs.insert(tid, elab.congr_info(&schemes, &eqs, tid, thm)?);
+    }
+    let mut lemmas_map = HashMap::<_, Vec<_>>::new();
+    for &a in lemmas {
+      let l = elab.lemma_info(&eqs, a)?;
+      if let Pat::App(t, _) = l.lhs { lemmas_map.entry(t).or_default().push(l) }
+    }
+    Ok(Rewriter {
+      elab, schemes, congrs, lemmas: lemmas_map,
+      repeat, timeout, steps: 0, depth: 0, used: vec![],
+    })
+  }
+
+  fn dest_app(&self, e: &LispVal) -> Option<(TermId, Vec<LispVal>)> { self.elab.dest_app(e) }
+
+  fn sort_of(&self, e: &LispVal) -> Option<SortId> {
+    let (t, _) = self.dest_app(e)?;
+    Some(self.elab.terms[t].ret.0)
+  }
+
+  /// If `e` is `eq a a` for some registered equality, return the reflexivity proof.
+  fn refl_proof(&self, e: &LispVal) -> Option<LispVal> {
+    let (t, args) = self.dest_app(e)?;
+    let sort = self.elab.terms[t].args.first()?.1.sort();
+    match (self.schemes.get(&sort), &*args) {
+      (Some(sc), [lhs, rhs]) if lhs == rhs && sc.eq == t =>
+        Some(LispVal::list(vec![LispVal::atom(sc.refl)])),
+      _ => None
+    }
+  }
+
+  fn matches(&self, p: &Pat, e: &LispVal, subst: &mut [Option<LispVal>]) -> bool {
+    match p {
+      &Pat::Var(i) => if let Some(v) = &subst[i] { v == e } else {
+        subst[i] = Some(e.clone());
+        true
+      },
+      Pat::App(t, ps) => match self.dest_app(e) {
+        Some((t2, es)) if *t == t2 => ps.iter().zip(&es).all(|(p, e)| self.matches(p, e, subst)),
+        _ => false
+      }
+    }
+  }
+
+  fn inst(&self, p: &Pat, subst: &[Option<LispVal>]) -> LispVal {
+    match p {
+      &Pat::Var(i) => subst[i].clone().expect("rhs variables occur in lhs"),
+      Pat::App(t, ps) => {
+        let mut args = vec![LispVal::atom(self.elab.terms[*t].atom)];
+        args.extend(ps.iter().map(|p| self.inst(p, subst)));
+        LispVal::list(args)
+      }
+    }
+  }
+
+  fn trans(&self, s: SortId, p1: LispVal, p2: LispVal) -> SResult<LispVal> {
+    let sc = self.schemes.get(&s).ok_or_else(||
+      format!("no equality registered for sort '{}'", self.elab.sorts[s].name))?;
+    Ok(LispVal::list(vec![LispVal::atom(sc.trans), p1, p2]))
+  }
+
+  fn step(&mut self) -> SResult<()> {
+    self.steps += 1;
+    if self.steps > MAX_REWRITES { return Err("too many rewrite steps (looping simp set?)".into()) }
+    if self.timeout.map_or(false, |t| t < Instant::now()) { return Err("timeout".into()) }
+    Ok(())
+  }
+
+  /// Rewrite the root of `e`, which is an application of `t`, using the first matching lemma.
+  fn rewrite_root(&mut self, t: TermId, e: &LispVal) -> Option<(LispVal, LispVal)> {
+    for l in self.lemmas.get(&t)? {
+      let mut subst = vec![None; l.nargs];
+      if self.matches(&l.lhs, e, &mut subst) {
+        let e2 = self.inst(&l.rhs, &subst);
+        match self.used.iter_mut().find(|(a, _)| *a == l.name) {
+          Some((_, n)) => *n += 1,
+          None => self.used.push((l.name, 1)),
+        }
+        return Some((e2, LispVal::list(vec![LispVal::atom(l.name)])))
+      }
+    }
+    None
+  }
+
+  /// Rewrite `e`, returning the new expression and a proof of `eq e e'`,
+  /// or `None` if no rewrites apply.
+  fn rewrite(&mut self, e: &LispVal) -> SResult<Option<(LispVal, LispVal)>> {
+    if self.depth >= MAX_DEPTH { return Err("rewrite depth exceeded (looping simp set?)".into()) }
+    self.depth += 1;
+    let res = self.rewrite_core(e);
+    self.depth -= 1;
+    res
+  }
+
+  fn rewrite_core(&mut self, e: &LispVal) -> SResult<Option<(LispVal, LispVal)>> {
+    self.step()?;
+    let (t, args) = if let Some(r) = self.dest_app(e) { r } else { return Ok(None) };
+    let s = self.elab.terms[t].ret.0;
+    let mut cur = None;
+    if let Some(c) = self.congrs.get(&t) {
+      let mut changed = false;
+      let mut new_args = args.clone();
+      let mut hyps = vec![LispVal::atom(c.thm)];
+      let chyps = c.hyps.clone();
+      for &(i, refl) in &*chyps {
+        match self.rewrite(&args[i])? {
+          Some((e2, p)) => { new_args[i] = e2; hyps.push(p); changed = true }
+          None => hyps.push(LispVal::list(vec![LispVal::atom(refl)])),
+        }
+      }
+      if changed {
+        let mut e1 = vec![LispVal::atom(self.elab.terms[t].atom)];
+        e1.extend(new_args);
+        cur = Some((LispVal::list(e1), LispVal::list(hyps)))
+      }
+    }
+    let e1 = cur.as_ref().map_or(e, |r| &r.0).clone();
+    if let Some((e2, p2)) = self.rewrite_root(t, &e1) {
+      let (e3, p3) = match if self.repeat { self.rewrite(&e2)? } else { None } {
+        Some((e3, p)) => (e3, self.trans(s, p2, p)?),
+        None => (e2, p2),
+      };
+      cur = Some(match cur {
+        None => (e3, p3),
+        Some((_, p1)) => (e3, self.trans(s, p1, p3)?),
+      })
+    }
+    Ok(cur)
+  }
+}
+
+/// A snapshot of the metavariable assignments, used to backtrack in `auto`.
+#[derive(Clone)]
+struct MVarState(Vec<LispVal>);
+
+impl Elaborator {
+  fn save_mvars(&self) -> MVarState {
+    MVarState(self.lc.mvars.iter().map(|m| m.as_ref_(|e| e.clone()).expect("mvar is a ref")).collect())
+  }
+
+  fn restore_mvars(&mut self, MVarState(saved): MVarState) {
+    self.lc.mvars.truncate(saved.len());
+    for (m, e) in self.lc.mvars.iter().zip(saved) { m.as_ref_(|r| *r = e); }
+  }
+
+  /// Search for a proof of the first goal, by backward chaining using the hypotheses in
+  /// the local context and the theorems `lemmas`, nesting theorem applications at most
+  /// `depth` deep. The search is iterative deepening, so shallower proofs are found first.
+  /// The result is a proof s-expression suitable for `refine`. All metavariable
+  /// assignments made during the search are undone before returning.
+  pub(crate) fn auto(&mut self, lemmas: &[AtomId], depth: usize) -> SResult<LispVal> {
+    let lemmas = lemmas.iter().map(|&a| Ok((a, self.get_thm(a)?))).collect::<SResult<Vec<_>>>()?;
+    let ty = self.lc.goals.iter().find_map(|g| g.goal_type()).ok_or("no goals")?;
+    let saved = self.save_mvars();
+    let base = self.lc.mvars.len();
+    for d in 1..=depth {
+      let res = self.auto_solve(&lemmas, base, &[(ty.clone(), d)]);
+      self.restore_mvars(saved.clone());
+      if let Some(mut ps) = res? { return Ok(ps.pop().expect("one goal")) }
+    }
+    Err(format!("no proof found up to depth {}", depth))
+  }
+
+  /// Solve the list of goals in order, each with its remaining depth, returning a proof
+  /// for each goal (in reverse order) or `None` if the search failed. On success the
+  /// assignments made to metavariables are kept, so that later goals see them.
+  /// A solution is rejected if it leaves any metavariable created by the search
+  /// (those numbered `base` and above) unassigned, since `refine` would not be able
+  /// to infer it from the returned proof either.
+  fn auto_solve(&mut self,
+    lemmas: &[(AtomId, ThmId)], base: usize, goals: &[(LispVal, usize)]
+  ) -> SResult<Option<Vec<LispVal>>> {
+    let ((g, depth), rest) = if let Some(r) = goals.split_first() { r } else {
+      let unassigned = self.lc.mvars[base..].iter()
+        .any(|m| m.unwrapped(|e| matches!(*e, LispKind::MVar(n, _) if n >= base)));
+      return Ok(if unassigned { None } else { Some(vec![]) })
+    };
+    if self.cur_timeout.map_or(false, |t| t < Instant::now()) { return Err("timeout".into()) }
+    if self.cancel.load(Ordering::Relaxed) { return Err("cancelled".into()) }
+    for i in 0..self.lc.proof_order.len() {
+      let (h, ty) = (self.lc.proof_order[i].0, self.lc.proof_order[i].1.clone());
+      let saved = self.save_mvars();
+      if let Ok(c) = self.unify_core(g, &ty) {
+        if let Some(mut ps) = self.auto_solve(lemmas, base, rest)? {
+          ps.push(LispVal::apply_conv(c, g.clone(), LispVal::atom(h)));
+          return Ok(Some(ps))
+        }
+      }
+      self.restore_mvars(saved);
+    }
+    // Using a hypothesis is free, but applying a theorem needs depth
+    if *depth == 0 { return Ok(None) }
+    for &(a, t) in lemmas {
+      let saved = self.save_mvars();
+      let tgts = self.thms[t].args.iter().map(|(_, ty)| self.type_target(ty)).collect::<Vec<InferTarget>>();
+      let args = tgts.into_iter().map(|tgt| self.lc.new_mvar(tgt, None)).collect();
+      let td = &self.env.thms[t];
+      let mut subst = Subst::new(&self.env, &td.heap, args);
+      let hyps = td.hyps.iter().map(|(_, h)| (subst.subst(h), depth - 1)).collect::<Vec<_>>();
+      let concl = subst.subst(&td.ret);
+      if let Ok(c) = self.unify_core(g, &concl) {
+        let n = hyps.len();
+        let mut sub = hyps;
+        sub.extend_from_slice(rest);
+        if let Some(mut ps) = self.auto_solve(lemmas, base, &sub)? {
+          // `ps` is in reverse order, so the hypotheses of this theorem are at the end
+          let mut app = vec![LispVal::atom(a)];
+          app.extend(ps.drain(ps.len() - n..).rev());
+          ps.push(LispVal::apply_conv(c, g.clone(), LispVal::list(app)));
+          return Ok(Some(ps))
+        }
+      }
+      self.restore_mvars(saved);
+    }
+    Ok(None)
+  }
+}
+
+/// The set of occurrences selected by a conversion tactic. Occurrences are numbered
+/// from 1 in pre-order, and `None` selects all of them.
+struct Occurrences<'a> {
+  sel: Option<&'a [usize]>,
+  count: usize,
+}
+
+impl Occurrences<'_> {
+  /// Count a new occurrence, and return true if it is selected.
+  fn next(&mut self) -> bool {
+    self.count += 1;
+    self.sel.map_or(true, |sel| sel.contains(&self.count))
+  }
+
+  /// Check that every selected occurrence was found.
+  fn check(&self, name: &ArcString) -> SResult<()> {
+    if self.count == 0 { return Err(format!("no occurrences of '{}'", name)) }
+    match self.sel.and_then(|sel| sel.iter().find(|&&n| n == 0 || n > self.count)) {
+      Some(n) => Err(format!("occurrence {} of '{}' not found (there are {})", n, name, self.count)),
+      None => Ok(())
+    }
+  }
+}
+
+impl Elaborator {
+  /// Create a new dummy variable `_1`, `_2`, ... of sort `s` in the local context.
+  pub(crate) fn fresh_dummy(&mut self, s: SortId) -> AtomId {
+    let mut i = 1;
+    let x = loop {
+      let a = self.get_atom(format!("_{}", i).as_bytes());
+      if !self.lc.vars.contains_key(&a) { break a }
+      i += 1;
+    };
+    self.lc.vars.insert(x, (true, InferSort::Bound(s)));
+    x
+  }
+
+  fn get_def(&self, t: AtomId) -> SResult<(TermId, Expr)> {
+    let tid = self.term(t).ok_or_else(|| format!("unknown term '{}'", self.data[t].name))?;
+    match &self.terms[tid].kind {
+      TermKind::Def(Some(val)) => Ok((tid, val.clone())),
+      _ => Err(format!("not a definition: {}", self.data[t].name)),
+    }
+  }
+
+  /// Compute the left (`right = false`) or right side of a conversion proof `c`,
+  /// written in the syntax of elaborated proofs: an expression is reflexivity,
+  /// `(t c1 ... cn)` is congruence, and `(:sym c)` and `(:unfold t es c)` are as in
+  /// [`ProofNode`](super::proof::ProofNode).
+  pub(crate) fn conv_side(&self, c: &LispVal, right: bool) -> SResult<LispVal> {
+    let err = || format!("invalid conversion: {}", self.print(c));
+    if c.is_atom() || c.is_mvar() { return Ok(c.clone()) }
+    let es = c.unwrapped(|e| if let LispKind::List(es) = e { Some(es.to_vec()) } else { None })
+      .ok_or_else(err)?;
+    let head = es.first().and_then(|e| e.as_atom()).ok_or_else(err)?;
+    match head {
+      AtomId::SYM => match &*es {
+        [_, c] => self.conv_side(c, !right),
+        _ => Err(err()),
+      },
+      AtomId::UNFOLD => match &*es {
+        [_, t, args, .., c] if es.len() <= 5 => if right { self.conv_side(c, true) } else {
+          let mut v = vec![t.clone()];
+          v.extend(Uncons::from(args.clone()));
+          Ok(LispVal::list(v))
+        },
+        _ => Err(err()),
+      },
+      _ => {
+        let mut v = vec![es[0].clone()];
+        for c in &es[1..] { v.push(self.conv_side(c, right)?) }
+        Ok(LispVal::list(v))
+      }
+    }
+  }
+
+  /// Substitute conversions `args` for the variables in the definition body `e`,
+  /// returning the substituted expression and a conversion from the body
+  /// instantiated with the left sides of `args` to it. The new dummy variables
+  /// are added to `dummies`.
+  fn subst_conv(&mut self, heap: &[ExprNode], dummies: &mut Vec<AtomId>,
+    subst: &mut Vec<Option<(LispVal, LispVal)>>, e: &ExprNode,
+  ) -> (LispVal, LispVal) {
+    match *e {
+      ExprNode::Ref(i) => {
+        if let Some(r) = &subst[i] { return r.clone() }
+        let r = self.subst_conv(heap, dummies, subst, &heap[i]);
+        subst[i] = Some(r.clone());
+        r
+      }
+      ExprNode::Dummy(_, s) => {
+        let x = self.fresh_dummy(s);
+        dummies.push(x);
+        let x = LispVal::atom(x);
+        (x.clone(), x)
+      }
+      ExprNode::App(t, ref es) => {
+        let a = LispVal::atom(self.terms[t].atom);
+        let (mut e2, mut c) = (vec![a.clone()], vec![a]);
+        for e in &**es {
+          let (e, c1) = self.subst_conv(heap, dummies, subst, e);
+          e2.push(e);
+          c.push(c1);
+        }
+        (LispVal::list(e2), LispVal::list(c))
+      }
+    }
+  }
+
+  fn unfold_core(&mut self, tid: TermId, val: &Expr, occs: &mut Occurrences<'_>,
+    dummies: &mut Vec<AtomId>, e: &LispVal,
+  ) -> (LispVal, Option<LispVal>) {
+    let (t, args) = if let Some(r) = self.dest_app(e) { r } else { return (e.clone(), None) };
+    let sel = t == tid && occs.next();
+    let mut changed = false;
+    let mut subst = Vec::with_capacity(val.heap.len());
+    for a in &args {
+      let (a2, c) = self.unfold_core(tid, val, occs, dummies, a);
+      changed |= c.is_some();
+      subst.push(Some((a2, c.unwrap_or_else(|| a.clone()))));
+    }
+    let head = LispVal::atom(self.terms[t].atom);
+    if sel {
+      subst.resize(val.heap.len(), None);
+      let (e2, c) = self.subst_conv(&val.heap, dummies, &mut subst, &val.head);
+      (e2, Some(LispVal::list(vec![LispVal::atom(AtomId::UNFOLD), head, LispVal::list(args), c])))
+    } else if changed {
+      let (mut e2, mut c) = (vec![head.clone()], vec![head]);
+      for (a2, c1) in subst.into_iter().flatten() { e2.push(a2); c.push(c1) }
+      (LispVal::list(e2), Some(LispVal::list(c)))
+    } else { (e.clone(), None) }
+  }
+
+  /// Unfold the selected occurrences of the definition `t` in `e`, returning the unfolded
+  /// expression and a conversion proof from `e` to it. Occurrences inside the arguments
+  /// of an unfolded occurrence are also considered, and dummy variables in the
+  /// definition are replaced by new dummies, which are removed again on failure.
+  pub(crate) fn conv_unfold(&mut self,
+    t: AtomId, occs: Option<&[usize]>, e: &LispVal
+  ) -> SResult<(LispVal, LispVal)> {
+    let (tid, val) = self.get_def(t)?;
+    let mut occs = Occurrences {sel: occs, count: 0};
+    let mut dummies = vec![];
+    let (e2, c) = self.unfold_core(tid, &val, &mut occs, &mut dummies, e);
+    if let Err(err) = occs.check(&self.data[t].name) {
+      for x in dummies { self.lc.vars.remove(&x); }
+      return Err(err)
+    }
+    Ok((e2, c.unwrap_or_else(|| e.clone())))
+  }
+
+  /// Match `e` against the definition body `p`, binding the definition arguments in
+  /// `args` and the dummy variables of the definition in `dummies`.
+  fn match_def(&self, heap: &[ExprNode], nargs: usize, pat: &ExprNode, e: &LispVal,
+    args: &mut [Option<LispVal>], dummies: &mut Vec<(AtomId, AtomId)>,
+  ) -> bool {
+    match *pat {
+      ExprNode::Ref(i) if i < nargs => if let Some(v) = &args[i] { v == e } else {
+        args[i] = Some(e.clone());
+        true
+      },
+      ExprNode::Ref(i) => self.match_def(heap, nargs, &heap[i], e, args, dummies),
+      ExprNode::Dummy(dummy, sort) => {
+        let x = if let Some(x) = e.as_atom() { x } else { return false };
+        if !matches!(self.lc.vars.get(&x), Some((_, InferSort::Bound(s))) if *s == sort) {
+          return false
+        }
+        if let Some(&(d, y)) = dummies.iter().find(|&&(d, y)| d == dummy || y == x) {
+          d == dummy && y == x
+        } else {
+          dummies.push((dummy, x));
+          true
+        }
+      }
+      ExprNode::App(t, ref ps) => match self.dest_app(e) {
+        Some((t2, es)) if t == t2 =>
+          ps.iter().zip(&es).all(|(p, e)| self.match_def(heap, nargs, p, e, args, dummies)),
+        _ => false
+      }
+    }
+  }
+
+  fn fold_core(&mut self, tid: TermId, val: &Expr, occs: &mut Occurrences<'_>,
+    e: &LispVal,
+  ) -> (LispVal, Option<LispVal>) {
+    let nargs = self.terms[tid].args.len();
+    let mut args = vec![None; nargs];
+    if self.match_def(&val.heap, nargs, &val.head, e, &mut args, &mut vec![]) && occs.next() {
+      let args = args.into_iter().zip(0..).map(|(a, i)| a.unwrap_or_else(|| {
+        let tgt = self.type_target(&self.terms[tid].args[i].1);
+        self.lc.new_mvar(tgt, None)
+      })).collect::<Vec<_>>();
+      let t = LispVal::atom(self.terms[tid].atom);
+      let mut e2 = vec![t.clone()];
+      e2.extend(args.iter().cloned());
+      let c = LispVal::list(vec![LispVal::atom(AtomId::UNFOLD), t, LispVal::list(args), e.clone()]);
+      return (LispVal::list(e2), Some(LispVal::list(vec![LispVal::atom(AtomId::SYM), c])))
+    }
+    let (t, args) = if let Some(r) = self.dest_app(e) { r } else { return (e.clone(), None) };
+    let head = LispVal::atom(self.terms[t].atom);
+    let (mut e2, mut c, mut changed) = (vec![head.clone()], vec![head], false);
+    for a in &args {
+      let (a2, c1) = self.fold_core(tid, val, occs, a);
+      changed |= c1.is_some();
+      e2.push(a2);
+      c.push(c1.unwrap_or_else(|| a.clone()));
+    }
+    if changed { (LispVal::list(e2), Some(LispVal::list(c))) } else { (e.clone(), None) }
+  }
+
+  /// Fold the selected instances of the body of definition `t` in `e` back into
+  /// applications of `t`, returning the folded expression and a conversion proof from
+  /// `e` to it. Instances are searched top-down, and subterms of a folded instance are
+  /// not searched. Arguments that do not appear in the body become metavariables,
+  /// which are removed again on failure.
+  pub(crate) fn conv_fold(&mut self,
+    t: AtomId, occs: Option<&[usize]>, e: &LispVal
+  ) -> SResult<(LispVal, LispVal)> {
+    let (tid, val) = self.get_def(t)?;
+    let mut head = &val.head;
+    while let ExprNode::Ref(i) = *head {
+      if i < self.terms[tid].args.len() {
+        return Err(format!("cannot fold '{}', which unfolds to a variable", self.data[t].name))
+      }
+      head = &val.heap[i];
+    }
+    let mut occs = Occurrences {sel: occs, count: 0};
+    let n = self.lc.mvars.len();
+    let (e2, c) = self.fold_core(tid, &val, &mut occs, e);
+    if let Err(err) = occs.check(&self.data[t].name) {
+      self.lc.mvars.truncate(n);
+      return Err(err)
+    }
+    Ok((e2, c.unwrap_or_else(|| e.clone())))
+  }
+
+  /// Lift the conversion `c` to a conversion on `e`, by applying it at the subterm
+  /// of `e` given by `path`, a list of (1-based) argument positions. The left side
+  /// of `c` must be the subterm at `path`.
+  pub(crate) fn conv_at(&self, e: &LispVal, path: &[usize], c: LispVal) -> SResult<LispVal> {
+    match path.split_first() {
+      None => if self.conv_side(&c, false)? == *e { Ok(c) } else {
+        Err(format!("conversion does not apply to {}", self.print(e)))
+      },
+      Some((&idx, path)) => {
+        let (t, mut args) = self.dest_app(e)
+          .ok_or_else(|| format!("not a term application: {}", self.print(e)))?;
+        let arg = args.get_mut(idx.wrapping_sub(1))
+          .ok_or_else(|| format!("argument {} out of range in {}", idx, self.print(e)))?;
+        *arg = self.conv_at(arg, path, c)?;
+        let mut v = vec![LispVal::atom(self.terms[t].atom)];
+        v.extend(args);
+        Ok(LispVal::list(v))
+      }
+    }
+  }
+
+  /// Change the first goal using the conversion `c`, whose left side must be the goal.
+  /// The goal is assigned to a `:conv` proof of a new goal, the right side of `c`,
+  /// which replaces it in the goal list.
+  pub(crate) fn conv_goal(&mut self, fsp: FileSpan, c: LispVal) -> SResult<()> {
+    let i = self.lc.goals.iter().position(|g| g.goal_type().is_some()).ok_or("no goals")?;
+    let g = self.lc.goals[i].clone();
+    let ty = g.goal_type().expect("is a goal");
+    if self.conv_side(&c, false)? != ty {
+      return Err(format!("conversion does not apply to the goal {}", self.print(&ty)))
+    }
+    let g2 = LispVal::new_ref(LispVal::goal(fsp, self.conv_side(&c, true)?));
+    g.as_ref_(|e| *e = LispVal::list(vec![LispVal::atom(AtomId::CONV), ty, c, g2.clone()]))
+      .expect("a goal is a ref");
+    self.lc.goals[i] = g2;
+    Ok(())
+  }
+}
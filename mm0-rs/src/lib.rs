@@ -59,6 +59,8 @@ pub use mm0_deepsize::deep_size_0;
 
 #[cfg(feature = "server")]
 #[macro_use] pub mod server;
+#[cfg(feature = "server")]
+pub mod debug_adapter;
 pub mod compiler;
 pub mod joiner;
 pub mod elab;
@@ -15,6 +15,10 @@ fn main() -> std::io::Result<()> {
       (@arg no_proofs: -n --("no-proofs") "Disable proof checking until (check-proofs #t)")
       (@arg quiet: -q --quiet "Hide diagnostic messages")
       (@arg strip: -s --("strip") "Don't add debugging data to .mmb files")
+      (@arg mmc_dump: --("mmc-dump") [KIND] possible_value[asm vcode]
+        "Print a listing (asm or vcode) of the code produced by mmc-finish")
+      (@arg mmc_debug_elf: --("mmc-debug-elf")
+        "Write the program produced by (mmc-finish 'NAME) to NAME.debug, with debug info")
       (@arg output: -o --output [FILE]
         "Print 'output' commands to a file (use '-' to print to stdout)")
       (@arg INPUT: +required "Sets the input file (.mm1 or .mm0)")
@@ -45,6 +49,10 @@ fn main() -> std::io::Result<()> {
       (@arg no_proofs: -n --("no-proofs") "Disable proof checking until (check-proofs #t)")
       (@arg debug: -d --debug "Enable debug logging")
       (@arg no_log_errors: -q --quiet "Don't print errors in server output log")));
+  #[cfg(feature = "server")]
+  let app = app.subcommand(clap::SubCommand::with_name("debug-adapter")
+    .about("MM1 lisp debugger, using the Debug Adapter Protocol")
+    .arg(clap::Arg::from_usage("-n, --no-proofs 'Disable proof checking until (check-proofs #t)'")));
 
   let m = app.get_matches();
 
@@ -61,6 +69,11 @@ fn main() -> std::io::Result<()> {
       if m.is_present("no_proofs") { mm0_rs::set_check_proofs(false) }
       mm0_rs::server::main(m)
     }
+    #[cfg(feature = "server")]
+    ("debug-adapter", Some(m)) => {
+      if m.is_present("no-proofs") { mm0_rs::set_check_proofs(false) }
+      mm0_rs::debug_adapter::main(m)?
+    }
     _ => unreachable!()
   }
   Ok(())
@@ -7,7 +7,7 @@ use mm0b_parser::MAX_BOUND_VARS;
 use zerocopy::{AsBytes, U32, U64};
 use crate::{
   Type, Expr, Proof, SortId, AtomId, AtomVec, TermKind, ThmKind,
-  TermVec, ExprNode, ProofNode, StmtTrace, DeclKey, Modifiers,
+  TermVec, ExprNode, ProofNode, StmtTrace, DeclKey, Modifiers, OutputString,
   FrozenEnv, FileRef, LinedString, ErrorLevel};
 
 #[allow(clippy::wildcard_imports)]
@@ -632,6 +632,16 @@ impl<'a, W: Write + Seek> Exporter<'a, W> {
             }
           }
         }
+        StmtTrace::InputString(ref is) => {
+          let OutputString {heap, exprs, ..} = &**is;
+          let mut reorder = Reorder::new(0, heap.len(), |i| i);
+          for e in &**exprs {
+            write_expr_proof(vec, heap, &mut reorder, &mut None, e, false)?;
+          }
+          vec.write_u8(0)?;
+          write_cmd_bytes(self, STMT_INPUT_STRING, vec)?;
+          vec.clear();
+        }
         StmtTrace::Global(_) |
         StmtTrace::OutputString(_) => {}
       }
@@ -1,7 +1,7 @@
 //! Importer for MMB files into the [`Environment`].
 
 use std::rc::Rc;
-use crate::{Environment, Modifiers, AtomId, TermId,
+use crate::{Environment, Modifiers, AtomId, TermId, StmtTrace, OutputString,
     Type, Term, Thm, TermKind, ThmKind, ExprNode, Expr, Proof};
 use crate::elab::proof::{IDedup, ProofKind, ProofHash, build};
 use crate::{FileRef, FileSpan, SliceExt};
@@ -365,6 +365,39 @@ fn parse_proof(
   Ok(Proof {heap, hyps, head: ids[ret].take()})
 }
 
+/// Parse the proof stream of an `input string` statement, which is a sequence of
+/// expressions with no variables, into an [`OutputString`]. The statement starts at `start`.
+fn parse_exprs(
+  fref: &FileRef, file: &BasicMmbFile<'_>, start: usize, it: &mut ProofIter<'_>,
+) -> Result<OutputString> {
+  use ParseError::StrError;
+  let (mut heap, mut stack) = (vec![], vec![]);
+  let mut pos = it.pos;
+  while let Some(e) = it.next() {
+    match e? {
+      ProofCmd::Term {tid, save} => {
+        let nargs = file.term(tid).ok_or(StrError("unknown term", pos))?.args().len();
+        let mid = stack.len().checked_sub(nargs).ok_or(StrError("stack underflow", pos))?;
+        let e = ExprNode::App(tid, stack.drain(mid..).collect());
+        stack.push(if save {
+          let h = heap.len();
+          heap.push(e);
+          ExprNode::Ref(h)
+        } else { e })
+      }
+      ProofCmd::Ref(i) => {
+        let i = usize::try_from(i).expect("impossible");
+        if i >= heap.len() { return Err(StrError("reference out of range", pos)) }
+        stack.push(ExprNode::Ref(i))
+      }
+      _ => return Err(StrError("bad input string expr", pos))
+    }
+    pos = it.pos;
+  }
+  let span = FileSpan {file: fref.clone(), span: (start..it.pos).into()};
+  Ok(OutputString {span, heap: heap.into_boxed_slice(), exprs: stack.into_boxed_slice()})
+}
+
 fn parse(fref: &FileRef, buf: &[u8], env: &mut Environment) -> Result<()> {
   use ParseError::StrError;
   let file = BasicMmbFile::parse(buf)?;
@@ -454,6 +487,10 @@ fn parse(fref: &FileRef, buf: &[u8], env: &mut Environment) -> Result<()> {
           vis, heap, hyps: hyps.into_boxed_slice(), ret,
         }).map_err(|_| StrError("double add term", start))?;
       }
+      NumdStmtCmd::InputString => {
+        let is = parse_exprs(fref, &file, start, &mut pf)?;
+        env.stmts.push(StmtTrace::InputString(Box::new(is)));
+      }
     }
     start = it.pos;
   }
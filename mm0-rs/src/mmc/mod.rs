@@ -10,11 +10,11 @@
 mod parser;
 mod proof;
 
-use std::{collections::HashMap, rc::Rc};
-use mmcc::{infer::TypeError, types::{IdxVec, LambdaId, hir, ty::CtxPrint}};
+use std::{collections::HashMap, io::{self, Write}, rc::Rc};
+use mmcc::{Symbol, infer::TypeError, types::{IdxVec, LambdaId, VarId, ast, hir, ty::CtxPrint}};
 use parser::{ItemIter, Parser, Keyword};
 use crate::{FileSpan, Span, AtomId, Remap, Remapper, Elaborator, ElabError,
-  elab::Result, LispVal, EnvDebug, FormatEnv};
+  elab::Result, LispVal, Uncons, EnvDebug, FormatEnv};
 
 use self::parser::Mm0ExprNode;
 
@@ -90,6 +90,46 @@ impl<'a> mmcc::ItemContext<Config> for ItemContext<'a> {
   }
 }
 
+/// A kind of listing of the generated code, produced by the `dump` subcommand of the compiler.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DumpKind {
+  /// The final machine code in Intel syntax (see [`mmcc::LinkedCode::write_asm`]).
+  Asm,
+  /// The code before register allocation (see [`mmcc::LinkedCode::write_vcode`]).
+  VCode,
+}
+
+impl DumpKind {
+  /// Parse the name of a listing kind, `asm` or `vcode`.
+  #[must_use] pub fn parse(s: &str) -> Option<Self> {
+    match s {
+      "asm" => Some(Self::Asm),
+      "vcode" => Some(Self::VCode),
+      _ => None,
+    }
+  }
+
+  fn write(self, code: &mmcc::LinkedCode, w: &mut impl io::Write) -> io::Result<()> {
+    match self {
+      Self::Asm => code.write_asm(w),
+      Self::VCode => code.write_vcode(w),
+    }
+  }
+}
+
+/// The options for the output of `mmc-finish`, which are set by command line options of
+/// `mm0-rs compile` and passed to the elaborator.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Options {
+  /// The kind of listing that `mmc-finish` prints to stdout, if any.
+  /// This is set by `--mmc-dump`.
+  pub dump: Option<DumpKind>,
+  /// If true, `(mmc-finish 'NAME)` also writes the program to `NAME.debug` in the current
+  /// directory, with a symbol table and line information for debuggers
+  /// (see [`mmcc::LinkedCode::write_elf_debug`]). This is set by `--mmc-debug-elf`.
+  pub debug_elf: bool,
+}
+
 /// The MMC compiler, which contains local state for the functions that have been
 /// loaded and typechecked thus far.
 #[derive(Clone, DeepSizeOf)]
@@ -124,8 +164,11 @@ impl Remap for Compiler {
 impl Compiler {
   /// Construct a new compiler object.
   pub fn new(elab: &mut Elaborator) -> Self {
+    // `mmc-finish` always generates a proof, so unprovable code is rejected when typechecking
+    let mut inner = mmcc::Compiler::default();
+    inner.proofs = true;
     Self {
-      inner: Default::default(),
+      inner: Rc::new(inner),
       predef: proof::Predefs::new(elab)
     }
   }
@@ -133,7 +176,9 @@ impl Compiler {
   /// Add the given MMC text (as a list of lisp literals) to the compiler state,
   /// performing typehecking but not code generation. This can be called multiple
   /// times to add multiple functions, but each lisp literal is already a list of
-  /// top level items that are typechecked as a unit.
+  /// top level items that are typechecked as a unit. In particular, the procedures
+  /// in a single lisp literal can be (mutually) recursive: all procedure signatures
+  /// are declared before any procedure body is checked.
   pub fn add(&mut self,
     elab: &mut Elaborator, sp: Span, it: impl Iterator<Item=LispVal>
   ) -> Result<()> {
@@ -141,7 +186,14 @@ impl Compiler {
     let fsp = FileSpan {file: elab.path.clone(), span: sp};
     let mut cache = HashMap::default();
     for e in it {
+      // If a procedure name is taken, the item would fail to parse with the same error,
+      // so we report it once and skip the whole group of items.
+      if let Err(e) = Parser::new(elab, &mut cache, compiler).declare_procs(&fsp, &e) {
+        elab.report(e);
+        continue
+      }
       let mut it = ItemIter::new(e);
+      let mut items = vec![];
       loop {
         let mut p = Parser::new(elab, &mut cache, compiler);
         let item = match p.parse_next_item(&fsp, &mut it) {
@@ -149,30 +201,92 @@ impl Compiler {
           Ok(Some(item)) => item,
           Ok(None) => break,
         };
-        let (var_names, lambdas) = p.finish();
-        let mut errors = vec![];
-        compiler.add(&item, var_names, ItemContext {
-          elab,
-          lambdas: &lambdas,
-          errors: &mut errors
-        })?;
-        for e in errors { elab.report(e) }
+        items.push((item, p.finish()));
+      }
+      let mut procs = vec![];
+      for (item, (var_names, lambdas)) in items {
+        if let ast::ItemKind::Proc {..} = item.k {
+          compiler.declare(&item, var_names.clone());
+          procs.push((item, (var_names, lambdas)));
+          continue
+        }
+        Self::add_item(compiler, elab, &item, var_names, &lambdas)?
+      }
+      for (item, (var_names, lambdas)) in procs {
+        Self::add_item(compiler, elab, &item, var_names, &lambdas)?
       }
     }
     Ok(())
   }
 
+  fn add_item(compiler: &mut mmcc::Compiler<Config>, elab: &mut Elaborator,
+    item: &ast::Item, var_names: IdxVec<VarId, Symbol>,
+    lambdas: &IdxVec<LambdaId, Mm0ExprNode>,
+  ) -> Result<()> {
+    let mut errors = vec![];
+    compiler.add(item, var_names, ItemContext { elab, lambdas, errors: &mut errors })?;
+    for e in errors { elab.report(e) }
+    Ok(())
+  }
+
   /// Once we are done adding functions, this function performs final linking to produce an executable.
   pub fn finish(&mut self, elab: &mut Elaborator, sp: Span, name: AtomId) -> Result<()> {
     let compiler = Rc::make_mut(&mut self.inner);
     if compiler.has_type_errors() {
       return Err(ElabError::new_e(sp, "Compilation failed due to previous errors"))
     }
-    proof::render_proof(&self.predef, elab, sp, name, &compiler.finish().proof())
+    let code = compiler.finish().map_err(|e| ElabError::new_e(sp, e.to_string()))?;
+    if let Some(kind) = elab.mmc_options.dump {
+      let out = io::stdout();
+      let mut out = out.lock();
+      writeln!(out, "; {}", elab.data[name].name).and_then(|()| kind.write(&code, &mut out))
+        .map_err(|e| ElabError::new_e(sp, e))?
+    }
+    if elab.mmc_options.debug_elf {
+      // Only the code in the current file gets line information
+      let line = |fsp: &FileSpan| (fsp.file == elab.path)
+        .then(|| elab.ast.source.to_pos(fsp.span.start).line + 1);
+      let path = format!("{}.debug", elab.data[name].name);
+      std::fs::File::create(&path).and_then(|mut file| code.write_elf_debug(&mut file, line))
+        .map_err(|e| ElabError::new_e(sp, format!("could not write {}: {}", path, e)))?
+    }
+    proof::render_proof(&self.predef, elab, sp, name, &code.proof())
+  }
+
+  /// Like [`finish`](Self::finish), but instead of an executable, this writes a relocatable
+  /// object file `NAME.o` to the current directory, in which the procedures in `exports`
+  /// can be called from C (see [`mmcc::LinkedCode::write_object`]). No proof is produced.
+  pub fn finish_object(&mut self,
+    elab: &mut Elaborator, sp: Span, name: AtomId, exports: &[Symbol]
+  ) -> Result<()> {
+    let compiler = Rc::make_mut(&mut self.inner);
+    if compiler.has_type_errors() {
+      return Err(ElabError::new_e(sp, "Compilation failed due to previous errors"))
+    }
+    let code = compiler.finish_with_exports(exports)
+      .map_err(|e| ElabError::new_e(sp, e.to_string()))?;
+    let path = format!("{}.o", elab.data[name].name);
+    std::fs::File::create(&path).and_then(|mut file| code.write_object(&mut file))
+      .map_err(|e| ElabError::new_e(sp, format!("could not write {}: {}", path, e)))
+  }
+
+  /// Link the functions added so far and return a listing of the generated code.
+  /// This does not change the compiler state, so more functions can be added afterwards.
+  /// The result is not cached: each call clones the compiler and relinks the whole program,
+  /// which is as expensive as [`finish`](Self::finish) (minus the proof).
+  pub fn dump(&self, sp: Span, kind: DumpKind) -> Result<String> {
+    if self.inner.has_type_errors() {
+      return Err(ElabError::new_e(sp, "Compilation failed due to previous errors"))
+    }
+    let code = (*self.inner).clone().finish().map_err(|e| ElabError::new_e(sp, e.to_string()))?;
+    let mut out = vec![];
+    kind.write(&code, &mut out).expect("writing to a vec");
+    Ok(String::from_utf8(out).expect("listings are valid UTF-8"))
   }
 
   /// Main entry point to the compiler. Does basic parsing and forwards to
-  /// [`add`](Self::add) and [`finish`](Self::finish).
+  /// [`add`](Self::add), [`finish`](Self::finish), [`finish_object`](Self::finish_object)
+  /// and [`dump`](Self::dump).
   pub fn call(&mut self, elab: &mut Elaborator, sp: Span, args: Vec<LispVal>) -> Result<LispVal> {
     let mut it = args.into_iter();
     let e = it.next().expect("expected 1 argument");
@@ -188,6 +302,24 @@ impl Compiler {
         self.finish(elab, sp, name)?;
         Ok(LispVal::undef())
       }
+      Some(Keyword::FinishObject) => {
+        let err = || ElabError::new_e(sp, "mmc-finish-object: syntax error");
+        let name = it.next().and_then(|e| e.as_atom()).ok_or_else(err)?;
+        let mut u = Uncons::from(it.next().ok_or_else(err)?);
+        let exports = u.by_ref().map(|e| e.as_atom().map(|a|
+          mmcc::intern(elab.data[a].name.as_str()))).collect::<Option<Vec<_>>>()
+          .filter(|_| u.is_empty()).ok_or_else(err)?;
+        self.add(elab, sp, it)?;
+        self.finish_object(elab, sp, name, &exports)?;
+        Ok(LispVal::undef())
+      }
+      Some(Keyword::Dump) => {
+        let kind = match it.next() {
+          None => Some(DumpKind::Asm),
+          Some(e) => e.as_atom().and_then(|a| DumpKind::parse(elab.data[a].name.as_str())),
+        }.ok_or_else(|| ElabError::new_e(sp, "mmc-dump: expected 'asm or 'vcode"))?;
+        Ok(LispVal::string(self.dump(sp, kind)?.into()))
+      }
       _ => Err(ElabError::new_e(sp,
         format!("mmc-compiler: unknown subcommand '{}'", elab.print(&e))))
     }
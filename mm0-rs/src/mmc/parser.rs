@@ -85,15 +85,18 @@ make_keywords! {
   Colon: ":",
   ColonEq: ":=",
   Const: "const",
+  Dump: "dump",
   Else: "else",
   Entail: "entail",
   Func: "func",
   Finish: "finish",
+  FinishObject: "finish-object",
   Ghost: "ghost",
   Global: "global",
   Implicit: "implicit",
   Intrinsic: "intrinsic",
   If: "if",
+  Inline: "inline",
   Le: "<=",
   Lt: "<",
   Main: "main",
@@ -216,6 +219,7 @@ enum ItemGroup {
   Global(Uncons),
   Const(Uncons),
   Intrinsic(Uncons),
+  Inline(Uncons),
 }
 
 #[derive(Debug, DeepSizeOf)]
@@ -224,6 +228,7 @@ enum ItemIterInner {
   Global(Uncons),
   Const(Uncons),
   Intrinsic(Box<ItemIter>),
+  Inline(Box<ItemIter>),
 }
 
 /// An iterator over items. This is not a proper iterator in the sense of implementing `Iterator`,
@@ -232,13 +237,14 @@ enum ItemIterInner {
 pub(crate) struct ItemIter {
   group: ItemIterInner,
   u: Uncons,
-  intrinsic: bool
+  intrinsic: bool,
+  inline: bool,
 }
 
 impl ItemIter {
   /// Construct a new iterator from an `I: Iterator<Item=LispVal>`.
   #[must_use] pub(crate) fn new(e: LispVal) -> Self {
-    Self { group: ItemIterInner::New, u: Uncons::New(e), intrinsic: false }
+    Self { group: ItemIterInner::New, u: Uncons::New(e), intrinsic: false, inline: false }
   }
 }
 
@@ -351,6 +357,20 @@ impl<'a, C> Parser<'a, C> {
       ElabError::new_e(sp, format!("unknown variable '{}'", name)))
   }
 
+  /// Recognizes the divisor `(y h)` in `{x // (y h)}` and `{x % (y h)}`, which is a pair of
+  /// the divisor `y` and a proof `h: y != 0`. This can only be confused with a function call
+  /// if the head is an atom, so we only accept it when the head is a local variable.
+  fn as_nonzero_divisor(&mut self, e: &LispVal) -> Option<(LispVal, LispVal)> {
+    let mut u = Uncons::from(e.clone());
+    let (y, pf) = (u.next()?, u.next()?);
+    if !u.is_empty() { return None }
+    match y.as_atom() {
+      Some(name) => { let name = self.as_symbol(name); self.ba.get_var(name)?; }
+      None => if !y.is_list() || self.head_keyword(&y).is_some() { return None },
+    }
+    Some((y, pf))
+  }
+
   fn parse_variant(&mut self, base: &FileSpan, e: &LispVal) -> Result<Option<Box<Variant>>> {
     Ok(if let Some((Keyword::Variant, mut u)) = self.head_keyword(e) {
       let span = try_get_fspan(base, e);
@@ -875,6 +895,7 @@ impl<'a, C> Parser<'a, C> {
     kind: &dyn Fn(Symbol) -> Result<ProcKind>,
     mut u: Uncons,
     intrinsic: bool,
+    inline: bool,
   ) -> Result<Item> {
     struct OutVal {
       index: u32,
@@ -902,6 +923,9 @@ impl<'a, C> Parser<'a, C> {
     };
     let kind = kind(name.k)?;
     let intrinsic = if intrinsic {
+      if inline {
+        return Err(ElabError::new_e(&name.span, "inline: intrinsics cannot be inlined"))
+      }
       Some(get_intrinsic(&name.span, name.k, IntrinsicProc::from_symbol)?)
     } else { None };
     self.compiler.forward_declare_proc(&name.span, name.k)?;
@@ -1011,7 +1035,7 @@ impl<'a, C> Parser<'a, C> {
     } else {None};
     let body = self.parse_block(&span, u)?;
     Ok(Spanned {span, k: ItemKind::Proc {
-      intrinsic, kind, name, tyargs, args, outs, rets, variant, body
+      intrinsic, inline, kind, name, tyargs, args, outs, rets, variant, body
     }})
   }
 
@@ -1039,19 +1063,22 @@ impl<'a, C> Parser<'a, C> {
 
   /// Parses the input lisp literal `e` into a list of top level items and appends them to `ast`.
   fn push_item_group(&mut self,
-    base: &FileSpan, e: &LispVal, intrinsic: bool,
+    base: &FileSpan, e: &LispVal, intrinsic: bool, inline: bool,
   ) -> Result<ItemGroup> {
     let span = try_get_fspan(base, e);
     Ok(match self.head_keyword(e) {
       Some((Keyword::Proc, u)) => {
         let f = |a| Ok(if a == Keyword::Main.as_symbol() {ProcKind::Main} else {ProcKind::Proc});
-        ItemGroup::Item(self.parse_proc(span, &f, u, intrinsic)?)
+        ItemGroup::Item(self.parse_proc(span, &f, u, intrinsic, inline)?)
       }
       Some((Keyword::Func, u)) => {
         let f = |_| Ok(ProcKind::Func);
-        ItemGroup::Item(self.parse_proc(span, &f, u, intrinsic)?)
+        ItemGroup::Item(self.parse_proc(span, &f, u, intrinsic, inline)?)
       }
+      _ if inline => return Err(ElabError::new_e(try_get_span(base, e),
+        "inline: expected a func or proc declaration")),
       Some((Keyword::Intrinsic, u)) => ItemGroup::Intrinsic(u),
+      Some((Keyword::Inline, u)) => ItemGroup::Inline(u),
       Some((Keyword::Global, u)) => ItemGroup::Global(u),
       Some((Keyword::Const, u)) => ItemGroup::Const(u),
       Some((Keyword::Typedef, mut u)) =>
@@ -1082,19 +1109,46 @@ impl<'a, C> Parser<'a, C> {
     })
   }
 
+  /// Forward declare all the (non-intrinsic) procedures in the input lisp literal `e`,
+  /// so that they can be referenced before they are defined. Malformed items are skipped,
+  /// because they will be reported during parsing, but a name that is already taken
+  /// is an error.
+  pub(crate) fn declare_procs(&mut self, base: &FileSpan, e: &LispVal) -> Result<()> {
+    for e in Uncons::New(e.clone()) {
+      match self.head_keyword(&e) {
+        Some((Keyword::Proc | Keyword::Func, mut u)) => {
+          let span = try_get_fspan(base, &e);
+          let e = match u.next() { Some(e) => e, None => continue };
+          let e = if e.as_atom().is_some() { e }
+            else if let Some(e) = Uncons::from(e).next() { e }
+            else { continue };
+          if let Some(a) = e.as_atom() {
+            let name = spanned(&span, &e, self.as_symbol(a));
+            self.compiler.forward_declare_proc(&name.span, name.k)?
+          }
+        }
+        Some((Keyword::Inline, u)) => self.declare_procs(base, &u.into())?,
+        _ => {}
+      }
+    }
+    Ok(())
+  }
+
   /// Extract the next item from the provided item iterator.
   pub(crate) fn parse_next_item(&mut self,
-    base: &FileSpan, ItemIter {group, u, intrinsic}: &mut ItemIter
+    base: &FileSpan, ItemIter {group, u, intrinsic, inline}: &mut ItemIter
   ) -> Result<Option<Item>> {
     self.with_ctx(|this| Ok(loop {
       match group {
         ItemIterInner::New => if let Some(e) = u.next() {
-          match this.push_item_group(base, &e, *intrinsic)? {
+          match this.push_item_group(base, &e, *intrinsic, *inline)? {
             ItemGroup::Item(it) => break Some(it),
             ItemGroup::Global(u2) => *group = ItemIterInner::Global(u2),
             ItemGroup::Const(u2) => *group = ItemIterInner::Const(u2),
             ItemGroup::Intrinsic(u) => *group = ItemIterInner::Intrinsic(
-              Box::new(ItemIter {group: ItemIterInner::New, u, intrinsic: true})),
+              Box::new(ItemIter {group: ItemIterInner::New, u, intrinsic: true, inline: false})),
+            ItemGroup::Inline(u) => *group = ItemIterInner::Inline(Box::new(
+              ItemIter {group: ItemIterInner::New, u, intrinsic: *intrinsic, inline: true})),
           }
         } else {
           break None
@@ -1109,7 +1163,8 @@ impl<'a, C> Parser<'a, C> {
         } else {
           *group = ItemIterInner::New
         }
-        ItemIterInner::Intrinsic(iter) => if let Some(item) = this.parse_next_item(base, iter)? {
+        ItemIterInner::Intrinsic(iter) |
+        ItemIterInner::Inline(iter) => if let Some(item) = this.parse_next_item(base, iter)? {
           break Some(item)
         } else {
           *group = ItemIterInner::New
@@ -1369,10 +1424,21 @@ impl<'a, C> Parser<'a, C> {
         (PrimOp::Sub, []) => err!("expected 1 or more arguments"),
         (PrimOp::Sub, [e]) => ExprKind::Unop(Unop::Neg, expr!(e)),
         (PrimOp::Sub, _) => {let args = exprs!(args); return Ok(self.ba.mk_sub(&span, args))}
+        (PrimOp::Div | PrimOp::Mod, [x, y] | [x, y, _]) => {
+          let op = if *prim == PrimOp::Div { Binop::Div } else { Binop::Mod };
+          let nz = if let [_, _, pf] = &*args { Some((y.clone(), pf.clone())) }
+            else { self.as_nonzero_divisor(y) };
+          if let Some((y, pf)) = nz {
+            ExprKind::DivNonzero(op, Box::new((*expr!(x), *expr!(y), *expr!(pf))))
+          } else {
+            ExprKind::Binop(op, expr!(x), expr!(y))
+          }
+        }
         (PrimOp::Shl, [a, b]) => ExprKind::Binop(Binop::Shl, expr!(a), expr!(b)),
         (PrimOp::Shr, [a, b]) => ExprKind::Binop(Binop::Shr, expr!(a), expr!(b)),
         (PrimOp::Typed, [e, ty]) => ExprKind::Typed(expr!(e), ty!(ty)),
         (PrimOp::As, [e, ty]) => ExprKind::As(expr!(e), ty!(ty)),
+        (PrimOp::Div | PrimOp::Mod, _) => err!("expected 2 or 3 arguments"),
         (PrimOp::Shl | PrimOp::Shr | PrimOp::Typed | PrimOp::As, _) =>
           err!("expected 2 arguments"),
         (PrimOp::Cast, args) => match args {
@@ -872,6 +872,8 @@ impl BuildAssemblyProc<'_> {
               parseMul(*ip, l, *self.start, rex.1, src, sz, v.1, self.hex[y], h1, h2, h3));
             [l, opch, inst, th]
           } else {
+            assert!(matches!(pinst, PInst::DivRem { signed: false, .. }),
+              "signed division is not supported by the x86 model");
             let inst = app!(self, (instDiv sz src));
             let th = thm!(self, (parseOpc[*self.start, *ip, l, rex.1, opch, inst]) =>
               parseDiv(*ip, l, *self.start, rex.1, src, sz, v.1, self.hex[y], h1, h2, h3));
@@ -940,6 +942,7 @@ impl BuildAssemblyProc<'_> {
   /// `s` is generated from the instruction assembly.
   fn parse_inst(&mut self, inst: &Inst<'_>, ip: Num) -> [ProofId; 3] {
     let p = &mut inst.content();
+    assert!(!inst.layout.opsz, "16-bit operations are not supported by the x86 model");
     let short = inst.layout.opc.len() == 1;
     if inst.layout.rex {
       let rex = parse_u8(p) & 15;
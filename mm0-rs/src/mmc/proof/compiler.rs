@@ -557,6 +557,7 @@ impl<'a> ProcProver<'a> {
         Statement::LabelGroup(..) => todo!(),
         Statement::PopLabelGroup => todo!(),
         Statement::DominatedBlock(_, _) => todo!(),
+        Statement::Span(_) => todo!(),
     }
       dbg!(stmt);
     } else {
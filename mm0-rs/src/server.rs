@@ -1,11 +1,11 @@
 //! Implements the bridge between mm0-rs and an editor via an lsp [`Connection`]
 
 use std::{fs, io};
-use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}, Condvar};
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, AtomicUsize, Ordering}, Condvar};
 use std::collections::{VecDeque, HashMap, HashSet, hash_map::{Entry, DefaultHasher}};
 use std::hash::{Hash, Hasher};
 use std::thread::{ThreadId, self};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use futures::{FutureExt, future::BoxFuture};
 use futures::channel::oneshot::{Sender as FSender, channel};
 use futures::executor::ThreadPool;
@@ -21,13 +21,14 @@ use crossbeam::channel::{SendError, RecvError};
 use clap::ArgMatches;
 use crate::{ArcList, ArcString, BoxError, FileRef, FileSpan, Span,
   MutexExt, CondvarExt};
-use mm1_parser::{Ast, parse};
+use mm1_parser::{Ast, parse, ast::{Binder, GenNota, Literal, SExpr, SExprKind, Stmt, StmtKind, Type}};
 use crate::mmb::import::elab as mmb_elab;
 use crate::mmu::import::elab as mmu_elab;
 use crate::compiler::FileContents;
-use crate::{ObjectKind, DeclKey, StmtTrace, AtomId, SortId, TermId, ThmId, LinedString, FrozenEnv,
+use crate::{ObjectKind, DeclKey, StmtTrace, AtomId, SortId, TermId, ThmId, ThmKind, LinedString, FrozenEnv,
   FrozenLispKind, FrozenAtomData};
-use crate::elab::{ElabResult, ElaborateBuilder, GoalListener,
+use crate::elab::{ElabError, ElabProgress, ElabResult, ElaborateBuilder, FileReader, GoalListener,
+  ProgressListener,
   local_context::InferSort, proof::Subst,
   lisp::{print::FormatEnv, pretty::Pretty, LispKind, Proc, BuiltinProc},
   spans::Spans};
@@ -35,6 +36,12 @@ use crate::elab::{ElabResult, ElaborateBuilder, GoalListener,
 // Disabled because vscode doesn't handle them properly
 const USE_LOCATION_LINKS: bool = false;
 
+/// The minimum time between two progress reports for the same elaboration.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The minimum time between two intermediate diagnostic reports for the same elaboration.
+const DIAGNOSTICS_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Debug)]
 struct ServerError(BoxError);
 
@@ -209,6 +216,7 @@ async fn elaborate(path: FileRef, start: Option<Position>,
   let source = text.clone();
 
   let mut deps = Vec::new();
+  let input_deps = Arc::new(Mutex::new(Vec::new()));
   let (ast, (cyc, toks, errors, env)) = if path.has_extension("mmb") {
     let (error, env) = mmb_elab(&path, &text);
     let errors = if let Err(e) = error {vec![e]} else {vec![]};
@@ -221,6 +229,9 @@ async fn elaborate(path: FileRef, start: Option<Position>,
     let (idx, ast) = parse(text.ascii().clone(), old_ast);
     let ast = Arc::new(ast);
     let rd = rd.push(path.clone());
+    // Progress reporting is cosmetic, so a failure to set it up should not stop elaboration
+    let progress = Progress::begin(format!("Elaborating {}", path.rel()), cancel.clone())
+      .unwrap_or_else(|e| { log!("failed to create progress: {:?}", e); None });
     let elab = ElaborateBuilder {
       ast: &ast,
       path: path.clone(),
@@ -255,45 +266,84 @@ async fn elaborate(path: FileRef, start: Option<Position>,
             }
           })
         }),
+      recv_progress: progress.map(|progress| {
+        let (path, file, source, ast) = (path.clone(), file.clone(), source.clone(), ast.clone());
+        let len = source.ascii().len().max(1);
+        let (mut last_report, mut last_diags) = (Instant::now(), Instant::now());
+        let (mut percentage, mut n_errors) = (0, 0);
+        ProgressListener::new(move |elab, p| {
+          let now = Instant::now();
+          match p {
+            ElabProgress::Stmt {idx, total, pos} => {
+              #[allow(clippy::integer_division)]
+              let pct = pos * 100 / len;
+              percentage = pct.try_into().unwrap_or(100);
+              if now.saturating_duration_since(last_report) >= PROGRESS_INTERVAL {
+                last_report = now;
+                drop(progress.report(format!("statement {}/{}", idx + 1, total), percentage));
+              }
+            }
+            ElabProgress::Import(dep) => {
+              last_report = now;
+              drop(progress.report(format!("waiting for import {}", dep.rel()), percentage));
+            }
+          }
+          if now.saturating_duration_since(last_diags) >= DIAGNOSTICS_INTERVAL &&
+            elab.errors().len() != n_errors && file.text.ulock().0 == version {
+            last_diags = now;
+            n_errors = elab.errors().len();
+            let errs = make_diagnostics(&path, &source, &ast, elab.errors());
+            drop(send_diagnostics(path.url().clone(), version, errs));
+          }
+        })
+      }),
+      recv_input: Some({
+        let input_deps = input_deps.clone();
+        FileReader::new(move |p| {
+          let (p, file) = vfs.get_or_insert(p)?;
+          let text = file.text.ulock().1.try_ascii().cloned().ok_or("expected a text file")?;
+          input_deps.ulock().push(p);
+          Ok(text)
+        })
+      }),
+      debugger: None,
+      #[cfg(feature = "mmc")]
+      mmc_options: Default::default(),
     }.elab();
     (Some(ast.clone()), elab.await)
   };
   for tok in toks {tok.hash(&mut hasher)}
+  // Files read by `input string` are dependencies too, but they do not pass through
+  // `recv_dep`, so we hash their elaboration results here, if they are available.
+  // If they are not, the cache check will fail and this file will be re-elaborated.
+  let input_deps = std::mem::take(&mut *input_deps.ulock());
+  for p in input_deps {
+    if deps.contains(&p) { continue }
+    if let Some(file) = vfs.get(&p) {
+      if let Some(Some(FileCache::Ready {hash, ..})) = file.parsed.try_lock().as_deref() {
+        hash.hash(&mut hasher)
+      }
+    }
+    deps.push(p)
+  }
   let hash = hasher.finish();
   let is_canceled = cancel.load(Ordering::SeqCst);
   log!("elabbed {:?}{}", path, if is_canceled {" (canceled)"} else {""});
   let no_change_since_elab = file.text.ulock().0 == version;
   if !is_canceled && no_change_since_elab {
-    let mut srcs = HashMap::new();
-    let mut to_loc = |fsp: &FileSpan| -> Location {
-      let fc = if fsp.file.ptr_eq(&path) {
-        &source
-      } else {
-        srcs.entry(fsp.file.ptr())
-        .or_insert_with(||
-          vfs.0.ulock().get(&fsp.file).expect("missing file")
-            .text.ulock().1.clone())
-      };
-      if let Some(file) = fc.try_ascii() {
-        file.to_loc(fsp)
-      } else {
-        Location {uri: fsp.file.url().clone(), range: Range::default()}
-      }
-    };
-
     if let Some(ast) = &ast {
       use std::fmt::Write;
       let (mut n_errs, mut n_warns, mut n_infos, mut n_hints) = (0, 0, 0, 0);
-      let errs: Vec<_> = ast.errors.iter().map(|e| e.to_diag(source.ascii()))
-        .chain(errors.iter().map(|e| e.to_diag(source.ascii(), &mut to_loc)))
-        .filter(|e| !e.message.is_empty())
-        .inspect(|err| match err.severity {
+      let errs = make_diagnostics(&path, &source, ast, &errors);
+      for err in &errs {
+        match err.severity {
           None => {}
           Some(DiagnosticSeverity::Error) => n_errs += 1,
           Some(DiagnosticSeverity::Warning) => n_warns += 1,
           Some(DiagnosticSeverity::Information) => n_infos += 1,
           Some(DiagnosticSeverity::Hint) => n_hints += 1,
-        }).collect();
+        }
+      }
 
       send_diagnostics(path.url().clone(), version, errs)?;
 
@@ -334,10 +384,36 @@ async fn elaborate(path: FileRef, start: Option<Position>,
       log!("{:?} affects {:?}", path, d);
       Job::DepChange(path.clone(), d.clone(), DepChangeReason::Elab).spawn();
     }
+    vfs.evict();
   }
   Ok(res)
 }
 
+/// Converts the parse errors in `ast` and the elaboration errors `errors` for the file `path`
+/// (with contents `source`) into diagnostics to send to the client.
+fn make_diagnostics(path: &FileRef, source: &FileContents, ast: &Ast, errors: &[ElabError]) -> Vec<Diagnostic> {
+  let mut srcs = HashMap::new();
+  let mut to_loc = |fsp: &FileSpan| -> Location {
+    let fc = if fsp.file.ptr_eq(path) {
+      source
+    } else {
+      srcs.entry(fsp.file.ptr())
+      .or_insert_with(||
+        SERVER.vfs.0.ulock().get(&fsp.file).expect("missing file")
+          .text.ulock().1.clone())
+    };
+    if let Some(file) = fc.try_ascii() {
+      file.to_loc(fsp)
+    } else {
+      Location {uri: fsp.file.url().clone(), range: Range::default()}
+    }
+  };
+  ast.errors.iter().map(|e| e.to_diag(source.ascii()))
+    .chain(errors.iter().map(|e| e.to_diag(source.ascii(), &mut to_loc)))
+    .filter(|e| !e.message.is_empty())
+    .collect()
+}
+
 async fn elaborate_and_report(path: FileRef, start: Option<Position>, cancel: Arc<AtomicBool>) {
   if let Err(e) =
     std::panic::AssertUnwindSafe(elaborate(path, start, cancel, Default::default()))
@@ -388,14 +464,41 @@ struct VirtualFile {
   parsed: FMutex<Option<FileCache>>,
   /// Files that depend on this one
   downstream: Mutex<HashSet<FileRef>>,
+  /// The last time this file was requested, for evicting unused files
+  last_used: Mutex<Instant>,
 }
 
+/// The approximate memory usage of a declaration in a [`FrozenEnv`], used for estimating
+/// the size of a file when the `memory` feature is not available.
+#[cfg(not(feature = "memory"))]
+const APPROX_DECL_SIZE: usize = 512;
+
 impl VirtualFile {
   fn new(version: Option<i32>, text: FileContents) -> VirtualFile {
     VirtualFile {
       text: Mutex::new((version, text)),
       parsed: FMutex::new(None),
-      downstream: Mutex::new(HashSet::new())
+      downstream: Mutex::new(HashSet::new()),
+      last_used: Mutex::new(Instant::now()),
+    }
+  }
+
+  fn touch(&self) { *self.last_used.ulock() = Instant::now() }
+
+  /// An estimate of the memory used by this file, in bytes.
+  fn size_estimate(&self) -> usize {
+    #[cfg(feature = "memory")] {
+      use mm0_deepsize::DeepSizeOf;
+      self.deep_size_of()
+    }
+    #[cfg(not(feature = "memory"))] {
+      let mut n = self.text.ulock().1.len();
+      if let Some(g) = self.parsed.try_lock() {
+        if let Some(FileCache::Ready {res: ElabResult::Ok(_, _, env), ..}) = &*g {
+          n += APPROX_DECL_SIZE * (env.data().len() + env.terms().len() + env.thms().len())
+        }
+      }
+      n
     }
   }
 }
@@ -405,19 +508,28 @@ struct Vfs(Mutex<HashMap<FileRef, Arc<VirtualFile>>>);
 
 impl Vfs {
   fn get(&self, path: &FileRef) -> Option<Arc<VirtualFile>> {
-    self.0.ulock().get(path).cloned()
+    let file = self.0.ulock().get(path).cloned()?;
+    file.touch();
+    Some(file)
+  }
+
+  fn read_file(path: &FileRef) -> io::Result<FileContents> {
+    if path.has_extension("mmb") {
+      FileContents::new_bin_from_file(path.path())
+    } else {
+      Ok(FileContents::new(fs::read_to_string(path.path())?))
+    }
   }
 
   fn get_or_insert(&self, path: FileRef) -> io::Result<(FileRef, Arc<VirtualFile>)> {
     match self.0.ulock().entry(path) {
-      Entry::Occupied(e) => Ok((e.key().clone(), e.get().clone())),
+      Entry::Occupied(e) => {
+        e.get().touch();
+        Ok((e.key().clone(), e.get().clone()))
+      }
       Entry::Vacant(e) => {
         let path = e.key().clone();
-        let fc = if path.has_extension("mmb") {
-          FileContents::new_bin_from_file(path.path())?
-        } else {
-          FileContents::new(fs::read_to_string(path.path())?)
-        };
+        let fc = Self::read_file(&path)?;
         let val = e.insert(Arc::new(VirtualFile::new(None, fc))).clone();
         Ok((path, val))
       }
@@ -460,11 +572,102 @@ impl Vfs {
     Ok(())
   }
 
+  /// Evicts the least recently used files from the VFS until the estimated memory usage
+  /// is within the `maxMemory` budget. Only files that are closed and not (transitively)
+  /// imported by an open file are eligible, and they will be re-read and re-elaborated
+  /// if they are needed again. Files that are being elaborated are also kept, along with
+  /// their known imports.
+  fn evict(&self) {
+    let max_memory = SERVER.options.ulock().max_memory;
+    let budget = match max_memory {
+      Some(n) if n != 0 => n << 20,
+      _ => return
+    };
+    let mut g = self.0.ulock();
+    let mut total = 0;
+    let mut deps = HashMap::new();
+    let mut stack = vec![];
+    let mut pending = HashSet::new();
+    for (path, file) in &*g {
+      total += file.size_estimate();
+      let parsed = file.parsed.try_lock();
+      if let Some(Some(FileCache::Ready {deps: ds, ..})) = parsed.as_deref() {
+        deps.insert(path.clone(), ds.clone());
+        if file.text.ulock().0.is_some() { stack.push(path.clone()) }
+      } else {
+        // The file is being elaborated, so it is live, and its imports are not known yet
+        // except through the `downstream` links of files it imported before
+        pending.insert(path.clone());
+        stack.push(path.clone());
+      }
+    }
+    if total <= budget { return }
+    for (path, file) in &*g {
+      for to in &*file.downstream.ulock() {
+        if pending.contains(to) {
+          deps.entry(to.clone()).or_insert_with(Vec::new).push(path.clone())
+        }
+      }
+    }
+    let mut live = HashSet::new();
+    while let Some(path) = stack.pop() {
+      if live.insert(path.clone()) {
+        if let Some(ds) = deps.get(&path) { stack.extend(ds.iter().cloned()) }
+      }
+    }
+    let mut candidates = g.iter()
+      .filter(|(path, _)| !live.contains(*path))
+      .map(|(path, file)| (*file.last_used.ulock(), path.clone()))
+      .collect::<Vec<_>>();
+    candidates.sort_by_key(|&(t, _)| t);
+    for (_, path) in candidates {
+      if total <= budget { break }
+      let file = g.remove(&path).expect("impossible");
+      total = total.saturating_sub(file.size_estimate());
+      log!("evict {:?}", path);
+      for from in &deps[&path] {
+        if let Some(file) = g.get(from) { file.downstream.ulock().remove(&path); }
+      }
+    }
+  }
+
+  /// Handles a change to `path` made outside the editor. Files that are open in the editor
+  /// are unaffected, because the editor's copy takes precedence. Otherwise the cached file
+  /// is dropped and re-read from disk (unless it was deleted), and everything
+  /// downstream of it is re-elaborated.
+  fn disk_change(&self, path: &FileRef, deleted: bool) -> Result<()> {
+    let mut g = self.0.ulock();
+    let file = match g.get(path) {
+      Some(file) if file.text.ulock().0.is_none() => file.clone(),
+      _ => return Ok(())
+    };
+    let downstream = file.downstream.ulock().clone();
+    let new = if deleted { None } else {
+      match Self::read_file(path) {
+        Ok(fc) => Some(fc),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into())
+      }
+    };
+    if let Some(fc) = new {
+      let file = VirtualFile {downstream: Mutex::new(downstream.clone()), ..VirtualFile::new(None, fc)};
+      g.insert(path.clone(), Arc::new(file));
+    } else {
+      g.remove(path);
+    }
+    drop(g);
+    for dep in downstream {
+      Job::DepChange(path.clone(), dep, DepChangeReason::Disk).spawn();
+    }
+    Ok(())
+  }
+
   fn update_downstream(&self, old_deps: &[FileRef], deps: &[FileRef], to: &FileRef) {
     for from in old_deps {
       if !deps.contains(from) {
-        let file = self.0.ulock().get(from).unwrap().clone();
-        file.downstream.ulock().remove(to);
+        // The file may have been removed from the VFS if it was deleted on disk
+        let file = self.0.ulock().get(from).cloned();
+        if let Some(file) = file { file.downstream.ulock().remove(to); }
       }
     }
     for from in deps {
@@ -485,6 +688,10 @@ enum RequestType {
   DocumentSymbol(DocumentSymbolParams),
   References(ReferenceParams),
   DocumentHighlight(DocumentHighlightParams),
+  FoldingRange(FoldingRangeParams),
+  SelectionRange(SelectionRangeParams),
+  DocumentLink(DocumentLinkParams),
+  CodeLens(CodeLensParams),
 }
 
 fn parse_request(Request {id, method, params}: Request) -> Result<Option<(RequestId, RequestType)>> {
@@ -496,6 +703,10 @@ fn parse_request(Request {id, method, params}: Request) -> Result<Option<(Reques
     "textDocument/documentSymbol"    => Some((id, RequestType::DocumentSymbol(from_value(params)?))),
     "textDocument/references"        => Some((id, RequestType::References(from_value(params)?))),
     "textDocument/documentHighlight" => Some((id, RequestType::DocumentHighlight(from_value(params)?))),
+    "textDocument/foldingRange"      => Some((id, RequestType::FoldingRange(from_value(params)?))),
+    "textDocument/selectionRange"    => Some((id, RequestType::SelectionRange(from_value(params)?))),
+    "textDocument/documentLink"      => Some((id, RequestType::DocumentLink(from_value(params)?))),
+    "textDocument/codeLens"          => Some((id, RequestType::CodeLens(from_value(params)?))),
     _ => None
   })
 }
@@ -534,6 +745,97 @@ fn send_diagnostics(uri: Url, version: Option<i32>, diagnostics: Vec<Diagnostic>
   })
 }
 
+/// The state of a [`Progress`] indicator. The `$/progress` notifications can only be sent after
+/// the client has responded to the `window/workDoneProgress/create` request, so until then the
+/// begin message and the latest report are held back here.
+enum ProgressState {
+  /// Waiting for the response to `window/workDoneProgress/create`
+  Pending(WorkDoneProgressBegin, Option<WorkDoneProgressReport>),
+  /// The token has been created and `Begin` has been sent
+  Created,
+  /// The progress has ended, or the client refused to create the token
+  Ended,
+}
+
+/// A [`ProgressState`] shared between a [`Progress`] and the main loop, which receives the
+/// response to `window/workDoneProgress/create`.
+type SharedProgress = Arc<Mutex<ProgressState>>;
+
+/// A work done progress indicator on the client, created using `window/workDoneProgress/create`
+/// and updated using `$/progress` notifications. The progress is ended when this is dropped.
+struct Progress {
+  token: ProgressToken,
+  cancel: Arc<AtomicBool>,
+  state: SharedProgress,
+}
+
+fn send_progress(token: ProgressToken, value: WorkDoneProgress) -> Result<()> {
+  send_message(Notification {
+    method: "$/progress".to_owned(),
+    params: to_value(ProgressParams {token, value: ProgressParamsValue::WorkDone(value)})?
+  })
+}
+
+impl Progress {
+  /// Creates a new progress indicator with the given title, if the client supports it.
+  /// If `cancel` has been set by the time the progress ends, it is reported as canceled.
+  fn begin(title: String, cancel: Arc<AtomicBool>) -> Result<Option<Progress>> {
+    if !SERVER.caps.ulock().work_done_progress { return Ok(None) }
+    let n = SERVER.progress_count.fetch_add(1, Ordering::Relaxed);
+    let token = NumberOrString::String(format!("mm0-rs/progress/{}", n));
+    let id = RequestId::from(format!("progress/{}", n));
+    let state = Arc::new(Mutex::new(ProgressState::Pending(WorkDoneProgressBegin {
+      title, cancellable: Some(false), message: None, percentage: Some(0)
+    }, None)));
+    SERVER.progress_reqs.ulock().insert(id.clone(), (token.clone(), state.clone()));
+    send_message(Request {
+      id,
+      method: "window/workDoneProgress/create".to_owned(),
+      params: to_value(WorkDoneProgressCreateParams {token: token.clone()})?
+    })?;
+    Ok(Some(Progress {token, cancel, state}))
+  }
+
+  /// Handles the client's response to `window/workDoneProgress/create`, sending the held back
+  /// `Begin` notification and latest report if the token was created.
+  fn created(token: ProgressToken, state: &SharedProgress, ok: bool) -> Result<()> {
+    // The lock is held while sending, so that no report can overtake the `Begin` notification
+    let mut guard = state.ulock();
+    let new = if ok {ProgressState::Created} else {ProgressState::Ended};
+    let old = std::mem::replace(&mut *guard, new);
+    if let (true, ProgressState::Pending(begin, report)) = (ok, old) {
+      send_progress(token.clone(), WorkDoneProgress::Begin(begin))?;
+      if let Some(report) = report {
+        send_progress(token, WorkDoneProgress::Report(report))?
+      }
+    }
+    drop(guard);
+    Ok(())
+  }
+
+  fn report(&self, message: String, percentage: u32) -> Result<()> {
+    let report = WorkDoneProgressReport {
+      cancellable: None, message: Some(message), percentage: Some(percentage)
+    };
+    match &mut *self.state.ulock() {
+      ProgressState::Pending(_, pending) => { *pending = Some(report); Ok(()) }
+      ProgressState::Created => send_progress(self.token.clone(), WorkDoneProgress::Report(report)),
+      ProgressState::Ended => Ok(())
+    }
+  }
+}
+
+impl Drop for Progress {
+  fn drop(&mut self) {
+    // If the token has not been created yet, `Begin` was never sent, so there is nothing to end
+    let old = std::mem::replace(&mut *self.state.ulock(), ProgressState::Ended);
+    if matches!(old, ProgressState::Created) {
+      let message = self.cancel.load(Ordering::SeqCst).then(|| "canceled".to_owned());
+      drop(send_progress(self.token.clone(), WorkDoneProgress::End(WorkDoneProgressEnd {message})))
+    }
+  }
+}
+
 type OpenRequests = Mutex<HashMap<RequestId, Arc<AtomicBool>>>;
 
 struct RequestHandler {
@@ -581,6 +883,14 @@ impl RequestHandler {
         self.finish(references(file.clone(), doc.position, true,
           |range| DocumentHighlight { range, kind: None }).await)
       }
+      RequestType::FoldingRange(FoldingRangeParams {text_document: doc, ..}) =>
+        self.finish(folding_range(&doc.uri.into())),
+      RequestType::SelectionRange(SelectionRangeParams {text_document: doc, positions, ..}) =>
+        self.finish(selection_range(&doc.uri.into(), &positions)),
+      RequestType::DocumentLink(DocumentLinkParams {text_document: doc, ..}) =>
+        self.finish(document_link(&doc.uri.into())),
+      RequestType::CodeLens(CodeLensParams {text_document: doc, ..}) =>
+        self.finish(code_lens(doc.uri.into()).await),
     }
   }
 
@@ -943,6 +1253,7 @@ async fn document_symbol(path: FileRef) -> Result<DocumentSymbolResponse, Respon
                 FrozenLispKind::Undef => return None,
                 FrozenLispKind::Proc(_) => SymbolKind::Function,
                 FrozenLispKind::AtomMap(_) |
+                FrozenLispKind::HashMap(_) |
                 FrozenLispKind::Annot(_, _) |
                 FrozenLispKind::Ref(_) => SymbolKind::Object,
               }))() {
@@ -952,12 +1263,341 @@ async fn document_symbol(path: FileRef) -> Result<DocumentSymbolResponse, Respon
           }
         }
       }
-      StmtTrace::OutputString(_) => {}
+      StmtTrace::OutputString(_) | StmtTrace::InputString(_) => {}
     }
   }
   Ok(DocumentSymbolResponse::Nested(res))
 }
 
+/// Gets the current text of a file and a parse of it, reusing the parse from the last
+/// elaboration if the text has not changed since then. Returns `None` for files which
+/// are not MM0/MM1 files.
+#[allow(clippy::type_complexity)]
+fn get_ast(path: &FileRef) -> Result<Option<(Arc<LinedString>, Arc<Ast>)>, ResponseError> {
+  let file = SERVER.vfs.get(path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "nonexistent file"))?;
+  if path.has_extension("mmu") { return Ok(None) }
+  let text = file.text.ulock().1.clone();
+  let lined = match text.try_ascii() {
+    Some(lined) => lined.clone(),
+    None => return Ok(None)
+  };
+  if let Some(Some(FileCache::Ready {source, ast: Some(ast), ..})) = file.parsed.try_lock().as_deref() {
+    if source.ptr_eq(&text) { return Ok(Some((lined, ast.clone()))) }
+  }
+  let ast = Arc::new(parse(lined.clone(), None).1);
+  Ok(Some((lined, ast)))
+}
+
+/// Collects the folding ranges in a file.
+struct Folder<'a> {
+  text: &'a LinedString,
+  res: Vec<FoldingRange>,
+}
+
+impl Folder<'_> {
+  /// Adds a folding range for the given span, if it spans more than one line.
+  /// Ranges starting on the same line as the previous range are skipped, so that
+  /// only the outermost range on each line is folded.
+  fn push(&mut self, sp: Span, kind: Option<FoldingRangeKind>) {
+    let start_line = self.text.to_pos(sp.start).line;
+    let Position {mut line, character} = self.text.to_pos(sp.end);
+    if character == 0 && line > start_line { line -= 1 }
+    if start_line < line && self.res.last().map_or(true, |r| r.start_line != start_line) {
+      self.res.push(FoldingRange {
+        start_line, start_character: None, end_line: line, end_character: None, kind
+      })
+    }
+  }
+
+  fn sexpr(&mut self, e: &SExpr) {
+    match &e.k {
+      SExprKind::List(es) => {
+        self.push(e.span, None);
+        for e in es { self.sexpr(e) }
+      }
+      SExprKind::DottedList(es, e2) => {
+        self.push(e.span, None);
+        for e in es { self.sexpr(e) }
+        self.sexpr(e2)
+      }
+      SExprKind::DocComment(_, e2) => {
+        self.push((e.span.start..e2.span.start).into(), Some(FoldingRangeKind::Comment));
+        self.sexpr(e2)
+      }
+      SExprKind::Formula(f) => self.push(f.0, None),
+      SExprKind::Atom(_) | SExprKind::Number(_) | SExprKind::String(_) |
+      SExprKind::Bool(_) | SExprKind::Undef => {}
+    }
+  }
+
+  fn stmt(&mut self, stmt: &Stmt) {
+    match &stmt.k {
+      StmtKind::DocComment(_, s) => {
+        let mut s = s;
+        while let StmtKind::DocComment(_, s2) = &s.k { s = s2 }
+        self.push((stmt.span.start..s.span.start).into(), Some(FoldingRangeKind::Comment));
+        self.stmt(s)
+      }
+      StmtKind::Annot(e, s) => {
+        self.sexpr(e);
+        self.stmt(s)
+      }
+      StmtKind::Decl(d) => {
+        self.push(stmt.span, None);
+        if let Some(val) = &d.val { self.sexpr(val) }
+      }
+      StmtKind::Do(es) | StmtKind::Inout {hs: es, ..} => {
+        self.push(stmt.span, None);
+        for e in es { self.sexpr(e) }
+      }
+      StmtKind::Sort(..) | StmtKind::Delimiter(_) | StmtKind::SimpleNota(_) |
+      StmtKind::Coercion {..} | StmtKind::Notation(_) | StmtKind::Import(..) =>
+        self.push(stmt.span, None),
+    }
+  }
+}
+
+fn folding_range(path: &FileRef) -> Result<Vec<FoldingRange>, ResponseError> {
+  let (text, ast) = match get_ast(path)? {
+    Some(r) => r,
+    None => return Ok(vec![])
+  };
+  let mut folder = Folder {text: &text, res: vec![]};
+  for s in &ast.stmts { folder.stmt(s) }
+  Ok(folder.res)
+}
+
+/// Collects the spans of all syntax nodes containing a position, from outermost to innermost.
+struct SelectionSpans<'a> {
+  text: &'a LinedString,
+  idx: usize,
+  res: Vec<Span>,
+}
+
+impl SelectionSpans<'_> {
+  /// Adds the span to the list and returns true if it contains the target position.
+  fn push(&mut self, sp: Span) -> bool {
+    if !(sp.start <= self.idx && self.idx <= sp.end) { return false }
+    if self.res.last() != Some(&sp) { self.res.push(sp) }
+    true
+  }
+
+  /// Descends into a math string, adding the spans of the parenthesized groups
+  /// and the token containing the target position.
+  fn formula(&mut self, sp: Span) {
+    if !self.push(sp) { return }
+    let s = self.text.as_bytes();
+    let trim = |mut sp: Span| {
+      while sp.start < sp.end && s[sp.start].is_ascii_whitespace() { sp.start += 1 }
+      while sp.start < sp.end && s[sp.end - 1].is_ascii_whitespace() { sp.end -= 1 }
+      sp
+    };
+    let inner = Span {start: sp.start + 1, end: sp.end.saturating_sub(1).max(sp.start + 1)};
+    self.push(trim(inner));
+    let (mut stack, mut groups) = (vec![], vec![]);
+    for (i, &c) in s.iter().enumerate().take(inner.end).skip(inner.start) {
+      match c {
+        b'(' => stack.push(i),
+        b')' => if let Some(start) = stack.pop() {
+          if start < self.idx && self.idx <= i { groups.push(Span::from(start..i + 1)) }
+        }
+        _ => {}
+      }
+    }
+    // groups are found innermost first
+    for &sp in groups.iter().rev() { self.push(sp); }
+    let (mut start, mut end) = (self.idx.max(inner.start), self.idx.min(inner.end));
+    while start > inner.start && !s[start - 1].is_ascii_whitespace() { start -= 1 }
+    while end < inner.end && !s[end].is_ascii_whitespace() { end += 1 }
+    if start < end { self.push((start..end).into()); }
+  }
+
+  fn sexpr(&mut self, e: &SExpr) {
+    if !self.push(e.span) { return }
+    match &e.k {
+      SExprKind::List(es) => { es.iter().any(|e| self.sexpr_contains(e)); }
+      SExprKind::DottedList(es, e2) => {
+        let _ = es.iter().any(|e| self.sexpr_contains(e)) || self.sexpr_contains(e2);
+      }
+      SExprKind::DocComment(_, e2) => self.sexpr(e2),
+      &SExprKind::Formula(f) => self.formula(f.0),
+      SExprKind::Atom(_) | SExprKind::Number(_) | SExprKind::String(_) |
+      SExprKind::Bool(_) | SExprKind::Undef => {}
+    }
+  }
+
+  fn sexpr_contains(&mut self, e: &SExpr) -> bool {
+    let n = self.res.len();
+    self.sexpr(e);
+    self.res.len() != n
+  }
+
+  fn binders(&mut self, bis: &[Binder]) {
+    for bi in bis {
+      if self.push(bi.span) {
+        if let Some(sp) = bi.local { self.push(sp); }
+        self.ty(bi.ty.as_ref());
+        return
+      }
+    }
+  }
+
+  fn ty(&mut self, ty: Option<&Type>) {
+    match ty {
+      Some(Type::Formula(f)) => self.formula(f.0),
+      Some(ty) => { self.push(ty.span()); }
+      None => {}
+    }
+  }
+
+  fn stmt(&mut self, stmt: &Stmt) {
+    if !self.push(stmt.span) { return }
+    match &stmt.k {
+      StmtKind::DocComment(_, s) => self.stmt(s),
+      StmtKind::Annot(e, s) => { self.sexpr(e); self.stmt(s) }
+      StmtKind::Decl(d) => {
+        self.push(d.id);
+        self.binders(&d.bis);
+        self.ty(d.ty.as_ref());
+        if let Some(val) = &d.val { self.sexpr(val) }
+      }
+      StmtKind::Do(es) | StmtKind::Inout {hs: es, ..} => for e in es { self.sexpr(e) },
+      &StmtKind::Sort(sp, _) | &StmtKind::Import(sp, _) => { self.push(sp); }
+      StmtKind::SimpleNota(n) => {
+        self.push(n.id);
+        self.formula(n.c.fmla.0);
+      }
+      &StmtKind::Coercion {id, from, to} => for sp in [id, from, to] { self.push(sp); },
+      StmtKind::Notation(GenNota {id, bis, ty, lits, ..}) => {
+        self.push(*id);
+        self.binders(bis);
+        self.ty(ty.as_ref());
+        for lit in lits {
+          match *lit {
+            Literal::Const(c, _) => self.formula(c.fmla.0),
+            Literal::Var(sp) => { self.push(sp); }
+          }
+        }
+      }
+      StmtKind::Delimiter(_) => {}
+    }
+  }
+}
+
+fn selection_range(path: &FileRef, positions: &[Position]) -> Result<Vec<SelectionRange>, ResponseError> {
+  let (text, ast) = match get_ast(path)? {
+    Some(r) => r,
+    None => return Ok(vec![])
+  };
+  Ok(positions.iter().map(|&pos| {
+    let mut spans = SelectionSpans {text: &text, idx: text.to_idx(pos).unwrap_or(0), res: vec![]};
+    let i = ast.stmts.partition_point(|s| s.span.end < spans.idx);
+    if let Some(s) = ast.stmts.get(i) { spans.stmt(s) }
+    let mut res = None;
+    for sp in spans.res {
+      res = Some(Box::new(SelectionRange {range: text.to_range(sp), parent: res}))
+    }
+    res.map_or_else(|| SelectionRange {range: Range::new(pos, pos), parent: None}, |r| *r)
+  }).collect())
+}
+
+fn document_link(path: &FileRef) -> Result<Vec<DocumentLink>, ResponseError> {
+  let (text, ast) = match get_ast(path)? {
+    Some(r) => r,
+    None => return Ok(vec![])
+  };
+  Ok(ast.imports.iter().filter_map(|&(sp, ref f)| {
+    let target = path.resolve_import(f).ok()?;
+    Some(DocumentLink {
+      range: text.to_range(sp),
+      target: Some(target.url().clone()),
+      tooltip: None,
+      data: None,
+    })
+  }).collect())
+}
+
+/// The client command used by the reference count code lens. The arguments are
+/// the document uri, the position of the theorem name, and the list of locations,
+/// as in vscode's `editor.action.showReferences`.
+const SHOW_REFERENCES: &str = "metamath-zero.showReferences";
+
+/// The client command used by the proof statistics code lens, which does nothing.
+/// (An empty command is rendered as clickable by clients, and fails when clicked.)
+const NO_OP: &str = "metamath-zero.noop";
+
+async fn code_lens(path: FileRef) -> Result<Vec<CodeLens>, ResponseError> {
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "code lens: nonexistent file"))?;
+  let env = elaborate(path.clone(), Some(Position::default()), Default::default(), Default::default())
+    .await.map_err(|e| response_err(ErrorCode::InternalError, format!("{:?}", e)))?;
+  let env = match env.into_response_error()? {
+    Some((_, env)) => env,
+    None => return Ok(vec![])
+  };
+  let text = file.text.ulock().1.ascii().clone();
+  let mut thm_refs = HashMap::<ThmId, Vec<Span>>::new();
+  for spans in env.spans() {
+    for &(sp, ref k) in spans {
+      let t = match *k {
+        ObjectKind::Thm(t) => t,
+        ObjectKind::Proof(ref p) => match p.uncons().next().unwrap_or(p).as_atom() {
+          Some(a) => if let Some(DeclKey::Thm(t)) = env.data()[a].decl() {t} else {continue},
+          None => continue
+        },
+        _ => continue
+      };
+      thm_refs.entry(t).or_default().push(sp)
+    }
+  }
+  let uses_sorry = env.uses_sorry();
+  // Without elaborated proofs every theorem looks like it uses sorry
+  let proofs_elaborated = !path.has_extension("mm0") && crate::get_check_proofs();
+  let mut res = vec![];
+  for (t, td) in env.thms().enum_iter() {
+    if td.span.file != path { continue }
+    let range = text.to_range(td.span.span);
+    let locs = thm_refs.get(&t).map_or_else(Vec::new, |v| v.iter()
+      .filter(|&&sp| sp != td.span.span)
+      .map(|&sp| Location {uri: path.url().clone(), range: text.to_range(sp)})
+      .collect());
+    let title = match locs.len() {
+      1 => "1 reference".into(),
+      n => format!("{} references", n),
+    };
+    res.push(CodeLens {
+      range,
+      command: Some(Command {
+        title,
+        command: SHOW_REFERENCES.into(),
+        arguments: Some(vec![
+          to_value(path.url()).expect("serialize"),
+          to_value(range.start).expect("serialize"),
+          to_value(locs).expect("serialize"),
+        ]),
+      }),
+      data: None,
+    });
+    let mut stats = vec![];
+    if let ThmKind::Thm(ref pf) = td.kind {
+      if let Some(pf) = pf { stats.push(format!("proof size {}", pf.size())) }
+      if let Some(spans) = env.find(td.full.start) {
+        stats.push(format!("elaborated in {:.1?}", spans.elab_time))
+      }
+      if proofs_elaborated && uses_sorry[t] { stats.push("uses sorry".into()) }
+    }
+    if !stats.is_empty() {
+      res.push(CodeLens {
+        range,
+        command: Some(Command {title: stats.join(" | "), command: NO_OP.into(), arguments: None}),
+        data: None,
+      })
+    }
+  }
+  Ok(res)
+}
+
 #[derive(Serialize_repr, Deserialize_repr)]
 #[repr(u8)]
 enum TraceKind {Sort, Decl, Global}
@@ -1001,6 +1641,7 @@ fn make_completion_item(path: &FileRef, fe: FormatEnv<'_>, ad: &FrozenAtomData,
         FrozenLispKind::String(_) |
         FrozenLispKind::Bool(_) |
         FrozenLispKind::AtomMap(_) |
+        FrozenLispKind::HashMap(_) |
         FrozenLispKind::Annot(_, _) |
         FrozenLispKind::Ref(_) => CompletionItemKind::Value,
         FrozenLispKind::Syntax(_) => CompletionItemKind::Event,
@@ -1169,6 +1810,10 @@ struct Server {
   caps: Mutex<ClientCapabilities>,
   reqs: OpenRequests,
   vfs: Vfs,
+  /// The number of progress tokens created so far, used to generate fresh tokens
+  progress_count: AtomicUsize,
+  /// The outstanding `window/workDoneProgress/create` requests
+  progress_reqs: Mutex<HashMap<RequestId, (ProgressToken, SharedProgress)>>,
   pool: ThreadPool,
   #[allow(clippy::type_complexity)]
   threads: Arc<(Mutex<VecDeque<(Job, Arc<AtomicBool>)>>, Condvar)>,
@@ -1198,6 +1843,8 @@ struct ClientCapabilities {
   reg_id: Option<RequestId>,
   definition_location_links: Option<bool>,
   goal_view: bool,
+  work_done_progress: bool,
+  watch_files: bool,
 }
 
 impl ClientCapabilities {
@@ -1208,10 +1855,17 @@ impl ClientCapabilities {
       Some(GotoCapability {dynamic_registration: Some(true), ..}) => Some(true),
       _ => Some(false)
     };
+    let work_done_progress = params.capabilities.window.as_ref()
+      .and_then(|w| w.work_done_progress).unwrap_or(false);
+    let watch_files = params.capabilities.workspace.as_ref()
+      .and_then(|w| w.did_change_watched_files.as_ref())
+      .and_then(|c| c.dynamic_registration).unwrap_or(false);
     let goal_view = params.initialization_options
       .and_then(|o| from_value(o).ok()).and_then(|o: InitOptions| o.extra_capabilities)
       .and_then(|c| c.goal_view).unwrap_or(false);
-    ClientCapabilities { reg_id: None, definition_location_links: dll, goal_view }
+    ClientCapabilities {
+      reg_id: None, definition_location_links: dll, goal_view, work_done_progress, watch_files
+    }
   }
 
   fn register(&mut self) -> Result<()> {
@@ -1231,6 +1885,16 @@ impl ClientCapabilities {
       register_options: None,
     });
 
+    if self.watch_files {
+      regs.push(Registration {
+        id: String::new(),
+        method: "workspace/didChangeWatchedFiles".into(),
+        register_options: Some(to_value(DidChangeWatchedFilesRegistrationOptions {
+          watchers: vec![FileSystemWatcher {glob_pattern: "**/*.{mm0,mm1,mmb}".into(), kind: None}]
+        })?),
+      });
+    }
+
     if !regs.is_empty() {
       register_capability("regs".into(), regs)?;
       self.reg_id = Some(String::from("regs").into());
@@ -1248,7 +1912,7 @@ impl ClientCapabilities {
   }
 }
 
-enum DepChangeReason { Open, Close, Elab }
+enum DepChangeReason { Open, Close, Elab, Disk }
 
 impl std::fmt::Display for DepChangeReason {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -1256,6 +1920,7 @@ impl std::fmt::Display for DepChangeReason {
       Self::Open => write!(f, "open"),
       Self::Close => write!(f, "close"),
       Self::Elab => write!(f, "elaboration"),
+      Self::Disk => write!(f, "change on disk"),
     }
   }
 }
@@ -1363,6 +2028,7 @@ struct ServerOptions {
   syntax_docs: Option<bool>,
   log_errors: Option<bool>,
   report_upstream_errors: Option<bool>,
+  max_memory: Option<usize>,
 }
 
 impl std::default::Default for ServerOptions {
@@ -1374,6 +2040,7 @@ impl std::default::Default for ServerOptions {
       syntax_docs: None,
       log_errors: None,
       report_upstream_errors: None,
+      max_memory: None,
     }
   }
 }
@@ -1424,6 +2091,13 @@ impl Server {
         document_symbol_provider: Some(OneOf::Left(true)),
         references_provider: Some(OneOf::Left(true)),
         document_highlight_provider: Some(OneOf::Left(true)),
+        folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+        selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+        document_link_provider: Some(DocumentLinkOptions {
+          resolve_provider: Some(false),
+          work_done_progress_options: Default::default(),
+        }),
+        code_lens_provider: Some(CodeLensOptions {resolve_provider: Some(false)}),
         ..Default::default()
       })?
     )?)?;
@@ -1432,6 +2106,8 @@ impl Server {
       conn,
       reqs: Mutex::new(HashMap::new()),
       vfs: Vfs(Mutex::new(HashMap::new())),
+      progress_count: AtomicUsize::new(0),
+      progress_reqs: Mutex::new(HashMap::new()),
       pool: ThreadPool::new()?,
       threads: Default::default(),
       options: Mutex::new(ServerOptions::default()),
@@ -1456,7 +2132,7 @@ impl Server {
 
     loop {
       match (|| -> Result<bool> {
-        let Server {conn, caps, reqs, vfs, options, ..} = &*SERVER;
+        let Server {conn, caps, reqs, vfs, options, progress_reqs, ..} = &*SERVER;
         match conn.receiver.recv() {
           Err(RecvError) => return Ok(true),
           Ok(Message::Request(req)) => {
@@ -1468,11 +2144,14 @@ impl Server {
             }
           }
           Ok(Message::Response(resp)) => {
+            let progress = progress_reqs.ulock().remove(&resp.id);
             if resp.id == get_config_id {
               if let Some(val) = resp.result {
                 let [config]: [ServerOptions; 1] = from_value(val)?;
                 *self.options.ulock() = config;
               }
+            } else if let Some((token, state)) = progress {
+              Progress::created(token, &state, resp.error.is_none())?
             } else {
               let mut caps = caps.ulock();
               if caps.reg_id.as_ref().map_or(false, |rid| rid == &resp.id) {
@@ -1520,6 +2199,7 @@ impl Server {
                 let path = doc.uri.into();
                 log!("close {:?}", path);
                 vfs.close(&path)?;
+                vfs.evict();
               }
               DidSaveTextDocument::METHOD => {
                 let DidSaveTextDocumentParams {text_document: doc, ..} = from_value(notif.params)?;
@@ -1530,6 +2210,14 @@ impl Server {
                 }
               }
               DidChangeConfiguration::METHOD => send_config_request()?,
+              DidChangeWatchedFiles::METHOD => {
+                let DidChangeWatchedFilesParams {changes} = from_value(notif.params)?;
+                for FileEvent {uri, typ} in changes {
+                  let path = uri.into();
+                  log!("disk change {:?}", path);
+                  vfs.disk_change(&path, typ == FileChangeType::Deleted)?;
+                }
+              }
               _ => {}
             }
           }
@@ -1,11 +1,14 @@
-import { commands, window, workspace, ExtensionContext, TextDocument, EndOfLine } from 'vscode';
+import { commands, debug, window, workspace, ExtensionContext, TextDocument, EndOfLine,
+	DebugAdapterExecutable } from 'vscode';
 
 import {
 	LanguageClient,
 	LanguageClientOptions,
 	ServerOptions,
 	ErrorAction,
-	CloseAction
+	CloseAction,
+	Location,
+	Position
 } from 'vscode-languageclient';
 
 let client: LanguageClient;
@@ -56,7 +59,25 @@ export function activate(context: ExtensionContext) {
 		commands.registerCommand('metamath-zero.shutdownServer',
 		  () => client.stop().then(() => {}, () => {})),
 		commands.registerCommand('metamath-zero.restartServer',
-			() => client.stop().then(startClient, startClient))
+			() => client.stop().then(startClient, startClient)),
+		// The lisp debugger is a separate mode of the same executable
+		debug.registerDebugAdapterDescriptorFactory('mm1', {
+			createDebugAdapterDescriptor() {
+				let config = workspace.getConfiguration('metamath-zero');
+				let mm0Path: string = config.get('executablePath') || 'mm0-rs';
+				return new DebugAdapterExecutable(mm0Path, ['debug-adapter']);
+			}
+		}),
+		// Used by the reference count code lens; the arguments are sent as JSON
+		// and need to be converted before calling the builtin references view.
+		commands.registerCommand('metamath-zero.showReferences',
+			(uri: string, pos: Position, locs: Location[]) => {
+				const conv = client.protocol2CodeConverter;
+				commands.executeCommand('editor.action.showReferences',
+					conv.asUri(uri), conv.asPosition(pos), locs.map(l => conv.asLocation(l)));
+			}),
+		// Used by the proof statistics code lens, which is informational only
+		commands.registerCommand('metamath-zero.noop', () => {})
 	);
 }
 